@@ -1,35 +1,107 @@
 module command_controller #(
     parameter ROC_INPUTS,
     parameter ROC_OUTPUTS,
-    parameter ROC_OUTPUT_BYTES
+    parameter ROC_OUTPUT_BYTES,
+    parameter ROC_SCAN_BYTES,
+    parameter ROC_TRACE_BYTES,
+    parameter ROC_DESIGN_HASH
 ) (
     input                       i_clk,
     input                       i_rx,
     input   [ROC_OUTPUTS-1:0]   i_roc_outputs,
+    input                       i_roc_tick,
     output  [ROC_INPUTS-1:0]    o_roc_inputs,
     output  reg                 o_tx,
     output  [31:0]              o_roc_tps,
-    output  reg                 o_roc_en
+    output  reg                 o_roc_en,
+    output  reg                 o_roc_step,
+    output  reg                 o_rom_we,
+    output  reg [23:0]          o_rom_addr,
+    output  reg [3:0]           o_rom_data,
+    output  reg                 o_scan_load,
+    output  reg                 o_scan_shift,
+    input                       i_scan_out,
+    output  reg                 o_trace_arm,
+    output  reg [23:0]          o_trace_addr,
+    input   [ROC_TRACE_BYTES*8-1:0] i_trace_data,
+    input   [1:0]               i_trace_state,
+    input   [23:0]              i_trace_start,
+    input   [23:0]              i_trace_count
 );
     /*---------------------------------
                 PARAMETERS
     ---------------------------------*/
+    // Frame (both directions): SOF, VERSION, SEQ, CMD/STATUS, LEN_HI, LEN_LO, PAYLOAD[LEN], CRC
+    // The CRC-8 (poly 0x07) covers everything from VERSION to the end of the payload
     parameter
-        BYTES_PER_COMMANDS      = 6;
+        PROTOCOL_VERSION        = 8'h03,
+        MAX_PAYLOAD             = 8,
+        MAX_BAD_FRAMES          = 4,
+        RX_TIMEOUT              = 24'd50000,
+        STEP_HALF_PERIOD        = 8'd16,
+        PING_BYTES              = 7,
+        TICKS_BYTES             = 8,
+        TRACE_STATUS_BYTES      = 7,
+        IDENTIFY_BYTES          = 5;
+
+    localparam
+        RESPONSE_BYTES          = (PING_BYTES > TICKS_BYTES) ? PING_BYTES : TICKS_BYTES,
+        ROC_BYTES               = (ROC_OUTPUT_BYTES > ROC_TRACE_BYTES) ? ROC_OUTPUT_BYTES : ROC_TRACE_BYTES,
+        CHANGE_MASK_BYTES       = (ROC_OUTPUT_BYTES + 7) >> 3,
+        TX_PAYLOAD_BITS         = (ROC_BYTES > RESPONSE_BYTES) ? ROC_BYTES*8 : RESPONSE_BYTES*8;
     /*---------------------------------
                   COMMANDS
     ---------------------------------*/
-    parameter 
+    parameter
         CMD_RESET               = 8'hC0,
         CMD_PING                = 8'hC1,
-        CMD_GET_OUTPUTS         = 8'hC2, 
+        CMD_GET_OUTPUTS         = 8'hC2,
         CMD_CAPTURE             = 8'hC3,
         CMD_SET_INPUT           = 8'hC4,
-        CMD_SET_RTPS            = 8'hC5, 
-        CMD_LOAD_ROM            = 8'hC6, 
+        CMD_SET_RTPS            = 8'hC5,
+        CMD_LOAD_ROM            = 8'hC6,
         CMD_DEBUG_LED           = 8'hC7,
         CMD_FAIL_ACK            = 8'hC8,
-        EOC                     = 8'hA5;
+        CMD_STEP                = 8'hC9,
+        CMD_PAUSE               = 8'hCA,
+        CMD_RUN_UNTIL           = 8'hCB,
+        CMD_GET_TICKS           = 8'hCC,
+        CMD_SCAN                = 8'hCD,
+        CMD_TRACE_ARM           = 8'hCE,
+        CMD_TRACE_STATUS        = 8'hCF,
+        CMD_READ_TRACE          = 8'hD0,
+        CMD_IDENTIFY            = 8'hD1,
+        CMD_GET_CHANGES         = 8'hD2,
+        CMD_STAGE_INPUT         = 8'hD3,
+        CMD_COMMIT_INPUTS       = 8'hD4,
+        SOF                     = 8'hA5;
+    /*---------------------------------
+                  STATUS
+    ---------------------------------*/
+    parameter
+        STATUS_ACK              = 8'h06,
+        STATUS_NAK              = 8'h15,
+        STATUS_FAIL             = 8'h5A;
+
+    function [7:0] crc8(input [7:0] crc, input [7:0] data);
+        integer i;
+        reg [7:0] c;
+        begin
+            c = crc ^ data;
+            for (i = 0; i < 8; i = i + 1)
+                c = c[7] ? ({c[6:0], 1'b0} ^ 8'h07) : {c[6:0], 1'b0};
+            crc8 = c;
+        end
+    endfunction
+
+    function [15:0] count_ones(input [CHANGE_MASK_BYTES*8-1:0] bits);
+        integer i;
+        begin
+            count_ones = 16'd0;
+            for (i = 0; i < CHANGE_MASK_BYTES*8; i = i + 1)
+                count_ones = count_ones + bits[i];
+        end
+    endfunction
 
     /*---------------------------------
               SERIAL RECEIVER
@@ -53,15 +125,15 @@ module command_controller #(
     reg             r_tx_start  = 1'b0;
     reg[7:0]        r_tx_data   = 8'd0;
     wire            tx_done;
-    
+
     uart_tx #(
         .BAUD_DIVIDER_COUNT(20)
     ) tx (
-        .i_clk(i_clk), 
-        .i_start(r_tx_start), 
-        .i_data(r_tx_data), 
+        .i_clk(i_clk),
+        .i_start(r_tx_start),
+        .i_data(r_tx_data),
 
-        .o_done(tx_done), 
+        .o_done(tx_done),
         .o_tx(o_tx)
     );
 
@@ -69,142 +141,436 @@ module command_controller #(
             COMMAND STATE MACHINE
     ---------------------------------*/
     parameter
-        s_IDLE                  = 3'b000,
-        s_RX                    = 3'b001,
-        s_RX_WAIT               = 3'b010,
-        s_CMD_CHECK             = 3'b011,
-        s_CMD_PROCESS           = 3'b100,
-        s_TX_WAIT               = 3'b101,
-        s_END                   = 3'b110,
-        s_FAILSAFE              = 3'b111;
-    reg[2:0]        r_state     = s_IDLE;
+        s_IDLE                  = 4'd0,
+        s_HEADER                = 4'd1,
+        s_PAYLOAD               = 4'd2,
+        s_CRC                   = 4'd3,
+        s_CHECK                 = 4'd4,
+        s_EXECUTE               = 4'd5,
+        s_RESPOND               = 4'd6,
+        s_TX_BYTE               = 4'd7,
+        s_TX_WAIT               = 4'd8,
+        s_SCAN                  = 4'd9,
+        s_SKIP                  = 4'd10;
+    reg[3:0]        r_state     = s_IDLE;
 
-    reg[BYTES_PER_COMMANDS-1:0] [7:0] r_cmd;
-    reg[2:0]        r_cmd_i     = 3'd0;
+    // Received frame
+    reg[4:0] [7:0]  r_header;
+    reg[2:0]        r_header_i  = 3'd0;
+    reg[MAX_PAYLOAD-1:0] [7:0] r_payload;
+    reg[15:0]       r_payload_i = 16'd0;
+    reg[7:0]        r_rx_crc    = 8'd0;
+    reg[7:0]        r_crc       = 8'd0;
+    reg[23:0]       r_timeout   = 24'd0;
 
-    wire[23:0]      three_byte;
-    assign          three_byte = {r_cmd[1], r_cmd[2], r_cmd[3]}; 
+    wire[7:0]       version     = r_header[0];
+    wire[7:0]       seq         = r_header[1];
+    wire[7:0]       cmd         = r_header[2];
+    wire[15:0]      len         = {r_header[3], r_header[4]};
 
-    wire[31:0]      four_byte;
-    assign          four_byte = {r_cmd[1], r_cmd[2], r_cmd[3], r_cmd[4]}; 
+    wire[23:0]      three_byte  = {r_payload[0], r_payload[1], r_payload[2]};
+    wire[31:0]      four_byte   = {r_payload[0], r_payload[1], r_payload[2], r_payload[3]};
+    wire[63:0]      eight_byte  = {r_payload[0], r_payload[1], r_payload[2], r_payload[3],
+                                   r_payload[4], r_payload[5], r_payload[6], r_payload[7]};
+
+    // Error recovery
+    reg             r_failsafe  = 1'b0;
+    reg[7:0]        r_bad_frames= 8'd0;
+    reg[7:0]        r_last_seq  = 8'd0;
+    reg             r_seq_valid = 1'b0;
+
+    // Response
+    reg[7:0]        r_status    = STATUS_ACK;
+    reg[15:0]       r_tx_len    = 16'd0;
+    reg[15:0]       r_tx_i      = 16'd0;
+    reg[7:0]        r_tx_crc    = 8'd0;
+    reg[TX_PAYLOAD_BITS-1:0] r_tx_payload;
 
     reg[(ROC_OUTPUT_BYTES*8)-1:0]    r_roc_outputs;
 
+    // Outputs as the last change read captured them, and as the one before. The response is
+    // built from these alone, so a retransmission reports the same changes. It is a mask with
+    // a bit per output byte followed by the bytes whose bit is set, which are picked out of the
+    // payload while it is sent.
+    reg[(ROC_OUTPUT_BYTES*8)-1:0]    r_reported     = {(ROC_OUTPUT_BYTES*8){1'b0}};
+    reg[(ROC_OUTPUT_BYTES*8)-1:0]    r_changes_base = {(ROC_OUTPUT_BYTES*8){1'b0}};
+    reg             r_changes_full  = 1'b0;
+    reg[CHANGE_MASK_BYTES*8-1:0]     r_tx_mask;
+    reg[CHANGE_MASK_BYTES*8-1:0]     r_tx_skip;
+    wire[CHANGE_MASK_BYTES*8-1:0]    change_mask;
+
+    genvar          g_byte;
+    generate
+        for (g_byte = 0; g_byte < CHANGE_MASK_BYTES*8; g_byte = g_byte + 1) begin : g_change_mask
+            if (g_byte < ROC_OUTPUT_BYTES)
+                assign change_mask[g_byte] = r_changes_full
+                    || r_reported[g_byte*8 +: 8] != r_changes_base[g_byte*8 +: 8];
+            else
+                assign change_mask[g_byte] = 1'b0;
+        end
+    endgenerate
+
     reg[ROC_INPUTS-1:0]     r_roc_inputs;
     assign          o_roc_inputs = r_roc_inputs;
 
+    // Inputs as they will be once the staged changes are committed. A commit is applied right
+    // after the next tick, or at once while the design stands still, so the changes all land
+    // on the same tick.
+    reg[ROC_INPUTS-1:0]     r_staged_inputs;
+    reg             r_commit    = 1'b0;
+
     reg[31:0]       r_tps       = 32'd0;
-    assign          o_roc_tps   = r_tps;
+    // Ticking stops while the controller is in failsafe
+    assign          o_roc_tps   = r_failsafe ? 32'd0 : r_tps;
+
+    initial         o_roc_en    = 1'b1;
+
+    // Ticks requested by the host, pulsed out one at a time. Boards of a partitioned build
+    // run at 0 rtps and are stepped in lockstep this way.
+    reg[31:0]       r_steps     = 32'd0;
+    reg[7:0]        r_step_timer= 8'd0;
+    initial         o_roc_step  = 1'b0;
+
+    // Ticks the design has run since the board was programmed. They are counted on the tick
+    // line itself, synchronized into this clock, so free running and stepped ticks both count.
+    reg[2:0]        r_tick_sync = 3'd0;
+    reg[63:0]       r_ticks     = 64'd0;
+
+    // Tick a run until command steps the design to. The counter catches up with a step well
+    // within the step period, so the comparison never runs past the target.
+    reg[63:0]       r_until     = 64'd0;
+    reg             r_until_en  = 1'b0;
 
-    reg[23:0]       r_output_i  = 24'd0;
+    // ROM writes are pulsed for one clock
+    initial         o_rom_we    = 1'b0;
+    initial         o_rom_addr  = 24'd0;
+    initial         o_rom_data  = 4'd0;
+
+    // The scan chain is captured when a scan is answered, so a retransmission reads it anew.
+    // Each payload byte is then shifted in from the chain before it is sent: even cycles
+    // sample the chain's output and odd cycles shift the next bit into place.
+    initial         o_scan_load = 1'b0;
+    initial         o_scan_shift= 1'b0;
+    reg[3:0]        r_scan_count= 4'd0;
+    reg[7:0]        r_scan_byte = 8'd0;
+
+    // Arming the trace buffer toggles its arm line, which it samples on the next tick.
+    // A row read is answered from the buffer's registered output once the address settled.
+    initial         o_trace_arm = 1'b0;
+    initial         o_trace_addr= 24'd0;
 
     always @(posedge i_clk) begin
+        o_roc_en                <= ~r_failsafe;
+        o_rom_we                <= 1'b0;
+        o_scan_load             <= 1'b0;
+        o_scan_shift            <= 1'b0;
+
+        r_tick_sync             <= {r_tick_sync[1:0], i_roc_tick};
+        if (r_tick_sync[1] && !r_tick_sync[2])
+            r_ticks             <= r_ticks + 1;
+
+        if (r_commit && ((r_tps == 32'd0 && r_steps == 32'd0 && !r_until_en && !o_roc_step)
+                || (!r_tick_sync[1] && r_tick_sync[2]))) begin
+            r_roc_inputs        <= r_staged_inputs;
+            r_commit            <= 1'b0;
+        end
+
+        if (r_failsafe) begin
+            r_steps             <= 32'd0;
+            r_until_en          <= 1'b0;
+            o_roc_step          <= 1'b0;
+        end
+        else if (r_step_timer != 8'd0)
+            r_step_timer        <= r_step_timer - 1;
+        else if (o_roc_step) begin
+            o_roc_step          <= 1'b0;
+            r_step_timer        <= STEP_HALF_PERIOD;
+        end
+        else if (r_steps != 32'd0) begin
+            o_roc_step          <= 1'b1;
+            r_steps             <= r_steps - 1;
+            r_step_timer        <= STEP_HALF_PERIOD;
+        end
+        else if (r_until_en) begin
+            if (r_ticks < r_until) begin
+                o_roc_step      <= 1'b1;
+                r_step_timer    <= STEP_HALF_PERIOD;
+            end
+            else
+                r_until_en      <= 1'b0;
+        end
+
         case (r_state)
             s_IDLE        : begin
                 r_tx_start              <= 1'b0;
-                r_output_i              <= 24'd0;
-                r_cmd_i                 <= 3'd0;
-                if (rx_new)
-                    r_state             <= s_RX;
+                r_header_i              <= 3'd0;
+                r_payload_i             <= 16'd0;
+                r_crc                   <= 8'd0;
+                r_timeout               <= 24'd0;
+                if (rx_new && rx_data == SOF)
+                    r_state             <= s_HEADER;
             end
-            s_RX          : begin
-                r_cmd[r_cmd_i]          <= rx_data;
-                r_cmd_i                 <= r_cmd_i + 1;
-                r_tx_data               <= rx_data;
-                r_tx_start              <= 1'b1;
 
-                if (r_cmd_i >= BYTES_PER_COMMANDS-1) 
-                    r_state             <= s_CMD_CHECK;
+            s_HEADER      : begin
+                if (rx_new) begin
+                    r_header[r_header_i]<= rx_data;
+                    r_header_i          <= r_header_i + 1;
+                    r_crc               <= crc8(r_crc, rx_data);
+                    r_timeout           <= 24'd0;
+                    if (r_header_i == 3'd4)
+                        r_state         <= ({r_header[3], rx_data} == 16'd0) ? s_CRC : s_PAYLOAD;
+                end
+                else if (r_timeout >= RX_TIMEOUT)
+                    r_state             <= s_IDLE;
                 else
-                    r_state             <= s_RX_WAIT;
+                    r_timeout           <= r_timeout + 1;
             end
 
-            s_RX_WAIT     : begin
-                if (rx_new)
-                    r_state             <= s_RX;
-                    r_tx_start          <= 1'b0;
+            s_PAYLOAD     : begin
+                if (rx_new) begin
+                    // Oversized payloads are still consumed so the frame boundary is kept
+                    if (r_payload_i < MAX_PAYLOAD)
+                        r_payload[r_payload_i] <= rx_data;
+                    r_payload_i         <= r_payload_i + 1;
+                    r_crc               <= crc8(r_crc, rx_data);
+                    r_timeout           <= 24'd0;
+                    if (r_payload_i == len - 1)
+                        r_state         <= s_CRC;
+                end
+                else if (r_timeout >= RX_TIMEOUT)
+                    r_state             <= s_IDLE;
+                else
+                    r_timeout           <= r_timeout + 1;
             end
 
-            s_CMD_CHECK   : begin
-                if (r_cmd[BYTES_PER_COMMANDS-1] == EOC && 
-                        r_cmd[0] >= CMD_RESET && 
-                        r_cmd[0] <= CMD_FAIL_ACK)
-                    r_state             <= s_TX_WAIT;
-                else 
-                    r_state             <= s_FAILSAFE;
+            s_CRC         : begin
+                if (rx_new) begin
+                    r_rx_crc            <= rx_data;
+                    r_state             <= s_CHECK;
+                end
+                else if (r_timeout >= RX_TIMEOUT)
+                    r_state             <= s_IDLE;
+                else
+                    r_timeout           <= r_timeout + 1;
             end
 
-            s_CMD_PROCESS : begin
-                case (r_cmd[0])
-                    CMD_RESET       : begin
-                        r_state         <= s_END;
+            s_CHECK       : begin
+                r_state                 <= s_RESPOND;
+                if (version != PROTOCOL_VERSION || len > MAX_PAYLOAD) begin
+                    r_failsafe          <= 1'b1;
+                    r_status            <= STATUS_FAIL;
+                end
+                else if (r_rx_crc != r_crc) begin
+                    r_bad_frames        <= r_bad_frames + 1;
+                    if (r_bad_frames + 1 >= MAX_BAD_FRAMES) begin
+                        r_failsafe      <= 1'b1;
+                        r_status        <= STATUS_FAIL;
                     end
-                    CMD_PING        : begin
-                        r_state         <= s_END;
-                    end 
-                    CMD_GET_OUTPUTS : begin
-                        if (r_output_i >= ROC_OUTPUT_BYTES) begin
-                            r_state     <= s_END;
-                        end
-                        else begin
-                            r_tx_data   <= r_roc_outputs[7:0];
-                            r_tx_start  <= 1'b1;
-                            r_roc_outputs <= r_roc_outputs >> 8;
-                            r_output_i  <= r_output_i + 1;
-                            r_state     <= s_TX_WAIT;
+                    else
+                        r_status        <= STATUS_NAK;
+                end
+                else begin
+                    r_bad_frames        <= 8'd0;
+                    if (r_failsafe) begin
+                        if (cmd == CMD_FAIL_ACK) begin
+                            r_failsafe  <= 1'b0;
+                            r_seq_valid <= 1'b0;
+                            r_status    <= STATUS_ACK;
                         end
-                    end 
-                    CMD_CAPTURE     : begin
-                        r_roc_outputs   <= i_roc_outputs;
-                        r_state         <= s_END;
+                        else
+                            r_status    <= STATUS_FAIL;
+                    end
+                    // A retransmission of the last executed command is acknowledged without running it again
+                    else if (r_seq_valid && seq == r_last_seq)
+                        r_status        <= STATUS_ACK;
+                    else if (cmd >= CMD_RESET && cmd <= CMD_COMMIT_INPUTS)
+                        r_state         <= s_EXECUTE;
+                    else
+                        r_status        <= STATUS_NAK;
+                end
+            end
+
+            s_EXECUTE     : begin
+                case (cmd)
+                    CMD_RESET       : begin
+                        r_roc_inputs            <= {ROC_INPUTS{1'b0}};
+                        r_staged_inputs         <= {ROC_INPUTS{1'b0}};
+                        r_commit                <= 1'b0;
+                    end
+                    CMD_CAPTURE     : r_roc_outputs <= i_roc_outputs;
+                    CMD_GET_CHANGES : begin
+                        r_changes_base          <= r_reported;
+                        r_reported              <= i_roc_outputs;
+                        r_changes_full          <= r_payload[0][0];
                     end
                     CMD_SET_INPUT   : begin
-                        r_roc_inputs[three_byte] <= r_cmd[4][0];
-                        r_state         <= s_END;
+                        if (three_byte < ROC_INPUTS) begin
+                            r_roc_inputs[three_byte]    <= r_payload[4][0];
+                            r_staged_inputs[three_byte] <= r_payload[4][0];
+                        end
+                    end
+                    CMD_STAGE_INPUT : begin
+                        if (three_byte < ROC_INPUTS)
+                            r_staged_inputs[three_byte] <= r_payload[3][0];
                     end
-                    CMD_SET_RTPS    : begin
-                        r_tps           <= four_byte;
-                        r_state         <= s_END;
-                    end 
+                    CMD_COMMIT_INPUTS: r_commit     <= 1'b1;
+                    CMD_SET_RTPS    : r_tps         <= four_byte;
+                    CMD_STEP        : r_steps       <= r_steps + four_byte;
+                    CMD_PAUSE       : begin
+                        r_tps                   <= 32'd0;
+                        r_steps                 <= 32'd0;
+                        r_until_en              <= 1'b0;
+                    end
+                    CMD_RUN_UNTIL   : begin
+                        r_tps                   <= 32'd0;
+                        r_until                 <= eight_byte;
+                        r_until_en              <= 1'b1;
+                    end
+                    CMD_TRACE_ARM   : o_trace_arm   <= ~o_trace_arm;
+                    CMD_READ_TRACE  : o_trace_addr  <= three_byte;
                     CMD_LOAD_ROM    : begin
+                        o_rom_we                <= 1'b1;
+                        o_rom_addr              <= three_byte;
+                        o_rom_data              <= r_payload[3][3:0];
+                    end
+                    default         : ;
+                endcase
+                r_last_seq              <= seq;
+                r_seq_valid             <= 1'b1;
+                r_status                <= STATUS_ACK;
+                r_state                 <= s_RESPOND;
+            end
 
-                    end 
-                    CMD_FAIL_ACK    : begin
+            s_RESPOND     : begin
+                r_tx_i                  <= 16'd0;
+                r_tx_crc                <= 8'd0;
+                if (r_status != STATUS_ACK)
+                    r_tx_len            <= 16'd0;
+                else if (cmd == CMD_GET_OUTPUTS) begin
+                    r_tx_len            <= ROC_OUTPUT_BYTES;
+                    r_tx_payload        <= r_roc_outputs;
+                end
+                else if (cmd == CMD_GET_CHANGES) begin
+                    r_tx_len            <= CHANGE_MASK_BYTES + count_ones(change_mask);
+                    r_tx_payload        <= r_reported;
+                    r_tx_mask           <= change_mask;
+                    r_tx_skip           <= change_mask;
+                end
+                else if (cmd == CMD_PING) begin
+                    r_tx_len            <= PING_BYTES;
+                    // Sent LSB first: version, then inputs and outputs as big endian 24 bit counts
+                    r_tx_payload        <= {
+                        ROC_OUTPUTS[7:0], ROC_OUTPUTS[15:8], ROC_OUTPUTS[23:16],
+                        ROC_INPUTS[7:0], ROC_INPUTS[15:8], ROC_INPUTS[23:16],
+                        PROTOCOL_VERSION};
+                end
+                else if (cmd == CMD_GET_TICKS) begin
+                    r_tx_len            <= TICKS_BYTES;
+                    // Sent LSB first, so the count goes out big endian
+                    r_tx_payload        <= {
+                        r_ticks[7:0], r_ticks[15:8], r_ticks[23:16], r_ticks[31:24],
+                        r_ticks[39:32], r_ticks[47:40], r_ticks[55:48], r_ticks[63:56]};
+                end
+                else if (cmd == CMD_SCAN) begin
+                    r_tx_len            <= ROC_SCAN_BYTES;
+                    o_scan_load         <= 1'b1;
+                end
+                else if (cmd == CMD_TRACE_STATUS) begin
+                    r_tx_len            <= TRACE_STATUS_BYTES;
+                    // Sent LSB first: the state, then the oldest row and the row count big endian
+                    r_tx_payload        <= {
+                        i_trace_count[7:0], i_trace_count[15:8], i_trace_count[23:16],
+                        i_trace_start[7:0], i_trace_start[15:8], i_trace_start[23:16],
+                        6'd0, i_trace_state};
+                end
+                else if (cmd == CMD_READ_TRACE)
+                    r_tx_len            <= ROC_TRACE_BYTES;
+                else if (cmd == CMD_IDENTIFY) begin
+                    r_tx_len            <= IDENTIFY_BYTES;
+                    // Sent LSB first: version, then the hash of the design big endian
+                    r_tx_payload        <= {
+                        ROC_DESIGN_HASH[7:0], ROC_DESIGN_HASH[15:8], ROC_DESIGN_HASH[23:16], ROC_DESIGN_HASH[31:24],
+                        PROTOCOL_VERSION};
+                end
+                else
+                    r_tx_len            <= 16'd0;
+                r_state                 <= s_TX_BYTE;
+            end
 
+            s_TX_BYTE     : begin
+                r_tx_start              <= 1'b1;
+                r_state                 <= s_TX_WAIT;
+                case (r_tx_i)
+                    16'd0   : begin
+                        r_tx_data       <= SOF;
+                        if (cmd == CMD_READ_TRACE)
+                            r_tx_payload<= i_trace_data;
                     end
-                    default         : begin
-                        r_state         <= s_FAILSAFE;
+                    16'd1   : r_tx_data <= PROTOCOL_VERSION;
+                    16'd2   : r_tx_data <= seq;
+                    16'd3   : r_tx_data <= r_status;
+                    16'd4   : r_tx_data <= r_tx_len[15:8];
+                    16'd5   : r_tx_data <= r_tx_len[7:0];
+                    default : begin
+                        if (r_tx_i < r_tx_len + 6 && cmd == CMD_SCAN)
+                            r_tx_data   <= r_scan_byte;
+                        else if (r_tx_i < CHANGE_MASK_BYTES + 6 && cmd == CMD_GET_CHANGES) begin
+                            r_tx_data   <= r_tx_mask[7:0];
+                            r_tx_mask   <= r_tx_mask >> 8;
+                        end
+                        else if (r_tx_i < r_tx_len + 6) begin
+                            r_tx_data   <= r_tx_payload[7:0];
+                            r_tx_payload<= r_tx_payload >> 8;
+                            r_tx_skip   <= r_tx_skip >> 1;
+                        end
+                        else
+                            r_tx_data   <= r_tx_crc;
                     end
                 endcase
             end
 
             s_TX_WAIT     : begin
                 r_tx_start              <= 1'b0;
-                if (tx_done) 
-                    r_state             <= s_CMD_PROCESS;
+                if (tx_done) begin
+                    if (r_tx_i != 16'd0 && r_tx_i < r_tx_len + 6)
+                        r_tx_crc        <= crc8(r_tx_crc, r_tx_data);
+                    if (r_tx_i >= r_tx_len + 6)
+                        r_state         <= s_IDLE;
+                    else begin
+                        r_tx_i          <= r_tx_i + 1;
+                        if (cmd == CMD_SCAN && r_tx_i + 1 >= 16'd6 && r_tx_i + 1 < r_tx_len + 6)
+                            r_state     <= s_SCAN;
+                        else if (cmd == CMD_GET_CHANGES && r_tx_i + 1 >= CHANGE_MASK_BYTES + 6 && r_tx_i + 1 < r_tx_len + 6)
+                            r_state     <= s_SKIP;
+                        else
+                            r_state     <= s_TX_BYTE;
+                    end
+                end
             end
 
-            s_END         : begin
-                r_tx_data               <= 8'd0;
-                r_output_i              <= 24'd0;
-                r_tx_start              <= 1'b0;
-                r_cmd_i                 <= 3'd0;
-                r_state                 <= s_IDLE;
+            s_SCAN        : begin
+                r_scan_count            <= r_scan_count + 1;
+                if (!r_scan_count[0]) begin
+                    r_scan_byte         <= {i_scan_out, r_scan_byte[7:1]};
+                    o_scan_shift        <= 1'b1;
+                end
+                if (r_scan_count == 4'd15)
+                    r_state             <= s_TX_BYTE;
             end
 
-            s_FAILSAFE    : begin
-                if (tx_done) begin
-                    r_tx_start          <= 1'b1;
-                    r_tx_data           <= 8'h5A;
-                end
+            // Unchanged output bytes are dropped from the payload, one per clock
+            s_SKIP        : begin
+                if (r_tx_skip[0])
+                    r_state             <= s_TX_BYTE;
                 else begin
-                    r_tx_start          <= 1'b0;
+                    r_tx_payload        <= r_tx_payload >> 8;
+                    r_tx_skip           <= r_tx_skip >> 1;
                 end
             end
 
             default       : begin
-                r_state                 <= s_FAILSAFE;
+                r_state                 <= s_IDLE;
             end
         endcase
     end
-endmodule
\ No newline at end of file
+endmodule
@@ -0,0 +1,10 @@
+// Stand-in for the Quartus tick_clk PLL used by toolchains without access to the vendor IP.
+// The tick clock then runs at the board clock, so RTPS is scaled by board_clk / 2^29.
+module tick_clk (
+    input       refclk,
+    output      outclk_0
+);
+
+    assign outclk_0 = refclk;
+
+endmodule
@@ -1,10 +1,28 @@
 module RoC #(
         parameter OUTPUTS,
-        parameter INPUTS
+        parameter INPUTS,
+        parameter TRACE_BYTES,
+        parameter PIN_INPUTS,
+        parameter PIN_OUTPUTS
     ) (
+        input                   clk,
         input                   tick,
         input   [INPUTS-1:0]    inputs,
-        output  [OUTPUTS-1:0]   outputs
+        output  [OUTPUTS-1:0]   outputs,
+        input                   rom_we,
+        input   [23:0]          rom_addr,
+        input   [3:0]           rom_data,
+        input                   scan_load,
+        input                   scan_shift,
+        output                  scan_out,
+        input                   trace_arm,
+        input   [23:0]          trace_addr,
+        output  [TRACE_BYTES*8-1:0] trace_data,
+        output  [1:0]           trace_state,
+        output  [23:0]          trace_start,
+        output  [23:0]          trace_count,
+        input   [PIN_INPUTS-1:0]  pin_in,
+        output  [PIN_OUTPUTS-1:0] pin_out
     );
 
 endmodule
\ No newline at end of file
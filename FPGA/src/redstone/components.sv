@@ -71,7 +71,8 @@ module torch (i_clk, i_in, o_out);
 	
 	parameter state = 1'b0;
 
-	reg buffer = state;
+	// The buffer holds the input, so a lit torch starts with it low
+	reg buffer = ~state;
 
 	assign o_out = ~buffer;
 
@@ -81,6 +82,57 @@ module torch (i_clk, i_in, o_out);
 
 endmodule
 
+module lamp (i_clk, i_in, o_out);
+
+	input  i_clk;
+	input  i_in;
+	output o_out;
+
+	parameter state = 1'b0;
+
+	// Lamps light up immediately but stay lit for 2 ticks after losing power.
+	// Like every input, the power is only sampled on the tick, so a lamp powered
+	// for less than a tick does not stay lit.
+	reg [1:0] buffer = {2{state}};
+
+	assign o_out = i_in | buffer[1];
+
+	always @(posedge i_clk) begin
+		if (i_in)
+			buffer <= 2'b11;
+		else
+			buffer <= {buffer[0], 1'b0};
+	end
+
+endmodule
+
+module button (i_clk, i_in, o_out);
+
+	input  i_clk;
+	input  i_in;
+	output o_out;
+
+	parameter
+		state = 1'b0,
+		ticks = 10;
+
+	// Every press flips the input. The button turns on right away and stays on for `ticks` ticks,
+	// presses while it is on are ignored like in game.
+	reg       last = 1'b0;
+	reg [3:0] count = state ? ticks - 1 : 4'd0;
+
+	assign o_out = (i_in != last) | (count != 4'd0);
+
+	always @(posedge i_clk) begin
+		if (i_in != last && count == 4'd0)
+			count <= ticks - 1;
+		else if (count != 4'd0)
+			count <= count - 1;
+		last <= i_in;
+	end
+
+endmodule
+
 module comp (i_clk, i_in, i_side, o_out);
 
 	input 		 i_clk;
@@ -89,25 +141,218 @@ module comp (i_clk, i_in, i_side, o_out);
 
 	output [3:0] o_out;
 
+	parameter 
+		state = 4'd0,
+		mode = 0,
+		far_en = 0,
+		far_ss = 4'd0;
+
 	reg [3:0] buffer = state;
 	assign o_out = buffer;
 
-	parameter 
-		state = 4'd0,
-		mode = 0;
+	// A container behind a solid block is only read when the block itself is not fully powered
+	wire [3:0] w_in = (far_en && i_in != 4'd15) ? far_ss : i_in;
 
 	generate 
 		if (mode == 0) begin
 			always @(posedge i_clk) begin
-				buffer = (i_in >= i_side) ? i_in : 4'd0;
+				buffer = (w_in >= i_side) ? w_in : 4'd0;
 			end
 		end 
 		else begin
 			always @(posedge i_clk) begin
-				buffer = (i_in >= i_side) ? (i_in - i_side) : 4'd0;
+				buffer = (w_in >= i_side) ? (w_in - i_side) : 4'd0;
 			end
 		end
 	endgenerate
 			
 
 endmodule
+
+module lut (i_clk, i_in, o_out);
+
+	input        i_clk;
+	input  [3:0] i_in;
+	output [3:0] o_out;
+
+	parameter state = 4'd0;
+
+	// A comparator replaced by its table. The table is decoded by the logic driving the input,
+	// the module only holds the result for a tick like the comparator would.
+	reg [3:0] buffer = state;
+	assign o_out = buffer;
+
+	always @(posedge i_clk) begin
+		buffer = i_in;
+	end
+
+endmodule
+
+module observer (i_clk, i_in, o_out);
+
+	input        i_clk;
+	input  [3:0] i_in;
+	output       o_out;
+
+	parameter
+		state = 1'b0,
+		last = 4'd0;
+
+	// A change of the watched state turns the observer on with the next tick, for a tick.
+	// Changes while it is on are not seen, like in game.
+	reg [3:0] seen = last;
+	reg       pulse = state;
+
+	assign o_out = pulse;
+
+	always @(posedge i_clk) begin
+		if (pulse)
+			pulse <= 1'b0;
+		else if (i_in != seen)
+			pulse <= 1'b1;
+		seen <= i_in;
+	end
+
+endmodule
+
+module rom (i_clk, i_we, i_addr, i_data, o_out);
+
+	input        i_clk;
+	input        i_we;
+	input [23:0] i_addr;
+	input  [3:0] i_data;
+	output [3:0] o_out;
+
+	parameter
+		addr = 24'd0,
+		width = 4,
+		on = 4'd15,
+		state = 4'd0;
+
+	// Written by LoadROM on the controller's clock, a one bit cell reads as `on` when set
+	reg [width-1:0] buffer = state;
+
+	generate
+		if (width == 1) begin
+			assign o_out = buffer[0] ? on : 4'd0;
+		end
+		else begin
+			assign o_out = buffer;
+		end
+	endgenerate
+
+	always @(posedge i_clk) begin
+		if (i_we && i_addr == addr)
+			buffer <= i_data[width-1:0];
+	end
+
+endmodule
+
+module scan (i_clk, i_load, i_shift, i_in, i_chain, o_out);
+
+	input              i_clk;
+	input              i_load;
+	input              i_shift;
+	input  [width-1:0] i_in;
+	input              i_chain;
+	output             o_out;
+
+	parameter width = 1;
+
+	// One stage of the scan chain: captures the component's state, then shifts it out
+	// least significant bit first, followed by the stages behind it
+	reg [width-1:0] buffer = {width{1'b0}};
+
+	assign o_out = buffer[0];
+
+	generate
+		if (width == 1) begin
+			always @(posedge i_clk) begin
+				if (i_load)
+					buffer <= i_in;
+				else if (i_shift)
+					buffer <= i_chain;
+			end
+		end
+		else begin
+			always @(posedge i_clk) begin
+				if (i_load)
+					buffer <= i_in;
+				else if (i_shift)
+					buffer <= {i_chain, buffer[width-1:1]};
+			end
+		end
+	endgenerate
+
+endmodule
+
+module trace (i_clk, i_tick, i_arm, i_probes, i_trigger, i_addr, o_data, o_state, o_start, o_count);
+
+	input                  i_clk;
+	input                  i_tick;
+	input                  i_arm;
+	input  [width-1:0]     i_probes;
+	input                  i_trigger;
+	input  [23:0]          i_addr;
+	output reg [width-1:0] o_data;
+	output [1:0]           o_state;
+	output [23:0]          o_start;
+	output [23:0]          o_count;
+
+	parameter width = 8;
+	parameter depth = 1024;
+	parameter pretrigger = 512;
+	// 0 rising edge, 1 falling edge, 2 either edge, 3 no trigger
+	parameter mode = 2'd3;
+
+	localparam ARMED = 2'd0, TRIGGERED = 2'd1, DONE = 2'd2;
+
+	// Records the probes on every tick into a ring buffer. Once the trigger sees its edge,
+	// the buffer keeps `pretrigger` ticks before it and fills the rest after it, then stops.
+	// Toggling i_arm starts a new capture with the next tick. The buffer is read on the
+	// controller's clock while the design is paused.
+	reg [width-1:0] mem [0:depth-1];
+	reg        r_arm   = 1'b0;
+	reg [1:0]  r_state = ARMED;
+	reg [23:0] r_ptr   = 24'd0;
+	reg [23:0] r_count = 24'd0;
+	reg [23:0] r_post  = 24'd0;
+	reg        r_last  = 1'b0;
+
+	wire        rearm = i_arm != r_arm;
+	wire [1:0]  state = rearm ? ARMED : r_state;
+	wire [23:0] ptr   = rearm ? 24'd0 : r_ptr;
+	wire [23:0] count = rearm ? 24'd0 : r_count;
+	wire        last  = rearm ? i_trigger : r_last;
+	wire        fire  = (mode == 2'd0) ? (i_trigger && !last) :
+	                    (mode == 2'd1) ? (!i_trigger && last) :
+	                    (mode == 2'd2) ? (i_trigger != last) : 1'b0;
+
+	assign o_state = r_state;
+	assign o_start = (r_count == depth) ? r_ptr : 24'd0;
+	assign o_count = r_count;
+
+	always @(posedge i_tick) begin
+		r_arm  <= i_arm;
+		r_last <= i_trigger;
+		if (state != DONE) begin
+			mem[ptr] <= i_probes;
+			r_ptr    <= (ptr == depth - 1) ? 24'd0 : ptr + 24'd1;
+			r_count  <= (count == depth) ? count : count + 24'd1;
+			if (state == ARMED && fire) begin
+				r_post  <= depth - pretrigger - 1;
+				r_state <= (depth - pretrigger == 1) ? DONE : TRIGGERED;
+			end
+			else if (state == TRIGGERED) begin
+				r_post  <= r_post - 24'd1;
+				r_state <= (r_post == 24'd1) ? DONE : TRIGGERED;
+			end
+			else
+				r_state <= state;
+		end
+	end
+
+	always @(posedge i_clk)
+		o_data <= mem[i_addr];
+
+endmodule
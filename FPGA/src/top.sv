@@ -1,3 +1,6 @@
+// Defines ROC_PIN_INPUTS and ROC_PIN_OUTPUTS when board peripherals are bound to the design
+`include "pins.vh"
+
 module top (
 	input       i_clk,
 	input 	    i_RX,
@@ -5,6 +8,12 @@ module top (
 	output 		o_TX,
     output      o_tick,
     output      o_debug
+`ifdef ROC_PIN_INPUTS
+    ,input  [`ROC_PIN_INPUTS-1:0]  i_pins
+`endif
+`ifdef ROC_PIN_OUTPUTS
+    ,output [`ROC_PIN_OUTPUTS-1:0] o_pins
+`endif
 );
 
     /*---------------------------------
@@ -20,20 +29,69 @@ module top (
 
     wire[31:0]              roc_tps;
     wire                    roc_clk_en;
+    wire                    roc_step;
+    wire                    tick_out;
+
+    wire                    rom_we;
+    wire[23:0]              rom_addr;
+    wire[3:0]               rom_data;
+
+    wire                    scan_load;
+    wire                    scan_shift;
+    wire                    scan_out;
+
+    wire                    trace_arm;
+    wire[23:0]              trace_addr;
+    wire[ROC_TRACE_BYTES*8-1:0] trace_data;
+    wire[1:0]               trace_state;
+    wire[23:0]              trace_start;
+    wire[23:0]              trace_count;
+
+    /*---------------------------------
+              BOARD PERIPHERALS
+    ---------------------------------*/
+`ifdef ROC_PIN_INPUTS
+    wire[`ROC_PIN_INPUTS-1:0]  pin_in = i_pins;
+`else
+    wire                        pin_in = 1'b0;
+`endif
+`ifdef ROC_PIN_OUTPUTS
+    wire[`ROC_PIN_OUTPUTS-1:0] pin_out;
+    assign o_pins = pin_out;
+`else
+    wire                        pin_out;
+`endif
 
     command_controller #(
         .ROC_INPUTS(ROC_INPUTS),
         .ROC_OUTPUTS(ROC_OUTPUTS),
-        .ROC_OUTPUT_BYTES((ROC_OUTPUTS+7)>>3)
+        .ROC_OUTPUT_BYTES((ROC_OUTPUTS+7)>>3),
+        .ROC_SCAN_BYTES((ROC_SCAN_BITS+7)>>3),
+        .ROC_TRACE_BYTES(ROC_TRACE_BYTES),
+        .ROC_DESIGN_HASH(ROC_DESIGN_HASH)
     ) cmd_ctrl (
         .i_clk(i_clk),
         .i_rx(i_RX),
         .i_roc_outputs(roc_outputs),
+        .i_roc_tick(tick_out),
 
         .o_roc_inputs(roc_inputs),
         .o_tx(o_TX),
         .o_roc_tps(roc_tps),
-        .o_roc_en(roc_clk_en)
+        .o_roc_en(roc_clk_en),
+        .o_roc_step(roc_step),
+        .o_rom_we(rom_we),
+        .o_rom_addr(rom_addr),
+        .o_rom_data(rom_data),
+        .o_scan_load(scan_load),
+        .o_scan_shift(scan_shift),
+        .i_scan_out(scan_out),
+        .o_trace_arm(trace_arm),
+        .o_trace_addr(trace_addr),
+        .i_trace_data(trace_data),
+        .i_trace_state(trace_state),
+        .i_trace_start(trace_start),
+        .i_trace_count(trace_count)
     );
 
     /*---------------------------------
@@ -42,16 +100,19 @@ module top (
     wire                    roc_tps_clk;
     wire                    tick_clk;
 
-    assign o_tick = roc_tps_clk;
+    // Steps requested over the command interface tick the design on top of the tps divider.
+    // A paused divider holds its clock wherever it stopped, so it is masked to let steps through.
+    assign tick_out = (roc_tps_clk & (|roc_tps)) | roc_step;
+    assign o_tick = tick_out;
 
-    tick_clk (
+    tick_clk tick_pll (
 		.refclk(i_clk),
-		.outclk_0(tick_clk),
+		.outclk_0(tick_clk)
 	);
 
     tps_clk_div #(
         .REF_CLK_SIZE(29)
-    ) (
+    ) tps_div (
         .i_clk(tick_clk),
         .i_tps(roc_tps),
         .i_en(roc_clk_en),
@@ -64,12 +125,34 @@ module top (
     ---------------------------------*/
     RoC #(
         .OUTPUTS(ROC_OUTPUTS),
-        .INPUTS(ROC_INPUTS)
+        .INPUTS(ROC_INPUTS),
+        .TRACE_BYTES(ROC_TRACE_BYTES),
+        .PIN_INPUTS($bits(pin_in)),
+        .PIN_OUTPUTS($bits(pin_out))
     ) roc (
-        .tick(roc_tps_clk),
+        .clk(i_clk),
+        .tick(tick_out),
         .inputs(roc_inputs),
 
-        .outputs(roc_outputs)
+        .outputs(roc_outputs),
+
+        .rom_we(rom_we),
+        .rom_addr(rom_addr),
+        .rom_data(rom_data),
+
+        .scan_load(scan_load),
+        .scan_shift(scan_shift),
+        .scan_out(scan_out),
+
+        .trace_arm(trace_arm),
+        .trace_addr(trace_addr),
+        .trace_data(trace_data),
+        .trace_state(trace_state),
+        .trace_start(trace_start),
+        .trace_count(trace_count),
+
+        .pin_in(pin_in),
+        .pin_out(pin_out)
     );
     
 endmodule
\ No newline at end of file
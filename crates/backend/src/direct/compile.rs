@@ -11,7 +11,9 @@ use smallvec::SmallVec;
 use tracing::trace;
 
 use super::node::{ForwardLink, Node, NodeId, NodeInput, NodeType, Nodes, NonMaxU8};
-use super::DirectBackend;
+use super::{DirectBackend, DirectTrace};
+use crate::fpga::partition::{is_analog, Partition};
+use crate::fpga::trace::{self, Recorder};
 
 #[derive(Debug, Default)]
 struct FinalGraphStats {
@@ -82,7 +84,11 @@ fn compile_node(
                 let target_id = NodeId::from_index(idx);
 
                 let weight = edge.weight();
-                ForwardLink::new(target_id, weight.ty == LinkType::Side, weight.ss)
+                if graph[edge.target()].ty == CNodeType::Observer {
+                    ForwardLink::observe(target_id)
+                } else {
+                    ForwardLink::new(target_id, weight.ty == LinkType::Side, weight.ss)
+                }
             })
             .collect()
     } else {
@@ -120,7 +126,11 @@ fn compile_node(
             noteblock_info.push((node.block.unwrap().0, *instrument, *note));
             NodeType::NoteBlock { noteblock_id }
         }
-        _ => {panic!()}
+        CNodeType::LUT { table, facing_diode } => NodeType::Lut {
+            table: *table,
+            facing_diode: *facing_diode,
+        },
+        CNodeType::Observer => NodeType::Observer,
     };
 
     Node {
@@ -173,6 +183,11 @@ pub fn compile(
         .map(|node| node.block.map(|(pos, id)| (pos, Block::from_id(id))))
         .collect();
     backend.nodes = Nodes::new(nodes);
+    backend.annotations = graph
+        .node_indices()
+        .filter(|&idx| !graph[idx].annotations.is_empty())
+        .map(|idx| (backend.nodes.get(nodes_map[&idx]), graph[idx].annotations.clone()))
+        .collect();
 
     // Create a mapping from block pos to backend NodeId
     for i in 0..backend.blocks.len() {
@@ -191,6 +206,17 @@ pub fn compile(
         }
     }
 
+    // Nodes recorded for the trace, the same way a board's trace buffer records them
+    if let Some(plan) = trace::plan(&graph, &Partition::whole(&graph), &options.trace) {
+        let node = |idx: NodeIdx| backend.nodes.get(nodes_map[&idx]);
+        backend.trace = Some(DirectTrace {
+            probes: plan.nodes.iter().map(|&idx| node(idx)).collect(),
+            trigger: plan.trigger.map(|idx| (node(idx), is_analog(&graph[idx].ty))),
+            recorder: Recorder::from_layout(&plan.layout),
+            layout: plan.layout,
+        });
+    }
+
     // Dot file output
     if options.export_dot_graph {
         std::fs::write("backend_graph.dot", format!("{}", backend)).unwrap();
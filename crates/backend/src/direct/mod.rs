@@ -6,9 +6,11 @@ mod tick;
 mod update;
 
 use crate::fpga::compiler::DeviceConfig;
+use crate::fpga::trace::{Recorder, Trace, TraceLayout};
 
 use super::JITBackend;
-use mchprs_redpiler::compile_graph::CompileGraph;
+use mchprs_redpiler::compile_graph::{Annotations, CompileGraph};
+use mchprs_redpiler::task_monitor::TaskMonitor;
 use mchprs_redpiler::{block_powered_mut, CompilerOptions};
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::{Block, ComparatorMode, Instrument};
@@ -107,6 +109,15 @@ enum Event {
     NoteBlockPlay { noteblock_id: u16 },
 }
 
+/// Nodes recorded for the trace, modelling a board's trace buffer so the two can be compared
+struct DirectTrace {
+    layout: TraceLayout,
+    probes: Vec<NodeId>,
+    /// The trigger node and whether its output is a signal strength
+    trigger: Option<(NodeId, bool)>,
+    recorder: Recorder,
+}
+
 #[derive(Default)]
 pub struct DirectBackend {
     nodes: Nodes,
@@ -115,6 +126,9 @@ pub struct DirectBackend {
     scheduler: TickScheduler,
     events: Vec<Event>,
     noteblock_info: Vec<(BlockPos, Instrument, u32)>,
+    trace: Option<DirectTrace>,
+    /// Annotations of the nodes that have any
+    annotations: FxHashMap<NodeId, Annotations>,
 }
 
 impl DirectBackend {
@@ -125,6 +139,7 @@ impl DirectBackend {
     fn set_node(&mut self, node_id: NodeId, powered: bool, new_power: u8) {
         let node = &mut self.nodes[node_id];
         let old_power = node.output_power;
+        let old_powered = node.powered;
 
         node.changed = true;
         node.powered = powered;
@@ -132,6 +147,12 @@ impl DirectBackend {
         for i in 0..node.updates.len() {
             let node = &self.nodes[node_id];
             let update_link = unsafe { *node.updates.get_unchecked(i) };
+            if update_link.observes() {
+                if old_powered != powered {
+                    update::observe(&mut self.scheduler, &mut self.nodes, update_link.node());
+                }
+                continue;
+            }
             let side = update_link.side();
             let distance = update_link.ss();
             let update = update_link.node();
@@ -174,6 +195,9 @@ impl JITBackend for DirectBackend {
         };
 
         debug!("Node {:?}: {:#?}", node_id, self.nodes[*node_id]);
+        if let Some(annotations) = self.annotations.get(node_id) {
+            debug!("Annotated with {:?}", annotations);
+        }
     }
 
     fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
@@ -185,7 +209,7 @@ impl JITBackend for DirectBackend {
             let Some((pos, block)) = self.blocks[i] else {
                 continue;
             };
-            if matches!(node.ty, NodeType::Comparator { .. }) {
+            if matches!(node.ty, NodeType::Comparator { .. } | NodeType::Lut { .. }) {
                 let block_entity = BlockEntity::Comparator {
                     output_strength: node.output_power,
                 };
@@ -198,6 +222,7 @@ impl JITBackend for DirectBackend {
         }
 
         self.pos_map.clear();
+        self.annotations.clear();
         self.noteblock_info.clear();
         self.events.clear();
     }
@@ -238,6 +263,24 @@ impl JITBackend for DirectBackend {
     }
 
     fn tick(&mut self) {
+        // Sampled before the tick like on the tick edge of a board
+        if let Some(trace) = &mut self.trace {
+            let mut row = Vec::new();
+            for (&node, probe) in trace.probes.iter().zip(&trace.layout.probes) {
+                let node = &self.nodes[node];
+                if probe.width == 1 {
+                    row.push(node.powered);
+                } else {
+                    row.extend((0..probe.width).map(|bit| (node.output_power >> bit) & 1 == 1));
+                }
+            }
+            let trigger = trace.trigger.is_some_and(|(node, analog)| {
+                let node = &self.nodes[node];
+                if analog { node.output_power > 0 } else { node.powered }
+            });
+            trace.recorder.record(row, trigger);
+        }
+
         let mut queues = self.scheduler.queues_this_tick();
 
         for node_id in queues.drain_iter() {
@@ -282,10 +325,12 @@ impl JITBackend for DirectBackend {
         ticks: Vec<TickEntry>,
         _plot: String,
         _name: String,
-        _config: Option<DeviceConfig>,
+        _devices: &[DeviceConfig],
         options: &CompilerOptions,
-    ) {
+        _monitor: &TaskMonitor,
+    ) -> Result<(), String> {
         compile::compile(self, graph, ticks, options);
+        Ok(())
     }
 
     fn has_pending_ticks(&self) -> bool {
@@ -293,6 +338,17 @@ impl JITBackend for DirectBackend {
     }
 
     fn set_rtps(&mut self, _rtps: u32) { }
+
+    fn arm_trace(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.recorder.arm();
+        }
+    }
+
+    fn trace(&mut self) -> Option<Trace> {
+        let trace = self.trace.as_ref()?;
+        Some(trace.recorder.trace(&trace.layout))
+    }
     fn run(&mut self) { }
     fn stop(&mut self) { }
 }
@@ -388,6 +444,8 @@ impl fmt::Display for DirectBackend {
                 NodeType::Wire => format!("Wire"),
                 NodeType::Constant => format!("Constant({})", node.output_power),
                 NodeType::NoteBlock { .. } => format!("NoteBlock"),
+                NodeType::Lut { table, .. } => format!("LUT({})", table.entries().count()),
+                NodeType::Observer => format!("Observer"),
             };
             let pos = if let Some((pos, _)) = self.blocks[id] {
                 format!("{}, {}, {}", pos.x, pos.y, pos.z)
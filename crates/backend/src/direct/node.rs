@@ -1,4 +1,5 @@
 use mchprs_blocks::blocks::ComparatorMode;
+use mchprs_redpiler::compile_graph::LookupTable;
 use smallvec::SmallVec;
 use std::num::NonZeroU8;
 use std::ops::{Index, IndexMut};
@@ -72,21 +73,33 @@ pub struct ForwardLink {
 
 impl ForwardLink {
     pub fn new(id: NodeId, side: bool, ss: u8) -> Self {
-        assert!(id.index() < (1 << 27));
+        assert!(id.index() < (1 << 26));
         // the clamp_weights compile pass should ensure ss < 15
         assert!(ss < 15);
         Self {
-            data: (id.index() as u32) << 5 | if side { 1 << 4 } else { 0 } | ss as u32,
+            data: (id.index() as u32) << 6 | if side { 1 << 4 } else { 0 } | ss as u32,
+        }
+    }
+
+    /// Link to an observer watching the node, which carries changes instead of power
+    pub fn observe(id: NodeId) -> Self {
+        assert!(id.index() < (1 << 26));
+        Self {
+            data: (id.index() as u32) << 6 | 1 << 5,
         }
     }
 
     pub fn node(self) -> NodeId {
         unsafe {
             // safety: ForwardLink is constructed using a NodeId
-            NodeId::from_index((self.data >> 5) as usize)
+            NodeId::from_index((self.data >> 6) as usize)
         }
     }
 
+    pub fn observes(self) -> bool {
+        self.data & (1 << 5) != 0
+    }
+
     pub fn side(self) -> bool {
         self.data & (1 << 4) != 0
     }
@@ -101,6 +114,7 @@ impl std::fmt::Debug for ForwardLink {
         f.debug_struct("ForwardLink")
             .field("node", &self.node())
             .field("side", &self.side())
+            .field("observes", &self.observes())
             .field("ss", &self.ss())
             .finish()
     }
@@ -128,6 +142,11 @@ pub enum NodeType {
     NoteBlock {
         noteblock_id: u16,
     },
+    Lut {
+        table: LookupTable,
+        facing_diode: bool,
+    },
+    Observer,
 }
 
 #[repr(align(16))]
@@ -49,6 +49,13 @@ impl DirectBackend {
                     self.set_node(node_id, new_strength > 0, new_strength);
                 }
             }
+            NodeType::Lut { table, .. } => {
+                let (input_power, side_input_power) = get_all_input(node);
+                let new_strength = table.get(input_power, side_input_power);
+                if new_strength != node.output_power {
+                    self.set_node(node_id, new_strength > 0, new_strength);
+                }
+            }
             NodeType::Lamp => {
                 let should_be_lit = get_bool_input(node);
                 if node.powered && !should_be_lit {
@@ -60,6 +67,14 @@ impl DirectBackend {
                     self.set_node(node_id, false, 0);
                 }
             }
+            NodeType::Observer => {
+                if node.powered {
+                    self.set_node(node_id, false, 0);
+                } else {
+                    schedule_tick(&mut self.scheduler, node_id, node, 1, TickPriority::Normal);
+                    self.set_node(node_id, true, 15);
+                }
+            }
             _ => {} //unreachable!("Node {:?} should not be ticked!", node.ty),
         }
     }
@@ -20,7 +20,9 @@ pub(super) fn update_node(
             let should_be_locked = get_bool_side(node);
             if should_be_locked != node.locked {
                 set_node_locked(node, should_be_locked);
+                notify_observers(scheduler, nodes, node_id);
             }
+            let node = &mut nodes[node_id];
             if node.locked || node.pending_tick {
                 return;
             }
@@ -71,6 +73,20 @@ pub(super) fn update_node(
                 schedule_tick(scheduler, node_id, node, 1, priority);
             }
         }
+        NodeType::Lut { table, facing_diode } => {
+            if node.pending_tick {
+                return;
+            }
+            let (input_power, side_input_power) = get_all_input(node);
+            if table.get(input_power, side_input_power) != node.output_power {
+                let priority = if facing_diode {
+                    TickPriority::High
+                } else {
+                    TickPriority::Normal
+                };
+                schedule_tick(scheduler, node_id, node, 1, priority);
+            }
+        }
         NodeType::Lamp => {
             let should_be_lit = get_bool_input(node);
             let lit = node.powered;
@@ -78,12 +94,14 @@ pub(super) fn update_node(
                 schedule_tick(scheduler, node_id, node, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
                 set_node(node, true);
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::Trapdoor => {
             let should_be_powered = get_bool_input(node);
             if node.powered != should_be_powered {
                 set_node(node, should_be_powered);
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::Wire => {
@@ -91,6 +109,7 @@ pub(super) fn update_node(
             if node.output_power != input_power {
                 node.output_power = input_power;
                 node.changed = true;
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         NodeType::NoteBlock { noteblock_id } => {
@@ -100,8 +119,28 @@ pub(super) fn update_node(
                 if should_be_powered {
                     events.push(Event::NoteBlockPlay { noteblock_id });
                 }
+                notify_observers(scheduler, nodes, node_id);
             }
         }
         _ => {} // unreachable!("Node {:?} should not be updated!", node.ty),
     }
 }
+
+/// An observer seeing the node it watches change
+#[inline(always)]
+pub(super) fn observe(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+    let node = &mut nodes[node_id];
+    if !node.powered && !node.pending_tick {
+        schedule_tick(scheduler, node_id, node, 1, TickPriority::Normal);
+    }
+}
+
+/// Lets the observers watching a node see a change made here rather than in `set_node`
+fn notify_observers(scheduler: &mut TickScheduler, nodes: &mut Nodes, node_id: NodeId) {
+    for i in 0..nodes[node_id].updates.len() {
+        let link = nodes[node_id].updates[i];
+        if link.observes() {
+            observe(scheduler, nodes, link.node());
+        }
+    }
+}
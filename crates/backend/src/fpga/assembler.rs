@@ -1,93 +1,411 @@
-use mchprs_blocks::blocks::Block;
+use mchprs_blocks::blocks::ComparatorMode;
 use petgraph::visit::EdgeRef;
-use mchprs_redpiler::compile_graph::{CompileGraph, LinkType, NodeType};
+use mchprs_redpiler::compile_graph::{CompileGraph, LinkType, LookupTable, NodeType};
+use mchprs_redpiler::TraceTrigger;
+use super::compiler::PeripheralKind;
+use super::partition::{is_analog, is_output, Partition};
+use super::pins::PinPlan;
+use super::trace::TracePlan;
+use super::{rom, scan};
+use rustc_hash::FxHashMap;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::path::Path;
 
-pub fn generate_verilog(graph: &CompileGraph, path: &Path) {
+pub fn generate_verilog(graph: &CompileGraph, part: &Partition, trace: Option<&TracePlan>, pins: &PinPlan, path: &Path) -> io::Result<()> {
+    let verilog = generate_partition(graph, part, trace, pins);
+
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix)?;
+    }
+    let mut file = File::create(path)?;
+    file.write_all(verilog.as_bytes())
+}
+
+/// Generates the `RoC` module implementing one board's share of the graph.
+/// Nets of other boards the partition reads come in after its own inputs
+/// and the nets other boards read go out after its own outputs.
+/// ROM cells are written through the `rom_*` port, the scan chain is read through the
+/// `scan_*` port and the trace buffer through the `trace_*` port, all on the controller's clock.
+/// Bound board peripherals are read from `pin_in` and shown on `pin_out`.
+pub fn generate_partition(graph: &CompileGraph, part: &Partition, trace: Option<&TracePlan>, pins: &PinPlan) -> String {
 
     let mut verilog = 
 "module RoC #(
     parameter OUTPUTS,
-    parameter INPUTS
+    parameter INPUTS,
+    parameter TRACE_BYTES,
+    parameter PIN_INPUTS,
+    parameter PIN_OUTPUTS
 ) (
+    input                   clk,
     input                   tick,
     input   [INPUTS-1:0]    inputs,
-    output  [OUTPUTS-1:0]   outputs
+    output  [OUTPUTS-1:0]   outputs,
+    input                   rom_we,
+    input   [23:0]          rom_addr,
+    input   [3:0]           rom_data,
+    input                   scan_load,
+    input                   scan_shift,
+    output                  scan_out,
+    input                   trace_arm,
+    input   [23:0]          trace_addr,
+    output  [TRACE_BYTES*8-1:0] trace_data,
+    output  [1:0]           trace_state,
+    output  [23:0]          trace_start,
+    output  [23:0]          trace_count,
+    input   [PIN_INPUTS-1:0]  pin_in,
+    output  [PIN_OUTPUTS-1:0] pin_out
 );\n\n".to_owned();
 
+    let roms: FxHashMap<_, _> = rom::layout(graph, part).into_iter().map(|cell| (cell.node, cell)).collect();
+
+    // Declare every net up front since nodes can be driven by nodes that come after them
+    let mut nodes = part.members.iter().chain(&part.imports).copied().collect::<Vec<_>>();
+    nodes.sort();
+    for &nodeid in &nodes {
+        let id = nodeid.index();
+        match graph[nodeid].ty {
+            NodeType::Lever | NodeType::PressurePlate | NodeType::Button |
+            NodeType::Lamp | NodeType::Repeater { .. } | NodeType::Torch |
+            NodeType::Trapdoor | NodeType::NoteBlock { .. } | NodeType::Observer => {
+                verilog.push_str(&format!("\twire w{id};\n"));
+            }
+            NodeType::Comparator { .. } | NodeType::LUT { .. } | NodeType::Constant | NodeType::Wire => {
+                verilog.push_str(&format!("\twire [3:0] w{id};\n"));
+            }
+            _ => ()
+        }
+    }
+    // The first stage of the scan chain drives `scan_out` itself
+    let chain = scan::layout(graph, part);
+    for &nodeid in chain.iter().skip(1) {
+        verilog.push_str(&format!("\twire w{}_scan;\n", nodeid.index()));
+    }
+    verilog.push('\n');
+
     let mut input_count = 0;
     let mut output_count = 0;
 
-    for nodeid in graph.node_indices() {
+    for &nodeid in &part.members {
         let node = &graph[nodeid];
         let id = nodeid.index();
-        if let Some((pos, blockid)) = node.block {
-            let block = Block::from_id(blockid);
-            let state = node.state.powered;
-
-            match node.ty {
-                NodeType::Lever | NodeType::PressurePlate | NodeType::Button => {
-                    verilog.push_str(&format!("\twire w{id};\n"));
-                    verilog.push_str(&format!("\tassign w{id} = inputs[{input_count}];\n"));
-                    input_count += 1;
-                }
-                NodeType::Lamp | NodeType::Trapdoor => {
-                    verilog.push_str(&format!("\tassign outputs[{output_count}] = ({});\n", 
-                        get_inputs_str(graph, id, Some(LinkType::Default))));
-                    output_count += 1;
+        let state = node.state.powered;
+
+        match node.ty {
+            NodeType::Lever | NodeType::PressurePlate => {
+                // A component bound to a switch or button is driven by it, its input bit is left unused
+                match pins.inputs.iter().position(|binding| binding.node == nodeid) {
+                    Some(pin) => verilog.push_str(&format!("\tassign w{id} = {}pin_in[{pin}];\n",
+                        if pins.inputs[pin].peripheral.active_low {"!"} else {""})),
+                    None => verilog.push_str(&format!("\tassign w{id} = inputs[{input_count}];\n")),
                 }
-                NodeType::Repeater { delay, facing_diode: _ } => {
-                    verilog.push_str(&format!("\twire w{};\n", id));
-                    verilog.push_str(&format!("\trepeater #({}, 1'b{}, {}, {}) c{} (.i_clk(tick), .i_in({}), .i_lock({}), .o_out(w{}));\n",
-                        delay,
-                        if state {1} else {0},
-                        if is_locker(graph, id) {1} else {0},
-                        if is_locking(graph, id) {1} else {0},
-                        id,
-                        get_inputs_str(graph, id, Some(LinkType::Default)),
-                        get_inputs_str(graph, id, Some(LinkType::Side)),
-                        id));
+                input_count += 1;
+            }
+            NodeType::Button => {
+                // Presses flip the input, the pulse is timed on the board and shown back as an output
+                verilog.push_str(&format!("\tbutton #(1'b{}, 10) c{id} (.i_clk(tick), .i_in(inputs[{input_count}]), .o_out(w{id}));\n",
+                    if state {1} else {0}));
+                verilog.push_str(&format!("\tassign outputs[{output_count}] = w{id};\n"));
+                input_count += 1;
+                output_count += 1;
+            }
+            NodeType::Lamp => {
+                verilog.push_str(&format!("\tlamp #(1'b{}) c{} (.i_clk(tick), .i_in({}), .o_out(w{}));\n",
+                    if state {1} else {0},
+                    id,
+                    get_inputs_str(graph, id, Some(LinkType::Default)),
+                    id));
+                verilog.push_str(&format!("\tassign outputs[{output_count}] = w{id};\n"));
+                output_count += 1;
+            }
+            NodeType::Wire => {
+                // The strongest signal reaching the wire, shown on a hex lamp if it is a dot
+                let input = get_analog_inputs(graph, id, LinkType::Default, "in", &mut verilog);
+                verilog.push_str(&format!("\tassign w{id} = {input};\n"));
+                if is_output(node) {
+                    verilog.push_str(&format!("\tassign outputs[{}:{output_count}] = w{id};\n", output_count + 3));
+                    output_count += 4;
                 }
-                NodeType::Torch => {
-                    verilog.push_str(&format!("\twire w{};\n", id));
-                    verilog.push_str(&format!("\ttorch #(1'b{}) c{} (.i_clk(tick), .i_in({}), .o_out(w{}));\n", 
-                        if state {1} else {0},
-                        id,
-                        get_inputs_str(graph, id, Some(LinkType::Default)),
-                        id));
+            }
+            NodeType::Trapdoor => {
+                verilog.push_str(&format!("\tassign w{id} = ({});\n",
+                    get_inputs_str(graph, id, Some(LinkType::Default))));
+                verilog.push_str(&format!("\tassign outputs[{output_count}] = w{id};\n"));
+                output_count += 1;
+            }
+            // Note blocks only play in the world, the net is there for the observers watching them
+            NodeType::NoteBlock { .. } => {
+                verilog.push_str(&format!("\tassign w{id} = ({});\n",
+                    get_inputs_str(graph, id, Some(LinkType::Default))));
+            }
+            NodeType::Observer => {
+                let (watched, last) = get_watched(graph, id);
+                verilog.push_str(&format!("\tobserver #(1'b{}, 4'd{last}) c{id} (.i_clk(tick), .i_in({watched}), .o_out(w{id}));\n",
+                    if state {1} else {0}));
+            }
+            NodeType::Repeater { delay, facing_diode: _ } => {
+                verilog.push_str(&format!("\trepeater #({}, 1'b{}, {}, {}) c{} (.i_clk(tick), .i_in({}), .i_lock({}), .o_out(w{}));\n",
+                    delay,
+                    if state {1} else {0},
+                    if is_locker(graph, id) {1} else {0},
+                    if is_locking(graph, id) {1} else {0},
+                    id,
+                    get_inputs_str(graph, id, Some(LinkType::Default)),
+                    get_inputs_str(graph, id, Some(LinkType::Side)),
+                    id));
+            }
+            NodeType::Torch => {
+                verilog.push_str(&format!("\ttorch #(1'b{}) c{} (.i_clk(tick), .i_in({}), .o_out(w{}));\n",
+                    if state {1} else {0},
+                    id,
+                    get_inputs_str(graph, id, Some(LinkType::Default)),
+                    id));
+            }
+            NodeType::Comparator { mode, far_input, facing_diode: _ } => {
+                let input = get_analog_inputs(graph, id, LinkType::Default, "in", &mut verilog);
+                let side = get_analog_inputs(graph, id, LinkType::Side, "side", &mut verilog);
+                verilog.push_str(&format!("\tcomp #(4'd{}, {}, {}, 4'd{}) c{} (.i_clk(tick), .i_in({}), .i_side({}), .o_out(w{}));\n",
+                    node.state.output_strength,
+                    match mode {
+                        ComparatorMode::Compare => 0,
+                        ComparatorMode::Subtract => 1,
+                    },
+                    if far_input.is_some() {1} else {0},
+                    far_input.unwrap_or(0),
+                    id,
+                    input,
+                    side,
+                    id));
+            }
+            NodeType::LUT { table, .. } => {
+                let input = get_analog_inputs(graph, id, LinkType::Default, "in", &mut verilog);
+                let side = get_analog_inputs(graph, id, LinkType::Side, "side", &mut verilog);
+                verilog.push_str(&format!("\tlut #(4'd{}) c{id} (.i_clk(tick), .i_in({}), .o_out(w{id}));\n",
+                    node.state.output_strength,
+                    decode_table(table, &input, &side)));
+            }
+            NodeType::Constant => match roms.get(&nodeid) {
+                Some(cell) => {
+                    let ss = node.state.output_strength;
+                    let (width, on, state) = match cell.on {
+                        Some(on) => (1, on, (ss != 0) as u8),
+                        None => (4, 0, ss),
+                    };
+                    verilog.push_str(&format!("\trom #(24'd{}, {width}, 4'd{on}, {width}'d{state}) c{id} (.i_clk(clk), .i_we(rom_we), .i_addr(rom_addr), .i_data(rom_data), .o_out(w{id}));\n",
+                        cell.addr));
                 }
-                NodeType::Comparator { mode: _, far_input: _, facing_diode: _} => {
+                None => verilog.push_str(&format!("\tassign w{id} = 4'd{};\n", node.state.output_strength)),
+            },
+            _ => ()
+        }
+    }
+
+    for (i, &nodeid) in chain.iter().enumerate() {
+        let id = nodeid.index();
+        let state = match graph[nodeid].ty {
+            NodeType::Repeater { .. } => format!("{{{}, w{id}}}", get_inputs_str(graph, id, Some(LinkType::Side))),
+            _ => format!("w{id}"),
+        };
+        let width = scan::width(&graph[nodeid].ty).unwrap_or(1);
+        let next = chain.get(i + 1).map_or("1'b0".to_owned(), |next| format!("w{}_scan", next.index()));
+        let out = if i == 0 { "scan_out".to_owned() } else { format!("w{id}_scan") };
+        verilog.push_str(&format!("\tscan #({width}) s{id} (.i_clk(clk), .i_load(scan_load), .i_shift(scan_shift), .i_in({state}), .i_chain({next}), .o_out({out}));\n"));
+    }
+    if chain.is_empty() {
+        verilog.push_str("\tassign scan_out = 1'b0;\n");
+    }
 
+    match trace {
+        Some(plan) => {
+            // Rows are padded to whole bytes, the first probe in the least significant bits
+            let bits = plan.layout.row_bytes() * 8;
+            let mut probes: Vec<String> = plan.nodes.iter().rev().map(|node| format!("w{}", node.index())).collect();
+            let padding = bits - plan.layout.row_bits();
+            if padding > 0 {
+                probes.insert(0, format!("{padding}'d0"));
+            }
+            let (mode, trigger) = match (plan.layout.trigger, plan.trigger) {
+                (Some(edge), Some(node)) => {
+                    let trigger = if is_analog(&graph[node].ty) { format!("w{} != 4'd0", node.index()) } else { format!("w{}", node.index()) };
+                    (match edge {
+                        TraceTrigger::Rising => 0,
+                        TraceTrigger::Falling => 1,
+                        TraceTrigger::Change => 2,
+                    }, trigger)
                 }
-                _ => ()
+                _ => (3, "1'b0".to_owned()),
+            };
+            verilog.push_str(&format!("\ttrace #({bits}, {}, {}, 2'd{mode}) t0 (.i_clk(clk), .i_tick(tick), .i_arm(trace_arm), .i_probes({{{}}}), .i_trigger({trigger}), .i_addr(trace_addr), .o_data(trace_data), .o_state(trace_state), .o_start(trace_start), .o_count(trace_count));\n",
+                plan.layout.depth,
+                plan.layout.pretrigger,
+                probes.join(", ")));
+        }
+        None => verilog.push_str("\tassign trace_data = 8'd0;\n\tassign trace_state = 2'd0;\n\tassign trace_start = 24'd0;\n\tassign trace_count = 24'd0;\n"),
+    }
+
+    let mut pin = 0;
+    for binding in &pins.outputs {
+        let node = binding.node;
+        let id = node.index();
+        let analog = is_analog(&graph[node].ty);
+        let signal = format!("w{id}");
+        let invert = if binding.peripheral.active_low {"~"} else {""};
+        match binding.peripheral.kind {
+            PeripheralKind::SevenSegment => {
+                let digit = if analog { signal } else { format!("{{3'd0, {signal}}}") };
+                verilog.push_str(&format!("\tassign pin_out[{}:{pin}] = {invert}({});\n", pin + 6, seven_segment(&digit)));
+                pin += 7;
+            }
+            _ => {
+                let on = if analog { format!("({signal} != 4'd0)") } else { signal };
+                verilog.push_str(&format!("\tassign pin_out[{pin}] = {invert}{on};\n"));
+                pin += 1;
             }
         }
     }
+    if pin == 0 {
+        verilog.push_str("\tassign pin_out = 1'b0;\n");
+    }
+
+    for &nodeid in &part.imports {
+        let id = nodeid.index();
+        if is_analog(&graph[nodeid].ty) {
+            verilog.push_str(&format!("\tassign w{id} = inputs[{}:{input_count}];\n", input_count + 3));
+            input_count += 4;
+        } else {
+            verilog.push_str(&format!("\tassign w{id} = inputs[{input_count}];\n"));
+            input_count += 1;
+        }
+    }
+    for &nodeid in &part.exports {
+        let id = nodeid.index();
+        if is_analog(&graph[nodeid].ty) {
+            verilog.push_str(&format!("\tassign outputs[{}:{output_count}] = w{id};\n", output_count + 3));
+            output_count += 4;
+        } else {
+            verilog.push_str(&format!("\tassign outputs[{output_count}] = w{id};\n"));
+            output_count += 1;
+        }
+    }
     verilog.push_str("endmodule");
+    verilog
+}
+
+/// Segments `gfedcba` lit to show a 4 bit value as a hex digit
+const SEGMENTS: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+
+/// Decodes a 4 bit value into the segments of a seven segment display
+fn seven_segment(digit: &str) -> String {
+    let mut decode = format!("7'h{:02X}", SEGMENTS[15]);
+    for value in (0..15).rev() {
+        decode = format!("({digit} == 4'd{value}) ? 7'h{:02X} : {decode}", SEGMENTS[value]);
+    }
+    decode
+}
 
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).unwrap();
-    let mut file = File::create(path).unwrap();
-    match file.write(verilog.as_bytes()) {
-        Err(..) => println!("    Error Writing to file"),
-        _ => ()
+/// Decodes a comparator's table from its input strengths. Only the strengths the table
+/// covers are compared, pairs it does not cover cannot be reached.
+fn decode_table(table: LookupTable, input: &str, side: &str) -> String {
+    let mut decode = "4'd0".to_owned();
+    for (input_ss, side_ss, output) in table.entries() {
+        if output == 0 {
+            continue;
+        }
+        let mut terms = Vec::new();
+        if table.input.count_ones() > 1 {
+            terms.push(format!("{input} == 4'd{input_ss}"));
+        }
+        if table.side.count_ones() > 1 {
+            terms.push(format!("{side} == 4'd{side_ss}"));
+        }
+        decode = match terms.is_empty() {
+            true => format!("4'd{output}"),
+            false => format!("({}) ? 4'd{output} : {decode}", terms.join(" && ")),
+        };
     }
+    decode
 }
 
+/// Boolean input of a node: any incoming signal that is still above 0 after the link's distance
 fn get_inputs_str (graph: &CompileGraph, node: usize, ty: Option<LinkType>) -> String {
     let mut inputs = "".to_owned();
     for edge in graph.edges_directed((node as u32).into(), petgraph::Direction::Incoming) {
         let weight = edge.weight(); 
         if ty == None || weight.ty == ty.unwrap() {
-            inputs.push_str(&format!("w{}|", edge.source().index()));
+            let source = edge.source().index();
+            if is_analog(&graph[edge.source()].ty) {
+                inputs.push_str(&format!("(w{} > 4'd{})|", source, weight.ss));
+            } else {
+                inputs.push_str(&format!("w{}|", source));
+            }
         }
     }
     inputs.pop();
+    if inputs.is_empty() {
+        inputs.push_str("1'b0");
+    }
     inputs
 }
 
+/// Signal strength input of a node: the strongest incoming signal after subtracting each link's distance.
+/// Emits the intermediate nets needed to compute the maximum and returns the expression for the result.
+fn get_analog_inputs (graph: &CompileGraph, node: usize, ty: LinkType, name: &str, verilog: &mut String) -> String {
+    let mut terms = Vec::new();
+    for edge in graph.edges_directed((node as u32).into(), petgraph::Direction::Incoming) {
+        let weight = edge.weight();
+        if weight.ty != ty {
+            continue;
+        }
+        let source = edge.source().index();
+        if !is_analog(&graph[edge.source()].ty) {
+            terms.push(format!("(w{} ? 4'd{} : 4'd0)", source, 15u8.saturating_sub(weight.ss)));
+        } else if weight.ss == 0 {
+            terms.push(format!("w{}", source));
+        } else {
+            terms.push(format!("(w{source} > 4'd{ss} ? w{source} - 4'd{ss} : 4'd0)", ss = weight.ss));
+        }
+    }
+
+    match terms.len() {
+        0 => "4'd0".to_owned(),
+        1 => terms.pop().unwrap(),
+        _ => {
+            let mut last = String::new();
+            for (i, term) in terms.iter().enumerate() {
+                let net = format!("c{node}_{name}{i}");
+                verilog.push_str(&format!("\twire [3:0] {net};\n"));
+                if i == 0 {
+                    verilog.push_str(&format!("\tassign {net} = {term};\n"));
+                } else {
+                    verilog.push_str(&format!("\tassign {net} = ({term} > {last}) ? {term} : {last};\n"));
+                }
+                last = net;
+            }
+            last
+        }
+    }
+}
+
+/// What an observer sees of the node it watches and what it saw when compiled: the power
+/// level of a wire, the power state of anything else. Constants never change. Repeaters
+/// changing their lock are not seen, the lock is not a net of the repeater's own.
+fn get_watched (graph: &CompileGraph, node: usize) -> (String, u8) {
+    let Some(edge) = graph.edges_directed((node as u32).into(), petgraph::Direction::Incoming).next() else {
+        return ("4'd0".to_owned(), 0);
+    };
+    let source = &graph[edge.source()];
+    let id = edge.source().index();
+    match source.ty {
+        NodeType::Constant => ("4'd0".to_owned(), 0),
+        NodeType::Wire => (format!("w{id}"), source.state.output_strength),
+        _ if is_analog(&source.ty) => (format!("{{3'd0, (w{id} != 4'd0)}}"), source.state.powered as u8),
+        _ => (format!("{{3'd0, w{id}}}"), source.state.powered as u8),
+    }
+}
+
 fn is_locking (graph: &CompileGraph, node: usize) -> bool {
     for edge in graph.edges_directed((node as u32).into(), petgraph::Direction::Incoming) {
         let link = &graph[edge.id()];
@@ -0,0 +1,249 @@
+//! Compiled builds kept under `FPGA/bin/<plot>/<name>`, managed with `/roc list`, `delete`,
+//! `rename` and `info`.
+//!
+//! Next to its `link.json` every build compiled since manifests were introduced keeps a
+//! `manifest.json` recording where and how it was compiled. Builds without one still load,
+//! they just have less to say about themselves.
+
+use super::linker::Linker;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::CompilerOptions;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Version of the manifest format, bumped whenever a field changes meaning
+pub const MANIFEST_VERSION: u32 = 1;
+
+const LINK_FILE: &str = "link.json";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Where and how a build was compiled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub name: String,
+    /// Seconds since the Unix epoch
+    pub compiled_at: u64,
+    /// Corners of the region the build was compiled from
+    pub bounds: (BlockPos, BlockPos),
+    /// Compiler options, as the flags `CompilerOptions::parse` takes
+    pub options: String,
+    /// Boards the build was compiled for, one per partition
+    pub devices: Vec<String>,
+    pub input_bits: u32,
+    pub output_bits: u32,
+    /// Hash of the blocks in the region, as the graph cache keys it
+    pub content_hash: u64,
+}
+
+impl Manifest {
+    pub fn new(
+        name: String,
+        bounds: (BlockPos, BlockPos),
+        options: &CompilerOptions,
+        devices: Vec<String>,
+        (input_bits, output_bits): (u32, u32),
+        content_hash: u64,
+    ) -> Manifest {
+        let compiled_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Manifest {
+            version: MANIFEST_VERSION,
+            name,
+            compiled_at,
+            bounds,
+            options: options.to_flags(),
+            devices,
+            input_bits,
+            output_bits,
+            content_hash,
+        }
+    }
+
+    pub fn options(&self) -> CompilerOptions {
+        CompilerOptions::parse(&self.options)
+    }
+
+    /// The manifest of the build in a directory, if it has one
+    pub fn read(dir: &Path) -> Result<Option<Manifest>, String> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+        let manifest: Manifest = serde_json::from_str(&json).map_err(|err| format!("{} is malformed: {}", path.display(), err))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(format!(
+                "{} has version {}, newer than the {} this server reads",
+                path.display(),
+                manifest.version,
+                MANIFEST_VERSION
+            ));
+        }
+        Ok(Some(manifest))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        let path = dir.join(MANIFEST_FILE);
+        fs::write(&path, json).map_err(|err| format!("could not write {}: {}", path.display(), err))
+    }
+
+    /// Removes the manifest of a build about to be recompiled, so a failed compile does not
+    /// leave the old one describing the new files
+    pub fn remove(dir: &Path) {
+        let path = dir.join(MANIFEST_FILE);
+        if path.exists() {
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Could not remove {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Directory holding the builds of a plot
+pub fn plot_dir(plot: &str) -> PathBuf {
+    Path::new("FPGA/bin").join(plot)
+}
+
+/// Names of the builds in a plot's directory, sorted
+pub fn list(plot_dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(plot_dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// The link of the build in a directory
+pub fn read_link(dir: &Path) -> Result<Linker, String> {
+    let path = dir.join(LINK_FILE);
+    if !path.exists() {
+        return Err(format!("{} is missing", path.display()));
+    }
+    let json = fs::read_to_string(&path).map_err(|err| format!("could not read {}: {}", path.display(), err))?;
+    serde_json::from_str(&json).map_err(|err| format!("{} is malformed: {}", path.display(), err))
+}
+
+/// Whether a name can be used for a build directory
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+/// Deletes a build and everything compiled for it
+pub fn delete(plot_dir: &Path, name: &str) -> Result<(), String> {
+    let dir = plot_dir.join(name);
+    if !is_valid_name(name) || !dir.is_dir() {
+        return Err(format!("There is no build named {}", name));
+    }
+    fs::remove_dir_all(&dir).map_err(|err| format!("Could not delete {}: {}", dir.display(), err))
+}
+
+/// Renames a build, along with the name its link and manifest record
+pub fn rename(plot_dir: &Path, old: &str, new: &str) -> Result<(), String> {
+    let from = plot_dir.join(old);
+    let to = plot_dir.join(new);
+    if !is_valid_name(old) || !from.is_dir() {
+        return Err(format!("There is no build named {}", old));
+    }
+    if !is_valid_name(new) {
+        return Err(format!("{} is not a valid build name", new));
+    }
+    if to.exists() {
+        return Err(format!("There already is a build named {}", new));
+    }
+    fs::rename(&from, &to).map_err(|err| format!("Could not rename {}: {}", from.display(), err))?;
+
+    // A build whose files cannot be updated still runs under its new name, it is loaded by directory
+    match read_link(&to) {
+        Ok(mut link) => {
+            link.name = new.to_owned();
+            for partition in &mut link.partitions {
+                partition.name = new.to_owned();
+            }
+            link.generate_link_file(&to.join(LINK_FILE));
+        }
+        Err(err) => warn!("Not renaming the link of {}: {}", new, err),
+    }
+    match Manifest::read(&to) {
+        Ok(Some(mut manifest)) => {
+            manifest.name = new.to_owned();
+            manifest.write(&to)?;
+        }
+        Ok(None) => (),
+        Err(err) => warn!("Not renaming the manifest of {}: {}", new, err),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_plot(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roc_builds_{}_{}", test, std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn build(plot_dir: &Path, name: &str) -> Manifest {
+        let link = Linker {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        link.generate_link_file(&plot_dir.join(name).join(LINK_FILE));
+        let bounds = (BlockPos::new(0, 0, 0), BlockPos::new(15, 15, 15));
+        let manifest = Manifest::new(name.to_owned(), bounds, &CompilerOptions::fpga(), vec!["board".to_owned()], (3, 2), 0xC0FFEE);
+        manifest.write(&plot_dir.join(name)).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn rename_and_delete() {
+        let plot_dir = temp_plot("rename");
+        let manifest = build(&plot_dir, "adder");
+        build(&plot_dir, "alu");
+        assert_eq!(list(&plot_dir), ["adder", "alu"]);
+        assert_eq!(manifest.options(), CompilerOptions::fpga());
+
+        assert_eq!(rename(&plot_dir, "adder", "alu").unwrap_err(), "There already is a build named alu");
+        assert_eq!(rename(&plot_dir, "adder", "../adder").unwrap_err(), "../adder is not a valid build name");
+        rename(&plot_dir, "adder", "counter").unwrap();
+        assert_eq!(read_link(&plot_dir.join("counter")).unwrap().name, "counter");
+        let renamed = Manifest::read(&plot_dir.join("counter")).unwrap().unwrap();
+        assert_eq!(renamed, Manifest { name: "counter".to_owned(), ..manifest });
+
+        delete(&plot_dir, "alu").unwrap();
+        assert_eq!(delete(&plot_dir, "alu").unwrap_err(), "There is no build named alu");
+        assert_eq!(list(&plot_dir), ["counter"]);
+        _ = fs::remove_dir_all(&plot_dir);
+    }
+
+    #[test]
+    fn broken_builds() {
+        let plot_dir = temp_plot("broken");
+        fs::create_dir_all(plot_dir.join("empty")).unwrap();
+        assert!(read_link(&plot_dir.join("empty")).unwrap_err().ends_with("link.json is missing"));
+        assert_eq!(Manifest::read(&plot_dir.join("empty")), Ok(None));
+
+        fs::create_dir_all(plot_dir.join("cut_off")).unwrap();
+        fs::write(plot_dir.join("cut_off").join(LINK_FILE), "{\"name\": \"cut").unwrap();
+        assert!(read_link(&plot_dir.join("cut_off")).unwrap_err().contains("is malformed"));
+
+        let mut manifest = build(&plot_dir, "future");
+        manifest.version = MANIFEST_VERSION + 1;
+        manifest.write(&plot_dir.join("future")).unwrap();
+        assert!(Manifest::read(&plot_dir.join("future")).unwrap_err().contains("newer than"));
+        _ = fs::remove_dir_all(&plot_dir);
+    }
+}
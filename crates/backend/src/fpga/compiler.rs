@@ -1,14 +1,8 @@
-use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
-use std::env;
-use std::os::windows::process::CommandExt;
-use std::path::Path;
-use std::process::Command;
-use serde_json;
+use std::path::PathBuf;
 use serde;
 
-use crate::fpga::FPGABackend;
+use crate::fpga::estimate::Resources;
+use crate::fpga::toolchain::{Toolchain, ToolchainConfig};
 
 #[derive(serde::Deserialize, Debug, Clone, Default)]
 pub struct DeviceConfig {
@@ -17,7 +11,21 @@ pub struct DeviceConfig {
     pub family:         String,
     pub command_com:    String,
     pub program_com:    String,
-    pub pin_assignments:PinAssignments
+    #[serde(default)]
+    pub jtag_index:     Option<u32>,
+    #[serde(default)]
+    pub package:        Option<String>,
+    pub pin_assignments:PinAssignments,
+    /// Logic available on the board, by default looked up from the `device`.
+    /// Builds that don't fit are split across several boards.
+    #[serde(default)]
+    pub capacity:       Option<Resources>,
+    /// Switches, buttons, LEDs and displays on the board that components can be bound to
+    /// with a `[pin <name>]` sign
+    #[serde(default)]
+    pub peripherals:    Vec<Peripheral>,
+    #[serde(skip)]
+    pub toolchain:      ToolchainConfig,
 }
 
 #[derive(serde::Deserialize, Debug, Clone, Default)]
@@ -30,104 +38,103 @@ pub struct PinAssignments {
     o_tick:         Option<String>
 }
 
-impl DeviceConfig {
-    pub fn create_project(&self, path: &Path, output_cnt: u32, input_cnt: u32) -> bool {
-
-        let mut tcl = format!(
-        "package require ::quartus::project
-project_new -overwrite -revision RoC RoC 
-set_global_assignment -name FAMILY \"{family}\"
-set_global_assignment -name DEVICE {device}
-set_global_assignment -name TOP_LEVEL_ENTITY top
-set_global_assignment -name ORIGINAL_QUARTUS_VERSION 23.1STD.1
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/top.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/interface/uart.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/interface/clk_div.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/interface/command_controller.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../redstone.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/redstone/components.sv
-set_global_assignment -name SYSTEMVERILOG_FILE ../../../../src/redstone/tps_clk_div.sv
-set_global_assignment -name SOURCE_FILE ../../../../ip/tick_clk.cmp
-set_global_assignment -name QIP_FILE ../../../../ip/tick_clk.qip
-set_global_assignment -name SIP_FILE ../../../../ip/tick_clk.sip
-set_parameter -name ROC_OUTPUTS {output_cnt}
-set_parameter -name ROC_INPUTS {input_cnt}
-set_location_assignment PIN_{i_rx} -to i_RX
-set_location_assignment PIN_{o_tx} -to o_TX
-set_location_assignment PIN_{i_clk} -to i_clk\n",
-        device = self.device,
-        family = self.family,
-        output_cnt = output_cnt,
-        input_cnt = input_cnt,
-        i_rx = self.pin_assignments.i_rx,
-        o_tx = self.pin_assignments.o_tx,
-        i_clk = self.pin_assignments.i_clk,
-        );
-
-        if let Some(i_rst) = &self.pin_assignments.i_rst {
-            tcl.push_str(&format!("set_location_assignment PIN_{} -to i_rst\n", i_rst));
-        }
-        if let Some(o_tick) = &self.pin_assignments.o_tick {
-            tcl.push_str(&format!("set_location_assignment PIN_{} -to o_tick\n", o_tick));
-        }
-        if let Some(o_debug) = &self.pin_assignments.o_debug {
-            tcl.push_str(&format!("set_location_assignment PIN_{} -to o_debug\n", o_debug));
-        }
-
-        tcl.push_str("export_assignments\nproject_close\n");
+impl PinAssignments {
+    /// Pin of every assigned top level port
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("i_clk", Some(&self.i_clk)),
+            ("i_RX", Some(&self.i_rx)),
+            ("i_rst", self.i_rst.as_ref()),
+            ("o_TX", Some(&self.o_tx)),
+            ("o_debug", self.o_debug.as_ref()),
+            ("o_tick", self.o_tick.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(port, pin)| Some((port, pin?.as_str())))
+    }
+}
 
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).unwrap();
-        let mut file = File::create(path).unwrap();
-        match file.write_all(tcl.as_bytes()) {
-            Err(..) => return false,
-            _ => ()
-        };
+/// A switch, button, LED or seven segment display wired to the board's pins
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Peripheral {
+    pub name:           String,
+    pub kind:           PeripheralKind,
+    /// The peripheral's pins, a display's segments in `a` to `g` order
+    pub pins:           Vec<String>,
+    /// Whether the peripheral is on while its pins are low
+    #[serde(default)]
+    pub active_low:     bool,
+}
 
-        env::set_var("quartus_sh", r"C:\intelFPGA_lite\23.1std\quartus\bin64");
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeripheralKind {
+    Switch,
+    Button,
+    Led,
+    SevenSegment,
+}
 
-        let out = Command::new("cmd")
-            .current_dir(prefix)
-            .args(&["/C", r"C:\intelFPGA_lite\23.1std\quartus\bin64\quartus_sh -t prj.tcl"])
-            .output()
-            .unwrap();
-        println!("{:?}", String::from_utf8_lossy(&out.stdout));
+impl PeripheralKind {
+    /// Whether the peripheral drives the design rather than showing it
+    pub fn is_input(self) -> bool {
+        matches!(self, PeripheralKind::Switch | PeripheralKind::Button)
+    }
 
-        return true;
+    /// Pins the peripheral is wired to
+    pub fn pins(self) -> usize {
+        match self {
+            PeripheralKind::SevenSegment => 7,
+            _ => 1,
+        }
     }
+}
 
-    pub fn compile (&self, path: &Path) -> CompilerResults {
-        let results = CompilerResults{state: true};
-        let out = Command::new("cmd")
-            .current_dir(path)
-            .args(&["/C", r"C:\intelFPGA_lite\23.1std\quartus\bin64\quartus_sh --flow compile RoC"])
-            .output()
-            .unwrap();
-        println!("{:?}", String::from_utf8_lossy(&out.stdout));
+impl DeviceConfig {
+    pub fn toolchain(&self) -> Toolchain {
+        self.toolchain.toolchain()
+    }
 
-        results
+    /// The peripheral of a name, which signs give in lowercase
+    pub fn peripheral(&self, name: &str) -> Option<&Peripheral> {
+        self.peripherals.iter().find(|peripheral| peripheral.name.eq_ignore_ascii_case(name))
     }
 
-    pub fn program (&self, path: &Path) -> ProgramResults {
-        let results = ProgramResults{};
-        let out = Command::new("cmd")
-            .current_dir(path)
-            .arg("/C")
-            .raw_arg(r#"C:\intelFPGA_lite\23.1std\quartus\bin64\quartus_pgm -c "DE-SoC [USB-1]" -m jtag -o "p;RoC.sof@2""#)
-            .output()
-            .unwrap();
-        println!("{:?}", String::from_utf8_lossy(&out.stdout));
-
-        results
+    /// Logic available on the board, if it is configured or the part is known
+    pub fn capacity(&self) -> Option<Resources> {
+        self.capacity.or_else(|| Resources::of_device(&self.device))
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceUsage {
+    pub resource: String,
+    pub used: u64,
+    pub available: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct CompilerResults {
-    pub state: bool
+    pub success: bool,
+    pub utilization: Vec<ResourceUsage>,
+    /// Slowest clock's maximum frequency in MHz
+    pub fmax: Option<f64>,
+    pub log: PathBuf,
 }
 
-pub struct ProgramResults {
+impl CompilerResults {
+    pub fn failed(log: PathBuf) -> CompilerResults {
+        CompilerResults {
+            log,
+            ..Default::default()
+        }
+    }
+}
 
+#[derive(Debug, Clone, Default)]
+pub struct ProgramResults {
+    pub success: bool,
+    pub log: PathBuf,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -149,8 +156,3 @@ impl CompilerStatus {
         }
     }
 }
-
-
-
-
-
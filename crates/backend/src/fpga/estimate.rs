@@ -0,0 +1,233 @@
+//! Quick estimate of the logic a design needs, so designs that won't fit are caught before
+//! spending minutes in the vendor toolchain.
+//!
+//! Counts are in flip-flops and 4 input LUTs (logic elements on Intel parts) and follow the
+//! code the assembler emits for each node. They are deliberately on the pessimistic side
+//! since synthesis usually packs the logic tighter.
+
+use super::partition::{is_analog, Partition};
+use super::{rom, scan};
+use mchprs_redpiler::compile_graph::{CompileGraph, LinkType, NodeIdx, NodeType};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign};
+
+/// Logic resources of a design or a board
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resources {
+    pub flip_flops: u64,
+    pub luts: u64,
+}
+
+/// The command controller, UART and tick divider every design is wrapped in
+pub const CONTROLLER: Resources = Resources {
+    flip_flops: 350,
+    luts: 450,
+};
+
+/// Capacity of the parts boards are commonly built around, matched by prefix of the device name
+const CAPACITY_TABLE: &[(&str, Resources)] = &[
+    // Cyclone V, logic elements and registers
+    ("5CEBA2", Resources { flip_flops: 37_736, luts: 25_000 }),
+    ("5CEBA4", Resources { flip_flops: 73_920, luts: 49_000 }),
+    ("5CEBA5", Resources { flip_flops: 116_240, luts: 77_000 }),
+    ("5CSEMA4", Resources { flip_flops: 60_376, luts: 40_000 }),
+    ("5CSEMA5", Resources { flip_flops: 128_300, luts: 85_000 }),
+    ("5CSEBA6", Resources { flip_flops: 166_036, luts: 110_000 }),
+    // iCE40, logic cells
+    ("hx1k", Resources { flip_flops: 1_280, luts: 1_280 }),
+    ("hx8k", Resources { flip_flops: 7_680, luts: 7_680 }),
+    ("up5k", Resources { flip_flops: 5_280, luts: 5_280 }),
+    // ECP5
+    ("25k", Resources { flip_flops: 24_288, luts: 24_288 }),
+    ("45k", Resources { flip_flops: 43_848, luts: 43_848 }),
+    ("85k", Resources { flip_flops: 83_640, luts: 83_640 }),
+];
+
+impl Resources {
+    pub const UNLIMITED: Resources = Resources {
+        flip_flops: u64::MAX,
+        luts: u64::MAX,
+    };
+
+    /// Capacity of a known part
+    pub fn of_device(device: &str) -> Option<Resources> {
+        let device = device.to_ascii_lowercase();
+        CAPACITY_TABLE
+            .iter()
+            .find(|(prefix, _)| device.starts_with(&prefix.to_ascii_lowercase()))
+            .map(|(_, capacity)| *capacity)
+    }
+
+    pub fn fits(&self, capacity: &Resources) -> bool {
+        self.flip_flops <= capacity.flip_flops && self.luts <= capacity.luts
+    }
+
+    /// Share of the scarcer resource that is used
+    pub fn utilization(&self, capacity: &Resources) -> f64 {
+        let ratio = |used: u64, available: u64| {
+            if available == 0 {
+                f64::INFINITY
+            } else {
+                used as f64 / available as f64
+            }
+        };
+        ratio(self.flip_flops, capacity.flip_flops).max(ratio(self.luts, capacity.luts))
+    }
+
+    pub fn saturating_add(self, other: Resources) -> Resources {
+        Resources {
+            flip_flops: self.flip_flops.saturating_add(other.flip_flops),
+            luts: self.luts.saturating_add(other.luts),
+        }
+    }
+
+    pub fn saturating_sub(self, other: Resources) -> Resources {
+        Resources {
+            flip_flops: self.flip_flops.saturating_sub(other.flip_flops),
+            luts: self.luts.saturating_sub(other.luts),
+        }
+    }
+}
+
+impl Add for Resources {
+    type Output = Resources;
+
+    fn add(self, other: Resources) -> Resources {
+        Resources {
+            flip_flops: self.flip_flops + other.flip_flops,
+            luts: self.luts + other.luts,
+        }
+    }
+}
+
+impl AddAssign for Resources {
+    fn add_assign(&mut self, other: Resources) {
+        *self = *self + other;
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} flip-flops, {} LUTs", self.flip_flops, self.luts)
+    }
+}
+
+/// LUTs to OR together `terms` single bit signals
+fn or_tree(terms: u64) -> u64 {
+    if terms <= 1 {
+        0
+    } else {
+        (terms - 1).div_ceil(3)
+    }
+}
+
+/// LUTs for the boolean input of a node: one per comparison of an analog source, plus the OR tree
+fn digital_input(graph: &CompileGraph, node: NodeIdx, ty: LinkType) -> u64 {
+    let mut terms = 0;
+    let mut compares = 0;
+    for edge in graph.edges_directed(node, Direction::Incoming) {
+        if edge.weight().ty != ty {
+            continue;
+        }
+        terms += 1;
+        if is_analog(&graph[edge.source()].ty) {
+            compares += 1;
+        }
+    }
+    compares + or_tree(terms)
+}
+
+/// LUTs for the signal strength input of a comparator: a subtractor per link with a distance,
+/// and a comparator and multiplexer per term to find the strongest
+fn analog_input(graph: &CompileGraph, node: NodeIdx, ty: LinkType) -> u64 {
+    let mut terms: u64 = 0;
+    let mut luts = 0;
+    for edge in graph.edges_directed(node, Direction::Incoming) {
+        if edge.weight().ty != ty {
+            continue;
+        }
+        terms += 1;
+        if is_analog(&graph[edge.source()].ty) && edge.weight().ss > 0 {
+            luts += 4;
+        }
+    }
+    luts + terms.saturating_sub(1) * 8
+}
+
+/// Resources one node needs, including its register in the command controller if it is an input or output
+/// and its scan stage
+pub fn node(graph: &CompileGraph, node: NodeIdx) -> Resources {
+    let (flip_flops, luts) = match graph[node].ty {
+        NodeType::Repeater { delay, .. } => {
+            let delay = delay as u64;
+            let lock = digital_input(graph, node, LinkType::Side);
+            (delay, delay + lock + digital_input(graph, node, LinkType::Default))
+        }
+        NodeType::Torch => (1, digital_input(graph, node, LinkType::Default).max(1)),
+        NodeType::Comparator { .. } => {
+            let inputs = analog_input(graph, node, LinkType::Default) + analog_input(graph, node, LinkType::Side);
+            (4, 8 + inputs)
+        }
+        // The table decodes in about a LUT per output bit instead of a subtractor
+        NodeType::LUT { .. } => {
+            let inputs = analog_input(graph, node, LinkType::Default) + analog_input(graph, node, LinkType::Side);
+            (4, 4 + inputs)
+        }
+        // The lamp's own two registers and the output capture
+        NodeType::Lamp => (3, 1 + digital_input(graph, node, LinkType::Default)),
+        NodeType::Trapdoor => (1, digital_input(graph, node, LinkType::Default).max(1)),
+        // The last state it saw, its pulse and the comparison of the two states
+        NodeType::Observer => (5, 4),
+        NodeType::Lever | NodeType::PressurePlate => (1, 0),
+        // Input and output registers, the last press and the tick counter
+        NodeType::Button => (7, 6),
+        // A ROM cell's register and the decoding of its address
+        NodeType::Constant if rom::is_rom(&graph[node]) => (4, 8),
+        // A hex lamp and its output capture
+        NodeType::Wire => (4, analog_input(graph, node, LinkType::Default)),
+        NodeType::Constant | NodeType::NoteBlock { .. } => (0, 0),
+    };
+    // A register and a multiplexer per bit of the scan stage
+    let scan = scan::width(&graph[node].ty).unwrap_or(0) as u64;
+    Resources {
+        flip_flops: flip_flops + scan,
+        luts: luts + scan,
+    }
+}
+
+/// Resources a board needs to run its partition, including the registers for nets to and
+/// from other boards and the command controller
+pub fn partition(graph: &CompileGraph, part: &Partition) -> Resources {
+    let mut total = CONTROLLER;
+    for &member in &part.members {
+        total += node(graph, member);
+    }
+    let cut_bits = (part.input_bits(graph) - part.own_inputs(graph)) + (part.output_bits(graph) - part.own_outputs(graph));
+    total.flip_flops += cut_bits as u64;
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_devices() {
+        assert_eq!(Resources::of_device("5CSEMA5F31C6"), Resources::of_device("5csema5"));
+        assert_eq!(Resources::of_device("85k").map(|r| r.luts), Some(83_640));
+        assert_eq!(Resources::of_device("hx8k-ct256").map(|r| r.luts), Some(7_680));
+        assert_eq!(Resources::of_device("unknown"), None);
+    }
+
+    #[test]
+    fn utilization_of_scarcer_resource() {
+        let used = Resources { flip_flops: 50, luts: 30 };
+        let capacity = Resources { flip_flops: 100, luts: 200 };
+        assert!(used.fits(&capacity));
+        assert_eq!(used.utilization(&capacity), 0.5);
+        assert!(!(used + used + used).fits(&capacity));
+    }
+}
@@ -0,0 +1,581 @@
+//! A behavioural interpreter for the modules of the component library.
+//!
+//! Every cell of the netlist interpreter in [`sim`](super::sim) is an [`Instance`] of its module,
+//! which evaluates the assigns and `always` blocks of `FPGA/src/redstone/components.sv` as written.
+//! Only the subset of Verilog the tick-clocked components use is understood, and signals are
+//! at most 32 bits wide.
+
+use super::sim::{error, mask, tokenize, BinOp, ElaborationError, Token, COMPONENTS_SV};
+use rustc_hash::FxHashMap;
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// A number literal and its width
+    Number(u32, u32),
+    Ident(String),
+    /// `name[bit]`
+    Index(String, Box<Expr>),
+    /// `name[msb:lsb]`
+    Slice(String, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    LogicNot(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `{msb, ..., lsb}`
+    Concat(Vec<Expr>),
+    /// `{count{expr}}`
+    Repeat(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    /// `name = expr` when blocking, `name <= expr` otherwise
+    Assign(String, Expr, bool),
+}
+
+type Range = Option<(Expr, Expr)>;
+
+#[derive(Debug, Clone)]
+enum Item {
+    Port(String, Range),
+    Param(String, Expr),
+    Reg(String, Range, Option<Expr>),
+    Wire(String, Range, Option<Expr>),
+    Assign(String, Expr),
+    Always(Stmt),
+    /// A conditional generate block
+    If(Expr, Vec<Item>, Vec<Item>),
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Token, ElaborationError> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token.map_or_else(|| error("unexpected end of file"), Ok)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ElaborationError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            error(format!("expected '{punct}', found {:?}", self.peek()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ElaborationError> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident.clone()),
+            token => error(format!("expected identifier, found {token:?}")),
+        }
+    }
+
+    fn range(&mut self) -> Result<Range, ElaborationError> {
+        if !self.eat("[") {
+            return Ok(None);
+        }
+        let msb = self.expr(0)?;
+        self.expect(":")?;
+        let lsb = self.expr(0)?;
+        self.expect("]")?;
+        Ok(Some((msb, lsb)))
+    }
+
+    /// Module items up to one of the given keywords, which is consumed
+    fn items(&mut self, end: &[&str]) -> Result<Vec<Item>, ElaborationError> {
+        let mut items = Vec::new();
+        loop {
+            let keyword = self.ident()?;
+            match keyword.as_str() {
+                keyword if end.contains(&keyword) => return Ok(items),
+                "input" | "output" => {
+                    let range = self.range()?;
+                    loop {
+                        items.push(Item::Port(self.ident()?, range.clone()));
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    self.expect(";")?;
+                }
+                "parameter" => {
+                    loop {
+                        let name = self.ident()?;
+                        self.expect("=")?;
+                        items.push(Item::Param(name, self.expr(0)?));
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    self.expect(";")?;
+                }
+                "reg" | "wire" => {
+                    let range = self.range()?;
+                    let name = self.ident()?;
+                    let value = if self.eat("=") { Some(self.expr(0)?) } else { None };
+                    self.expect(";")?;
+                    items.push(if keyword == "reg" {
+                        Item::Reg(name, range, value)
+                    } else {
+                        Item::Wire(name, range, value)
+                    });
+                }
+                "assign" => {
+                    let name = self.ident()?;
+                    self.expect("=")?;
+                    let value = self.expr(0)?;
+                    self.expect(";")?;
+                    items.push(Item::Assign(name, value));
+                }
+                "always" => {
+                    self.expect("@")?;
+                    self.expect("(")?;
+                    if !self.eat_keyword("posedge") || self.ident()? != "i_clk" {
+                        return error("only always @(posedge i_clk) is supported");
+                    }
+                    self.expect(")")?;
+                    items.push(Item::Always(self.stmt()?));
+                }
+                "generate" => items.extend(self.items(&["endgenerate"])?),
+                "if" => items.push(self.generate_if()?),
+                keyword => return error(format!("unsupported module item {keyword}")),
+            }
+        }
+    }
+
+    /// The branches of a generate `if`, whose `if` is already consumed
+    fn generate_if(&mut self) -> Result<Item, ElaborationError> {
+        self.expect("(")?;
+        let cond = self.expr(0)?;
+        self.expect(")")?;
+        let then = self.generate_block()?;
+        let otherwise = if !self.eat_keyword("else") {
+            Vec::new()
+        } else if self.eat_keyword("if") {
+            vec![self.generate_if()?]
+        } else {
+            self.generate_block()?
+        };
+        Ok(Item::If(cond, then, otherwise))
+    }
+
+    fn generate_block(&mut self) -> Result<Vec<Item>, ElaborationError> {
+        if self.ident()? != "begin" {
+            return error("expected begin");
+        }
+        self.items(&["end"])
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, ElaborationError> {
+        let ident = self.ident()?;
+        match ident.as_str() {
+            "begin" => {
+                let mut stmts = Vec::new();
+                while !self.eat_keyword("end") {
+                    stmts.push(self.stmt()?);
+                }
+                Ok(Stmt::Block(stmts))
+            }
+            "if" => {
+                self.expect("(")?;
+                let cond = self.expr(0)?;
+                self.expect(")")?;
+                let then = self.stmt()?;
+                let otherwise = if self.eat_keyword("else") { Some(Box::new(self.stmt()?)) } else { None };
+                Ok(Stmt::If(cond, Box::new(then), otherwise))
+            }
+            _ => {
+                let blocking = if self.eat("=") {
+                    true
+                } else {
+                    self.expect("<=")?;
+                    false
+                };
+                let value = self.expr(0)?;
+                self.expect(";")?;
+                Ok(Stmt::Assign(ident, value, blocking))
+            }
+        }
+    }
+
+    fn expr(&mut self, min_bp: u8) -> Result<Expr, ElaborationError> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Punct("?")) if min_bp == 0 => {
+                    self.pos += 1;
+                    let a = self.expr(0)?;
+                    self.expect(":")?;
+                    let b = self.expr(0)?;
+                    lhs = Expr::Ternary(Box::new(lhs), Box::new(a), Box::new(b));
+                }
+                Some(Token::Punct(punct)) => {
+                    let Some((op, bp)) = BinOp::from_punct(punct) else {
+                        return Ok(lhs);
+                    };
+                    if bp <= min_bp {
+                        return Ok(lhs);
+                    }
+                    self.pos += 1;
+                    let rhs = self.expr(bp)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ElaborationError> {
+        match self.next()? {
+            Token::Punct("(") => {
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct("~") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Punct("!") => Ok(Expr::LogicNot(Box::new(self.unary()?))),
+            Token::Punct("{") => {
+                let first = self.expr(0)?;
+                if self.eat("{") {
+                    let mut parts = vec![self.expr(0)?];
+                    while self.eat(",") {
+                        parts.push(self.expr(0)?);
+                    }
+                    self.expect("}")?;
+                    self.expect("}")?;
+                    return Ok(Expr::Repeat(Box::new(first), Box::new(Expr::Concat(parts))));
+                }
+                let mut parts = vec![first];
+                while self.eat(",") {
+                    parts.push(self.expr(0)?);
+                }
+                self.expect("}")?;
+                Ok(Expr::Concat(parts))
+            }
+            Token::Number(value, width) => Ok(Expr::Number(*value, *width)),
+            Token::Ident(name) => {
+                if !self.eat("[") {
+                    return Ok(Expr::Ident(name.clone()));
+                }
+                let msb = self.expr(0)?;
+                let expr = if self.eat(":") {
+                    Expr::Slice(name.clone(), Box::new(msb), Box::new(self.expr(0)?))
+                } else {
+                    Expr::Index(name.clone(), Box::new(msb))
+                };
+                self.expect("]")?;
+                Ok(expr)
+            }
+            token => error(format!("unexpected {token:?} in expression")),
+        }
+    }
+}
+
+/// A module of the component library, as written
+#[derive(Debug, Clone)]
+pub struct Module {
+    name: String,
+    items: Vec<Item>,
+}
+
+impl Module {
+    /// Parses a module out of `components.sv`
+    pub fn parse(name: &str) -> Result<Module, ElaborationError> {
+        let tokens = tokenize(COMPONENTS_SV)?;
+        let start = tokens
+            .windows(2)
+            .position(|pair| pair[0] == Token::Ident("module".to_owned()) && pair[1] == Token::Ident(name.to_owned()));
+        let Some(start) = start else {
+            return error(format!("module {name} is not in components.sv"));
+        };
+        let mut parser = Parser { tokens: &tokens, pos: start + 2 };
+        // The header only lists the ports, they are declared again below it
+        while !parser.eat(";") {
+            parser.next()?;
+        }
+        let items = parser.items(&["endmodule"])?;
+        Ok(Module { name: name.to_owned(), items })
+    }
+
+    pub fn has_port(&self, name: &str) -> bool {
+        self.items.iter().any(|item| matches!(item, Item::Port(port, _) if port == name))
+    }
+
+    /// Elaborates the module with parameters given in the order they are declared
+    pub fn instantiate(&self, params: &[u32]) -> Result<Instance, ElaborationError> {
+        let mut instance = Instance {
+            name: self.name.clone(),
+            values: FxHashMap::default(),
+            assigns: Vec::new(),
+            always: Vec::new(),
+        };
+        // Parameters first, since the widths declared before them can depend on them
+        let mut given = params.iter();
+        for item in &self.items {
+            if let Item::Param(name, default) = item {
+                let (value, width) = instance.eval(default)?;
+                let value = given.next().map_or(value, |value| value & mask(width));
+                instance.values.insert(name.clone(), (value, width));
+            }
+        }
+        if given.next().is_some() {
+            return error(format!("{} takes {} parameters, {} given", self.name, instance.values.len(), params.len()));
+        }
+        instance.elaborate(&self.items)?;
+        instance.settle()?;
+        Ok(instance)
+    }
+}
+
+/// An elaborated module, holding the value of every signal
+#[derive(Debug, Clone)]
+pub struct Instance {
+    name: String,
+    /// Value and width of the signals and parameters
+    values: FxHashMap<String, (u32, u32)>,
+    assigns: Vec<(String, Expr)>,
+    always: Vec<Stmt>,
+}
+
+impl Instance {
+    /// Name of the module this is an instance of
+    pub fn module(&self) -> &str {
+        &self.name
+    }
+
+    fn elaborate(&mut self, items: &[Item]) -> Result<(), ElaborationError> {
+        for item in items {
+            match item {
+                Item::Param(..) => {}
+                Item::Port(name, range) => {
+                    let width = self.width_of(range)?;
+                    self.values.insert(name.clone(), (0, width));
+                }
+                Item::Reg(name, range, init) => {
+                    let width = self.width_of(range)?;
+                    let value = match init {
+                        Some(init) => self.eval(init)?.0 & mask(width),
+                        None => 0,
+                    };
+                    self.values.insert(name.clone(), (value, width));
+                }
+                Item::Wire(name, range, value) => {
+                    let width = self.width_of(range)?;
+                    self.values.insert(name.clone(), (0, width));
+                    if let Some(value) = value {
+                        self.assigns.push((name.clone(), value.clone()));
+                    }
+                }
+                Item::Assign(name, value) => self.assigns.push((name.clone(), value.clone())),
+                Item::Always(stmt) => self.always.push(stmt.clone()),
+                Item::If(cond, then, otherwise) => {
+                    let branch = if self.eval(cond)?.0 != 0 { then } else { otherwise };
+                    self.elaborate(branch)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn width_of(&self, range: &Range) -> Result<u32, ElaborationError> {
+        match range {
+            Some((msb, lsb)) => {
+                let width = self.eval(msb)?.0.wrapping_sub(self.eval(lsb)?.0).wrapping_add(1);
+                if !(1..=32).contains(&width) {
+                    return error(format!("{}: unsupported width of {width} bits", self.name));
+                }
+                Ok(width)
+            }
+            None => Ok(1),
+        }
+    }
+
+    fn get(&self, name: &str) -> Result<(u32, u32), ElaborationError> {
+        match self.values.get(name) {
+            Some(value) => Ok(*value),
+            None => error(format!("{}: {name} is not declared", self.name)),
+        }
+    }
+
+    /// Value and self-determined width of an expression
+    fn eval(&self, expr: &Expr) -> Result<(u32, u32), ElaborationError> {
+        let (value, width) = match expr {
+            Expr::Number(value, width) => (*value, *width),
+            Expr::Ident(name) => self.get(name)?,
+            Expr::Index(name, bit) => {
+                let (value, _) = self.get(name)?;
+                let bit = self.eval(bit)?.0;
+                (if bit < 32 { value >> bit } else { 0 }, 1)
+            }
+            Expr::Slice(name, msb, lsb) => {
+                let (value, _) = self.get(name)?;
+                let (msb, lsb) = (self.eval(msb)?.0, self.eval(lsb)?.0);
+                if msb < lsb || msb >= 32 {
+                    return error(format!("{}: invalid part select of {name}", self.name));
+                }
+                (value >> lsb, msb - lsb + 1)
+            }
+            Expr::Not(expr) => {
+                let (value, width) = self.eval(expr)?;
+                (!value, width)
+            }
+            Expr::LogicNot(expr) => ((self.eval(expr)?.0 == 0) as u32, 1),
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, a_width) = self.eval(lhs)?;
+                let (b, b_width) = self.eval(rhs)?;
+                let value = match op {
+                    BinOp::Or => a | b,
+                    BinOp::And => a & b,
+                    BinOp::Xor => a ^ b,
+                    BinOp::LogicOr => (a != 0 || b != 0) as u32,
+                    BinOp::LogicAnd => (a != 0 && b != 0) as u32,
+                    BinOp::Eq => (a == b) as u32,
+                    BinOp::Ne => (a != b) as u32,
+                    BinOp::Gt => (a > b) as u32,
+                    BinOp::Ge => (a >= b) as u32,
+                    BinOp::Lt => (a < b) as u32,
+                    BinOp::Le => (a <= b) as u32,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                };
+                (value, if op.is_comparison() { 1 } else { a_width.max(b_width) })
+            }
+            Expr::Ternary(cond, a, b) => {
+                let (a, a_width) = self.eval(a)?;
+                let (b, b_width) = self.eval(b)?;
+                let value = if self.eval(cond)?.0 != 0 { a } else { b };
+                (value, a_width.max(b_width))
+            }
+            Expr::Concat(parts) => {
+                let mut value = 0u32;
+                let mut width = 0;
+                for part in parts {
+                    let (part, part_width) = self.eval(part)?;
+                    value = value.checked_shl(part_width).unwrap_or(0) | part;
+                    width += part_width;
+                }
+                (value, width)
+            }
+            Expr::Repeat(count, expr) => {
+                let count = self.eval(count)?.0;
+                let (part, part_width) = self.eval(expr)?;
+                let value = (0..count).fold(0u32, |value, _| value.checked_shl(part_width).unwrap_or(0) | part);
+                (value, part_width * count)
+            }
+        };
+        Ok((value & mask(width), width))
+    }
+
+    /// Sets a signal without propagating it, returns whether it changed
+    pub fn set(&mut self, name: &str, value: u32) -> Result<bool, ElaborationError> {
+        let Some((old, width)) = self.values.get_mut(name) else {
+            return error(format!("{}: {name} is not declared", self.name));
+        };
+        let value = value & mask(*width);
+        let changed = *old != value;
+        *old = value;
+        Ok(changed)
+    }
+
+    /// Propagates the continuous assigns until every signal is stable
+    pub fn settle(&mut self) -> Result<(), ElaborationError> {
+        let assigns = std::mem::take(&mut self.assigns);
+        let settled = self.settle_assigns(&assigns);
+        self.assigns = assigns;
+        settled
+    }
+
+    fn settle_assigns(&mut self, assigns: &[(String, Expr)]) -> Result<(), ElaborationError> {
+        for _ in 0..=assigns.len() {
+            let mut changed = false;
+            for (name, value) in assigns {
+                let value = self.eval(value)?.0;
+                changed |= self.set(name, value)?;
+            }
+            if !changed {
+                return Ok(());
+            }
+        }
+        error(format!("{}: combinational loop", self.name))
+    }
+
+    fn exec(&mut self, stmt: &Stmt, deferred: &mut Vec<(String, u32)>) -> Result<(), ElaborationError> {
+        match stmt {
+            Stmt::Block(stmts) => {
+                for stmt in stmts {
+                    self.exec(stmt, deferred)?;
+                }
+            }
+            Stmt::If(cond, then, otherwise) => {
+                if self.eval(cond)?.0 != 0 {
+                    self.exec(then, deferred)?;
+                } else if let Some(otherwise) = otherwise {
+                    self.exec(otherwise, deferred)?;
+                }
+            }
+            Stmt::Assign(name, value, blocking) => {
+                let value = self.eval(value)?.0;
+                if *blocking {
+                    self.set(name, value)?;
+                } else {
+                    deferred.push((name.clone(), value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn width(&self, name: &str) -> Result<u32, ElaborationError> {
+        Ok(self.get(name)?.1)
+    }
+
+    pub fn value(&self, name: &str) -> Result<u32, ElaborationError> {
+        Ok(self.get(name)?.0)
+    }
+
+    pub fn set_input(&mut self, name: &str, value: u32) -> Result<(), ElaborationError> {
+        self.set(name, value)?;
+        self.settle()
+    }
+
+    /// A rising edge of `i_clk`
+    pub fn clock(&mut self) -> Result<(), ElaborationError> {
+        let mut deferred = Vec::new();
+        let always = std::mem::take(&mut self.always);
+        let executed = always.iter().try_for_each(|stmt| self.exec(stmt, &mut deferred));
+        self.always = always;
+        executed?;
+        for (name, value) in deferred {
+            self.set(&name, value)?;
+        }
+        self.settle()
+    }
+}
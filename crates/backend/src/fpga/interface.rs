@@ -1,94 +1,520 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
 use std::time::Duration;
 use serialport::SerialPort;
-
-
-
+use super::trace::{TraceState, TraceStatus};
+
+/// Version of the framing spoken by `command_controller.sv`
+pub const PROTOCOL_VERSION: u8 = 0x03;
+/// Marks the start of every frame
+pub const SOF: u8 = 0xA5;
+/// Largest command payload the controller buffers
+pub const MAX_COMMAND_PAYLOAD: usize = 8;
+/// Consecutive frames with a bad checksum after which the controller gives up and enters failsafe
+pub const MAX_BAD_FRAMES: u32 = 4;
+/// How many times a command is resent after a NAK, a timeout or a corrupted response
+pub const MAX_RETRIES: u32 = 3;
+
+/// `command_com` of a board found by probing the serial ports for the design it runs
+pub const AUTO_PORT: &str = "auto";
+/// Baud rate of the command controller's UART
+pub const BAUD_RATE: u32 = 2_500_000;
+
+pub const STATUS_ACK: u8 = 0x06;
+pub const STATUS_NAK: u8 = 0x15;
+pub const STATUS_FAIL: u8 = 0x5A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FPGACommand {
     Reset,
     Ping,
-    GetOutupts, 
+    GetOutputs,
     Capture,
-    SetInputs(u32,u8,u8) ,
-    SetRTPS(u32), 
-    LoadROM(u32,u8), 
+    SetInputs(u32,u8,u8),
+    SetRTPS(u32),
+    LoadROM(u32,u8),
     DebugLED,
-    FailAck,             
+    FailAck,
+    /// Runs the design for a number of ticks on top of its tick rate
+    Step(u32),
+    /// Stops the tick rate and drops any ticks still to be stepped
+    Pause,
+    /// Stops the tick rate and steps the design until its tick counter reaches the given tick
+    RunUntil(u64),
+    /// Reads the tick counter, the number of ticks the design has run since the board was programmed
+    GetTicks,
+    /// Captures the scan chain and reads it back
+    Scan,
+    /// Starts a new trace capture with the next tick
+    TraceArm,
+    /// Reads where the trace buffer's recording stands
+    TraceStatus,
+    /// Reads a row of the trace buffer
+    ReadTrace(u32),
+    /// Reads the hash of the design the board was programmed with
+    Identify,
+    /// Captures the outputs and reads back the bytes that changed since the last time, or all
+    /// of them if set. See [`encode_changes`].
+    GetChanges(bool),
+    /// Queues an input change until the next `CommitInputs`
+    StageInput(u32, u8),
+    /// Applies the queued input changes together on the next tick edge, or at once while the
+    /// design is paused
+    CommitInputs,
+}
+
+impl FPGACommand {
+    pub fn code(&self) -> u8 {
+        match self {
+            FPGACommand::Reset => 0xC0,
+            FPGACommand::Ping => 0xC1,
+            FPGACommand::GetOutputs => 0xC2,
+            FPGACommand::Capture => 0xC3,
+            FPGACommand::SetInputs(..) => 0xC4,
+            FPGACommand::SetRTPS(..) => 0xC5,
+            FPGACommand::LoadROM(..) => 0xC6,
+            FPGACommand::DebugLED => 0xC7,
+            FPGACommand::FailAck => 0xC8,
+            FPGACommand::Step(..) => 0xC9,
+            FPGACommand::Pause => 0xCA,
+            FPGACommand::RunUntil(..) => 0xCB,
+            FPGACommand::GetTicks => 0xCC,
+            FPGACommand::Scan => 0xCD,
+            FPGACommand::TraceArm => 0xCE,
+            FPGACommand::TraceStatus => 0xCF,
+            FPGACommand::ReadTrace(..) => 0xD0,
+            FPGACommand::Identify => 0xD1,
+            FPGACommand::GetChanges(..) => 0xD2,
+            FPGACommand::StageInput(..) => 0xD3,
+            FPGACommand::CommitInputs => 0xD4,
+        }
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        match *self {
+            FPGACommand::SetInputs(id, ty, state) => {
+                let id = id.to_be_bytes();
+                vec![id[1], id[2], id[3], ty, state]
+            }
+            FPGACommand::SetRTPS(value) | FPGACommand::Step(value) => value.to_be_bytes().to_vec(),
+            FPGACommand::RunUntil(tick) => tick.to_be_bytes().to_vec(),
+            FPGACommand::LoadROM(addr, data) | FPGACommand::StageInput(addr, data) => {
+                let addr = addr.to_be_bytes();
+                vec![addr[1], addr[2], addr[3], data]
+            }
+            FPGACommand::ReadTrace(addr) => addr.to_be_bytes()[1..].to_vec(),
+            FPGACommand::GetChanges(full) => vec![full as u8],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Decodes a command sent by the host, the inverse of [`FPGACommand::code`] and [`FPGACommand::payload`]
+    pub fn decode(code: u8, payload: &[u8]) -> Option<FPGACommand> {
+        let u24 = |b: &[u8]| u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        Some(match (code, payload.len()) {
+            (0xC0, 0) => FPGACommand::Reset,
+            (0xC1, 0) => FPGACommand::Ping,
+            (0xC2, 0) => FPGACommand::GetOutputs,
+            (0xC3, 0) => FPGACommand::Capture,
+            (0xC4, 5) => FPGACommand::SetInputs(u24(payload), payload[3], payload[4]),
+            (0xC5, 4) => FPGACommand::SetRTPS(u32::from_be_bytes(payload.try_into().unwrap())),
+            (0xC6, 4) => FPGACommand::LoadROM(u24(payload), payload[3]),
+            (0xC7, 0) => FPGACommand::DebugLED,
+            (0xC8, 0) => FPGACommand::FailAck,
+            (0xC9, 4) => FPGACommand::Step(u32::from_be_bytes(payload.try_into().unwrap())),
+            (0xCA, 0) => FPGACommand::Pause,
+            (0xCB, 8) => FPGACommand::RunUntil(u64::from_be_bytes(payload.try_into().unwrap())),
+            (0xCC, 0) => FPGACommand::GetTicks,
+            (0xCD, 0) => FPGACommand::Scan,
+            (0xCE, 0) => FPGACommand::TraceArm,
+            (0xCF, 0) => FPGACommand::TraceStatus,
+            (0xD0, 3) => FPGACommand::ReadTrace(u24(payload)),
+            (0xD1, 0) => FPGACommand::Identify,
+            (0xD2, 1) => FPGACommand::GetChanges(payload[0] & 1 != 0),
+            (0xD3, 4) => FPGACommand::StageInput(u24(payload), payload[3]),
+            (0xD4, 0) => FPGACommand::CommitInputs,
+            _ => return None,
+        })
+    }
+}
+
+/// A successfully acknowledged command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ack,
+    Pong { version: u8, inputs: u32, outputs: u32 },
+    Outputs(Vec<u8>),
+    Ticks(u64),
+    /// The scan chain, packed like the outputs
+    Scan(Vec<u8>),
+    TraceStatus(TraceStatus),
+    /// A row of the trace buffer, packed like the outputs
+    TraceRow(Vec<u8>),
+    /// The hash of the loaded design, see [`design_hash`]
+    Identity { version: u8, design: u32 },
+    /// The output bytes that changed, as [`encode_changes`] lays them out
+    Changes(Vec<u8>),
+}
+
+impl Response {
+    fn decode(cmd: &FPGACommand, payload: Vec<u8>) -> Result<Response, InterfaceError> {
+        match cmd {
+            FPGACommand::Ping => {
+                if payload.len() != 7 {
+                    return Err(InterfaceError::Malformed("ping response must be 7 bytes"));
+                }
+                Ok(Response::Pong {
+                    version: payload[0],
+                    inputs: u32::from_be_bytes([0, payload[1], payload[2], payload[3]]),
+                    outputs: u32::from_be_bytes([0, payload[4], payload[5], payload[6]]),
+                })
+            }
+            FPGACommand::Identify => match payload[..] {
+                [version, a, b, c, d] => Ok(Response::Identity {
+                    version,
+                    design: u32::from_be_bytes([a, b, c, d]),
+                }),
+                _ => Err(InterfaceError::Malformed("identity must be a version and a 4 byte hash")),
+            },
+            FPGACommand::GetOutputs => Ok(Response::Outputs(payload)),
+            FPGACommand::Scan => Ok(Response::Scan(payload)),
+            FPGACommand::ReadTrace(..) => Ok(Response::TraceRow(payload)),
+            FPGACommand::GetChanges(..) => Ok(Response::Changes(payload)),
+            FPGACommand::TraceStatus => {
+                let u24 = |b: &[u8]| u32::from_be_bytes([0, b[0], b[1], b[2]]);
+                match (payload.len(), payload.first().and_then(|&code| TraceState::from_code(code))) {
+                    (7, Some(state)) => Ok(Response::TraceStatus(TraceStatus {
+                        state,
+                        start: u24(&payload[1..4]),
+                        count: u24(&payload[4..7]),
+                    })),
+                    _ => Err(InterfaceError::Malformed("trace status must be a state and two 3 byte counts")),
+                }
+            }
+            FPGACommand::GetTicks => match payload.try_into() {
+                Ok(ticks) => Ok(Response::Ticks(u64::from_be_bytes(ticks))),
+                Err(_) => Err(InterfaceError::Malformed("tick count must be 8 bytes")),
+            },
+            _ => Ok(Response::Ack),
+        }
+    }
+
+    /// The frame payload carrying this response
+    pub fn payload(&self) -> Vec<u8> {
+        match self {
+            Response::Ack => Vec::new(),
+            Response::Pong { version, inputs, outputs } => {
+                let inputs = inputs.to_be_bytes();
+                let outputs = outputs.to_be_bytes();
+                vec![*version, inputs[1], inputs[2], inputs[3], outputs[1], outputs[2], outputs[3]]
+            }
+            Response::Outputs(outputs) => outputs.clone(),
+            Response::Ticks(ticks) => ticks.to_be_bytes().to_vec(),
+            Response::Scan(scan) => scan.clone(),
+            Response::TraceStatus(status) => {
+                let start = status.start.to_be_bytes();
+                let count = status.count.to_be_bytes();
+                vec![status.state.code(), start[1], start[2], start[3], count[1], count[2], count[3]]
+            }
+            Response::TraceRow(row) | Response::Changes(row) => row.clone(),
+            Response::Identity { version, design } => {
+                let mut payload = vec![*version];
+                payload.extend_from_slice(&design.to_be_bytes());
+                payload
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InterfaceError {
+    /// The serial port was never opened or has been closed
+    NotConnected,
+    Io(io::Error),
+    /// No response arrived in time, even after retrying
+    Timeout,
+    /// The device kept rejecting the command
+    Nak,
+    /// Responses kept arriving with a bad checksum
+    Checksum,
+    Malformed(&'static str),
+    /// The device speaks a different protocol version
+    VersionMismatch(u8),
+    /// The device is in failsafe and did not recover after a `FailAck`
+    Failsafe,
 }
 
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceError::NotConnected => write!(f, "serial port is not connected"),
+            InterfaceError::Io(err) => write!(f, "serial io error: {err}"),
+            InterfaceError::Timeout => write!(f, "device did not respond"),
+            InterfaceError::Nak => write!(f, "device rejected the command"),
+            InterfaceError::Checksum => write!(f, "response checksum mismatch"),
+            InterfaceError::Malformed(reason) => write!(f, "malformed response: {reason}"),
+            InterfaceError::VersionMismatch(version) => write!(
+                f,
+                "device speaks protocol version {version}, expected {PROTOCOL_VERSION}"
+            ),
+            InterfaceError::Failsafe => write!(f, "device is stuck in failsafe"),
+        }
+    }
+}
+
+impl std::error::Error for InterfaceError {}
+
+impl From<io::Error> for InterfaceError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => InterfaceError::Timeout,
+            _ => InterfaceError::Io(err),
+        }
+    }
+}
+
+/// CRC-8 with polynomial 0x07, no reflection and a zero initial value
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        let mut crc = crc ^ byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Lays out the output bytes that differ between two reads as a `GetChanges` response: a mask
+/// with a bit per output byte, LSB first, followed by the bytes whose bit is set in order.
+/// `full` sets every bit, for a host that lost track of the outputs.
+pub fn encode_changes(last: &[u8], outputs: &[u8], full: bool) -> Vec<u8> {
+    let mut mask = vec![0; outputs.len().div_ceil(8)];
+    let mut changed = Vec::new();
+    for (i, (&old, &new)) in last.iter().zip(outputs).enumerate() {
+        if full || old != new {
+            mask[i / 8] |= 1 << (i % 8);
+            changed.push(new);
+        }
+    }
+    mask.extend(changed);
+    mask
+}
+
+/// Applies a `GetChanges` response to the outputs as last read, returning the indices of the
+/// bytes that changed, or `None` if the response does not fit the outputs
+pub fn apply_changes(outputs: &mut [u8], changes: &[u8]) -> Option<Vec<usize>> {
+    let (mask, mut bytes) = changes.split_at_checked(outputs.len().div_ceil(8))?;
+    let mut changed = Vec::new();
+    for (i, output) in outputs.iter_mut().enumerate() {
+        if mask[i / 8] >> (i % 8) & 1 == 1 {
+            let (&byte, rest) = bytes.split_first()?;
+            bytes = rest;
+            if *output != byte {
+                *output = byte;
+                changed.push(i);
+            }
+        }
+    }
+    bytes.is_empty().then_some(changed)
+}
+
+/// Hash identifying a generated design, the FNV-1a hash of its `redstone.sv`. It is baked into
+/// the bitstream and read back with `Identify`, so a board running another design is caught.
+pub fn design_hash(verilog: &[u8]) -> u32 {
+    verilog.iter().fold(0x811C_9DC5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Serial ports a board may be attached to. On Linux the USB serial devices are listed from
+/// `/dev` as well, since enumerating them through udev is not always available.
+pub fn available_ports() -> Vec<String> {
+    let mut ports: Vec<String> = serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|port| port.port_name)
+        .collect();
+    if let Ok(entries) = std::fs::read_dir("/dev") {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with("ttyUSB") || name.starts_with("ttyACM") {
+                ports.push(format!("/dev/{name}"));
+            }
+        }
+    }
+    ports.sort();
+    ports.dedup();
+    ports
+}
+
+/// A frame as sent in either direction:
+/// `SOF, VERSION, SEQ, CODE, LEN_HI, LEN_LO, PAYLOAD[LEN], CRC` where the CRC covers `VERSION..PAYLOAD`.
+/// `code` is the command from the host and the status from the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub version: u8,
+    pub seq: u8,
+    pub code: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(seq: u8, code: u8, payload: Vec<u8>) -> Frame {
+        Frame { version: PROTOCOL_VERSION, seq, code, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let len = (self.payload.len() as u16).to_be_bytes();
+        let mut bytes = vec![SOF, self.version, self.seq, self.code, len[0], len[1]];
+        bytes.extend_from_slice(&self.payload);
+        bytes.push(crc8(&bytes[1..]));
+        bytes
+    }
+
+    /// Reads the next frame without validating it, returning whether its checksum matched.
+    /// Bytes before the start of frame are skipped.
+    pub fn read_raw<R: Read + ?Sized>(reader: &mut R) -> Result<(Frame, bool), InterfaceError> {
+        let mut byte = [0];
+        let mut skipped = 0;
+        loop {
+            reader.read_exact(&mut byte)?;
+            if byte[0] == SOF {
+                break;
+            }
+            skipped += 1;
+            if skipped > 64 {
+                return Err(InterfaceError::Malformed("no start of frame"));
+            }
+        }
+
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload)?;
+        reader.read_exact(&mut byte)?;
+
+        let frame = Frame {
+            version: header[0],
+            seq: header[1],
+            code: header[2],
+            payload,
+        };
+        let crc_ok = crc8(&frame.encode()[1..6 + len]) == byte[0];
+        Ok((frame, crc_ok))
+    }
+
+    /// Reads the next frame, rejecting it if it is corrupted or from another protocol version
+    pub fn read<R: Read + ?Sized>(reader: &mut R) -> Result<Frame, InterfaceError> {
+        let (frame, crc_ok) = Frame::read_raw(reader)?;
+        if frame.version != PROTOCOL_VERSION {
+            return Err(InterfaceError::VersionMismatch(frame.version));
+        }
+        if !crc_ok {
+            return Err(InterfaceError::Checksum);
+        }
+        Ok(frame)
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Interface {
     pub serial_conn: SerialConnection,
-    pub outputs: Vec<u8>,
+    seq: u8,
 }
 
 impl Interface {
-    pub fn new(name: &str, baud: u32, timeout: u32, outputs: usize) -> Interface{
-        Interface{
-            serial_conn: SerialConnection::new(name, baud, timeout), 
-            outputs: Vec::with_capacity(outputs)
+    pub fn new(name: &str, baud: u32, timeout: u32) -> Interface {
+        Interface {
+            serial_conn: SerialConnection::new(name, baud, timeout),
+            seq: 0,
         }
     }
 
-    pub fn serial_start(&mut self, name: &str, baud: u32) {
+    /// Talks to a device over an already open transport
+    pub fn with_transport(transport: impl Transport + 'static) -> Interface {
+        Interface {
+            serial_conn: SerialConnection::from_transport(transport),
+            seq: 0,
+        }
+    }
+
+    pub fn serial_start(&mut self, name: &str, baud: u32) -> bool {
         self.serial_conn = SerialConnection::new(name, baud, 20);
-        self.serial_conn.start();
-    }
-
-    pub fn send_command(&mut self, cmd: FPGACommand) -> bool {
-
-        self.serial_conn.clear_buffer();
-
-        let bytes: Vec<u8> = match cmd {
-            FPGACommand::Reset =>                                
-                vec![0xC0,0,0,0,0,0xA5],
-            FPGACommand::Ping =>                                 
-                vec![0xC1,0,0,0,0,0xA5],
-            FPGACommand::GetOutupts =>                           
-                vec![0xC2,0,0,0,0,0xA5], 
-            FPGACommand::Capture =>                              
-                vec![0xC3,0,0,0,0,0xA5],
-            FPGACommand::SetInputs(id,ty,state)  => 
-                vec![0xC4,
-                    (id>>16 & 0xFF) as u8,
-                    (id>>8 & 0xFF) as u8,
-                    (id & 0xFF) as u8,
-                    ((ty & 0x01 << 7) | (state & 0x0F)) as u8,
-                    0xA5],
-            FPGACommand::SetRTPS(rtps) =>                   
-                vec![0xC5,
-                    (rtps>>24 & 0xFF) as u8,
-                    (rtps>>16 & 0xFF) as u8,
-                    (rtps>>8 & 0xFF) as u8,
-                    (rtps & 0xFF) as u8,
-                    0xA5],
-            FPGACommand::LoadROM(addr,data) =>          
-                vec![0xC6,
-                    (addr>>16 & 0xFF) as u8,
-                    (addr>>8 & 0xFF) as u8,
-                    (addr & 0xFF) as u8,
-                    data,
-                    0xA5],
-            FPGACommand::DebugLED =>                             
-                vec![0xC7,0,0,0,0,0xA5],
-            FPGACommand::FailAck =>                              
-                vec![0xC8,0,0,0,0,0xA5],
-        };
-        self.serial_conn.write(&bytes);
+        self.serial_conn.start()
+    }
 
-        let mut response: Vec<u8> = vec![0; 6];
-        self.serial_conn.read(&mut response); 
+    /// Sends a command and waits for it to be acknowledged.
+    ///
+    /// NAKs, timeouts and corrupted responses are retried with the same sequence number, which the
+    /// device uses to acknowledge a retransmission without executing it twice. A device in failsafe
+    /// is recovered with a `FailAck` before the command is retried.
+    pub fn send_command(&mut self, cmd: FPGACommand) -> Result<Response, InterfaceError> {
+        let frame = Frame::new(self.next_seq(), cmd.code(), cmd.payload());
+        let mut recovered = false;
+        let mut last_err = InterfaceError::Timeout;
+
+        for _ in 0..=MAX_RETRIES {
+            match self.transact(&frame) {
+                Ok(reply) => match reply.code {
+                    STATUS_ACK => return Response::decode(&cmd, reply.payload),
+                    STATUS_NAK => last_err = InterfaceError::Nak,
+                    STATUS_FAIL => {
+                        if cmd == FPGACommand::FailAck || recovered {
+                            return Err(InterfaceError::Failsafe);
+                        }
+                        self.recover()?;
+                        recovered = true;
+                    }
+                    _ => last_err = InterfaceError::Malformed("unknown status"),
+                },
+                Err(err @ (InterfaceError::Timeout | InterfaceError::Checksum | InterfaceError::Malformed(_))) => {
+                    last_err = err;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err)
+    }
 
-        if response != bytes {
-            //println!("CMD doesnt match");
-            return false;
+    /// Reads the hash of the design the device runs
+    pub fn identify(&mut self) -> Result<u32, InterfaceError> {
+        match self.send_command(FPGACommand::Identify)? {
+            Response::Identity { design, .. } => Ok(design),
+            _ => Err(InterfaceError::Malformed("expected an identity")),
         }
+    }
 
-        match cmd {
-            FPGACommand::GetOutupts => 
-                self.serial_conn.read(&mut self.outputs),                      
-            _ => false
-        }        
+    /// Acknowledges the device's failsafe so it resumes accepting commands
+    fn recover(&mut self) -> Result<(), InterfaceError> {
+        let frame = Frame::new(self.next_seq(), FPGACommand::FailAck.code(), Vec::new());
+        for _ in 0..=MAX_RETRIES {
+            match self.transact(&frame) {
+                Ok(reply) if reply.code == STATUS_ACK => return Ok(()),
+                Ok(_) | Err(InterfaceError::Timeout | InterfaceError::Checksum | InterfaceError::Malformed(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Err(InterfaceError::Failsafe)
+    }
+
+    /// Writes a frame and reads its response, skipping stale responses to earlier frames
+    fn transact(&mut self, frame: &Frame) -> Result<Frame, InterfaceError> {
+        let conn = self.serial_conn.transport()?;
+        conn.clear_input()?;
+        conn.write_all(&frame.encode())?;
+        conn.flush()?;
+        loop {
+            let reply = Frame::read(conn)?;
+            if reply.seq == frame.seq {
+                return Ok(reply);
+            }
+        }
+    }
+
+    fn next_seq(&mut self) -> u8 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
     }
 
     pub fn stop(&mut self) {
@@ -104,19 +530,25 @@ pub struct BinaryIterator {
 
 impl BinaryIterator {
     pub fn new (buffer: Vec<u8>) -> BinaryIterator {
-        BinaryIterator{ 
+        BinaryIterator{
             data: (buffer),
-            index: (0) 
+            index: (0)
         }
     }
 
+    /// Reads the next `size` bits as a number, least significant bit first
     pub fn next (&mut self, size: u8) -> Option<u8> {
-        
+
         let len = self.data.len() * 8;
         let res = if self.index + size as usize > len {
             None
         } else {
-            Some((self.data[self.index/8] >> (self.index % 8)) & 0x01)
+            let mut value = 0;
+            for bit in 0..size as usize {
+                let index = self.index + bit;
+                value |= ((self.data[index / 8] >> (index % 8)) & 0x01) << bit;
+            }
+            Some(value)
         };
 
         self.index += size as usize;
@@ -124,55 +556,96 @@ impl BinaryIterator {
     }
 }
 
+/// A byte stream to the device's command controller
+pub trait Transport: Read + Write + Send + fmt::Debug {
+    /// Discards anything received but not yet read
+    fn clear_input(&mut self) -> io::Result<()>;
+}
 
+impl Transport for Box<dyn SerialPort> {
+    fn clear_input(&mut self) -> io::Result<()> {
+        Ok(self.clear(serialport::ClearBuffer::Input)?)
+    }
+}
+
+impl Transport for TcpStream {
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.set_nonblocking(true)?;
+        let mut buf = [0; 256];
+        let cleared = loop {
+            match self.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => (),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.set_nonblocking(false)?;
+        cleared
+    }
+}
+
+/// A connection to the command controller, either a serial port or a `tcp://host:port` address
 #[derive(Default, Debug)]
 pub struct SerialConnection {
     port_name: String,
     baud_rate: u32,
     timeout: u32,
-    conn: Option<Box<dyn SerialPort>>,
-
+    conn: Option<Box<dyn Transport>>,
 }
 
 impl SerialConnection {
     pub fn new (name: &str, baud: u32, timeout: u32) -> SerialConnection {
-        SerialConnection{port_name: name.to_string(), baud_rate: baud, timeout: timeout, conn: None}
+        SerialConnection{port_name: name.to_string(), baud_rate: baud, timeout, conn: None}
+    }
+
+    pub fn from_transport (transport: impl Transport + 'static) -> SerialConnection {
+        SerialConnection{conn: Some(Box::new(transport)), ..Default::default()}
     }
 
     pub fn start (&mut self) -> bool{
+        let timeout = Duration::from_millis(self.timeout as u64);
+        if let Some(addr) = self.port_name.strip_prefix("tcp://") {
+            self.conn = TcpStream::connect(addr)
+                .and_then(|stream| {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_nodelay(true)?;
+                    Ok(stream)
+                })
+                .ok()
+                .map(|stream| Box::new(stream) as Box<dyn Transport>);
+            return self.conn.is_some();
+        }
+
         self.conn = serialport::new(&self.port_name, self.baud_rate)
-            .timeout(Duration::from_millis(self.timeout as u64))
+            .timeout(timeout)
             .parity(serialport::Parity::None)
             .data_bits(serialport::DataBits::Eight)
             .stop_bits(serialport::StopBits::One)
             .open()
-            .ok();
+            .ok()
+            .map(|port| Box::new(port) as Box<dyn Transport>);
 
-        !self.conn.is_none()
+        self.conn.is_some()
     }
 
     pub fn stop (&mut self) {
-        drop(self.conn.as_mut().unwrap())
+        self.conn = None;
     }
 
-    pub fn read (&mut self, buffer: &mut Vec<u8>) -> bool {
-        self.conn.as_mut().unwrap().read_exact(buffer).is_ok()
+    pub fn is_connected (&self) -> bool {
+        self.conn.is_some()
     }
 
-    pub fn clear_buffer (&mut self) -> bool {
-        self.conn.as_mut().unwrap().clear(serialport::ClearBuffer::Input).is_ok()
-    }
-
-    pub fn write (&mut self, data: &Vec<u8>) -> bool {
-        self.conn.as_mut().unwrap().write(data).is_ok()
-    }
-
-    pub fn write_byte (&mut self, data: u8) -> bool {
-        self.conn.as_mut().unwrap().write(&vec![data]).is_ok()
+    fn transport (&mut self) -> Result<&mut dyn Transport, InterfaceError> {
+        match &mut self.conn {
+            Some(conn) => Ok(conn.as_mut()),
+            None => Err(InterfaceError::NotConnected),
+        }
     }
 }
 
-#[derive(Default, Clone, Copy, PartialEq)]
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub enum DeviceStatus {
     #[default]
     Inactive,
@@ -192,4 +665,4 @@ impl DeviceStatus {
             DeviceStatus::Failed => "&cStopped",
         }
     }
-}
\ No newline at end of file
+}
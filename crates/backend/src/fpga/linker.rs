@@ -1,29 +1,70 @@
 use std::{collections::HashMap, fs::File, io::Write, path::Path};
 
-use crate::fpga::{compiler::DeviceConfig, interface::{BinaryIterator, Interface}, FPGABackend};
+use crate::fpga::{estimate::Resources, interface::BinaryIterator, partition::CutLink, pins::PinLink, trace::TraceLayout};
 use mchprs_blocks::{blocks::{Block, ButtonFace, Lever, LeverFace, RedstoneWire, RedstoneWireSide, StoneButton, TrapdoorHalf}, BlockDirection, BlockPos};
+use mchprs_redpiler::block_powered_mut;
+use mchprs_redpiler::compile_graph::Annotations;
+use mchprs_redstone::comparator;
+use mchprs_world::World;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
+use tracing::warn;
 
-impl FPGABackend {
-    pub fn from_link_file(link: Linker, path: String, config: DeviceConfig) -> FPGABackend {
-        FPGABackend { 
-            fpga: Default::default(),
-            path: path,
-            config: config,
-            link: link
-        }
-    }
-}
-
-
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Linker {
     pub name: String,
+    /// Board the build was compiled for
+    #[serde(default)]
+    pub device: String,
     pub outputs: Vec<IntfBlock>,
     pub output_bits: u32,
     pub inputs: Vec<IntfBlock>,
     pub input_bits: u32,
+    /// Input bits after the world's ones, carrying nets from other boards
+    #[serde(default)]
+    pub imported_bits: u32,
+    /// Output bits after the world's ones, carrying nets to other boards
+    #[serde(default)]
+    pub exported_bits: u32,
+    /// Links of the boards a partitioned build is split across, each built in `p<index>`
+    #[serde(default)]
+    pub partitions: Vec<Linker>,
+    /// Nets carried between the partitions
+    #[serde(default)]
+    pub cuts: Vec<CutLink>,
+    /// Logic the board's share of the build was estimated to need
+    #[serde(default)]
+    pub estimate: Resources,
+    /// ROM cells in address order, loaded into the design when the build runs
+    #[serde(default)]
+    pub roms: Vec<IntfBlock>,
+    /// Components on the scan chain in chain order, holding their state as last read back
+    #[serde(default)]
+    pub scan: Vec<IntfBlock>,
+    /// Nodes the board records into its trace buffer
+    #[serde(default)]
+    pub trace: Option<TraceLayout>,
+    /// Board peripherals bound to components, inputs first
+    #[serde(default)]
+    pub pins: Vec<PinLink>,
+    /// Hash of the generated design the board has to identify with, `None` for builds from
+    /// before boards could be identified
+    #[serde(default)]
+    pub design_hash: Option<u32>,
+    /// Components annotated by signs, in the order they were compiled
+    #[serde(default)]
+    pub annotations: Vec<AnnotatedBlock>,
+    /// Inputs changed since the blocks to change were last collected
+    #[serde(skip)]
+    pub(crate) changed_inputs: Vec<usize>,
+}
+
+/// A component annotated by signs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnnotatedBlock {
+    pub pos: BlockPos,
+    #[serde(flatten)]
+    pub annotations: Annotations,
 }
 
 impl Linker {
@@ -43,7 +84,14 @@ impl Linker {
 
     pub fn add_block(&mut self, block: Block, pos: BlockPos) {
         if let Some(intf) = IntfBlock::new(block, pos) {
-            if IntfBlock::is_input(block) {
+            if let IntfType::Button { .. } = intf.ty {
+                // Presses flip the input bit, the board times the pulse and drives the button back as an output
+                self.input_bits += 1;
+                self.inputs.push(IntfBlock { state: 0, ..intf.clone() });
+                self.output_bits += 1;
+                self.outputs.push(intf);
+            }
+            else if IntfBlock::is_input(block) {
                 self.input_bits += intf.bit_count() as u32;
                 self.inputs.push(intf);
             }
@@ -54,6 +102,84 @@ impl Linker {
         }
     }
 
+    /// Records what the signs next to a component say about it
+    pub fn annotate(&mut self, pos: BlockPos, annotations: &Annotations) {
+        if !annotations.is_empty() {
+            self.annotations.push(AnnotatedBlock { pos, annotations: annotations.clone() });
+        }
+    }
+
+    /// Adds the next ROM cell, stored as one bit reading as `on` or as a full signal strength
+    pub fn add_rom(&mut self, block: Block, pos: BlockPos, ss: u8, on: Option<u8>) {
+        let ty = match on {
+            Some(on) => IntfType::BinROM { block: block.get_id(), on },
+            None => IntfType::HexROM { block: block.get_id() },
+        };
+        let mut rom = IntfBlock { ty, pos, state: 0 };
+        rom.set_rom(ss);
+        self.roms.push(rom);
+    }
+
+    /// Adds the next stage of the scan chain
+    pub fn add_scan(&mut self, block: Block, pos: BlockPos) {
+        let ty = match block {
+            Block::RedstoneRepeater { .. } => IntfType::Repeater { block: block.get_id() },
+            Block::RedstoneTorch { .. } | Block::RedstoneWallTorch { .. } => IntfType::Torch { block: block.get_id() },
+            Block::RedstoneComparator { .. } => IntfType::Comparator { block: block.get_id() },
+            _ => {
+                warn!("{:?} at {} has no scan stage", block, pos);
+                return;
+            }
+        };
+        self.scan.push(IntfBlock { ty, pos, state: 0 });
+    }
+
+    /// Length of the scan chain
+    pub fn scan_bits(&self) -> u32 {
+        self.scan.iter().map(|stage| stage.bit_count() as u32).sum()
+    }
+
+    /// Stores the state shifted out of the scan chain and returns the blocks showing it
+    pub fn read_scan(&mut self, data: &mut BinaryIterator) -> Vec<(BlockPos, Block)> {
+        let mut res = Vec::with_capacity(self.scan.len());
+        for stage in &mut self.scan {
+            stage.set_state(data.next(stage.bit_count()).unwrap_or(0));
+            res.push((stage.pos, stage.get_block()));
+        }
+        res
+    }
+
+    /// Output strength of every comparator on the scan chain as last read back
+    pub fn comparator_outputs(&self) -> Vec<(BlockPos, u8)> {
+        self.scan
+            .iter()
+            .filter(|stage| matches!(stage.ty, IntfType::Comparator { .. }))
+            .map(|stage| (stage.pos, stage.state))
+            .collect()
+    }
+
+    /// The scan stage of the component at a position
+    pub fn scan_stage(&self, pos: BlockPos) -> Option<&IntfBlock> {
+        self.scan.iter().find(|stage| stage.pos == pos)
+    }
+
+    /// Rereads the ROM cells from the containers in the world, so the next run loads what they hold now
+    pub fn read_roms<W: World>(&mut self, world: &W) {
+        for rom in &mut self.roms {
+            let block = world.get_block(rom.pos);
+            if comparator::has_override(block) {
+                rom.set_rom(comparator::get_override(block, world, rom.pos));
+            } else {
+                warn!("ROM cell at {} is no longer a container, keeping its last contents", rom.pos);
+            }
+        }
+    }
+
+    /// Address and data of every ROM cell, as sent with `LoadROM`
+    pub fn rom_contents(&self) -> Vec<(u32, u8)> {
+        self.roms.iter().enumerate().map(|(addr, rom)| (addr as u32, rom.state)).collect()
+    }
+
     pub fn get_output_bytes(&self) -> usize {
         ((self.output_bits + 7) / 8 )as usize
     }
@@ -62,18 +188,59 @@ impl Linker {
         ((self.output_bits + 7) / 8) as usize
     }
 
-    pub fn toggle_input(&mut self, pos: BlockPos) -> (u32, u8, u8) {
+    /// Boards the build was compiled for, one per partition
+    pub fn devices(&self) -> Vec<String> {
+        if self.partitions.is_empty() {
+            vec![self.device.clone()]
+        } else {
+            self.partitions.iter().map(|link| link.device.clone()).collect()
+        }
+    }
+
+    pub fn has_input(&self, pos: BlockPos) -> bool {
+        self.inputs.iter().any(|input| input.pos == pos)
+    }
+
+    pub fn toggle_input(&mut self, pos: BlockPos) -> Option<(u32, u8, u8)> {
         let mut id = 0;
-        for input in &mut self.inputs {
+        for (idx, input) in self.inputs.iter_mut().enumerate() {
             if input.pos == pos {
                 input.set_state(!input.state);
-                return (id, 0, input.state);
+                self.changed_inputs.push(idx);
+                return Some((id, 0, input.state));
             }
             id += input.bit_count() as u32;
         }
-        (0,0,0)
+        None
     }
 
+    /// Sets an input to the given state, returning its id
+    pub fn set_input(&mut self, pos: BlockPos, state: u8) -> Option<u32> {
+        let mut id = 0;
+        for (idx, input) in self.inputs.iter_mut().enumerate() {
+            if input.pos == pos {
+                input.set_state(state);
+                self.changed_inputs.push(idx);
+                return Some(id);
+            }
+            id += input.bit_count() as u32;
+        }
+        None
+    }
+
+    /// The state of every input bit, in the order they are wired into the design
+    pub fn input_states(&self) -> Vec<bool> {
+        let mut states = Vec::new();
+        for input in &self.inputs {
+            for bit in 0..input.bit_count() {
+                states.push((input.state >> bit) & 1 == 1);
+            }
+        }
+        states
+    }
+
+    /// Stores the outputs read from the board, returning the blocks of the outputs whose state
+    /// changed and of the inputs changed since the last call
     pub fn get_blocks_to_change(&mut self, data: &mut BinaryIterator) -> Vec<(BlockPos, Block)> {
         let mut res: Vec<(BlockPos, Block)> = Vec::new();
         for output in &mut self.outputs {
@@ -83,17 +250,23 @@ impl Linker {
                 }
                 None => 0,
             };
+            let last = output.state;
             output.set_state(state);
-            res.push((output.pos, output.get_block()));
+            if output.state != last {
+                res.push((output.pos, output.get_block()));
+            }
         }
-        for input in &self.inputs {
-            res.push((input.pos, input.get_block()));
+        for idx in self.changed_inputs.drain(..) {
+            let input = &self.inputs[idx];
+            if !matches!(input.ty, IntfType::Button { .. }) {
+                res.push((input.pos, input.get_block()));
+            }
         }
         res
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntfBlock {
     ty: IntfType,
     pos: BlockPos,
@@ -150,10 +323,14 @@ impl IntfBlock {
             IntfType::Lever {..} | 
             IntfType::PressurePlate | 
             IntfType::Button {..} |
-            IntfType::BinROM => 
+            IntfType::BinROM {..} |
+            IntfType::Torch {..} => 
                 1,
+            IntfType::Repeater {..} =>
+                2,
             IntfType::HexLamp |
-            IntfType::HexROM => 
+            IntfType::HexROM {..} |
+            IntfType::Comparator {..} => 
                 4,
         }
     }
@@ -165,10 +342,14 @@ impl IntfBlock {
             IntfType::Lever {..} | 
             IntfType::PressurePlate | 
             IntfType::Button {..} | 
-            IntfType::BinROM => 
+            IntfType::BinROM {..} |
+            IntfType::Torch {..} => 
                 state & 0x01,
-            IntfType::HexROM | 
-            IntfType::HexLamp =>
+            IntfType::Repeater {..} =>
+                state & 0x03,
+            IntfType::HexROM {..} | 
+            IntfType::HexLamp |
+            IntfType::Comparator {..} =>
                 state & 0x0F,
         };
         self.state = state;
@@ -190,10 +371,43 @@ impl IntfBlock {
                 Block::StoneButton { button: StoneButton { face:f, facing:fa, powered:self.state == 1 } },
             IntfType::PressurePlate =>
                 Block::StonePressurePlate { powered:self.state == 1 },
-            IntfType::BinROM =>
-                todo!("BinROM"),
-            IntfType::HexROM =>
-                todo!("BinROM"),
+            IntfType::BinROM { block, .. } |
+            IntfType::HexROM { block } =>
+                Block::from_id(block),
+            IntfType::Repeater { block } |
+            IntfType::Torch { block } |
+            IntfType::Comparator { block } => {
+                let mut block = Block::from_id(block);
+                let lit = match self.ty {
+                    IntfType::Comparator { .. } => self.state != 0,
+                    _ => self.state & 0x01 == 1,
+                };
+                if let Some(powered) = block_powered_mut(&mut block) {
+                    *powered = lit;
+                }
+                if let Block::RedstoneRepeater { repeater } = &mut block {
+                    repeater.locked = self.state & 0x02 != 0;
+                }
+                block
+            }
+        }
+    }
+
+    /// State as last read back from the board, the signal strength for comparators
+    pub fn state(&self) -> u8 {
+        self.state
+    }
+
+    /// Stores the signal strength a ROM cell's container gives off
+    fn set_rom(&mut self, ss: u8) {
+        match self.ty {
+            IntfType::BinROM { on, .. } => {
+                if ss != 0 && ss != on {
+                    warn!("ROM cell at {} gives off {} but its region stores only 0 and {}, reading it as {}", self.pos, ss, on, on);
+                }
+                self.set_state((ss != 0) as u8);
+            }
+            _ => self.set_state(ss),
         }
     }
 
@@ -212,7 +426,7 @@ impl IntfBlock {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum IntfType {
     Lamp,
     Trapdoor {facing: BlockDirection, half: TrapdoorHalf},
@@ -220,6 +434,14 @@ enum IntfType {
     Lever {face: LeverFace, facing: BlockDirection},
     Button {face: ButtonFace, facing: BlockDirection},
     PressurePlate,
-    BinROM, //TODO
-    HexROM, //TODO
+    /// A container read as a ROM cell holding a single bit, which reads as the signal strength `on`
+    BinROM {block: u32, on: u8},
+    /// A container read as a ROM cell holding a signal strength
+    HexROM {block: u32},
+    /// A repeater on the scan chain, one bit for its output and one for its lock
+    Repeater {block: u32},
+    /// A torch on the scan chain
+    Torch {block: u32},
+    /// A comparator on the scan chain holding its output strength
+    Comparator {block: u32},
 }
\ No newline at end of file
@@ -0,0 +1,479 @@
+//! An in-process stand-in for the board's `command_controller.sv`, used to exercise [`Interface`]
+//! without hardware.
+//!
+//! With a design loaded the mock is a complete software board: [`MockServer`] serves the command
+//! protocol over TCP and evaluates the generated Verilog in the [`Netlist`] interpreter at the
+//! requested tick rate.
+//!
+//! [`Interface`]: crate::fpga::interface::Interface
+
+use crate::fpga::sim::{self, Netlist};
+use crate::fpga::interface::{
+    self, FPGACommand, Frame, InterfaceError, Response, Transport, MAX_BAD_FRAMES, MAX_COMMAND_PAYLOAD,
+    PROTOCOL_VERSION, STATUS_ACK, STATUS_FAIL, STATUS_NAK,
+};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a served connection waits for a command before advancing the design
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Upper bound on the ticks run at once after the emulator was left idle
+const MAX_CATCHUP_TICKS: f64 = 100_000.0;
+
+/// One end of an in-memory serial link created by [`loopback`]
+#[derive(Debug)]
+pub struct LoopbackPort {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    buffer: VecDeque<u8>,
+    timeout: Duration,
+}
+
+/// Creates a connected pair of ports. Reads time out like a real serial port after `timeout`
+/// and report end of file once the other end is dropped.
+pub fn loopback(timeout: Duration) -> (LoopbackPort, LoopbackPort) {
+    let (a_tx, a_rx) = mpsc::channel();
+    let (b_tx, b_rx) = mpsc::channel();
+    let port = |tx, rx| LoopbackPort {
+        tx,
+        rx,
+        buffer: VecDeque::new(),
+        timeout,
+    };
+    (port(a_tx, b_rx), port(b_tx, a_rx))
+}
+
+impl Read for LoopbackPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            match self.rx.recv_timeout(self.timeout) {
+                Ok(data) => self.buffer.extend(data),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "loopback read timed out"))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for LoopbackPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for LoopbackPort {
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        loop {
+            match self.rx.try_recv() {
+                Ok(_) => (),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Misbehaviour injected into the next frame the mock receives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Treat the command as if it arrived with a bad checksum
+    CorruptCommand,
+    /// Execute the command but flip a bit of the response after its checksum is computed
+    CorruptResponse,
+    /// Execute the command but never respond
+    DropResponse,
+    /// Enter failsafe before looking at the command
+    EnterFailsafe,
+}
+
+/// Registers of the mocked controller, shared with the test driving it
+#[derive(Debug, Default)]
+pub struct MockState {
+    pub inputs: Vec<bool>,
+    /// Inputs as they will be once the staged changes are committed
+    pub staged: Vec<bool>,
+    /// Whether committed changes wait for the next tick
+    commit_pending: bool,
+    /// Live outputs of the design, latched by `Capture`
+    pub outputs: Vec<bool>,
+    pub captured: Vec<u8>,
+    /// Outputs as `GetChanges` last read them, and as the read before that
+    reported: Vec<u8>,
+    changes_base: Vec<u8>,
+    changes_full: bool,
+    pub tps: u32,
+    pub failsafe: bool,
+    /// Commands executed, not counting acknowledged retransmissions
+    pub executed: u32,
+    pub faults: VecDeque<Fault>,
+    /// Redstone ticks run by the loaded design
+    pub ticks: u64,
+    /// Hash the loaded design answers `Identify` with
+    pub design_hash: u32,
+    bad_frames: u32,
+    last_seq: Option<u8>,
+    design: Option<Netlist>,
+    last_advance: Option<Instant>,
+    tick_carry: f64,
+}
+
+impl MockState {
+    pub fn new(inputs: usize, outputs: usize) -> MockState {
+        MockState {
+            inputs: vec![false; inputs],
+            staged: vec![false; inputs],
+            outputs: vec![false; outputs],
+            captured: vec![0; outputs.div_ceil(8)],
+            reported: vec![0; outputs.div_ceil(8)],
+            changes_base: vec![0; outputs.div_ceil(8)],
+            ..Default::default()
+        }
+    }
+
+    /// Programs the mock with a design, resetting it like a freshly configured board
+    pub fn load(&mut self, netlist: Netlist) {
+        *self = MockState::new(netlist.inputs(), netlist.outputs().len());
+        self.outputs = netlist.outputs().to_vec();
+        self.design = Some(netlist);
+    }
+
+    /// Runs the design for the ticks that are due at `tps` since the last call.
+    /// Like the hardware, the design stands still while the controller is in failsafe.
+    pub fn advance(&mut self, now: Instant) {
+        let elapsed = match self.last_advance.replace(now) {
+            Some(last) => now.saturating_duration_since(last),
+            None => return,
+        };
+        if self.design.is_none() || self.failsafe || self.tps == 0 {
+            return;
+        }
+
+        let due = (self.tick_carry + elapsed.as_secs_f64() * self.tps as f64).min(MAX_CATCHUP_TICKS);
+        let ticks = due.floor();
+        self.tick_carry = due - ticks;
+        if ticks >= 1.0 && self.commit_pending {
+            self.apply_staged();
+        }
+        let Some(design) = &mut self.design else {
+            return;
+        };
+        for _ in 0..ticks as u64 {
+            design.tick();
+        }
+        self.ticks += ticks as u64;
+        self.outputs.copy_from_slice(design.outputs());
+    }
+
+    /// Applies the committed input changes all at once
+    fn apply_staged(&mut self) {
+        self.commit_pending = false;
+        for id in 0..self.staged.len() {
+            if self.inputs[id] != self.staged[id] {
+                self.set_input(id, self.staged[id]);
+            }
+        }
+    }
+
+    /// Runs the design for a number of ticks right away
+    fn step(&mut self, ticks: u64) {
+        if ticks > 0 && self.commit_pending {
+            self.apply_staged();
+        }
+        if let Some(design) = &mut self.design {
+            for _ in 0..ticks {
+                design.tick();
+            }
+            self.ticks += ticks;
+            self.outputs.copy_from_slice(design.outputs());
+        }
+    }
+
+    fn set_input(&mut self, id: usize, value: bool) {
+        if let Some(input) = self.inputs.get_mut(id) {
+            *input = value;
+            self.staged[id] = value;
+            if let Some(design) = &mut self.design {
+                design.set_input(id, value);
+                self.outputs.copy_from_slice(design.outputs());
+            }
+        }
+    }
+
+    /// Handles one received frame the way `command_controller.sv` does, returning the response
+    fn handle(&mut self, frame: &Frame, crc_ok: bool) -> Frame {
+        let reply = |status, payload| Frame::new(frame.seq, status, payload);
+
+        if frame.version != PROTOCOL_VERSION || frame.payload.len() > MAX_COMMAND_PAYLOAD {
+            self.failsafe = true;
+            return reply(STATUS_FAIL, Vec::new());
+        }
+        if !crc_ok {
+            self.bad_frames += 1;
+            if self.bad_frames >= MAX_BAD_FRAMES {
+                self.failsafe = true;
+                return reply(STATUS_FAIL, Vec::new());
+            }
+            return reply(STATUS_NAK, Vec::new());
+        }
+        self.bad_frames = 0;
+
+        let Some(cmd) = FPGACommand::decode(frame.code, &frame.payload) else {
+            return reply(if self.failsafe { STATUS_FAIL } else { STATUS_NAK }, Vec::new());
+        };
+        if self.failsafe {
+            if cmd != FPGACommand::FailAck {
+                return reply(STATUS_FAIL, Vec::new());
+            }
+            self.failsafe = false;
+            self.last_seq = None;
+            return reply(STATUS_ACK, Vec::new());
+        }
+
+        if self.last_seq != Some(frame.seq) {
+            self.execute(cmd);
+            self.last_seq = Some(frame.seq);
+        }
+        let response = match cmd {
+            FPGACommand::Ping => Response::Pong {
+                version: PROTOCOL_VERSION,
+                inputs: self.inputs.len() as u32,
+                outputs: self.outputs.len() as u32,
+            },
+            FPGACommand::GetOutputs => Response::Outputs(self.captured.clone()),
+            // Answered from registers only the command sets, so a retransmission gets the same changes
+            FPGACommand::GetChanges(..) => Response::Changes(interface::encode_changes(&self.changes_base, &self.reported, self.changes_full)),
+            FPGACommand::GetTicks => Response::Ticks(self.ticks),
+            FPGACommand::Identify => Response::Identity {
+                version: PROTOCOL_VERSION,
+                design: self.design_hash,
+            },
+            FPGACommand::Scan => Response::Scan(self.design.as_mut().map_or_else(Vec::new, |design| sim::pack(&design.scan()))),
+            FPGACommand::TraceStatus => Response::TraceStatus(self.design.as_ref().map_or_else(Default::default, |design| design.trace_status())),
+            FPGACommand::ReadTrace(addr) => Response::TraceRow(self.design.as_ref().map_or_else(|| vec![0], |design| design.trace_row(addr))),
+            _ => Response::Ack,
+        };
+        reply(STATUS_ACK, response.payload())
+    }
+
+    fn execute(&mut self, cmd: FPGACommand) {
+        self.executed += 1;
+        match cmd {
+            FPGACommand::Reset => {
+                for id in 0..self.inputs.len() {
+                    self.set_input(id, false);
+                }
+            }
+            FPGACommand::Capture => self.captured = sim::pack(&self.outputs),
+            FPGACommand::GetChanges(full) => {
+                self.changes_base = std::mem::replace(&mut self.reported, sim::pack(&self.outputs));
+                self.changes_full = full;
+            }
+            FPGACommand::SetInputs(id, _, state) => self.set_input(id as usize, state & 1 != 0),
+            FPGACommand::StageInput(id, state) => {
+                if let Some(staged) = self.staged.get_mut(id as usize) {
+                    *staged = state & 1 != 0;
+                }
+            }
+            FPGACommand::CommitInputs => {
+                self.commit_pending = true;
+                if self.tps == 0 {
+                    self.apply_staged();
+                }
+            }
+            FPGACommand::SetRTPS(rtps) => self.tps = rtps,
+            FPGACommand::Step(ticks) => self.step(ticks as u64),
+            FPGACommand::Pause => self.tps = 0,
+            FPGACommand::RunUntil(tick) => {
+                self.tps = 0;
+                self.step(tick.saturating_sub(self.ticks));
+            }
+            FPGACommand::TraceArm => {
+                if let Some(design) = &mut self.design {
+                    design.arm_trace();
+                }
+            }
+            FPGACommand::LoadROM(addr, data) => {
+                if let Some(design) = &mut self.design {
+                    design.load_rom(addr, data);
+                    self.outputs.copy_from_slice(design.outputs());
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A mocked controller serving one end of a link on its own thread
+pub struct MockController {
+    pub state: Arc<Mutex<MockState>>,
+    thread: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockController {
+    /// Serves `port` until the other end of the link is dropped
+    pub fn spawn(port: impl Transport + 'static, state: MockState) -> MockController {
+        MockController::serve(port, Arc::new(Mutex::new(state)))
+    }
+
+    /// Serves `port` with registers that may be shared with other connections
+    pub fn serve(mut port: impl Transport + 'static, state: Arc<Mutex<MockState>>) -> MockController {
+        let thread_state = state.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || loop {
+            let received = Frame::read_raw(&mut port);
+            if thread_stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut state = thread_state.lock().unwrap();
+            state.advance(Instant::now());
+            let (frame, crc_ok) = match received {
+                Ok(frame) => frame,
+                Err(InterfaceError::Timeout | InterfaceError::Malformed(_)) => continue,
+                Err(_) => return,
+            };
+
+            let fault = state.faults.pop_front();
+            if fault == Some(Fault::EnterFailsafe) {
+                state.failsafe = true;
+            }
+            let reply = state.handle(&frame, crc_ok && fault != Some(Fault::CorruptCommand));
+            drop(state);
+
+            let mut bytes = reply.encode();
+            match fault {
+                Some(Fault::DropResponse) => continue,
+                Some(Fault::CorruptResponse) => bytes[3] ^= 0x01,
+                _ => (),
+            }
+            if port.write_all(&bytes).is_err() {
+                return;
+            }
+        });
+        MockController {
+            state,
+            thread: Some(thread),
+            stop,
+        }
+    }
+
+    /// Waits for the host to hang up
+    pub fn join(mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+
+    /// Hangs up on the host once the current read times out, and waits for the thread to end
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+}
+
+/// A software board listening for hosts on a TCP socket. Dropping it closes the socket and hangs
+/// up on every connected host.
+#[derive(Debug)]
+pub struct MockServer {
+    pub state: Arc<Mutex<MockState>>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Starts accepting connections. Every connection talks to the same board.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<MockServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server_state = state.clone();
+        let server_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut connections: Vec<MockController> = Vec::new();
+            for stream in listener.incoming() {
+                if server_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                if stream.set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+                    continue;
+                }
+                _ = stream.set_nodelay(true);
+                connections.retain(|connection| !connection.is_finished());
+                connections.push(MockController::serve(stream, server_state.clone()));
+            }
+            for connection in connections {
+                connection.stop();
+            }
+        });
+        Ok(MockServer {
+            state,
+            addr,
+            thread: Some(thread),
+            stop,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Programs the board with a design
+    pub fn load(&self, netlist: Netlist) {
+        let mut state = self.state.lock().unwrap();
+        state.load(netlist);
+        state.advance(Instant::now());
+    }
+
+    /// Sets the hash the board answers `Identify` with, as baked into its bitstream
+    pub fn set_design_hash(&self, hash: u32) {
+        self.state.lock().unwrap().design_hash = hash;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake the accept loop, which only sees the flag once a connection comes in
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(if addr.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() });
+        }
+        if TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_err() {
+            // Leave the thread be rather than hang if the listener can't be reached
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
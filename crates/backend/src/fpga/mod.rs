@@ -1,52 +1,602 @@
-mod assembler;
+pub(crate) mod assembler;
 pub mod linker;
+pub mod partition;
+pub mod estimate;
+pub mod rom;
+pub mod scan;
+pub mod trace;
+pub mod pins;
+pub mod builds;
 pub mod interface;
 pub mod compiler;
+pub mod toolchain;
+pub mod sim;
+mod hdl;
+pub mod mock;
 
 use super::JITBackend;
-use mchprs_redpiler::compile_graph::{CompileGraph, NodeType};
+use mchprs_redpiler::compile_graph::CompileGraph;
 use crate::fpga::linker::Linker;
+use crate::fpga::partition::CutLink;
 use crate::CompilerOptions;
 use compiler::DeviceConfig;
+use mchprs_redpiler::task_monitor::TaskMonitor;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
 use mchprs_world::World;
 use mchprs_world::TickEntry;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use toolchain::{FpgaToolchain, Project};
+use trace::Trace;
+use tracing::{debug, info, warn};
 
 
-use interface::{Interface, FPGACommand, BinaryIterator};
+use interface::{Interface, InterfaceError, FPGACommand, Response, BinaryIterator, DeviceStatus, AUTO_PORT, BAUD_RATE};
 
-use std::fs::{remove_dir_all, copy};
+use std::fs::remove_dir_all;
+
+/// Longest a board is given to run the ticks it was asked to before it is considered stuck
+const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait before reading the tick counter of a board that has not got there yet
+const STEP_POLL_INTERVAL: Duration = Duration::from_millis(1);
 
 #[derive(Default, Debug)]
 pub struct FPGABackend {
-    fpga: Interface,
     path: String,
+    boards: Vec<Board>,
+    /// Nets carried between the boards of a partitioned build
+    cuts: Vec<CutLink>,
+    status: DeviceStatus,
+    /// Why the last run could not connect to the boards, or lost them
+    error: Option<String>,
+    /// The plot's rtps, which a single board runs at on its own tick clock. 0 while the plot is
+    /// paused or runs at unlimited rtps, when the server steps the board instead.
+    rtps: u32,
+}
+
+/// One board running its share of the build
+#[derive(Default, Debug)]
+struct Board {
+    fpga: Interface,
     config: DeviceConfig,
-    pub link: Linker,
+    link: Linker,
+    /// Directory under `FPGA/bin` holding the board's design
+    dir: String,
+    /// Every input bit as last sent, followed by the nets read from other boards
+    inputs: Vec<bool>,
+    /// Tick the board's counter was last run until
+    ticks: u64,
+    /// Output bytes as last read with `GetChanges`
+    reported: Vec<u8>,
+    /// Whether the next read has to fetch every output, as after a run starts or a failed read
+    resync: bool,
+    /// Input changes made during the current server tick, sent together before the next tick
+    staged: Vec<(u32, u8)>,
+}
+
+impl Board {
+    fn new(link: Linker, config: DeviceConfig, dir: String) -> Board {
+        let mut inputs = link.input_states();
+        inputs.resize((link.input_bits + link.imported_bits) as usize, false);
+        let reported = vec![0; (link.output_bits + link.exported_bits).div_ceil(8) as usize];
+        Board {
+            fpga: Default::default(),
+            config,
+            link,
+            dir,
+            inputs,
+            ticks: 0,
+            reported,
+            resync: true,
+            staged: Vec::new(),
+        }
+    }
+
+    /// Where the bitstream programmed into the board is kept
+    fn bitstream(&self) -> PathBuf {
+        Path::new("FPGA/bin").join(&self.dir).join(self.config.toolchain().bitstream_name())
+    }
+
+    /// Opens the board's command port and checks that the board runs this build's design. A
+    /// board whose `command_com` is `auto` is looked for on every serial port by that design.
+    fn connect(&mut self) -> Result<(), (DeviceStatus, String)> {
+        let name = &self.config.name;
+        if self.config.command_com.eq_ignore_ascii_case(AUTO_PORT) {
+            let Some(expected) = self.link.design_hash else {
+                return Err((
+                    DeviceStatus::Disconnected,
+                    format!("{} was compiled before boards could identify themselves, set the command_com of {}", self.dir, name),
+                ));
+            };
+            for port in interface::available_ports() {
+                let mut fpga = Interface::default();
+                if fpga.serial_start(&port, BAUD_RATE) && fpga.identify().is_ok_and(|design| design == expected) {
+                    info!("Found {} on {}", name, port);
+                    self.fpga = fpga;
+                    return Ok(());
+                }
+            }
+            return Err((DeviceStatus::Disconnected, format!("no serial port has a board running {}", self.dir)));
+        }
+
+        if !self.fpga.serial_start(&self.config.command_com, BAUD_RATE) {
+            return Err((DeviceStatus::Disconnected, format!("could not open {} for {}", self.config.command_com, name)));
+        }
+        // Builds from before boards could identify themselves are trusted to be what was programmed
+        let Some(expected) = self.link.design_hash else {
+            return Ok(());
+        };
+        match self.fpga.identify() {
+            Ok(design) if design == expected => Ok(()),
+            Ok(design) => Err((
+                DeviceStatus::Failed,
+                format!(
+                    "{} is running design {:08x}, not the {:08x} of {}. Program it with this build's bitstream or recompile it",
+                    name, design, expected, self.dir
+                ),
+            )),
+            Err(err) => Err((DeviceStatus::Failed, format!("{} did not identify its design: {}", name, err))),
+        }
+    }
+
+    /// Output bits including the nets read by other boards
+    fn outputs_len(&self) -> usize {
+        self.link.output_bits as usize + self.link.exported_bits as usize
+    }
+
+    /// Sends a command, logging it if the board could not be reached
+    fn command(&mut self, cmd: FPGACommand) -> Option<Response> {
+        match self.fpga.send_command(cmd) {
+            Ok(response) => Some(response),
+            Err(InterfaceError::NotConnected) => None,
+            Err(err) => {
+                warn!("{:?} failed on {}: {}", cmd, self.config.name, err);
+                None
+            }
+        }
+    }
+
+    /// Latches and reads back every output of the design
+    fn outputs(&mut self) -> Option<Vec<u8>> {
+        self.command(FPGACommand::Capture)?;
+        match self.command(FPGACommand::GetOutputs)? {
+            Response::Outputs(outputs) => Some(outputs),
+            _ => None,
+        }
+    }
+
+    /// Brings the outputs as last read up to date, fetching only the bytes that changed unless
+    /// the board and the server lost track of each other. Returns whether the read succeeded.
+    fn read_changes(&mut self) -> bool {
+        let full = self.resync;
+        // A read that fails halfway may still have moved the board on, so the next one starts over
+        self.resync = true;
+        let Some(Response::Changes(changes)) = self.command(FPGACommand::GetChanges(full)) else {
+            return false;
+        };
+        if interface::apply_changes(&mut self.reported, &changes).is_none() {
+            warn!("{} sent changes that do not fit its {} outputs", self.config.name, self.outputs_len());
+            return false;
+        }
+        self.resync = false;
+        true
+    }
+
+    /// Sends the queued input changes, which the board applies together so they land on the
+    /// same tick. Returns whether they were committed.
+    ///
+    /// A batch is only committed once the board staged all of it. Otherwise it stays queued and
+    /// is staged again in full with the next commit, which is harmless since every change sets
+    /// an input to a state rather than toggling it.
+    fn commit_inputs(&mut self) -> bool {
+        if self.staged.is_empty() {
+            return false;
+        }
+        for i in 0..self.staged.len() {
+            let (id, state) = self.staged[i];
+            if self.command(FPGACommand::StageInput(id, state)).is_none() {
+                return false;
+            }
+        }
+        if self.command(FPGACommand::CommitInputs).is_none() {
+            return false;
+        }
+        self.staged.clear();
+        true
+    }
+
+    /// Shifts out the state of every component on the scan chain
+    fn scan(&mut self) -> Option<Vec<u8>> {
+        match self.command(FPGACommand::Scan)? {
+            Response::Scan(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Downloads the rows recorded in the board's trace buffer
+    fn trace(&mut self) -> Option<Trace> {
+        let layout = self.link.trace.clone()?;
+        let Response::TraceStatus(status) = self.command(FPGACommand::TraceStatus)? else {
+            return None;
+        };
+        let mut rows = Vec::with_capacity(status.count as usize);
+        for i in 0..status.count {
+            let Response::TraceRow(row) = self.command(FPGACommand::ReadTrace((status.start + i) % layout.depth))? else {
+                return None;
+            };
+            rows.push(unpack(&row, layout.row_bits() as usize));
+        }
+        Some(Trace {
+            trigger: status.trigger_row(&layout),
+            probes: layout.probes,
+            rows,
+        })
+    }
+
+    /// Reads the board's tick counter
+    fn tick_count(&mut self) -> Option<u64> {
+        match self.command(FPGACommand::GetTicks)? {
+            Response::Ticks(ticks) => Some(ticks),
+            _ => None,
+        }
+    }
+
+    /// Runs the design for exactly `ticks` more ticks and waits for the board to get there.
+    /// The tick the board is at is only moved on once the board confirmed it, so this returns
+    /// false when the board could not be reached or did not get there in time.
+    fn advance(&mut self, ticks: u64) -> bool {
+        let target = self.ticks + ticks;
+        if self.command(FPGACommand::RunUntil(target)).is_none() {
+            return false;
+        }
+        let start = Instant::now();
+        loop {
+            let Some(count) = self.tick_count() else {
+                return false;
+            };
+            if count >= target {
+                if count > target {
+                    warn!("{} ran to tick {}, past {}", self.config.name, count, target);
+                }
+                self.ticks = count;
+                return true;
+            }
+            if start.elapsed() > STEP_TIMEOUT {
+                warn!("{} is stuck at tick {} of {}", self.config.name, count, target);
+                return false;
+            }
+            std::thread::sleep(STEP_POLL_INTERVAL);
+        }
+    }
+}
+
+impl FPGABackend {
+    /// Loads a build compiled earlier, running each board's share on the given boards
+    pub fn from_link_file(mut link: Linker, path: String, configs: Vec<DeviceConfig>) -> FPGABackend {
+        let cuts = std::mem::take(&mut link.cuts);
+        let links = match std::mem::take(&mut link.partitions) {
+            partitions if partitions.is_empty() => vec![link],
+            partitions => partitions,
+        };
+        let single = links.len() == 1;
+        let boards = links
+            .into_iter()
+            .zip(configs.into_iter().chain(std::iter::repeat(DeviceConfig::default())))
+            .enumerate()
+            .map(|(idx, (link, config))| {
+                let dir = if single { path.clone() } else { format!("{}/p{}", path, idx) };
+                Board::new(link, config, dir)
+            })
+            .collect();
+        FPGABackend {
+            path,
+            boards,
+            cuts,
+            status: Default::default(),
+            error: None,
+            rtps: 0,
+        }
+    }
+
+    /// Names of the boards the build was compiled for, one per partition
+    pub fn devices(&self) -> Vec<String> {
+        self.boards
+            .iter()
+            .map(|board| {
+                if board.link.device.is_empty() {
+                    board.config.name.clone()
+                } else {
+                    board.link.device.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Input and output bits the world exchanges with the boards, over all partitions
+    pub fn io_bits(&self) -> (u32, u32) {
+        self.boards
+            .iter()
+            .fold((0, 0), |(inputs, outputs), board| (inputs + board.link.input_bits, outputs + board.link.output_bits))
+    }
+
+    /// Whether every board's share of the build has a bitstream to program, which builds that
+    /// were only generated get with `/roc import`
+    pub fn has_bitstream(&self) -> bool {
+        self.boards.iter().all(|board| board.bitstream().exists())
+    }
+
+    /// Copies a bitstream compiled elsewhere into the build, for the board of a partition
+    pub fn import_bitstream(&self, partition: usize, file: &Path) -> Result<PathBuf, String> {
+        let Some(board) = self.boards.get(partition) else {
+            return Err(format!("{} has {} partitions", self.path, self.boards.len()));
+        };
+        let target = board.bitstream();
+        let expected = target.extension();
+        if file.extension() != expected {
+            return Err(format!(
+                "{} is not a .{} bitstream",
+                file.display(),
+                expected.unwrap_or_default().to_string_lossy()
+            ));
+        }
+        std::fs::copy(file, &target).map_err(|err| format!("could not copy {}: {}", file.display(), err))?;
+        Ok(target)
+    }
+
+    /// Runs the build on other boards of the same parts, one per partition
+    pub fn set_devices(&mut self, configs: Vec<DeviceConfig>) {
+        for (board, config) in self.boards.iter_mut().zip(configs) {
+            board.config = config;
+        }
+    }
+
+    /// Rereads the ROM cells from the world, so the next run loads the containers' current contents
+    pub fn read_roms<W: World>(&mut self, world: &W) {
+        for board in &mut self.boards {
+            board.link.read_roms(world);
+        }
+    }
+
+    /// Sends the input changes queued during the server tick to the boards
+    fn commit_inputs(&mut self) {
+        let mut committed = false;
+        for board in &mut self.boards {
+            committed |= board.commit_inputs();
+        }
+        if committed {
+            self.sync();
+        }
+    }
+
+    /// Whether the build is split across several boards
+    pub fn is_partitioned(&self) -> bool {
+        self.boards.len() > 1
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        self.status
+    }
+
+    /// Why the last run could not connect to the boards or lost them, if it did
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Estimated share of the fullest board in use, if the capacity of any board is known
+    pub fn utilization(&self) -> Option<f64> {
+        self.boards
+            .iter()
+            .filter_map(|board| Some(board.link.estimate.utilization(&board.config.capacity()?)))
+            .reduce(f64::max)
+    }
+
+    /// Measures the round trip to the slowest board
+    pub fn ping(&mut self) -> Option<Duration> {
+        let mut latency = Duration::ZERO;
+        for board in &mut self.boards {
+            let start = Instant::now();
+            if board.command(FPGACommand::Ping).is_none() {
+                if self.status == DeviceStatus::Connected {
+                    self.status = DeviceStatus::Disconnected;
+                }
+                return None;
+            }
+            latency = latency.max(start.elapsed());
+        }
+        Some(latency)
+    }
+
+    /// Steps the boards by exactly `ticks` ticks. When a board does not confirm it got there, the
+    /// server no longer knows which tick it is at, so the boards are disconnected.
+    fn step(&mut self, ticks: u64) {
+        if self.status != DeviceStatus::Connected {
+            return;
+        }
+        // The nets between boards are forwarded after every tick, so partitioned builds are stepped one tick at a time
+        let (rounds, ticks) = if self.is_partitioned() { (ticks, 1) } else { (1, ticks) };
+        for _ in 0..rounds {
+            for idx in 0..self.boards.len() {
+                if self.boards[idx].advance(ticks) {
+                    continue;
+                }
+                let board = &self.boards[idx];
+                self.disconnect(format!("{} did not confirm the tick after {}", board.config.name, board.ticks));
+                return;
+            }
+            self.sync();
+        }
+    }
+
+    /// Whether the board ticks on its own clock. Only a single board does, since the nets between
+    /// the boards of a split build are forwarded after every tick.
+    fn free_running(&self) -> bool {
+        self.rtps != 0 && !self.is_partitioned()
+    }
+
+    /// Lets a single board tick on its own clock at the plot's rtps
+    fn resume(&mut self) {
+        if self.status != DeviceStatus::Connected || !self.free_running() {
+            return;
+        }
+        let rtps = self.rtps;
+        for board in &mut self.boards {
+            board.command(FPGACommand::SetRTPS(rtps));
+        }
+    }
+
+    /// Stops the boards' own tick clocks and reads the tick each of them stopped at, so they can
+    /// be stepped from there. Returns false if the boards were lost.
+    fn pause(&mut self) -> bool {
+        if self.status != DeviceStatus::Connected {
+            return false;
+        }
+        for idx in 0..self.boards.len() {
+            let board = &mut self.boards[idx];
+            board.command(FPGACommand::Pause);
+            match board.tick_count() {
+                Some(ticks) => board.ticks = ticks,
+                None => {
+                    let name = board.config.name.clone();
+                    self.disconnect(format!("{} did not report the tick it paused at", name));
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Drops the connections to the boards once the server lost track of which tick they are at
+    fn disconnect(&mut self, err: String) {
+        warn!("Disconnecting {}: {}", self.path, err);
+        self.error = Some(err);
+        self.status = DeviceStatus::Disconnected;
+        for board in &mut self.boards {
+            board.fpga = Default::default();
+        }
+    }
+
+    /// Forwards the nets cut between boards until every board sees the values the others drive.
+    /// Nets can pass combinationally through a board, so this may take a round per board.
+    fn sync(&mut self) {
+        if self.cuts.is_empty() {
+            return;
+        }
+        for _ in 0..=self.boards.len() {
+            let mut outputs = Vec::with_capacity(self.boards.len());
+            for (idx, board) in self.boards.iter_mut().enumerate() {
+                let exported = self.cuts.iter().any(|cut| cut.source == idx);
+                let bytes = if exported { board.outputs() } else { None };
+                outputs.push(unpack(&bytes.unwrap_or_default(), board.outputs_len()));
+            }
+            let inputs: Vec<Vec<bool>> = self.boards.iter().map(|board| board.inputs.clone()).collect();
+            let changes = partition::forward(&self.cuts, &outputs, &inputs);
+            if changes.is_empty() {
+                return;
+            }
+            for (target, bit, value) in changes {
+                let board = &mut self.boards[target];
+                board.inputs[bit as usize] = value;
+                board.command(FPGACommand::SetInputs(bit, 0, value as u8));
+            }
+        }
+        warn!("Nets between the boards of {} did not settle", self.path);
+    }
+}
+
+/// Unpacks output bytes as sent by the command controller, padding missing bits with zeroes
+fn unpack(bytes: &[u8], bits: usize) -> Vec<bool> {
+    (0..bits)
+        .map(|bit| bytes.get(bit / 8).is_some_and(|byte| (byte >> (bit % 8)) & 1 == 1))
+        .collect()
 }
 
 impl JITBackend for FPGABackend {
-    fn inspect(&mut self, _pos: BlockPos) {}
+    fn inspect(&mut self, pos: BlockPos) {
+        let Some(board) = self.boards.iter_mut().find(|board| board.link.scan_stage(pos).is_some()) else {
+            debug!("{} has no scan stage on any board", pos);
+            return;
+        };
+        let Some(data) = board.scan() else {
+            return;
+        };
+        board.link.read_scan(&mut BinaryIterator::new(data));
+        if let Some(stage) = board.link.scan_stage(pos) {
+            debug!("{}: {:?} (state {})", pos, stage.get_block(), stage.state());
+        }
+    }
 
-    fn reset<W: World>(&mut self, _world: &mut W, _io_only: bool) {}
+    // Internal components never show in the world while the build runs, so their state is
+    // read back from the boards even for io only builds
+    fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
+        self.flush(world, io_only);
+        for board in &mut self.boards {
+            if let Some(data) = board.scan() {
+                scan::write_back(&mut board.link, data, world);
+            }
+        }
+    }
 
     fn on_use_block(&mut self, pos: BlockPos) {
-        let (id, ty, state) = self.link.toggle_input(pos); 
-        self.fpga.send_command(FPGACommand::SetInputs(id, ty, state));
+        let Some(board) = self.boards.iter_mut().find(|board| board.link.has_input(pos)) else {
+            return;
+        };
+        if let Some((id, _, state)) = board.link.toggle_input(pos) {
+            board.inputs[id as usize] = state & 1 == 1;
+            board.staged.push((id, state));
+        }
+    }
+
+    fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        let Some(board) = self.boards.iter_mut().find(|board| board.link.has_input(pos)) else {
+            warn!("Tried to set pressure plate state for a {:?} which is not an input", pos);
+            return;
+        };
+        if let Some(id) = board.link.set_input(pos, powered as u8) {
+            board.inputs[id as usize] = powered;
+            board.staged.push((id, powered as u8));
+        }
     }
 
-    fn set_pressure_plate(&mut self, _pos: BlockPos, _powered: bool) {}
+    fn tick(&mut self) {
+        self.tickn(1);
+    }
 
-    fn tick(&mut self) {}
+    // Inputs changed during a server tick reach the boards together before the next tick,
+    // the same as the direct backend sees them. A free running board ticks on its own.
+    fn tickn(&mut self, ticks: u64) {
+        self.commit_inputs();
+        if ticks == 0 || self.free_running() {
+            return;
+        }
+        self.step(ticks);
+    }
 
-    fn flush<W: World>(&mut self, world: &mut W, _io_only: bool) { 
-        self.fpga.send_command(FPGACommand::Capture);
-        self.fpga.send_command(FPGACommand::GetOutupts);
-        let mut output_iter: BinaryIterator = BinaryIterator::new(self.fpga.outputs.clone());
-        for (pos, block) in self.link.get_blocks_to_change(&mut output_iter) {
-            world.set_block(pos, block);
+    // A free running board is paused for the exact number of ticks, then runs on at the plot's rtps
+    fn advance(&mut self, ticks: u64) {
+        if !self.free_running() {
+            self.tickn(ticks);
+            return;
+        }
+        self.commit_inputs();
+        if ticks == 0 || !self.pause() {
+            return;
+        }
+        self.step(ticks);
+        self.resume();
+    }
+
+    fn flush<W: World>(&mut self, world: &mut W, _io_only: bool) {
+        // A paused build still sees its inputs change once per server tick
+        self.commit_inputs();
+        for board in &mut self.boards {
+            if !board.read_changes() {
+                continue;
+            }
+            let mut output_iter: BinaryIterator = BinaryIterator::new(board.reported.clone());
+            for (pos, block) in board.link.get_blocks_to_change(&mut output_iter) {
+                world.set_block(pos, block);
+            }
         }
     }
 
@@ -56,50 +606,195 @@ impl JITBackend for FPGABackend {
         _ticks: Vec<TickEntry>,
         plot: String,
         name: String,
-        config: Option<DeviceConfig>,
-        _options: &CompilerOptions,
-    ) {
-        let path = format!("{}/{}", plot, name);
-        self.link.name = name;
-        self.config = config.unwrap();
-        self.path = path;
-        for nodeid in graph.node_indices() {
-            let node = &graph[nodeid];
-            if let Some((pos, blockid)) = node.block {
-                let block = Block::from_id(blockid);
-                self.link.add_block(block, pos);
-            }
-        }
-        println!("generating link file");
-        self.link.generate_link_file(Path::new(&format!("FPGA/bin/{}/link.json", self.path)));
-        println!("generating veruilog");
-        assembler::generate_verilog(&graph, Path::new(&format!("FPGA/bin/{}/redstone.sv", self.path)));
-        println!("create_project");
-        self.config.create_project(Path::new(&format!("FPGA/bin/{}/prj/prj.tcl",self.path)), self.link.output_bits as u32, self.link.input_bits);
-        println!("compile");
-        self.config.compile(Path::new(&format!("FPGA/bin/{}/prj", self.path)));
-        println!("done");
-        _ = copy(Path::new(&format!("FPGA/bin/{}/prj/RoC.sof", self.path)), Path::new(&format!("FPGA/bin/{}/RoC.sof", self.path)));
-        _ = remove_dir_all(Path::new(&format!("FPGA/bin/{}/prj", self.path)));  
+        devices: &[DeviceConfig],
+        options: &CompilerOptions,
+        monitor: &TaskMonitor,
+    ) -> Result<(), String> {
+        self.path = format!("{}/{}", plot, name);
+        monitor.set_message("Estimating resources".to_string());
+        let (parts, cuts) = match partition::partition(&graph, devices) {
+            Ok(partitioned) => partitioned,
+            Err(err) => {
+                warn!("Cannot place {} on the FPGAs: {}", self.path, err);
+                return Err(err.to_string());
+            }
+        };
+        if parts.len() > 1 {
+            info!("Splitting {} across {} FPGAs with {} nets between them", self.path, parts.len(), cuts.len());
+        }
+
+        monitor.set_message("Generating Verilog".to_string());
+        let single = parts.len() == 1;
+        let traced = trace::owner(&graph, &parts, &options.trace);
+        self.boards.clear();
+        for (idx, part) in parts.iter().enumerate() {
+            let mut link = Linker {
+                name: name.clone(),
+                device: devices[idx].name.clone(),
+                ..Default::default()
+            };
+            for &nodeid in &part.members {
+                if let Some((pos, blockid)) = graph[nodeid].block {
+                    link.add_block(Block::from_id(blockid), pos);
+                    link.annotate(pos, &graph[nodeid].annotations);
+                }
+            }
+            rom::link(&graph, part, &mut link);
+            scan::link(&graph, part, &mut link);
+            let plan = if traced == Some(idx) { trace::plan(&graph, part, &options.trace) } else { None };
+            if let Some(plan) = &plan {
+                trace::link(plan, &mut link);
+            }
+            let pin_plan = pins::plan(&graph, part, Some(&devices[idx]))?;
+            pins::link(&graph, &pin_plan, &mut link);
+            link.imported_bits = part.input_bits(&graph) - part.own_inputs(&graph);
+            link.exported_bits = part.output_bits(&graph) - part.own_outputs(&graph);
+            link.estimate = estimate::partition(&graph, part);
+            match devices[idx].capacity() {
+                Some(capacity) => info!(
+                    "Estimated {} for {} ({:.0}% of {})",
+                    link.estimate,
+                    devices[idx].name,
+                    link.estimate.utilization(&capacity) * 100.0,
+                    devices[idx].device
+                ),
+                None => info!("Estimated {} for {}", link.estimate, devices[idx].name),
+            }
+            let dir = if single { self.path.clone() } else { format!("{}/p{}", self.path, idx) };
+            let verilog = format!("FPGA/bin/{}/redstone.sv", dir);
+            if let Err(err) = assembler::generate_verilog(&graph, part, plan.as_ref(), &pin_plan, Path::new(&verilog)) {
+                warn!("Failed to write the Verilog of {}: {}", dir, err);
+                return Err(format!("could not write {}: {}", verilog, err));
+            }
+            link.design_hash = std::fs::read(&verilog).ok().map(|verilog| interface::design_hash(&verilog));
+            self.boards.push(Board::new(link, devices[idx].clone(), dir));
+        }
+        self.cuts = cuts;
+
+        let link = if single {
+            self.boards[0].link.clone()
+        } else {
+            Linker {
+                name: name.clone(),
+                device: devices[0].name.clone(),
+                partitions: self.boards.iter().map(|board| board.link.clone()).collect(),
+                cuts: self.cuts.clone(),
+                ..Default::default()
+            }
+        };
+        link.generate_link_file(Path::new(&format!("FPGA/bin/{}/link.json", self.path)));
+
+        for board in &self.boards {
+            if monitor.cancelled() {
+                return Err("cancelled".to_string());
+            }
+            let build_dir = format!("FPGA/bin/{}", board.dir);
+            let toolchain = board.config.toolchain();
+            let mut project = Project::new(&build_dir, board.outputs_len() as u32, board.inputs.len() as u32);
+            project.scan_bits = board.link.scan_bits();
+            project.trace_bytes = board.link.trace.as_ref().map_or(0, |trace| trace.row_bytes());
+            (project.pin_inputs, project.pin_outputs) = pins::board_pins(&board.config, &board.link.pins);
+            project.design_hash = board.link.design_hash.unwrap_or(0);
+            if !options.compile_verilog {
+                monitor.set_message(format!("Bundling the design for {}", board.config.name));
+                if let Err(err) = project.write_bundle(&board.config) {
+                    warn!("Failed to bundle the design of {}: {}", board.dir, err);
+                    return Err(format!("could not bundle the design for {}: {}", board.config.name, err));
+                }
+                info!("Generated {} without compiling it, see {}", board.dir, project.bundle_dir().display());
+                continue;
+            }
+            monitor.set_message(format!("Creating project for {}", board.config.name));
+            if let Err(err) = toolchain.create_project(&board.config, &project) {
+                warn!("Failed to create FPGA project for {}: {}", board.dir, err);
+                return Err(format!("could not create the project for {}: {}", board.config.name, err));
+            }
+            let results = toolchain.compile(&board.config, &project, monitor);
+            _ = remove_dir_all(&project.project_dir);
+            if monitor.cancelled() {
+                return Err("cancelled".to_string());
+            }
+            if !results.success {
+                warn!("FPGA compilation of {} failed, see {}", board.dir, results.log.display());
+                return Err(format!("compilation for {} failed, see {}", board.config.name, results.log.display()));
+            }
+            info!("Compiled {} (fmax: {:?} MHz)", board.dir, results.fmax);
+            for usage in &results.utilization {
+                info!("    {}: {} / {:?}", usage.resource, usage.used, usage.available);
+            }
+        }
+        Ok(())
     }
 
     fn run(&mut self) {
-        println!("programming");
-        self.config.program(Path::new(&format!("FPGA/bin/{}", self.path)));
-        println!("serial start");
-        println!("{}", self.link.get_output_bytes());
-        self.fpga.outputs = vec![0; self.link.get_output_bytes()];
-        self.fpga.serial_start(&self.config.command_com, 2500000);
-        self.set_rtps(10);
+        self.status = DeviceStatus::Programming;
+        self.error = None;
+        for board in &mut self.boards {
+            let build_dir = format!("FPGA/bin/{}", board.dir);
+            let results = board.config.toolchain().program(&board.config, Path::new(&build_dir));
+            if !results.success {
+                warn!("Programming {} failed, see {}", board.config.name, results.log.display());
+                self.status = DeviceStatus::Failed;
+                self.error = Some(format!("programming {} failed, see {}", board.config.name, results.log.display()));
+                return;
+            }
+            if let Err((status, err)) = board.connect() {
+                warn!("Not running {}: {}", board.dir, err);
+                self.status = status;
+                self.error = Some(err);
+                board.fpga = Default::default();
+                return;
+            }
+            board.resync = true;
+            board.staged.clear();
+            for (id, state) in board.inputs.clone().into_iter().enumerate() {
+                if state {
+                    board.command(FPGACommand::SetInputs(id as u32, 0, 1));
+                }
+            }
+            for (addr, data) in board.link.rom_contents() {
+                board.command(FPGACommand::LoadROM(addr, data));
+            }
+            board.command(FPGACommand::Pause);
+            board.ticks = board.tick_count().unwrap_or(0);
+        }
+        self.status = DeviceStatus::Connected;
+        self.sync();
+        self.resume();
     }
 
     fn stop(&mut self) {
-        self.fpga = Default::default();
+        for board in &mut self.boards {
+            board.fpga = Default::default();
+        }
+        self.status = DeviceStatus::Inactive;
     }
 
+    // A single board runs at the plot's rtps on its own tick clock, so it is not held back by a
+    // serial round trip per tick. Split builds, and any build at 0 or unlimited rtps, are stepped
+    // by `tickn` tick for tick in sync with the server.
     fn set_rtps(&mut self, rtps: u32) {
-        self.fpga.send_command(FPGACommand::SetRTPS(rtps));
+        let was_free_running = self.free_running();
+        self.rtps = rtps;
+        if self.free_running() {
+            self.resume();
+        } else if was_free_running {
+            self.pause();
+        }
     }
 
     fn has_pending_ticks(&self) -> bool {false}
-}
\ No newline at end of file
+
+    fn arm_trace(&mut self) {
+        for board in &mut self.boards {
+            if board.link.trace.is_some() {
+                board.command(FPGACommand::TraceArm);
+            }
+        }
+    }
+
+    // A split build only records on the board holding the trigger
+    fn trace(&mut self) -> Option<Trace> {
+        self.boards.iter_mut().find(|board| board.link.trace.is_some())?.trace()
+    }
+}
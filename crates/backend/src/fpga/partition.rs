@@ -0,0 +1,251 @@
+//! Splitting a build that does not fit on one board across several.
+//!
+//! Every node is placed on exactly one board. An edge whose source sits on another board is cut:
+//! the source's net is appended to its board's outputs and read back from extra inputs on the
+//! board that needs it. The host forwards cut nets between boards and steps every board in
+//! lockstep, so the combined design ticks exactly like the single board one.
+
+use super::compiler::DeviceConfig;
+use super::estimate::{self, Resources};
+use mchprs_blocks::blocks::Block;
+use mchprs_redpiler::compile_graph::{CompileGraph, CompileNode, NodeIdx, NodeType};
+use mchprs_redstone::wire;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Whether a node drives a 4 bit signal strength instead of a single on/off bit
+pub fn is_analog(ty: &NodeType) -> bool {
+    matches!(ty, NodeType::Comparator { .. } | NodeType::LUT { .. } | NodeType::Constant | NodeType::Wire)
+}
+
+/// Inputs of the design driven by the world
+pub fn is_input(ty: &NodeType) -> bool {
+    matches!(ty, NodeType::Lever | NodeType::PressurePlate | NodeType::Button)
+}
+
+/// Outputs of the design shown in the world. Buttons are timed on the board, so they are both.
+/// Wires are left in FPGA builds as dots kept as hex lamps by `--wire-dot-out`, or when watched
+/// by an observer. Only the dots are shown.
+pub fn is_output(node: &CompileNode) -> bool {
+    match node.ty {
+        NodeType::Lamp | NodeType::Trapdoor | NodeType::Button => true,
+        NodeType::Wire => node.block.is_some_and(|(_, id)| {
+            matches!(Block::from_id(id), Block::RedstoneWire { wire } if wire::is_dot(wire))
+        }),
+        _ => false,
+    }
+}
+
+/// The nodes placed on one board
+#[derive(Debug, Clone, Default)]
+pub struct Partition {
+    pub members: Vec<NodeIdx>,
+    /// Nodes on other boards this one reads, wired to the inputs after its own
+    pub imports: Vec<NodeIdx>,
+    /// Nodes other boards read, wired to the outputs after its own
+    pub exports: Vec<NodeIdx>,
+}
+
+impl Partition {
+    /// The whole graph on a single board
+    pub fn whole(graph: &CompileGraph) -> Partition {
+        Partition {
+            members: graph.node_indices().collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, node: NodeIdx) -> bool {
+        self.members.binary_search(&node).is_ok()
+    }
+
+    /// Input bits driven by the world
+    pub fn own_inputs(&self, graph: &CompileGraph) -> u32 {
+        self.members.iter().filter(|&&node| is_input(&graph[node].ty)).count() as u32
+    }
+
+    /// Output bits shown in the world
+    pub fn own_outputs(&self, graph: &CompileGraph) -> u32 {
+        self.members
+            .iter()
+            .filter(|&&node| is_output(&graph[node]))
+            .map(|&node| width(graph, node))
+            .sum()
+    }
+
+    /// Width of the board's input port
+    pub fn input_bits(&self, graph: &CompileGraph) -> u32 {
+        self.own_inputs(graph) + self.imports.iter().map(|&node| width(graph, node)).sum::<u32>()
+    }
+
+    /// Width of the board's output port
+    pub fn output_bits(&self, graph: &CompileGraph) -> u32 {
+        self.own_outputs(graph) + self.exports.iter().map(|&node| width(graph, node)).sum::<u32>()
+    }
+}
+
+/// Bits a node's net takes in a port
+fn width(graph: &CompileGraph, node: NodeIdx) -> u32 {
+    if is_analog(&graph[node].ty) {
+        4
+    } else {
+        1
+    }
+}
+
+/// A net carried from one board to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CutLink {
+    pub source: usize,
+    /// First bit of the net in the source board's outputs
+    pub output: u32,
+    pub target: usize,
+    /// First bit of the net in the target board's inputs
+    pub input: u32,
+    pub width: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionError {
+    /// The build needs more logic than the boards provide together
+    TooLarge { needed: Resources, available: Resources },
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionError::TooLarge { needed, available } => write!(
+                f,
+                "the build needs an estimated {} but the boards only fit {}",
+                needed, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PartitionError {}
+
+/// Splits the graph into as few partitions as the boards' capacities allow, in board order.
+///
+/// Nodes are placed in breadth first order so connected logic tends to stay on one board
+/// and few edges are cut. Boards of unknown capacity take everything left. Registers for
+/// the cut nets are not planned for, so boards should not be filled to the last flip-flop.
+pub fn partition(graph: &CompileGraph, devices: &[DeviceConfig]) -> Result<(Vec<Partition>, Vec<CutLink>), PartitionError> {
+    // What is left of each board next to the command controller
+    let capacities: Vec<Resources> = devices
+        .iter()
+        .map(|device| device.capacity().unwrap_or(Resources::UNLIMITED).saturating_sub(estimate::CONTROLLER))
+        .collect();
+    let needed = graph.node_indices().fold(estimate::CONTROLLER, |sum, node| sum + estimate::node(graph, node));
+    let available = capacities.iter().fold(estimate::CONTROLLER, |sum, capacity| sum.saturating_add(*capacity));
+    let too_large = PartitionError::TooLarge { needed, available };
+    if capacities.is_empty() || !needed.fits(&available) {
+        return Err(too_large);
+    }
+
+    let mut parts = vec![Partition::default()];
+    let mut used = Resources::default();
+    for node in placement_order(graph) {
+        let cost = estimate::node(graph, node);
+        if !(used + cost).fits(&capacities[parts.len() - 1]) {
+            if parts.len() == capacities.len() || parts.last().unwrap().members.is_empty() {
+                return Err(too_large);
+            }
+            parts.push(Partition::default());
+            used = Resources::default();
+        }
+        used += cost;
+        parts.last_mut().unwrap().members.push(node);
+    }
+
+    let mut owner = FxHashMap::default();
+    for (idx, part) in parts.iter_mut().enumerate() {
+        part.members.sort();
+        for &node in &part.members {
+            owner.insert(node, idx);
+        }
+    }
+    for part in &mut parts {
+        for &node in &part.members {
+            for edge in graph.edges_directed(node, Direction::Incoming) {
+                if !part.contains(edge.source()) && !part.imports.contains(&edge.source()) {
+                    part.imports.push(edge.source());
+                }
+            }
+        }
+        part.imports.sort();
+    }
+    for idx in 0..parts.len() {
+        let imports = parts[idx].imports.clone();
+        for node in imports {
+            let exports = &mut parts[owner[&node]].exports;
+            if !exports.contains(&node) {
+                exports.push(node);
+            }
+        }
+    }
+    for part in &mut parts {
+        part.exports.sort();
+    }
+
+    let mut links = Vec::new();
+    for (target, part) in parts.iter().enumerate() {
+        let mut input = part.own_inputs(graph);
+        for &node in &part.imports {
+            let source = owner[&node];
+            let mut output = parts[source].own_outputs(graph);
+            for &export in parts[source].exports.iter().take_while(|&&export| export != node) {
+                output += width(graph, export);
+            }
+            links.push(CutLink {
+                source,
+                output,
+                target,
+                input,
+                width: width(graph, node),
+            });
+            input += width(graph, node);
+        }
+    }
+    Ok((parts, links))
+}
+
+/// Every node once, each connected group of nodes breadth first from its lowest index
+fn placement_order(graph: &CompileGraph) -> Vec<NodeIdx> {
+    let mut visited = FxHashSet::default();
+    let mut order = Vec::new();
+    for start in graph.node_indices() {
+        if !visited.insert(start) {
+            continue;
+        }
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+            for next in graph.neighbors_undirected(node) {
+                if visited.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Cut nets whose value on the source board differs from what the target board was last given,
+/// as `(target, input bit, value)`
+pub fn forward(links: &[CutLink], outputs: &[Vec<bool>], inputs: &[Vec<bool>]) -> Vec<(usize, u32, bool)> {
+    let mut changes = Vec::new();
+    for link in links {
+        for bit in 0..link.width {
+            let value = outputs[link.source][(link.output + bit) as usize];
+            if inputs[link.target][(link.input + bit) as usize] != value {
+                changes.push((link.target, link.input + bit, value));
+            }
+        }
+    }
+    changes
+}
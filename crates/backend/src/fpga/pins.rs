@@ -0,0 +1,203 @@
+//! Board peripherals bound to components with `[pin <name>]` signs.
+//!
+//! A lever or pressure plate bound to a switch or button is driven by it instead of by the
+//! world. Any other component bound to an LED lights it while the component is powered, and one
+//! bound to a seven segment display shows its signal strength as a hex digit. Bound peripherals
+//! are numbered in the order of their components, inputs and outputs separately, and reach the
+//! design through the `pin_in` and `pin_out` ports that `top.sv` wires to the board's pins.
+
+use super::compiler::{DeviceConfig, Peripheral};
+use super::linker::Linker;
+use super::partition::Partition;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::compile_graph::{CompileGraph, NodeIdx, NodeType};
+use serde::{Deserialize, Serialize};
+
+/// A component and the peripheral it is bound to
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub node: NodeIdx,
+    pub peripheral: Peripheral,
+}
+
+/// The peripherals a partition's design drives or is driven by
+#[derive(Debug, Clone, Default)]
+pub struct PinPlan {
+    pub inputs: Vec<Binding>,
+    pub outputs: Vec<Binding>,
+}
+
+/// Where a peripheral's signal comes from or goes to in the world, kept in `link.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinLink {
+    pub peripheral: String,
+    pub pos: BlockPos,
+}
+
+/// Resolves the bindings of a partition against the board it runs on. Builds on no board
+/// in particular, like the simulation without devices, have nothing to bind to.
+pub fn plan(graph: &CompileGraph, part: &Partition, device: Option<&DeviceConfig>) -> Result<PinPlan, String> {
+    let mut plan = PinPlan::default();
+    let Some(device) = device else {
+        return Ok(plan);
+    };
+    for &node in &part.members {
+        let Some(name) = &graph[node].annotations.pin else {
+            continue;
+        };
+        let at = graph[node].block.map_or_else(String::new, |(pos, _)| format!(" at {}", pos));
+        let Some(peripheral) = device.peripheral(name) else {
+            return Err(format!("{} has no peripheral {}", device.name, name));
+        };
+        if peripheral.pins.len() != peripheral.kind.pins() {
+            return Err(format!(
+                "{} of {} needs {} pins, it has {}",
+                peripheral.name,
+                device.name,
+                peripheral.kind.pins(),
+                peripheral.pins.len()
+            ));
+        }
+        if let Some((port, _)) = device.pin_assignments.iter().find(|(_, pin)| peripheral.pins.iter().any(|p| p == pin)) {
+            return Err(format!("{} of {} shares a pin with {}", peripheral.name, device.name, port));
+        }
+        let bound = [&plan.inputs, &plan.outputs].into_iter().flatten().any(|binding| binding.peripheral.name == peripheral.name);
+        if bound {
+            return Err(format!("{} is bound to more than one component", peripheral.name));
+        }
+
+        let binding = Binding {
+            node,
+            peripheral: peripheral.clone(),
+        };
+        let ty = &graph[node].ty;
+        if peripheral.kind.is_input() {
+            if !matches!(ty, NodeType::Lever | NodeType::PressurePlate) {
+                return Err(format!("{} can only drive levers and pressure plates, not the {:?}{}", peripheral.name, ty, at));
+            }
+            plan.inputs.push(binding);
+        } else {
+            if matches!(ty, NodeType::NoteBlock { .. }) {
+                return Err(format!("{} cannot show the note block{}", peripheral.name, at));
+            }
+            plan.outputs.push(binding);
+        }
+    }
+    Ok(plan)
+}
+
+/// Adds the bindings to the link, inputs first
+pub fn link(graph: &CompileGraph, plan: &PinPlan, link: &mut Linker) {
+    for binding in plan.inputs.iter().chain(&plan.outputs) {
+        if let Some((pos, _)) = graph[binding.node].block {
+            link.pins.push(PinLink {
+                peripheral: binding.peripheral.name.clone(),
+                pos,
+            });
+        }
+    }
+}
+
+/// The board pins behind each bit of `pin_in` and `pin_out` for a link's bindings
+pub fn board_pins(device: &DeviceConfig, pins: &[PinLink]) -> (Vec<String>, Vec<String>) {
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for pin in pins {
+        if let Some(peripheral) = device.peripheral(&pin.peripheral) {
+            let bits = if peripheral.kind.is_input() { &mut inputs } else { &mut outputs };
+            bits.extend(peripheral.pins.iter().cloned());
+        }
+    }
+    (inputs, outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fpga::assembler;
+    use crate::fpga::compiler::PeripheralKind;
+    use crate::fpga::sim::Netlist;
+    use mchprs_redpiler::compile_graph::{Annotations, CompileLink, CompileNode, NodeState};
+
+    fn peripheral(name: &str, kind: PeripheralKind, active_low: bool) -> Peripheral {
+        Peripheral {
+            name: name.to_owned(),
+            kind,
+            pins: (0..kind.pins()).map(|pin| format!("{name}_{pin}")).collect(),
+            active_low,
+        }
+    }
+
+    fn board() -> DeviceConfig {
+        DeviceConfig {
+            name: "board".to_owned(),
+            peripherals: vec![
+                peripheral("SW0", PeripheralKind::Switch, false),
+                peripheral("LED0", PeripheralKind::Led, false),
+                peripheral("HEX0", PeripheralKind::SevenSegment, true),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn node(ty: NodeType, state: NodeState, x: i32, pin: &str) -> CompileNode {
+        CompileNode {
+            ty,
+            block: Some((BlockPos::new(x, 0, 0), 0)),
+            state,
+            is_input: false,
+            is_output: false,
+            annotations: Annotations {
+                pin: Some(pin.to_owned()),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// A lever on a switch inverted by a torch on an LED, and a constant on a display
+    fn graph(lever_pin: &str) -> CompileGraph {
+        let mut graph = CompileGraph::new();
+        let lever = graph.add_node(node(NodeType::Lever, NodeState::simple(false), 0, lever_pin));
+        let torch = graph.add_node(node(NodeType::Torch, NodeState::simple(true), 1, "led0"));
+        graph.add_node(node(NodeType::Constant, NodeState::ss(10), 2, "hex0"));
+        graph.add_edge(lever, torch, CompileLink::default(0));
+        graph
+    }
+
+    #[test]
+    fn peripherals_drive_and_show_the_design() {
+        let graph = graph("sw0");
+        let part = Partition::whole(&graph);
+        let plan = plan(&graph, &part, Some(&board())).unwrap();
+        let verilog = assembler::generate_partition(&graph, &part, None, &plan);
+        let mut netlist = Netlist::elaborate(&verilog, 1, 1).unwrap();
+        let mut linker = Linker::default();
+        link(&graph, &plan, &mut linker);
+        assert_eq!(board_pins(&board(), &linker.pins).0, vec!["SW0_0"]);
+
+        // The display is active low and shows A
+        let segments = |netlist: &Netlist| netlist.pins()[1..].iter().rev().fold(0, |value, &pin| value << 1 | pin as u8);
+        assert!(netlist.pins()[0]);
+        assert_eq!(segments(&netlist), !0x77 & 0x7F);
+
+        netlist.set_pin(0, true);
+        netlist.tick();
+        assert!(!netlist.pins()[0]);
+        // The lever's input bit no longer drives it
+        netlist.set_input(0, false);
+        netlist.tick();
+        assert!(!netlist.pins()[0]);
+    }
+
+    #[test]
+    fn bad_bindings() {
+        let bind = |lever_pin: &str, device: Option<&DeviceConfig>| {
+            let graph = graph(lever_pin);
+            plan(&graph, &Partition::whole(&graph), device)
+        };
+        assert_eq!(bind("sw9", Some(&board())).unwrap_err(), "board has no peripheral sw9");
+        assert_eq!(bind("led0", Some(&board())).unwrap_err(), "LED0 is bound to more than one component");
+        // Without a board there is nothing to bind to
+        assert!(bind("sw9", None).unwrap().outputs.is_empty());
+    }
+}
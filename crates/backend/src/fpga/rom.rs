@@ -0,0 +1,96 @@
+//! ROMs kept in containers read by comparators, like the program of a CPU build.
+//!
+//! Every container is a ROM cell with its own address, loaded over `LoadROM` when the build
+//! starts running, so a program can be changed by editing the containers and running the
+//! build again without synthesizing it anew. Neighbouring cells form a region with
+//! consecutive addresses. A region whose cells hold either nothing or one signal strength
+//! is stored one bit per cell, any other region four bits per cell.
+//!
+//! Comparators read every cell at once, so the cells map to registers rather than block RAM.
+//! Containers read through a solid block are not ROM cells, their value is part of the design.
+
+use super::linker::Linker;
+use super::partition::Partition;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::compile_graph::{CompileGraph, CompileNode, NodeIdx, NodeType};
+use petgraph::Direction;
+use rustc_hash::FxHashSet;
+
+/// Furthest apart two cells of a region can be on any axis, enough to leave room for the comparators between them
+const REGION_SPACING: i32 = 2;
+
+/// A ROM cell of the design
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomCell {
+    pub node: NodeIdx,
+    pub addr: u32,
+    /// Signal strength a set bit reads as, for cells stored one bit each
+    pub on: Option<u8>,
+}
+
+/// Whether a node is a container the redstone reads as a ROM cell
+pub fn is_rom(node: &CompileNode) -> bool {
+    node.ty == NodeType::Constant && node.is_input
+}
+
+/// The ROM cells of a partition that are read by something, in address order
+pub fn layout(graph: &CompileGraph, part: &Partition) -> Vec<RomCell> {
+    let mut cells: Vec<(NodeIdx, BlockPos)> = part
+        .members
+        .iter()
+        .filter(|&&node| is_rom(&graph[node]) && graph.neighbors_directed(node, Direction::Outgoing).next().is_some())
+        .filter_map(|&node| Some((node, graph[node].block?.0)))
+        .collect();
+    cells.sort_by_key(|(_, pos)| (pos.y, pos.z, pos.x));
+
+    let near = |a: BlockPos, b: BlockPos| {
+        (a.x - b.x).abs() <= REGION_SPACING && (a.y - b.y).abs() <= REGION_SPACING && (a.z - b.z).abs() <= REGION_SPACING
+    };
+    let mut placed = vec![false; cells.len()];
+    let mut layout = Vec::with_capacity(cells.len());
+    for start in 0..cells.len() {
+        if placed[start] {
+            continue;
+        }
+        placed[start] = true;
+        let mut region = vec![start];
+        let mut next = 0;
+        while next < region.len() {
+            let pos = cells[region[next]].1;
+            for other in 0..cells.len() {
+                if !placed[other] && near(pos, cells[other].1) {
+                    placed[other] = true;
+                    region.push(other);
+                }
+            }
+            next += 1;
+        }
+        region.sort();
+
+        let values: FxHashSet<u8> = region.iter().map(|&cell| graph[cells[cell].0].state.output_strength).collect();
+        let on = if values.len() == 2 && values.contains(&0) {
+            values.into_iter().max()
+        } else {
+            None
+        };
+        for cell in region {
+            layout.push(RomCell {
+                node: cells[cell].0,
+                addr: layout.len() as u32,
+                on,
+            });
+        }
+    }
+    layout
+}
+
+/// Adds the ROM cells of a partition to its link, so their contents can be loaded when it runs
+pub fn link(graph: &CompileGraph, part: &Partition, link: &mut Linker) {
+    for cell in layout(graph, part) {
+        let node = &graph[cell.node];
+        if let Some((pos, id)) = node.block {
+            link.add_rom(Block::from_id(id), pos, node.state.output_strength, cell.on);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Scan chain reading back the state of the components the world cannot see from the outputs.
+//!
+//! Every repeater, torch and comparator gets a scan stage on the controller's clock. `Scan`
+//! captures all stages at once and shifts the chain out to the host, first stage first and each
+//! stage least significant bit first, without disturbing the design. A repeater's stage holds
+//! whether it is powered and whether it is locked, a torch's whether it is lit and a
+//! comparator's its output strength.
+
+use super::interface::BinaryIterator;
+use super::linker::Linker;
+use super::partition::Partition;
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::Block;
+use mchprs_redpiler::compile_graph::{CompileGraph, NodeIdx, NodeType};
+use mchprs_world::World;
+
+/// Bits a node's scan stage holds, if it has one
+pub fn width(ty: &NodeType) -> Option<u32> {
+    match ty {
+        NodeType::Repeater { .. } => Some(2),
+        NodeType::Torch => Some(1),
+        NodeType::Comparator { .. } | NodeType::LUT { .. } => Some(4),
+        _ => None,
+    }
+}
+
+/// The nodes of a partition with a scan stage, in chain order
+pub fn layout(graph: &CompileGraph, part: &Partition) -> Vec<NodeIdx> {
+    part.members
+        .iter()
+        .copied()
+        .filter(|&node| width(&graph[node].ty).is_some() && graph[node].block.is_some())
+        .collect()
+}
+
+/// Adds the scan stages of a partition to its link, so the state read back can be put in the world
+pub fn link(graph: &CompileGraph, part: &Partition, link: &mut Linker) {
+    for node in layout(graph, part) {
+        if let Some((pos, id)) = graph[node].block {
+            link.add_scan(Block::from_id(id), pos);
+        }
+    }
+}
+
+/// Puts the state shifted out of a scan chain in the world, including the output strength
+/// comparators keep in their block entity
+pub fn write_back<W: World>(link: &mut Linker, data: Vec<u8>, world: &mut W) {
+    for (pos, block) in link.read_scan(&mut BinaryIterator::new(data)) {
+        world.set_block(pos, block);
+    }
+    for (pos, output_strength) in link.comparator_outputs() {
+        world.set_block_entity(pos, BlockEntity::Comparator { output_strength });
+    }
+}
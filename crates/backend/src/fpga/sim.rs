@@ -0,0 +1,1130 @@
+//! A cycle accurate interpreter for the Verilog emitted by the assembler.
+//!
+//! It elaborates the generated `RoC` module and evaluates it one tick at a time, which
+//! allows checking the code generation against the other backends without hardware.
+//! Only the subset of Verilog the assembler emits is understood.
+//!
+//! The instantiated components run the modules of `FPGA/src/redstone/components.sv` through
+//! the interpreter in `hdl`, so there is no second description of their behaviour to keep in
+//! sync. Only the trace buffer, which `hdl` cannot read, is modelled by `Recorder`.
+
+use super::assembler;
+use super::interface::BinaryIterator;
+use super::linker::Linker;
+use super::partition::{self, CutLink, Partition};
+use super::trace::{self, Recorder, Trace, TraceStatus};
+use super::{hdl, pins, rom, scan};
+use crate::JITBackend;
+use crate::fpga::compiler::DeviceConfig;
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::compile_graph::CompileGraph;
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use mchprs_redpiler::{CompilerOptions, TraceTrigger};
+use mchprs_world::{TickEntry, World};
+use rustc_hash::FxHashMap;
+use std::fmt;
+use tracing::{debug, warn};
+
+pub(super) const COMPONENTS_SV: &str = include_str!("../../../../FPGA/src/redstone/components.sv");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElaborationError(String);
+
+impl fmt::Display for ElaborationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "elaboration failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for ElaborationError {}
+
+pub(super) fn error<T>(msg: impl Into<String>) -> Result<T, ElaborationError> {
+    Err(ElaborationError(msg.into()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Token {
+    Ident(String),
+    /// A sized or unsized number literal and its width
+    Number(u32, u32),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: &[&str] = &[
+    ">>", "<<", ">=", "<=", "==", "!=", "&&", "||", "(", ")", "[", "]", "{", "}", ":", ";", ",", ".",
+    "#", "@", "=", "|", "&", "^", "~", "!", "?", ">", "<", "-", "+", "*", "/",
+];
+
+pub(super) fn tokenize(src: &str) -> Result<Vec<Token>, ElaborationError> {
+    let mut tokens = Vec::new();
+    let bytes = src.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if src[i..].starts_with("//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '`' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'`') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(src[start..i].to_owned()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            let value: u32 = src[start..i].parse().unwrap();
+            if i < bytes.len() && bytes[i] == b'\'' {
+                let radix = match bytes.get(i + 1) {
+                    Some(b'd') => 10,
+                    Some(b'b') => 2,
+                    Some(b'h') => 16,
+                    _ => return error("unsupported number literal"),
+                };
+                i += 2;
+                let digits = i;
+                while i < bytes.len() && (bytes[i].is_ascii_hexdigit() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let digits = src[digits..i].replace('_', "");
+                let Ok(number) = u32::from_str_radix(&digits, radix) else {
+                    return error(format!("invalid number literal {}", &src[start..i]));
+                };
+                tokens.push(Token::Number(number, value));
+            } else {
+                tokens.push(Token::Number(value, 32));
+            }
+        } else {
+            let Some(punct) = PUNCTUATION.iter().find(|p| src[i..].starts_with(**p)) else {
+                return error(format!("unexpected character '{c}'"));
+            };
+            tokens.push(Token::Punct(punct));
+            i += punct.len();
+        }
+    }
+    Ok(tokens)
+}
+
+pub(super) fn mask(width: u32) -> u32 {
+    if width >= 32 {
+        u32::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinOp {
+    Or,
+    And,
+    Xor,
+    LogicOr,
+    LogicAnd,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Add,
+    Sub,
+}
+
+impl BinOp {
+    pub(super) fn from_punct(punct: &str) -> Option<(BinOp, u8)> {
+        // Binding power, following Verilog's operator precedence
+        Some(match punct {
+            "||" => (BinOp::LogicOr, 1),
+            "&&" => (BinOp::LogicAnd, 2),
+            "|" => (BinOp::Or, 3),
+            "^" => (BinOp::Xor, 4),
+            "&" => (BinOp::And, 5),
+            "==" => (BinOp::Eq, 6),
+            "!=" => (BinOp::Ne, 6),
+            ">" => (BinOp::Gt, 7),
+            ">=" => (BinOp::Ge, 7),
+            "<" => (BinOp::Lt, 7),
+            "<=" => (BinOp::Le, 7),
+            "+" => (BinOp::Add, 8),
+            "-" => (BinOp::Sub, 8),
+            _ => return None,
+        })
+    }
+
+    pub(super) fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::LogicOr | BinOp::LogicAnd | BinOp::Eq | BinOp::Ne | BinOp::Gt | BinOp::Ge | BinOp::Lt | BinOp::Le
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ExprKind {
+    Const(u32),
+    Net(usize),
+    /// `inputs[lsb +: width]`
+    Input(usize),
+    Not(Box<Expr>),
+    LogicNot(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `{msb, ..., lsb}`
+    Concat(Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    width: u32,
+}
+
+impl Expr {
+    fn eval(&self, nets: &[u32], inputs: &[bool]) -> u32 {
+        let value = match &self.kind {
+            ExprKind::Const(value) => *value,
+            ExprKind::Net(net) => nets[*net],
+            ExprKind::Input(lsb) => (0..self.width as usize).fold(0, |value, bit| value | (inputs[lsb + bit] as u32) << bit),
+            ExprKind::Not(expr) => !expr.eval(nets, inputs),
+            ExprKind::LogicNot(expr) => (expr.eval(nets, inputs) == 0) as u32,
+            ExprKind::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(nets, inputs);
+                let b = rhs.eval(nets, inputs);
+                match op {
+                    BinOp::Or => a | b,
+                    BinOp::And => a & b,
+                    BinOp::Xor => a ^ b,
+                    BinOp::LogicOr => (a != 0 || b != 0) as u32,
+                    BinOp::LogicAnd => (a != 0 && b != 0) as u32,
+                    BinOp::Eq => (a == b) as u32,
+                    BinOp::Ne => (a != b) as u32,
+                    BinOp::Gt => (a > b) as u32,
+                    BinOp::Ge => (a >= b) as u32,
+                    BinOp::Lt => (a < b) as u32,
+                    BinOp::Le => (a <= b) as u32,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                }
+            }
+            ExprKind::Ternary(cond, a, b) => {
+                if cond.eval(nets, inputs) != 0 {
+                    a.eval(nets, inputs)
+                } else {
+                    b.eval(nets, inputs)
+                }
+            }
+            ExprKind::Concat(parts) => parts
+                .iter()
+                .fold(0, |value, part| (value << part.width) | part.eval(nets, inputs)),
+        };
+        value & mask(self.width)
+    }
+
+    /// Value as bits least significant first, for expressions wider than 32 bits
+    fn bits(&self, nets: &[u32], inputs: &[bool]) -> Vec<bool> {
+        match &self.kind {
+            ExprKind::Concat(parts) => parts.iter().rev().flat_map(|part| part.bits(nets, inputs)).collect(),
+            _ => {
+                let value = self.eval(nets, inputs);
+                (0..self.width).map(|bit| bit < 32 && (value >> bit) & 1 == 1).collect()
+            }
+        }
+    }
+}
+
+/// An instance of a module in `components.sv`, run by the interpreter in `hdl`
+#[derive(Debug, Clone)]
+struct Cell {
+    name: String,
+    instance: hdl::Instance,
+    /// The ports connected to the design, apart from `o_out`
+    ports: Vec<(String, Expr)>,
+    output: usize,
+}
+
+impl Cell {
+    fn port(&self, port: &str, nets: &[u32], inputs: &[bool]) -> u32 {
+        self.ports
+            .iter()
+            .find(|(name, _)| name == port)
+            .map_or(0, |(_, expr)| expr.eval(nets, inputs))
+    }
+
+    fn new(name: String, instance: hdl::Instance, ports: Vec<(String, Expr)>) -> Result<Cell, ElaborationError> {
+        let Some((_, output)) = ports.iter().find(|(port, _)| port == "o_out") else {
+            return error(format!("{name}: output is not connected"));
+        };
+        let ExprKind::Net(output) = output.kind else {
+            return error(format!("{name}: output must drive a net"));
+        };
+        let ports = ports.into_iter().filter(|(port, _)| port != "o_out").collect();
+        Ok(Cell { name, instance, ports, output })
+    }
+
+    /// Drives the connected ports from the design and reads the output back
+    fn update(&mut self, nets: &[u32], inputs: &[bool]) -> Result<u32, ElaborationError> {
+        let mut changed = false;
+        for (port, expr) in &self.ports {
+            changed |= self.instance.set(port, expr.eval(nets, inputs))?;
+        }
+        if changed {
+            self.instance.settle()?;
+        }
+        self.instance.value("o_out")
+    }
+
+    /// Clocks the cell on its own with ports the design leaves unconnected held for the edge,
+    /// like the controller's clock does for `LoadROM` and `Scan`
+    fn strobe(&mut self, ports: &[(&str, u32)]) -> Result<(), ElaborationError> {
+        for (port, value) in ports {
+            self.instance.set_input(port, *value)?;
+        }
+        self.instance.clock()?;
+        for (port, _) in ports {
+            self.instance.set_input(port, 0)?;
+        }
+        Ok(())
+    }
+
+    fn is(&self, module: &str) -> bool {
+        self.instance.module() == module
+    }
+}
+
+/// The trace buffer of a design, recording on every tick apart from the cells
+#[derive(Debug, Clone)]
+struct TraceCell {
+    recorder: Recorder,
+    probes: Expr,
+    trigger: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Net(usize),
+    /// `outputs[msb:lsb]`
+    Outputs(usize, u32),
+    /// `pin_out[msb:lsb]`
+    Pins(usize, u32),
+}
+
+/// An elaborated `RoC` module
+#[derive(Debug, Clone, Default)]
+pub struct Netlist {
+    net_names: FxHashMap<String, usize>,
+    widths: Vec<u32>,
+    nets: Vec<u32>,
+    assigns: Vec<(Target, Expr)>,
+    cells: Vec<Cell>,
+    /// The host's inputs followed by the pins read from bound peripherals
+    inputs: Vec<bool>,
+    host_inputs: usize,
+    outputs: Vec<bool>,
+    /// Pins driven for bound peripherals
+    pins: Vec<bool>,
+    trace: Option<TraceCell>,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    netlist: Netlist,
+    /// The modules of `components.sv` instantiated so far
+    modules: FxHashMap<String, hdl::Module>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Token, ElaborationError> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token.map_or_else(|| error("unexpected end of file"), Ok)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ElaborationError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            error(format!("expected '{punct}', found {:?}", self.peek()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ElaborationError> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            token => error(format!("expected identifier, found {token:?}")),
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, ElaborationError> {
+        match self.next()? {
+            Token::Number(value, _) => Ok(*value),
+            token => error(format!("expected number, found {token:?}")),
+        }
+    }
+
+    fn module(&mut self) -> Result<(), ElaborationError> {
+        if self.ident()? != "module" || self.ident()? != "RoC" {
+            return error("expected module RoC");
+        }
+        // The header only declares the parameters and ports, which are fixed
+        while !self.eat(";") {
+            self.next()?;
+        }
+        loop {
+            match self.ident()? {
+                "endmodule" => return Ok(()),
+                "wire" => self.wire()?,
+                "assign" => self.assign()?,
+                module => self.instance(module)?,
+            }
+        }
+    }
+
+    fn range(&mut self) -> Result<(u32, u32), ElaborationError> {
+        let msb = self.number()?;
+        let lsb = if self.eat(":") { self.number()? } else { msb };
+        self.expect("]")?;
+        Ok((msb, lsb))
+    }
+
+    fn wire(&mut self) -> Result<(), ElaborationError> {
+        let width = if self.eat("[") {
+            let (msb, lsb) = self.range()?;
+            msb - lsb + 1
+        } else {
+            1
+        };
+        loop {
+            let name = self.ident()?;
+            if self.netlist.net_names.contains_key(name) {
+                return error(format!("{name} is declared twice"));
+            }
+            self.netlist.net_names.insert(name.to_owned(), self.netlist.nets.len());
+            self.netlist.nets.push(0);
+            self.netlist.widths.push(width);
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(";")
+    }
+
+    fn assign(&mut self) -> Result<(), ElaborationError> {
+        let target = match self.ident()? {
+            "outputs" => {
+                self.expect("[")?;
+                let (msb, lsb) = self.range()?;
+                if msb as usize >= self.netlist.outputs.len() {
+                    return error(format!("outputs[{msb}] is out of range"));
+                }
+                Target::Outputs(lsb as usize, msb - lsb + 1)
+            }
+            "pin_out" => {
+                let (msb, lsb) = if self.eat("[") { self.range()? } else { (0, 0) };
+                if self.netlist.pins.len() <= msb as usize {
+                    self.netlist.pins.resize(msb as usize + 1, false);
+                }
+                Target::Pins(lsb as usize, msb - lsb + 1)
+            }
+            name => Target::Net(self.net(name)?),
+        };
+        self.expect("=")?;
+        let expr = self.expr(0)?;
+        self.expect(";")?;
+        self.netlist.assigns.push((target, expr));
+        Ok(())
+    }
+
+    fn net(&self, name: &str) -> Result<usize, ElaborationError> {
+        match self.netlist.net_names.get(name) {
+            Some(net) => Ok(*net),
+            None => error(format!("{name} is not declared")),
+        }
+    }
+
+    /// The trace buffer, of which only the recorded nets matter. Reading it back is modelled by
+    /// `Netlist::trace_status` and `Netlist::trace_row`.
+    fn trace(&mut self, params: &[u32]) -> Result<(), ElaborationError> {
+        let name = self.ident()?.to_owned();
+        if self.netlist.trace.is_some() {
+            return error(format!("{name}: the design already has a trace buffer"));
+        }
+        let [_, depth, pretrigger, mode] = params else {
+            return error(format!("{name}: trace takes 4 parameters"));
+        };
+        if *depth < 2 || pretrigger >= depth {
+            return error(format!("{name}: invalid trace window of {pretrigger} in {depth} ticks"));
+        }
+        let trigger = match mode {
+            0 => Some(TraceTrigger::Rising),
+            1 => Some(TraceTrigger::Falling),
+            2 => Some(TraceTrigger::Change),
+            _ => None,
+        };
+        let mut probes = None;
+        let mut trigger_expr = None;
+        self.expect("(")?;
+        while !self.eat(")") {
+            self.expect(".")?;
+            let port = self.ident()?;
+            self.expect("(")?;
+            match port {
+                "i_probes" => probes = Some(self.expr(0)?),
+                "i_trigger" => trigger_expr = Some(self.expr(0)?),
+                _ => {
+                    self.ident()?;
+                }
+            }
+            self.expect(")")?;
+            self.eat(",");
+        }
+        self.expect(";")?;
+        let (Some(probes), Some(trigger_expr)) = (probes, trigger_expr) else {
+            return error(format!("{name}: probes and trigger must be connected"));
+        };
+        self.netlist.trace = Some(TraceCell {
+            recorder: Recorder::new(*depth, *pretrigger, trigger),
+            probes,
+            trigger: trigger_expr,
+        });
+        Ok(())
+    }
+
+    fn instance(&mut self, module: &str) -> Result<(), ElaborationError> {
+        let mut params = Vec::new();
+        if self.eat("#") {
+            self.expect("(")?;
+            while !self.eat(")") {
+                params.push(self.expr(0)?.eval(&[], &[]));
+                self.eat(",");
+            }
+        }
+        if module == "trace" {
+            return self.trace(&params);
+        }
+        if !self.modules.contains_key(module) {
+            self.modules.insert(module.to_owned(), hdl::Module::parse(module)?);
+        }
+        let instance = self.modules[module].instantiate(&params)?;
+        let name = self.ident()?.to_owned();
+        self.expect("(")?;
+        let mut ports = Vec::new();
+        while !self.eat(")") {
+            self.expect(".")?;
+            let port = self.ident()?;
+            if !self.modules[module].has_port(port) {
+                return error(format!("{module} has no port {port}"));
+            }
+            self.expect("(")?;
+            if port == "i_clk" {
+                // Everything runs off the tick clock
+                self.ident()?;
+            } else if module == "rom" && port != "o_out" {
+                // The write port of ROM cells is driven by `Netlist::load_rom`
+                self.ident()?;
+            } else if module == "scan" && (port == "i_load" || port == "i_shift") {
+                // Capturing and shifting the chain is driven by `Netlist::scan`
+                self.ident()?;
+            } else {
+                ports.push((port.to_owned(), self.expr(0)?));
+            }
+            self.expect(")")?;
+            self.eat(",");
+        }
+        self.expect(";")?;
+        let cell = Cell::new(name, instance, ports)?;
+        self.netlist.cells.push(cell);
+        Ok(())
+    }
+
+    fn expr(&mut self, min_bp: u8) -> Result<Expr, ElaborationError> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Punct("?")) if min_bp == 0 => {
+                    self.pos += 1;
+                    let a = self.expr(0)?;
+                    self.expect(":")?;
+                    let b = self.expr(0)?;
+                    let width = a.width.max(b.width);
+                    lhs = Expr {
+                        kind: ExprKind::Ternary(Box::new(lhs), Box::new(a), Box::new(b)),
+                        width,
+                    };
+                }
+                Some(Token::Punct(punct)) => {
+                    let Some((op, bp)) = BinOp::from_punct(punct) else {
+                        return Ok(lhs);
+                    };
+                    if bp <= min_bp {
+                        return Ok(lhs);
+                    }
+                    self.pos += 1;
+                    let rhs = self.expr(bp)?;
+                    let width = if op.is_comparison() { 1 } else { lhs.width.max(rhs.width) };
+                    lhs = Expr {
+                        kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                        width,
+                    };
+                }
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ElaborationError> {
+        match self.next()? {
+            Token::Punct("(") => {
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct("~") => {
+                let expr = self.unary()?;
+                let width = expr.width;
+                Ok(Expr { kind: ExprKind::Not(Box::new(expr)), width })
+            }
+            Token::Punct("!") => {
+                let expr = self.unary()?;
+                Ok(Expr { kind: ExprKind::LogicNot(Box::new(expr)), width: 1 })
+            }
+            Token::Punct("{") => {
+                let mut parts = vec![self.expr(0)?];
+                while self.eat(",") {
+                    parts.push(self.expr(0)?);
+                }
+                self.expect("}")?;
+                let width = parts.iter().map(|part| part.width).sum();
+                Ok(Expr { kind: ExprKind::Concat(parts), width })
+            }
+            Token::Number(value, width) => Ok(Expr {
+                kind: ExprKind::Const(value & mask(*width)),
+                width: *width,
+            }),
+            Token::Ident(name) if name == "inputs" => {
+                self.expect("[")?;
+                let (msb, lsb) = self.range()?;
+                if msb as usize >= self.netlist.inputs.len() {
+                    return error(format!("inputs[{msb}] is out of range"));
+                }
+                Ok(Expr { kind: ExprKind::Input(lsb as usize), width: msb - lsb + 1 })
+            }
+            Token::Ident(name) if name == "pin_in" => {
+                // Pins are read like further inputs after the host's
+                self.expect("[")?;
+                let (msb, lsb) = self.range()?;
+                let end = self.netlist.host_inputs + msb as usize + 1;
+                if self.netlist.inputs.len() < end {
+                    self.netlist.inputs.resize(end, false);
+                }
+                Ok(Expr {
+                    kind: ExprKind::Input(self.netlist.host_inputs + lsb as usize),
+                    width: msb - lsb + 1,
+                })
+            }
+            Token::Ident(name) => {
+                let net = self.net(name)?;
+                Ok(Expr {
+                    kind: ExprKind::Net(net),
+                    width: self.netlist.widths[net],
+                })
+            }
+            token => error(format!("unexpected {token:?} in expression")),
+        }
+    }
+}
+
+impl Netlist {
+    /// Elaborates a generated `RoC` module with the given IO widths
+    pub fn elaborate(verilog: &str, inputs: usize, outputs: usize) -> Result<Netlist, ElaborationError> {
+        let tokens = tokenize(verilog)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            netlist: Netlist {
+                inputs: vec![false; inputs],
+                host_inputs: inputs,
+                outputs: vec![false; outputs],
+                ..Default::default()
+            },
+            modules: FxHashMap::default(),
+        };
+        // The other output ports besides `outputs`: the first stage of the scan chain and the
+        // trace buffer, tied off when the design has none
+        for (name, width) in [("scan_out", 1), ("trace_data", 8), ("trace_state", 2), ("trace_start", 24), ("trace_count", 24)] {
+            parser.netlist.net_names.insert(name.to_owned(), parser.netlist.nets.len());
+            parser.netlist.nets.push(0);
+            parser.netlist.widths.push(width);
+        }
+        parser.module()?;
+        let mut netlist = parser.netlist;
+        netlist.settle();
+        Ok(netlist)
+    }
+
+    /// Propagates combinational logic until every net is stable
+    fn settle(&mut self) {
+        // Every combinational path is at most as long as the number of drivers
+        let limit = self.assigns.len() + self.cells.len() + 1;
+        for _ in 0..limit {
+            let mut changed = false;
+            for (target, expr) in &self.assigns {
+                let value = expr.eval(&self.nets, &self.inputs);
+                match *target {
+                    Target::Net(net) => {
+                        let value = value & mask(self.widths[net]);
+                        changed |= self.nets[net] != value;
+                        self.nets[net] = value;
+                    }
+                    Target::Outputs(lsb, width) => {
+                        for bit in 0..width as usize {
+                            let value = (value >> bit) & 1 == 1;
+                            changed |= self.outputs[lsb + bit] != value;
+                            self.outputs[lsb + bit] = value;
+                        }
+                    }
+                    Target::Pins(lsb, width) => {
+                        for bit in 0..width as usize {
+                            let value = (value >> bit) & 1 == 1;
+                            changed |= self.pins[lsb + bit] != value;
+                            self.pins[lsb + bit] = value;
+                        }
+                    }
+                }
+            }
+            for cell in &mut self.cells {
+                let value = cell
+                    .update(&self.nets, &self.inputs)
+                    .unwrap_or_else(|err| panic!("{}: {err}", cell.name));
+                let value = value & mask(self.widths[cell.output]);
+                changed |= self.nets[cell.output] != value;
+                self.nets[cell.output] = value;
+            }
+            if !changed {
+                return;
+            }
+        }
+        panic!("combinational loop in the generated design");
+    }
+
+    /// Advances the design by one redstone tick
+    pub fn tick(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            let row = trace.probes.bits(&self.nets, &self.inputs);
+            let trigger = trace.trigger.eval(&self.nets, &self.inputs) != 0;
+            trace.recorder.record(row, trigger);
+        }
+        // Every cell's inputs were driven when the design last settled, so clocking them one
+        // after another still sees the values from before the edge
+        for cell in &mut self.cells {
+            cell.instance.clock().unwrap_or_else(|err| panic!("{}: {err}", cell.name));
+        }
+        self.settle();
+    }
+
+    /// Writes a ROM cell through its write port like `LoadROM` does, ignoring addresses no cell has
+    pub fn load_rom(&mut self, addr: u32, data: u8) {
+        for cell in self.cells.iter_mut().filter(|cell| cell.is("rom")) {
+            cell.strobe(&[("i_we", 1), ("i_addr", addr), ("i_data", data as u32)])
+                .unwrap_or_else(|err| panic!("{}: {err}", cell.name));
+        }
+        self.settle();
+    }
+
+    /// Captures every stage of the scan chain and shifts it out like `Scan` does,
+    /// first stage first and each stage least significant bit first
+    pub fn scan(&mut self) -> Vec<bool> {
+        for cell in self.cells.iter_mut().filter(|cell| cell.is("scan")) {
+            cell.strobe(&[("i_load", 1)]).unwrap_or_else(|err| panic!("{}: {err}", cell.name));
+        }
+        self.settle();
+
+        let mut bits = Vec::new();
+        let mut next = self.net_names.get("scan_out").copied();
+        while let Some(net) = next.take() {
+            let Some(cell) = self.cells.iter().find(|cell| cell.output == net && cell.is("scan")) else {
+                break;
+            };
+            let (Ok(buffer), Ok(width)) = (cell.instance.value("buffer"), cell.instance.width("buffer")) else {
+                break;
+            };
+            bits.extend((0..width).map(|bit| (buffer >> bit) & 1 == 1));
+            if let Some((_, Expr { kind: ExprKind::Net(chain), .. })) = cell.ports.iter().find(|(port, _)| port == "i_chain") {
+                next = Some(*chain);
+            }
+        }
+        bits
+    }
+
+    /// Starts a new trace capture with the next tick, like `TraceArm` does
+    pub fn arm_trace(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.recorder.arm();
+        }
+    }
+
+    /// Where the trace buffer's recording stands, like `TraceStatus` reads it
+    pub fn trace_status(&self) -> TraceStatus {
+        self.trace.as_ref().map_or_else(TraceStatus::default, |trace| trace.recorder.status())
+    }
+
+    /// A row of the trace buffer packed like `ReadTrace` sends it
+    pub fn trace_row(&self, addr: u32) -> Vec<u8> {
+        match &self.trace {
+            Some(trace) => {
+                let mut row = pack(trace.recorder.row(addr));
+                row.resize(trace.probes.width.div_ceil(8) as usize, 0);
+                row
+            }
+            None => vec![0],
+        }
+    }
+
+    /// The trace recorded so far, rows oldest first
+    pub fn trace_rows(&self) -> Vec<Vec<bool>> {
+        self.trace.as_ref().map_or_else(Vec::new, |trace| trace.recorder.rows())
+    }
+
+    pub fn set_input(&mut self, bit: usize, value: bool) {
+        self.inputs[bit] = value;
+        self.settle();
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.host_inputs
+    }
+
+    pub fn outputs(&self) -> &[bool] {
+        &self.outputs
+    }
+
+    /// Sets a pin read from a bound switch or button, pins the design does not read are ignored
+    pub fn set_pin(&mut self, bit: usize, value: bool) {
+        if let Some(pin) = self.inputs.get_mut(self.host_inputs + bit) {
+            *pin = value;
+            self.settle();
+        }
+    }
+
+    /// Pins driven for bound LEDs and displays
+    pub fn pins(&self) -> &[bool] {
+        &self.pins
+    }
+
+    /// Outputs packed the same way the command controller sends them
+    pub fn output_bytes(&self) -> Vec<u8> {
+        pack(&self.outputs)
+    }
+
+    /// Current value of a net
+    pub fn net(&self, name: &str) -> Option<u32> {
+        self.net_names.get(name).map(|net| self.nets[*net])
+    }
+
+    /// Current value driven into a port of a component instance
+    pub fn port(&self, cell: &str, port: &str) -> Option<u32> {
+        let cell = self.cells.iter().find(|c| c.name == cell)?;
+        Some(cell.port(port, &self.nets, &self.inputs))
+    }
+}
+
+/// Packs bits into bytes least significant bit first, the way the command controller sends them
+pub fn pack(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0; bits.len().div_ceil(8)];
+    for (bit, value) in bits.iter().enumerate() {
+        bytes[bit / 8] |= (*value as u8) << (bit % 8);
+    }
+    bytes
+}
+
+/// Runs the FPGA code generation in the netlist interpreter instead of on a board.
+///
+/// Inputs are sampled on the tick edge like on hardware, so pending ticks from the
+/// world and pulses shorter than a tick are not carried over. Given boards with a
+/// capacity, the build is partitioned and every board's share is simulated on its own,
+/// with the nets between them forwarded like [`FPGABackend`](super::FPGABackend) does.
+#[derive(Default)]
+pub struct SimBackend {
+    parts: Vec<(Netlist, Linker)>,
+    cuts: Vec<CutLink>,
+}
+
+impl SimBackend {
+    /// Forwards the nets cut between partitions until they settle
+    fn sync(&mut self) {
+        if self.cuts.is_empty() {
+            return;
+        }
+        for _ in 0..=self.parts.len() {
+            let outputs: Vec<Vec<bool>> = self.parts.iter().map(|(netlist, _)| netlist.outputs().to_vec()).collect();
+            let inputs: Vec<Vec<bool>> = self.parts.iter().map(|(netlist, _)| netlist.inputs.clone()).collect();
+            let changes = partition::forward(&self.cuts, &outputs, &inputs);
+            if changes.is_empty() {
+                return;
+            }
+            for (target, bit, value) in changes {
+                self.parts[target].0.set_input(bit as usize, value);
+            }
+        }
+        panic!("nets between partitions did not settle");
+    }
+}
+
+impl JITBackend for SimBackend {
+    fn compile(
+        &mut self,
+        graph: CompileGraph,
+        _ticks: Vec<TickEntry>,
+        _plot: String,
+        name: String,
+        devices: &[DeviceConfig],
+        options: &CompilerOptions,
+        _monitor: &TaskMonitor,
+    ) -> Result<(), String> {
+        let (parts, cuts) = if devices.is_empty() {
+            (vec![Partition::whole(&graph)], Vec::new())
+        } else {
+            partition::partition(&graph, devices).map_err(|err| err.to_string())?
+        };
+        let traced = trace::owner(&graph, &parts, &options.trace);
+
+        for (idx, part) in parts.iter().enumerate() {
+            let mut link = Linker {
+                name: name.clone(),
+                ..Default::default()
+            };
+            for &nodeid in &part.members {
+                if let Some((pos, blockid)) = graph[nodeid].block {
+                    link.add_block(Block::from_id(blockid), pos);
+                    link.annotate(pos, &graph[nodeid].annotations);
+                }
+            }
+
+            rom::link(&graph, part, &mut link);
+            scan::link(&graph, part, &mut link);
+            let plan = if traced == Some(idx) { trace::plan(&graph, part, &options.trace) } else { None };
+            if let Some(plan) = &plan {
+                trace::link(plan, &mut link);
+            }
+
+            let pin_plan = pins::plan(&graph, part, devices.get(idx))?;
+            pins::link(&graph, &pin_plan, &mut link);
+
+            let verilog = assembler::generate_partition(&graph, part, plan.as_ref(), &pin_plan);
+            let inputs = link.input_bits + part.input_bits(&graph) - part.own_inputs(&graph);
+            let outputs = link.output_bits + part.output_bits(&graph) - part.own_outputs(&graph);
+            let mut netlist = Netlist::elaborate(&verilog, inputs as usize, outputs as usize).map_err(|err| {
+                warn!("Could not elaborate the generated design:\n{}", verilog);
+                err.to_string()
+            })?;
+            for (bit, state) in link.input_states().into_iter().enumerate() {
+                netlist.set_input(bit, state);
+            }
+            for (addr, data) in link.rom_contents() {
+                netlist.load_rom(addr, data);
+            }
+            self.parts.push((netlist, link));
+        }
+        self.cuts = cuts;
+        self.sync();
+        Ok(())
+    }
+
+    fn run(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    fn tick(&mut self) {
+        for (netlist, _) in &mut self.parts {
+            netlist.tick();
+        }
+        self.sync();
+    }
+
+    fn on_use_block(&mut self, pos: BlockPos) {
+        for (netlist, link) in &mut self.parts {
+            if let Some((id, _, state)) = link.toggle_input(pos) {
+                netlist.set_input(id as usize, state == 1);
+            }
+        }
+        self.sync();
+    }
+
+    fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        let mut found = false;
+        for (netlist, link) in &mut self.parts {
+            if let Some(id) = link.set_input(pos, powered as u8) {
+                netlist.set_input(id as usize, powered);
+                found = true;
+            }
+        }
+        if !found {
+            warn!("Tried to set pressure plate state for a {:?} which is not an input", pos);
+        }
+        self.sync();
+    }
+
+    fn flush<W: World>(&mut self, world: &mut W, io_only: bool) {
+        for (netlist, link) in &mut self.parts {
+            let mut outputs = BinaryIterator::new(netlist.output_bytes());
+            for (pos, block) in link.get_blocks_to_change(&mut outputs) {
+                world.set_block(pos, block);
+            }
+        }
+        if io_only {
+            return;
+        }
+        // Components only visible inside the design are read back over the scan chain like on a board
+        for (netlist, link) in &mut self.parts {
+            let mut scan = BinaryIterator::new(pack(&netlist.scan()));
+            for (pos, block) in link.read_scan(&mut scan) {
+                world.set_block(pos, block);
+            }
+        }
+    }
+
+    fn reset<W: World>(&mut self, world: &mut W, io_only: bool) {
+        self.flush(world, io_only);
+        for (netlist, link) in &mut self.parts {
+            scan::write_back(link, pack(&netlist.scan()), world);
+        }
+    }
+
+    fn has_pending_ticks(&self) -> bool {
+        false
+    }
+
+    fn inspect(&mut self, pos: BlockPos) {
+        for (netlist, link) in &mut self.parts {
+            if link.scan_stage(pos).is_none() {
+                continue;
+            }
+            link.read_scan(&mut BinaryIterator::new(pack(&netlist.scan())));
+            if let Some(stage) = link.scan_stage(pos) {
+                debug!("{}: {:?} (state {})", pos, stage.get_block(), stage.state());
+            }
+        }
+    }
+
+    fn set_rtps(&mut self, _rtps: u32) {}
+
+    fn arm_trace(&mut self) {
+        for (netlist, _) in &mut self.parts {
+            netlist.arm_trace();
+        }
+    }
+
+    fn trace(&mut self) -> Option<Trace> {
+        let (netlist, link) = self.parts.iter().find(|(_, link)| link.trace.is_some())?;
+        let layout = link.trace.as_ref()?;
+        Some(Trace {
+            probes: layout.probes.clone(),
+            rows: netlist.trace_rows(),
+            trigger: netlist.trace_status().trigger_row(layout),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a module of `components.sv` with random inputs and ticks, which fails if it uses
+    /// Verilog the interpreter does not understand
+    fn run_module(module: &str, params: &[u32]) {
+        let module = hdl::Module::parse(module).unwrap();
+        let mut instance = module.instantiate(params).unwrap();
+        let ports: Vec<&str> = ["i_in", "i_side", "i_lock", "i_we", "i_addr", "i_data", "i_load", "i_shift", "i_chain"]
+            .into_iter()
+            .filter(|port| module.has_port(port))
+            .collect();
+
+        let mut seed = 0x9E37_79B9u32;
+        for _ in 0..200 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            if seed % 4 == 3 {
+                instance.clock().unwrap();
+            } else {
+                instance.set_input(ports[(seed >> 2) as usize % ports.len()], seed >> 8).unwrap();
+            }
+            instance.value("o_out").unwrap();
+        }
+    }
+
+    #[test]
+    fn components_run_in_hdl() {
+        for delay in 1..=4 {
+            for state in 0..=1 {
+                for (lock_out, lockable) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    run_module("repeater", &[delay, state, lock_out, lockable]);
+                }
+            }
+        }
+        for state in 0..=1 {
+            run_module("torch", &[state]);
+            run_module("lamp", &[state]);
+            run_module("observer", &[state, 9]);
+            for ticks in [1, 10, 15] {
+                run_module("button", &[state, ticks]);
+            }
+        }
+        for mode in 0..=1 {
+            for far_en in 0..=1 {
+                run_module("comp", &[7, mode, far_en, 12]);
+            }
+        }
+        run_module("lut", &[5]);
+        for width in [1, 4] {
+            run_module("rom", &[3, width, 15, 0]);
+        }
+        for width in [1, 4, 32] {
+            run_module("scan", &[width]);
+        }
+    }
+
+    #[test]
+    fn cells_follow_their_module() {
+        let verilog = "
+            module RoC (tick, inputs, outputs);
+                wire w0, w1;
+                torch #(1'b0) t0 (.i_clk(tick), .i_in(inputs[0]), .o_out(w0));
+                repeater #(2, 1'b0, 0, 0) r0 (.i_clk(tick), .i_in(w0), .i_lock(1'b0), .o_out(w1));
+                assign outputs[0] = w0;
+                assign outputs[1] = w1;
+            endmodule
+        ";
+        let mut netlist = Netlist::elaborate(verilog, 1, 2).unwrap();
+        assert_eq!(netlist.outputs(), [false, false]);
+        netlist.tick();
+        assert_eq!(netlist.outputs(), [true, false]);
+        netlist.tick();
+        netlist.tick();
+        assert_eq!(netlist.outputs(), [true, true]);
+        netlist.set_input(0, true);
+        assert_eq!(netlist.outputs(), [true, true]);
+        netlist.tick();
+        assert_eq!(netlist.outputs(), [false, true]);
+        assert_eq!(netlist.port("r0", "i_in"), Some(0));
+    }
+}
@@ -0,0 +1,98 @@
+use super::{FpgaToolchain, Project};
+use crate::fpga::compiler::{CompilerResults, DeviceConfig, ProgramResults};
+use crate::fpga::interface::design_hash;
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use crate::fpga::mock::MockServer;
+use crate::fpga::sim::Netlist;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Emulated boards by the address they serve, kept alive for the lifetime of the server
+static BOARDS: Mutex<Vec<(String, MockServer)>> = Mutex::new(Vec::new());
+
+/// Runs designs on an emulated board inside the server instead of on hardware.
+///
+/// "Programming" starts a [`MockServer`] on the device's `command_com`, which has to be a
+/// `tcp://host:port` address, so the rest of the FPGA flow works without a board attached.
+#[derive(Debug, Clone, Default)]
+pub struct Emulator;
+
+/// Elaborates the design in the build directory, sized by its `parameters.vh`. The emulated
+/// board identifies with the hash of the `redstone.sv` it actually loaded.
+fn load_design(build_dir: &Path) -> io::Result<(Netlist, u32)> {
+    let verilog = fs::read_to_string(build_dir.join("redstone.sv"))?;
+    let parameters = fs::read_to_string(build_dir.join("parameters.vh"))?;
+    let parameter = |name: &str| {
+        parameters
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("parameter {name} = "))?.strip_suffix(';')?.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("parameters.vh does not set {name}")))
+    };
+    let netlist = Netlist::elaborate(&verilog, parameter("ROC_INPUTS")?, parameter("ROC_OUTPUTS")?).map_err(io::Error::other)?;
+    Ok((netlist, design_hash(verilog.as_bytes())))
+}
+
+fn program_board(addr: &str, (design, hash): (Netlist, u32)) -> io::Result<()> {
+    let mut boards = BOARDS.lock().unwrap();
+    if let Some((_, board)) = boards.iter().find(|(board_addr, _)| board_addr == addr) {
+        board.load(design);
+        board.set_design_hash(hash);
+        return Ok(());
+    }
+    let board = MockServer::bind(addr)?;
+    board.load(design);
+    board.set_design_hash(hash);
+    boards.push((addr.to_owned(), board));
+    Ok(())
+}
+
+impl FpgaToolchain for Emulator {
+    fn bitstream_name(&self) -> &'static str {
+        "redstone.sv"
+    }
+
+    fn create_project(&self, _device: &DeviceConfig, project: &Project) -> io::Result<()> {
+        project.write_parameters()
+    }
+
+    fn compile(&self, _device: &DeviceConfig, project: &Project, monitor: &TaskMonitor) -> CompilerResults {
+        let log = project.compile_log();
+        monitor.set_message("Elaborating".to_string());
+        match load_design(&project.build_dir) {
+            Ok(_) => {
+                _ = fs::write(&log, "design elaborated for the emulator\n");
+                CompilerResults {
+                    success: true,
+                    log,
+                    ..Default::default()
+                }
+            }
+            Err(err) => {
+                _ = fs::write(&log, format!("{err}\n"));
+                CompilerResults::failed(log)
+            }
+        }
+    }
+
+    fn program(&self, device: &DeviceConfig, build_dir: &Path) -> ProgramResults {
+        let log = build_dir.join("program.log");
+        let result = match device.command_com.strip_prefix("tcp://") {
+            Some(addr) => load_design(build_dir).and_then(|design| program_board(addr, design)),
+            None => Err(io::Error::other(format!(
+                "the emulator serves tcp:// command ports, not {}",
+                device.command_com
+            ))),
+        };
+        let message = match &result {
+            Ok(()) => format!("emulating {} on {}\n", device.name, device.command_com),
+            Err(err) => format!("{err}\n"),
+        };
+        _ = fs::write(&log, message);
+        ProgramResults {
+            success: result.is_ok(),
+            log,
+        }
+    }
+}
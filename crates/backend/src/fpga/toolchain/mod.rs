@@ -0,0 +1,264 @@
+//! FPGA vendor toolchains used to turn a generated design into a bitstream and program it.
+
+mod emulator;
+mod quartus;
+mod yosys;
+
+pub use emulator::Emulator;
+pub use quartus::Quartus;
+pub use yosys::Yosys;
+
+use crate::fpga::compiler::{CompilerResults, DeviceConfig, ProgramResults};
+use enum_dispatch::enum_dispatch;
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Root of the RoC HDL sources
+pub const HDL_ROOT: &str = "FPGA";
+
+/// HDL sources shared by every design, relative to [`HDL_ROOT`]
+const HDL_SOURCES: &[&str] = &[
+    "src/top.sv",
+    "src/interface/uart.sv",
+    "src/interface/clk_div.sv",
+    "src/interface/command_controller.sv",
+    "src/redstone/components.sv",
+    "src/redstone/tps_clk_div.sv",
+];
+
+/// Everything a toolchain needs to know about the design being built
+#[derive(Debug, Clone)]
+pub struct Project {
+    /// Directory holding the generated `redstone.sv`, `parameters.vh` and the final bitstream
+    pub build_dir: PathBuf,
+    /// Scratch directory for the toolchain's own project files
+    pub project_dir: PathBuf,
+    pub outputs: u32,
+    pub inputs: u32,
+    /// Length of the design's scan chain
+    pub scan_bits: u32,
+    /// Bytes of a row of the design's trace buffer
+    pub trace_bytes: u32,
+    /// Board pins read into each bit of the design's `pin_in`
+    pub pin_inputs: Vec<String>,
+    /// Board pins driven by each bit of the design's `pin_out`
+    pub pin_outputs: Vec<String>,
+    /// Hash of the generated design, answered by the board to `Identify`
+    pub design_hash: u32,
+}
+
+impl Project {
+    pub fn new(build_dir: impl Into<PathBuf>, outputs: u32, inputs: u32) -> Project {
+        let build_dir = build_dir.into();
+        Project {
+            project_dir: build_dir.join("prj"),
+            build_dir,
+            outputs,
+            inputs,
+            scan_bits: 0,
+            trace_bytes: 0,
+            pin_inputs: Vec::new(),
+            pin_outputs: Vec::new(),
+            design_hash: 0,
+        }
+    }
+
+    /// All HDL files that make up the design, as absolute paths
+    pub fn sources(&self) -> io::Result<Vec<PathBuf>> {
+        let root = fs::canonicalize(HDL_ROOT)?;
+        let mut sources: Vec<PathBuf> = HDL_SOURCES.iter().map(|src| root.join(src)).collect();
+        sources.push(fs::canonicalize(self.build_dir.join("redstone.sv"))?);
+        Ok(sources)
+    }
+
+    /// Top level port of every bound peripheral pin, with the pin
+    pub fn pins(&self) -> impl Iterator<Item = (String, &str)> {
+        let inputs = self.pin_inputs.iter().enumerate().map(|(bit, pin)| (format!("i_pins[{bit}]"), pin.as_str()));
+        let outputs = self.pin_outputs.iter().enumerate().map(|(bit, pin)| (format!("o_pins[{bit}]"), pin.as_str()));
+        inputs.chain(outputs)
+    }
+
+    /// Writes the headers `top.sv` includes to size the design's IO
+    pub fn write_parameters(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.build_dir)?;
+        fs::write(
+            self.build_dir.join("parameters.vh"),
+            format!(
+                "parameter ROC_OUTPUTS = {};\nparameter ROC_INPUTS = {};\nparameter ROC_SCAN_BITS = {};\nparameter ROC_TRACE_BYTES = {};\nparameter ROC_DESIGN_HASH = 32'h{:08x};\n",
+                self.outputs.max(1),
+                self.inputs.max(1),
+                self.scan_bits,
+                self.trace_bytes.max(1),
+                self.design_hash
+            ),
+        )?;
+        // The ports for bound peripherals only exist when there are any, so no stray pin is driven
+        let mut pins = String::new();
+        if !self.pin_inputs.is_empty() {
+            pins.push_str(&format!("`define ROC_PIN_INPUTS {}\n", self.pin_inputs.len()));
+        }
+        if !self.pin_outputs.is_empty() {
+            pins.push_str(&format!("`define ROC_PIN_OUTPUTS {}\n", self.pin_outputs.len()));
+        }
+        fs::write(self.build_dir.join("pins.vh"), pins)
+    }
+
+    /// Directory of the portable copy of the design written for builds that are only generated
+    pub fn bundle_dir(&self) -> PathBuf {
+        self.build_dir.join("hdl")
+    }
+
+    /// Copies every source of the design next to its headers, so it can be synthesized or
+    /// simulated on another machine. `files.f` lists the sources in order for the usual
+    /// simulators, and `pins.csv` the board pin of every top level port. The tick clock is
+    /// the generic one instead of the vendor PLL.
+    pub fn write_bundle(&self, device: &DeviceConfig) -> io::Result<()> {
+        self.write_parameters()?;
+        let dir = self.bundle_dir();
+        fs::create_dir_all(&dir)?;
+        let root = Path::new(HDL_ROOT);
+        let mut files = String::from("+incdir+.\n");
+        let sources = HDL_SOURCES
+            .iter()
+            .chain(&["src/interface/tick_clk_generic.sv"])
+            .map(|src| root.join(src))
+            .chain([self.build_dir.join("redstone.sv")]);
+        for source in sources {
+            let name = source.file_name().unwrap_or_default();
+            fs::copy(&source, dir.join(name))?;
+            files.push_str(&format!("{}\n", name.to_string_lossy()));
+        }
+        for header in ["parameters.vh", "pins.vh"] {
+            fs::copy(self.build_dir.join(header), dir.join(header))?;
+        }
+        fs::write(dir.join("files.f"), files)?;
+
+        let mut pins = String::from("port,pin\n");
+        let ports = device.pin_assignments.iter().map(|(port, pin)| (port.to_owned(), pin)).chain(self.pins());
+        for (port, pin) in ports {
+            pins.push_str(&format!("{},{}\n", port, pin));
+        }
+        fs::write(dir.join("pins.csv"), pins)
+    }
+
+    pub fn compile_log(&self) -> PathBuf {
+        self.build_dir.join("compile.log")
+    }
+
+    pub fn program_log(&self) -> PathBuf {
+        self.build_dir.join("program.log")
+    }
+}
+
+#[enum_dispatch]
+pub trait FpgaToolchain {
+    /// File name of the bitstream this toolchain produces
+    fn bitstream_name(&self) -> &'static str;
+    /// Writes the toolchain's project files into the project directory
+    fn create_project(&self, device: &DeviceConfig, project: &Project) -> io::Result<()>;
+    /// Synthesizes, places and routes the project, leaving the bitstream in the build directory.
+    /// Each stage is reported to the monitor, and cancelling it stops the running tool.
+    fn compile(&self, device: &DeviceConfig, project: &Project, monitor: &TaskMonitor) -> CompilerResults;
+    /// Programs the device with the bitstream found in the build directory
+    fn program(&self, device: &DeviceConfig, build_dir: &Path) -> ProgramResults;
+}
+
+#[enum_dispatch(FpgaToolchain)]
+#[derive(Debug, Clone)]
+pub enum Toolchain {
+    Quartus,
+    Yosys,
+    Emulator,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolchainKind {
+    #[default]
+    Quartus,
+    Yosys,
+    Emulator,
+}
+
+impl FromStr for ToolchainKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "quartus" => Ok(ToolchainKind::Quartus),
+            "yosys" | "nextpnr" | "open" => Ok(ToolchainKind::Yosys),
+            "emulator" | "mock" => Ok(ToolchainKind::Emulator),
+            _ => Err(format!("unknown fpga compiler: {s}")),
+        }
+    }
+}
+
+/// Server wide toolchain settings
+#[derive(Debug, Clone, Default)]
+pub struct ToolchainConfig {
+    pub kind: ToolchainKind,
+    /// Directory containing the toolchain's executables, `None` to search `PATH`
+    pub tools_path: Option<PathBuf>,
+}
+
+impl ToolchainConfig {
+    pub fn new(compiler: &str, tools_path: &str) -> Result<ToolchainConfig, String> {
+        Ok(ToolchainConfig {
+            kind: compiler.parse()?,
+            tools_path: (!tools_path.is_empty()).then(|| PathBuf::from(tools_path)),
+        })
+    }
+
+    pub fn toolchain(&self) -> Toolchain {
+        let tools = Tools(self.tools_path.clone());
+        match self.kind {
+            ToolchainKind::Quartus => Quartus::new(tools).into(),
+            ToolchainKind::Yosys => Yosys::new(tools).into(),
+            ToolchainKind::Emulator => Emulator.into(),
+        }
+    }
+}
+
+/// Resolves toolchain executables
+#[derive(Debug, Clone, Default)]
+struct Tools(Option<PathBuf>);
+
+impl Tools {
+    fn command(&self, tool: &str) -> Command {
+        match &self.0 {
+            Some(path) => Command::new(path.join(tool)),
+            None => Command::new(tool),
+        }
+    }
+}
+
+/// How often a running tool is checked for having finished or been cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs a tool with its output appended to `log`, returning whether it exited successfully.
+/// The tool is killed if the monitor is cancelled while it runs.
+fn run_logged(mut command: Command, dir: &Path, log: &Path, monitor: &TaskMonitor) -> io::Result<bool> {
+    let log = File::options().create(true).append(true).open(log)?;
+    let mut child = command
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .spawn()?;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+        if monitor.cancelled() {
+            child.kill()?;
+            child.wait()?;
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
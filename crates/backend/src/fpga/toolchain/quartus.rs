@@ -0,0 +1,157 @@
+use super::{run_logged, FpgaToolchain, Project, Tools, HDL_ROOT};
+use crate::fpga::compiler::{CompilerResults, DeviceConfig, ProgramResults, ResourceUsage};
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Intel Quartus Prime, driven through `quartus_sh` and `quartus_pgm`
+#[derive(Debug, Clone, Default)]
+pub struct Quartus {
+    tools: Tools,
+}
+
+impl Quartus {
+    pub(super) fn new(tools: Tools) -> Quartus {
+        Quartus { tools }
+    }
+}
+
+/// Tools `quartus_sh --flow compile` runs, with what they are reported as
+const QUARTUS_STAGES: &[(&str, &str)] = &[
+    ("quartus_map", "Synthesizing"),
+    ("quartus_fit", "Fitting"),
+    ("quartus_asm", "Assembling bitstream"),
+    ("quartus_sta", "Timing analysis"),
+];
+
+/// Quartus' TCL interpreter wants forward slashes and no verbatim prefix
+fn tcl_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    path.strip_prefix("//?/").map(str::to_owned).unwrap_or(path)
+}
+
+impl FpgaToolchain for Quartus {
+    fn bitstream_name(&self) -> &'static str {
+        "RoC.sof"
+    }
+
+    fn create_project(&self, device: &DeviceConfig, project: &Project) -> io::Result<()> {
+        fs::create_dir_all(&project.project_dir)?;
+        project.write_parameters()?;
+        let ip = fs::canonicalize(HDL_ROOT)?.join("ip");
+
+        let mut tcl = format!(
+        "package require ::quartus::project
+project_new -overwrite -revision RoC RoC
+set_global_assignment -name FAMILY \"{family}\"
+set_global_assignment -name DEVICE {device}
+set_global_assignment -name TOP_LEVEL_ENTITY top
+set_global_assignment -name SEARCH_PATH \"{build_dir}\"\n",
+        family = device.family,
+        device = device.device,
+        build_dir = tcl_path(&fs::canonicalize(&project.build_dir)?),
+        );
+
+        for source in project.sources()? {
+            tcl.push_str(&format!("set_global_assignment -name SYSTEMVERILOG_FILE \"{}\"\n", tcl_path(&source)));
+        }
+        tcl.push_str(&format!("set_global_assignment -name SOURCE_FILE \"{}\"\n", tcl_path(&ip.join("tick_clk.cmp"))));
+        tcl.push_str(&format!("set_global_assignment -name QIP_FILE \"{}\"\n", tcl_path(&ip.join("tick_clk.qip"))));
+        tcl.push_str(&format!("set_global_assignment -name SIP_FILE \"{}\"\n", tcl_path(&ip.join("tick_clk.sip"))));
+
+        for (port, pin) in device.pin_assignments.iter() {
+            tcl.push_str(&format!("set_location_assignment PIN_{} -to {}\n", pin, port));
+        }
+        for (port, pin) in project.pins() {
+            // Braced so TCL leaves the bit select alone
+            tcl.push_str(&format!("set_location_assignment PIN_{} -to {{{}}}\n", pin, port));
+        }
+
+        tcl.push_str("export_assignments\nproject_close\n");
+        fs::write(project.project_dir.join("prj.tcl"), tcl)?;
+
+        let mut command = self.tools.command("quartus_sh");
+        command.args(["-t", "prj.tcl"]);
+        if !run_logged(command, &project.project_dir, &project.compile_log(), &TaskMonitor::default())? {
+            return Err(io::Error::other("quartus_sh failed to create the project"));
+        }
+        Ok(())
+    }
+
+    fn compile(&self, _device: &DeviceConfig, project: &Project, monitor: &TaskMonitor) -> CompilerResults {
+        let log = project.compile_log();
+        let mut success = true;
+        for (tool, stage) in QUARTUS_STAGES {
+            monitor.set_message(stage.to_string());
+            let mut command = self.tools.command(tool);
+            command.arg("RoC");
+            match run_logged(command, &project.project_dir, &log, monitor) {
+                Ok(true) => {}
+                Ok(false) => {
+                    success = false;
+                    break;
+                }
+                Err(_) => return CompilerResults::failed(log),
+            }
+        }
+
+        let bitstream = project.project_dir.join(self.bitstream_name());
+        let copied = fs::copy(&bitstream, project.build_dir.join(self.bitstream_name())).is_ok();
+
+        CompilerResults {
+            success: success && copied,
+            utilization: fs::read_to_string(project.project_dir.join("RoC.fit.summary"))
+                .map(|summary| parse_fit_summary(&summary))
+                .unwrap_or_default(),
+            fmax: fs::read_to_string(project.project_dir.join("RoC.sta.rpt"))
+                .ok()
+                .and_then(|report| parse_fmax(&report)),
+            log,
+        }
+    }
+
+    fn program(&self, device: &DeviceConfig, build_dir: &Path) -> ProgramResults {
+        let log = build_dir.join("program.log");
+        let mut command = self.tools.command("quartus_pgm");
+        command.args(["-c", &device.program_com, "-m", "jtag", "-o"]);
+        command.arg(format!("p;{}@{}", self.bitstream_name(), device.jtag_index.unwrap_or(1)));
+        ProgramResults {
+            success: run_logged(command, build_dir, &log, &TaskMonitor::default()).unwrap_or(false),
+            log,
+        }
+    }
+}
+
+/// Reads lines like `Total registers : 1,234` or `Logic utilization (in ALMs) : 12 / 32,070 ( < 1 % )`
+fn parse_fit_summary(summary: &str) -> Vec<ResourceUsage> {
+    let number = |s: &str| s.trim().replace(',', "").parse::<u64>().ok();
+    summary
+        .lines()
+        .filter_map(|line| {
+            let (resource, value) = line.split_once(" : ")?;
+            let value = value.split('(').next().unwrap();
+            let (used, available) = match value.split_once('/') {
+                Some((used, available)) => (number(used)?, Some(number(available)?)),
+                None => (number(value)?, None),
+            };
+            Some(ResourceUsage {
+                resource: resource.trim().to_owned(),
+                used,
+                available,
+            })
+        })
+        .collect()
+}
+
+/// Takes the slowest clock over every timing corner's `Fmax Summary` table
+fn parse_fmax(report: &str) -> Option<f64> {
+    report
+        .lines()
+        .filter_map(|line| {
+            let row = line.strip_prefix("; ")?;
+            let (fmax, _) = row.split_once(" MHz ;")?;
+            fmax.trim().parse::<f64>().ok()
+        })
+        .reduce(f64::min)
+}
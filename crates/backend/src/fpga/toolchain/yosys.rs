@@ -0,0 +1,196 @@
+use super::{run_logged, FpgaToolchain, Project, Tools, HDL_ROOT};
+use crate::fpga::compiler::{CompilerResults, DeviceConfig, ProgramResults, ResourceUsage};
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The open source flow: Yosys for synthesis, nextpnr for place and route and openFPGALoader for programming.
+///
+/// For this flow `DeviceConfig::device` is the nextpnr device flag (e.g. `25k` or `hx8k`)
+/// and `DeviceConfig::package` the package name.
+#[derive(Debug, Clone, Default)]
+pub struct Yosys {
+    tools: Tools,
+}
+
+impl Yosys {
+    pub(super) fn new(tools: Tools) -> Yosys {
+        Yosys { tools }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arch {
+    Ecp5,
+    Ice40,
+}
+
+impl Arch {
+    fn from_family(family: &str) -> io::Result<Arch> {
+        let family = family.to_ascii_lowercase();
+        if family.contains("ecp5") {
+            Ok(Arch::Ecp5)
+        } else if family.contains("ice40") {
+            Ok(Arch::Ice40)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("the yosys flow does not support the {family} family"),
+            ))
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Arch::Ecp5 => "ecp5",
+            Arch::Ice40 => "ice40",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NextpnrReport {
+    #[serde(default)]
+    utilization: HashMap<String, NextpnrUsage>,
+    #[serde(default)]
+    fmax: HashMap<String, NextpnrFmax>,
+}
+
+#[derive(Deserialize)]
+struct NextpnrUsage {
+    used: u64,
+    available: u64,
+}
+
+#[derive(Deserialize)]
+struct NextpnrFmax {
+    achieved: f64,
+}
+
+impl FpgaToolchain for Yosys {
+    fn bitstream_name(&self) -> &'static str {
+        "RoC.bit"
+    }
+
+    fn create_project(&self, device: &DeviceConfig, project: &Project) -> io::Result<()> {
+        let arch = Arch::from_family(&device.family)?;
+        fs::create_dir_all(&project.project_dir)?;
+        project.write_parameters()?;
+
+        let mut sources = project.sources()?;
+        // Vendor PLL IP is not available here, so the tick clock runs straight off the board clock
+        sources.push(fs::canonicalize(HDL_ROOT)?.join("src/interface/tick_clk_generic.sv"));
+
+        let mut script = format!("read_verilog -sv -I{}", fs::canonicalize(&project.build_dir)?.display());
+        for source in sources {
+            script.push_str(&format!(" {}", source.display()));
+        }
+        script.push_str(&format!("\nsynth_{} -top top -json RoC.json\n", arch.name()));
+        fs::write(project.project_dir.join("RoC.ys"), script)?;
+
+        let mut constraints = String::new();
+        let pins = device.pin_assignments.iter().map(|(port, pin)| (port.to_owned(), pin)).chain(project.pins());
+        for (port, pin) in pins {
+            match arch {
+                Arch::Ecp5 => constraints.push_str(&format!("LOCATE COMP \"{port}\" SITE \"{pin}\";\n")),
+                Arch::Ice40 => constraints.push_str(&format!("set_io {port} {pin}\n")),
+            }
+        }
+        let constraints_file = match arch {
+            Arch::Ecp5 => "pins.lpf",
+            Arch::Ice40 => "pins.pcf",
+        };
+        fs::write(project.project_dir.join(constraints_file), constraints)
+    }
+
+    fn compile(&self, device: &DeviceConfig, project: &Project, monitor: &TaskMonitor) -> CompilerResults {
+        let log = project.compile_log();
+        let Ok(arch) = Arch::from_family(&device.family) else {
+            return CompilerResults::failed(log);
+        };
+        let dir = &project.project_dir;
+        let bitstream = match fs::canonicalize(&project.build_dir) {
+            Ok(build_dir) => build_dir.join(self.bitstream_name()),
+            Err(_) => return CompilerResults::failed(log),
+        };
+
+        let mut yosys = self.tools.command("yosys");
+        yosys.args(["-s", "RoC.ys"]);
+
+        let mut nextpnr = self.tools.command(&format!("nextpnr-{}", arch.name()));
+        nextpnr.arg(format!("--{}", device.device));
+        if let Some(package) = &device.package {
+            nextpnr.args(["--package", package]);
+        }
+        nextpnr.args(["--json", "RoC.json", "--report", "report.json"]);
+
+        let pack = match arch {
+            Arch::Ecp5 => {
+                nextpnr.args(["--lpf", "pins.lpf", "--textcfg", "RoC.config"]);
+                let mut ecppack = self.tools.command("ecppack");
+                ecppack.arg("RoC.config").arg(&bitstream);
+                ecppack
+            }
+            Arch::Ice40 => {
+                nextpnr.args(["--pcf", "pins.pcf", "--asc", "RoC.asc"]);
+                let mut icepack = self.tools.command("icepack");
+                icepack.arg("RoC.asc").arg(&bitstream);
+                icepack
+            }
+        };
+
+        let mut success = true;
+        for (command, stage) in [(yosys, "Synthesizing"), (nextpnr, "Placing and routing"), (pack, "Packing bitstream")] {
+            monitor.set_message(stage.to_string());
+            if !run_logged(command, dir, &log, monitor).unwrap_or(false) {
+                success = false;
+                break;
+            }
+        }
+
+        let report: Option<NextpnrReport> = fs::read_to_string(dir.join("report.json"))
+            .ok()
+            .and_then(|report| serde_json::from_str(&report).ok());
+        let (utilization, fmax) = match report {
+            Some(report) => (
+                report
+                    .utilization
+                    .into_iter()
+                    .map(|(resource, usage)| ResourceUsage {
+                        resource,
+                        used: usage.used,
+                        available: Some(usage.available),
+                    })
+                    .collect(),
+                report.fmax.values().map(|fmax| fmax.achieved).reduce(f64::min),
+            ),
+            None => (Vec::new(), None),
+        };
+
+        CompilerResults {
+            success,
+            utilization,
+            fmax,
+            log,
+        }
+    }
+
+    fn program(&self, device: &DeviceConfig, build_dir: &Path) -> ProgramResults {
+        let log = build_dir.join("program.log");
+        let mut command = self.tools.command("openFPGALoader");
+        if !device.program_com.is_empty() {
+            command.args(["-c", &device.program_com]);
+        }
+        if Arch::from_family(&device.family).ok() == Some(Arch::Ice40) {
+            command.args(["--file-type", "bin"]);
+        }
+        command.arg(self.bitstream_name());
+        ProgramResults {
+            success: run_logged(command, build_dir, &log, &TaskMonitor::default()).unwrap_or(false),
+            log,
+        }
+    }
+}
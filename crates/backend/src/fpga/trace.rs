@@ -0,0 +1,382 @@
+//! Trace buffer recording chosen nodes tick by tick around a trigger, and export of the
+//! recording as a VCD waveform.
+//!
+//! The nodes are picked with `/rp trace` and built into the design as a `trace` module writing
+//! one row per tick into on-chip RAM. Once the trigger node sees its edge, the buffer keeps
+//! `pretrigger` ticks before it and fills up the rest of its `depth` ticks after it, then stops
+//! until it is armed again. Without a trigger it always holds the last `depth` ticks.
+//! [`Recorder`] models the module for the netlist interpreter and the direct backend, so a
+//! trace from either of them lines up with one downloaded from a board.
+
+use super::linker::Linker;
+use super::partition::{is_analog, Partition};
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::compile_graph::{CompileGraph, CompileNode, NodeIdx, NodeType};
+use mchprs_redpiler::{TraceOptions, TraceTrigger};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+/// Length of a redstone tick, the time step of the waveform
+const TIMESCALE: &str = "100 ms";
+
+/// Bits a node's net takes in a trace row, if it has a net to record
+pub fn width(ty: &NodeType) -> Option<u32> {
+    match ty {
+        NodeType::Lever | NodeType::PressurePlate | NodeType::Button | NodeType::Lamp | NodeType::Repeater { .. } | NodeType::Torch | NodeType::Observer => Some(1),
+        ty if is_analog(ty) => Some(4),
+        _ => None,
+    }
+}
+
+/// A recorded node, named by its annotations or after its block so the waveform maps back to the world
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probe {
+    pub pos: BlockPos,
+    pub name: String,
+    pub width: u32,
+}
+
+impl Probe {
+    fn new(node: &CompileNode, pos: BlockPos, width: u32) -> Probe {
+        let kind = match node.ty {
+            NodeType::Repeater { .. } => "repeater",
+            NodeType::Torch => "torch",
+            NodeType::Comparator { .. } | NodeType::LUT { .. } => "comparator",
+            NodeType::Lamp => "lamp",
+            NodeType::Button => "button",
+            NodeType::Lever => "lever",
+            NodeType::PressurePlate => "pressure_plate",
+            NodeType::Observer => "observer",
+            NodeType::Wire => "wire",
+            _ => "constant",
+        };
+        Probe {
+            pos,
+            name: node.annotations.name().unwrap_or_else(|| format!("{}_{}_{}_{}", kind, pos.x, pos.y, pos.z)),
+            width,
+        }
+    }
+}
+
+/// How a design records its trace, kept in `link.json`
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TraceLayout {
+    /// Recorded nodes, the first one in the least significant bits of a row
+    pub probes: Vec<Probe>,
+    pub trigger: Option<TraceTrigger>,
+    pub depth: u32,
+    pub pretrigger: u32,
+}
+
+impl TraceLayout {
+    /// Bits of a row
+    pub fn row_bits(&self) -> u32 {
+        self.probes.iter().map(|probe| probe.width).sum()
+    }
+
+    /// Bytes of a row as read back from a board
+    pub fn row_bytes(&self) -> u32 {
+        self.row_bits().div_ceil(8)
+    }
+}
+
+/// The nodes a design records and the node triggering it
+#[derive(Debug, Clone, Default)]
+pub struct TracePlan {
+    pub nodes: Vec<NodeIdx>,
+    pub trigger: Option<NodeIdx>,
+    pub layout: TraceLayout,
+}
+
+/// Resolves the traced blocks to the nodes of a partition. Blocks optimized away or without a
+/// net to record are skipped with a warning, as is a trigger on another board.
+pub fn plan(graph: &CompileGraph, part: &Partition, options: &TraceOptions) -> Option<TracePlan> {
+    let find = |pos: BlockPos| {
+        part.members
+            .iter()
+            .copied()
+            .find(|&node| graph[node].block.is_some_and(|(block, _)| block == pos) && width(&graph[node].ty).is_some())
+    };
+    let mut plan = TracePlan::default();
+    for &pos in &options.probes {
+        match find(pos) {
+            Some(node) if !plan.nodes.contains(&node) => {
+                let width = width(&graph[node].ty).unwrap();
+                plan.nodes.push(node);
+                plan.layout.probes.push(Probe::new(&graph[node], pos, width));
+            }
+            Some(_) => (),
+            None => warn!("Not tracing {}, it is not a node of this design", pos),
+        }
+    }
+    if plan.nodes.is_empty() {
+        return None;
+    }
+    if let Some((pos, edge)) = options.trigger {
+        match find(pos) {
+            Some(node) => {
+                plan.trigger = Some(node);
+                plan.layout.trigger = Some(edge);
+            }
+            None => warn!("Trace trigger {} is not a node of this design, recording the last ticks instead", pos),
+        }
+    }
+    plan.layout.depth = options.depth.clamp(2, TraceOptions::MAX_DEPTH);
+    plan.layout.pretrigger = options.pretrigger.min(plan.layout.depth - 1);
+    Some(plan)
+}
+
+/// The partition holding the trace of a split build: the one with the trigger, otherwise the
+/// one with the first traced block
+pub fn owner(graph: &CompileGraph, parts: &[Partition], options: &TraceOptions) -> Option<usize> {
+    let holds = |pos: BlockPos| {
+        parts.iter().position(|part| {
+            part.members.iter().any(|&node| graph[node].block.is_some_and(|(block, _)| block == pos))
+        })
+    };
+    options
+        .trigger
+        .and_then(|(pos, _)| holds(pos))
+        .or_else(|| options.probes.iter().find_map(|&pos| holds(pos)))
+}
+
+/// Adds the trace of a partition to its link, so a downloaded trace can be named
+pub fn link(plan: &TracePlan, link: &mut Linker) {
+    link.trace = Some(plan.layout.clone());
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceState {
+    /// Recording and waiting for the trigger
+    #[default]
+    Armed,
+    /// Recording the ticks after the trigger
+    Triggered,
+    /// The window is captured and recording stopped
+    Done,
+}
+
+impl TraceState {
+    pub fn code(self) -> u8 {
+        match self {
+            TraceState::Armed => 0,
+            TraceState::Triggered => 1,
+            TraceState::Done => 2,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<TraceState> {
+        Some(match code {
+            0 => TraceState::Armed,
+            1 => TraceState::Triggered,
+            2 => TraceState::Done,
+            _ => return None,
+        })
+    }
+}
+
+/// Where the recording stands, as read with `TraceStatus`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceStatus {
+    pub state: TraceState,
+    /// Address of the oldest row
+    pub start: u32,
+    /// Rows recorded, up to the depth of the buffer
+    pub count: u32,
+}
+
+impl TraceStatus {
+    /// Index of the trigger row among the rows read back oldest first, once the window is captured
+    pub fn trigger_row(&self, layout: &TraceLayout) -> Option<usize> {
+        if self.state != TraceState::Done || layout.trigger.is_none() {
+            return None;
+        }
+        (self.count as usize).checked_sub((layout.depth - layout.pretrigger) as usize)
+    }
+}
+
+/// Behavioural model of the `trace` module in `components.sv`
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    depth: usize,
+    pretrigger: usize,
+    trigger: Option<TraceTrigger>,
+    rows: Vec<Vec<bool>>,
+    ptr: usize,
+    count: usize,
+    /// Rows still to record after the trigger
+    post: usize,
+    state: TraceState,
+    last: bool,
+    rearm: bool,
+}
+
+impl Recorder {
+    pub fn new(depth: u32, pretrigger: u32, trigger: Option<TraceTrigger>) -> Recorder {
+        Recorder {
+            depth: depth as usize,
+            pretrigger: pretrigger as usize,
+            trigger,
+            rows: vec![Vec::new(); depth as usize],
+            ..Default::default()
+        }
+    }
+
+    pub fn from_layout(layout: &TraceLayout) -> Recorder {
+        Recorder::new(layout.depth, layout.pretrigger, layout.trigger)
+    }
+
+    /// Starts a new capture with the next row, like toggling the module's arm input
+    pub fn arm(&mut self) {
+        self.rearm = true;
+    }
+
+    /// Records the row sampled on a tick edge, with the trigger node's output at that edge
+    pub fn record(&mut self, row: Vec<bool>, trigger: bool) {
+        if std::mem::take(&mut self.rearm) {
+            self.state = TraceState::Armed;
+            self.ptr = 0;
+            self.count = 0;
+            self.last = trigger;
+        }
+        let last = std::mem::replace(&mut self.last, trigger);
+        if self.state == TraceState::Done {
+            return;
+        }
+        self.rows[self.ptr] = row;
+        self.ptr = (self.ptr + 1) % self.depth;
+        self.count = (self.count + 1).min(self.depth);
+        let fire = match self.trigger {
+            Some(TraceTrigger::Rising) => trigger && !last,
+            Some(TraceTrigger::Falling) => !trigger && last,
+            Some(TraceTrigger::Change) => trigger != last,
+            None => false,
+        };
+        match self.state {
+            TraceState::Armed if fire => {
+                self.post = self.depth - self.pretrigger - 1;
+                self.state = if self.post == 0 { TraceState::Done } else { TraceState::Triggered };
+            }
+            TraceState::Triggered => {
+                self.post -= 1;
+                if self.post == 0 {
+                    self.state = TraceState::Done;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn status(&self) -> TraceStatus {
+        TraceStatus {
+            state: self.state,
+            start: if self.count == self.depth { self.ptr as u32 } else { 0 },
+            count: self.count as u32,
+        }
+    }
+
+    /// The row at an address of the buffer
+    pub fn row(&self, addr: u32) -> &[bool] {
+        self.rows.get(addr as usize).map_or(&[][..], |row| row.as_slice())
+    }
+
+    /// Every recorded row, oldest first
+    pub fn rows(&self) -> Vec<Vec<bool>> {
+        let status = self.status();
+        (0..status.count)
+            .map(|i| self.row((status.start + i) % self.depth as u32).to_vec())
+            .collect()
+    }
+
+    /// The recording so far, named after the probes of a layout
+    pub fn trace(&self, layout: &TraceLayout) -> Trace {
+        Trace {
+            probes: layout.probes.clone(),
+            rows: self.rows(),
+            trigger: self.status().trigger_row(layout),
+        }
+    }
+}
+
+/// A downloaded recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub probes: Vec<Probe>,
+    /// Rows oldest first, each probe's bits least significant first
+    pub rows: Vec<Vec<bool>>,
+    /// Row the trigger fired on
+    pub trigger: Option<usize>,
+}
+
+impl Trace {
+    /// Renders the recording as a VCD waveform, one time step per tick from the oldest row
+    pub fn to_vcd(&self) -> String {
+        let mut vcd = format!("$version MCHPRS $end\n$timescale {TIMESCALE} $end\n$scope module redstone $end\n");
+        let ids: Vec<String> = (0..self.probes.len() + 1).map(vcd_id).collect();
+        for (probe, id) in self.probes.iter().zip(&ids) {
+            vcd.push_str(&format!("$var wire {} {} {} $end\n", probe.width, id, probe.name));
+        }
+        let trigger_id = &ids[self.probes.len()];
+        if self.trigger.is_some() {
+            vcd.push_str(&format!("$var wire 1 {trigger_id} trigger $end\n"));
+        }
+        vcd.push_str("$upscope $end\n$enddefinitions $end\n");
+
+        let mut last: Vec<Option<String>> = vec![None; self.probes.len() + 1];
+        for (time, row) in self.rows.iter().enumerate() {
+            let mut values = Vec::with_capacity(self.probes.len() + 1);
+            let mut bit = 0;
+            for (probe, id) in self.probes.iter().zip(&ids) {
+                let value: String = (0..probe.width as usize)
+                    .rev()
+                    .map(|i| if row.get(bit + i).copied().unwrap_or(false) { '1' } else { '0' })
+                    .collect();
+                bit += probe.width as usize;
+                values.push(if probe.width == 1 { format!("{value}{id}") } else { format!("b{value} {id}") });
+            }
+            if self.trigger.is_some() {
+                values.push(format!("{}{trigger_id}", (self.trigger == Some(time)) as u8));
+            }
+
+            let mut changes = String::new();
+            for (value, last) in values.into_iter().zip(&mut last) {
+                if last.as_ref() != Some(&value) {
+                    changes.push_str(&value);
+                    changes.push('\n');
+                    *last = Some(value);
+                }
+            }
+            if time == 0 {
+                vcd.push_str(&format!("#0\n$dumpvars\n{changes}$end\n"));
+            } else if !changes.is_empty() {
+                vcd.push_str(&format!("#{time}\n{changes}"));
+            }
+        }
+        vcd.push_str(&format!("#{}\n", self.rows.len()));
+        vcd
+    }
+
+    pub fn write_vcd(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_vcd())
+    }
+}
+
+/// Short identifier of a VCD variable, counting in the printable characters
+fn vcd_id(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
@@ -5,24 +5,27 @@ use mchprs_blocks::BlockPos;
 use mchprs_world::TickEntry;
 use mchprs_world::{for_each_block_mut_optimized, World};
 use std::fs;
-use std::path::Path;
 use std::sync::mpsc::Sender;
 use std::sync::Mutex;
 use std::time::Instant;
-use tracing::debug;
-use fpga::linker::Linker;
+use tracing::{debug, warn};
+use fpga::builds::{self, Manifest};
+use fpga::trace::Trace;
 
 
 use mchprs_redpiler::{
     compile_graph::CompileGraph, 
+    graph_cache,
     CompilerOptions, 
     passes::make_default_pass_manager,
+    task_monitor::TaskMonitor,
     CompilerInput,
     BackendVariant,
 };
 use enum_dispatch::enum_dispatch;
 use direct::DirectBackend;
 use fpga::FPGABackend;
+use fpga::sim::SimBackend;
 
 use crate::fpga::compiler::DeviceConfig;
 
@@ -35,9 +38,10 @@ pub trait JITBackend {
         ticks: Vec<TickEntry>,
         plot: String,
         name: String,
-        config: Option<DeviceConfig>,
+        devices: &[DeviceConfig],
         options: &CompilerOptions,  
-    );
+        monitor: &TaskMonitor,
+    ) -> Result<(), String>;
     fn run(&mut self);
     fn stop(&mut self);
     fn tick(&mut self);
@@ -46,6 +50,10 @@ pub trait JITBackend {
             self.tick();
         }
     }
+    /// Runs exactly `ticks` ticks for `/radvance`, even on a backend ticking on its own clock
+    fn advance(&mut self, ticks: u64) {
+        self.tickn(ticks);
+    }
     fn on_use_block(&mut self, pos: BlockPos);
     fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool);
     fn flush<W: World>(&mut self, world: &mut W, io_only: bool);
@@ -53,21 +61,28 @@ pub trait JITBackend {
     fn has_pending_ticks(&self) -> bool;
     fn inspect(&mut self, pos: BlockPos);
     fn set_rtps(&mut self, rtps: u32);
+    /// Starts a new trace capture, waiting for the trigger again
+    fn arm_trace(&mut self);
+    /// The trace recorded so far, if the build records one
+    fn trace(&mut self) -> Option<Trace>;
 }
 
 #[enum_dispatch(JITBackend)] 
 pub enum BackendDispatcher {
     DirectBackend,
     FPGABackend,
+    SimBackend,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BackendStatus {
     Stopped,
     Redpiling,
     Compiling,
     Ready,
-    Active
+    Active,
+    /// The compile did not finish, with the reason why
+    Failed(String),
 }
 
 impl BackendStatus {
@@ -78,6 +93,7 @@ impl BackendStatus {
             BackendStatus::Compiling => "&eCompiling".to_string(),
             BackendStatus::Ready =>     "&2    Ready".to_string(),
             BackendStatus::Active =>    "&a   Active".to_string(),
+            BackendStatus::Failed(_) => "&4   Failed".to_string(),
         }
     }
 }
@@ -85,7 +101,11 @@ impl BackendStatus {
 pub enum BackendMsg {
     BackendStatus{backend: String, status: BackendStatus},
     New{backend: String, options: CompilerOptions},
-    Delete{backend: String}
+    Delete{backend: String},
+    /// Estimated share of the FPGA the build takes up, from 0 to 1
+    Utilization{backend: String, utilization: f64},
+    /// What a compile in progress is doing
+    Progress{backend: String, message: String},
 }
 
 pub struct Backend {
@@ -97,29 +117,49 @@ pub struct Backend {
 }
 
 impl Backend {
-    pub fn from_data(plot: (i32,i32), sender: Sender<BackendMsg>, config: DeviceConfig) -> Vec<Backend> {
+    /// Loads the builds compiled for a plot: FPGA builds from their link files, and direct
+    /// builds from cached graphs that still match the world. Build directories that cannot be
+    /// loaded are skipped with a warning.
+    pub fn from_data<W: World>(plot: (i32,i32), sender: Sender<BackendMsg>, configs: &[DeviceConfig], world: &Mutex<W>) -> Vec<Backend> {
         let mut backends: Vec<Backend> = Vec::new();
-        let path_str = format!("FPGA/bin/{}-{}",plot.0,plot.1);
-        let path = Path::new(&path_str);
-        if path.is_dir() {
-
-            for entry in fs::read_dir(path).unwrap() {
-                let link_path = entry.unwrap().path().join("link.json");
-                let links_str = fs::read_to_string(link_path).unwrap();
-                let link: Linker = serde_json::from_str(&links_str).unwrap();
-
-                let name = link.name.clone();
-
-                let backend = FPGABackend::from_link_file(link, format!("{}-{}/{}",plot.0,plot.1,name), config.clone());
-                let new_sender = sender.clone();
-                _ = new_sender.send(BackendMsg::New { backend: name.clone(), options: CompilerOptions::fpga() });
-                _ = new_sender.send(BackendMsg::BackendStatus { backend: name.clone(), status: BackendStatus::Ready });
-                backends.push(Backend { 
+        let plot = format!("{}-{}", plot.0, plot.1);
+        for name in builds::list(&builds::plot_dir(&plot)) {
+            match Backend::load_fpga(&plot, &name, sender.clone(), configs) {
+                Ok(backend) => backends.push(backend),
+                Err(err) => warn!("Skipping the FPGA build {} of plot {}: {}", name, plot, err),
+            }
+        }
+
+        if let Ok(entries) = fs::read_dir(graph_cache::plot_dir(&plot)) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_none_or(|ext| ext != "bc") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()).map(str::to_owned) else {
+                    continue;
+                };
+                if backends.iter().any(|backend| backend.name == name) {
+                    continue;
+                }
+                let Some(cached) = graph_cache::load(&path, &*world.lock().unwrap()) else {
+                    continue;
+                };
+
+                let mut jit = BackendDispatcher::DirectBackend(Default::default());
+                let result = jit.compile(cached.graph, Vec::new(), plot.clone(), name.clone(), &[], &cached.options, &TaskMonitor::default());
+                if let Err(reason) = result {
+                    warn!("Could not reload {} from its cached graph: {}", name, reason);
+                    continue;
+                }
+                _ = sender.send(BackendMsg::New { backend: name.clone(), options: cached.options.clone() });
+                _ = sender.send(BackendMsg::BackendStatus { backend: name.clone(), status: BackendStatus::Ready });
+                backends.push(Backend {
                     is_active: false,
                     sender: sender.clone(),
-                    name: name.clone(),
-                    jit: BackendDispatcher::FPGABackend(backend),
-                    options: CompilerOptions::fpga()  
+                    name,
+                    jit,
+                    options: cached.options,
                 });
             }
         }
@@ -127,51 +167,158 @@ impl Backend {
         backends
     }
 
+    /// Loads an FPGA build from its directory, running it on the boards it was compiled for if
+    /// they are among the configured ones
+    pub fn load_fpga(plot: &str, name: &str, sender: Sender<BackendMsg>, configs: &[DeviceConfig]) -> Result<Backend, String> {
+        let dir = builds::plot_dir(plot).join(name);
+        let link = builds::read_link(&dir)?;
+        let options = match Manifest::read(&dir) {
+            Ok(manifest) => manifest.map_or_else(CompilerOptions::fpga, |manifest| manifest.options()),
+            Err(err) => {
+                warn!("Loading {} without its manifest: {}", name, err);
+                CompilerOptions::fpga()
+            }
+        };
+        let devices = link
+            .devices()
+            .iter()
+            .map(|device| {
+                configs
+                    .iter()
+                    .find(|config| &config.name == device)
+                    .or(configs.first())
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        // The directory names the build, so one renamed by hand still finds its files
+        let backend = FPGABackend::from_link_file(link, format!("{}/{}", plot, name), devices);
+        _ = sender.send(BackendMsg::New { backend: name.to_owned(), options: options.clone() });
+        _ = sender.send(BackendMsg::BackendStatus { backend: name.to_owned(), status: BackendStatus::Ready });
+        if let Some(utilization) = backend.utilization() {
+            _ = sender.send(BackendMsg::Utilization { backend: name.to_owned(), utilization });
+        }
+        Ok(Backend {
+            is_active: false,
+            sender,
+            name: name.to_owned(),
+            jit: BackendDispatcher::FPGABackend(backend),
+            options,
+        })
+    }
+
+    /// Redpiles the region and compiles it for the chosen backend. Progress is reported to the
+    /// monitor, and a cancelled compile stops between steps without reporting anything further.
+    ///
+    /// Direct builds reuse the graph cached by an earlier compile of the same region if nothing
+    /// in it has changed, and cache the graph otherwise.
     pub fn new <W: World>(
         sender: Sender<BackendMsg>,
         name: String,
         plot: String,
-        config: Option<DeviceConfig>,
+        devices: Vec<DeviceConfig>,
         world: &Mutex<W>,
         bounds: (BlockPos, BlockPos),
         options: CompilerOptions,
         ticks: Vec<TickEntry>,
-    ) -> Backend{
-
+        monitor: &TaskMonitor,
+    ) -> Result<Backend, String> {
+        if monitor.cancelled() {
+            return Err("cancelled".to_string());
+        }
         _ = sender.send(BackendMsg::New { backend: name.clone(), options: options.clone() });
 
         debug!("Starting compile");
         let start = Instant::now();
 
-        let input = CompilerInput { world: world, bounds };
-        let pass_manager = make_default_pass_manager::<W>();
-        let graph = pass_manager.run_passes(&options, &input);
+        let cache_path = graph_cache::path(&plot, &name);
+        // Lookup tables depend on the strengths comparators hold, which the cache key leaves out
+        let use_cache = graph_cache::enabled()
+            && options.backend_variant == BackendVariant::Direct
+            && !options.export
+            && !options.lut;
+        let is_fpga = options.backend_variant == BackendVariant::FPGA;
+        let mut content_hash = None;
+        let cached = if use_cache {
+            monitor.set_message("Checking cached graph".to_string());
+            graph_cache::load(&cache_path, &*world.lock().unwrap())
+                .filter(|cached| cached.fits(bounds, &options))
+        } else {
+            None
+        };
+
+        let graph = match cached {
+            Some(cached) => {
+                debug!("Loaded cached graph for {}", name);
+                cached.graph
+            }
+            None => {
+                // Hashed before redpiling so edits made while compiling invalidate the cache
+                let key = (use_cache || is_fpga).then(|| graph_cache::key(&*world.lock().unwrap(), bounds, &options));
+                let input = CompilerInput { world: world, bounds };
+                let pass_manager = make_default_pass_manager::<W>();
+                let graph = pass_manager.run_passes(&options, &input, monitor);
+                if monitor.cancelled() {
+                    return Err("cancelled".to_string());
+                }
+                content_hash = key;
+                if let Some(key) = key.filter(|_| use_cache) {
+                    if let Err(err) = graph_cache::store(&cache_path, key, bounds, &options, &graph) {
+                        warn!("Could not cache the graph of {}: {}", name, err);
+                    }
+                }
+                graph
+            }
+        };
 
         let mut jit = match options.backend_variant {
             BackendVariant::Direct => BackendDispatcher::DirectBackend(Default::default()),
-            BackendVariant::FPGA => BackendDispatcher::FPGABackend(Default::default())
+            BackendVariant::FPGA => BackendDispatcher::FPGABackend(Default::default()),
+            BackendVariant::FPGASim => BackendDispatcher::SimBackend(Default::default()),
         };
 
         _ = sender.send(BackendMsg::BackendStatus { backend: name.clone(), status: BackendStatus::Compiling });
 
-        jit.compile(
+        let build_dir = builds::plot_dir(&plot).join(&name);
+        if is_fpga {
+            Manifest::remove(&build_dir);
+        }
+        let result = jit.compile(
             graph,
             ticks,
             plot,
             name.clone(),
-            config,
-            &options);
+            &devices,
+            &options,
+            monitor);
+        if monitor.cancelled() {
+            return Err("cancelled".to_string());
+        }
+        if let Err(reason) = result {
+            _ = sender.send(BackendMsg::BackendStatus { backend: name.clone(), status: BackendStatus::Failed(reason.clone()) });
+            return Err(reason);
+        }
 
         _ = sender.send(BackendMsg::BackendStatus { backend: name.clone(), status: BackendStatus::Ready });
+        if let BackendDispatcher::FPGABackend(fpga) = &jit {
+            if let Some(utilization) = fpga.utilization() {
+                _ = sender.send(BackendMsg::Utilization { backend: name.clone(), utilization });
+            }
+            let manifest = Manifest::new(name.clone(), bounds, &options, fpga.devices(), fpga.io_bits(), content_hash.unwrap_or_default());
+            if let Err(err) = manifest.write(&build_dir) {
+                warn!("Could not write the manifest of {}: {}", name, err);
+            }
+        }
         debug!("Compile completed in {:?}", start.elapsed());
 
-        Backend{ 
+        Ok(Backend{ 
             is_active: false,
             sender: sender,
             name: name,
             jit: jit,
             options: options,
-        }
+        })
     }
 
     pub fn options(&self) -> &CompilerOptions {
@@ -196,12 +343,23 @@ impl Backend {
         &mut self.jit
     }
 
+    /// The FPGA backend, if this build runs on a board
+    pub fn fpga(&mut self) -> Option<&mut FPGABackend> {
+        match &mut self.jit {
+            BackendDispatcher::FPGABackend(fpga) => Some(fpga),
+            _ => None,
+        }
+    }
+
     pub fn run(&mut self) {
         self.backend().run();
         _ = self.sender.send(BackendMsg::BackendStatus { backend: self.name.clone(), status: BackendStatus::Active });
     }
 
-    pub fn stop(&mut self) {
+    /// Stops the build after putting its final state in the world
+    pub fn stop<W: World>(&mut self, world: &mut W) {
+        let io_only = self.options.io_only;
+        self.backend().reset(world, io_only);
         self.backend().stop();
         _ = self.sender.send(BackendMsg::BackendStatus { backend: self.name.clone(), status: BackendStatus::Ready });
     }
@@ -214,6 +372,10 @@ impl Backend {
         self.backend().tickn(ticks);
     }
 
+    pub fn advance(&mut self, ticks: u64) {
+        self.backend().advance(ticks);
+    }
+
     pub fn on_use_block(&mut self, pos: BlockPos) {
         self.backend().on_use_block(pos);
     }
@@ -231,6 +393,14 @@ impl Backend {
         self.backend().inspect(pos);
     }
 
+    pub fn arm_trace(&mut self) {
+        self.backend().arm_trace();
+    }
+
+    pub fn trace(&mut self) -> Option<Trace> {
+        self.backend().trace()
+    }
+
     pub fn has_pending_ticks(&mut self) -> bool {
         self.backend().has_pending_ticks()
     }
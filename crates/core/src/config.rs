@@ -74,6 +74,8 @@ gen_config! {
     luckperms: Option<PermissionsConfig> = None,
     block_in_hitbox: bool = true,
     auto_redpiler: bool = false,
+    fpga_compiler: String = "Quartus".to_string(),
+    fpga_tools_path: String = "".to_string(),
     velocity: Option<VelocityConfig> = None
 }
 
@@ -9,11 +9,14 @@ use mchprs_network::packets::clientbound::{
 };
 use mchprs_network::packets::PacketEncoder;
 use mchprs_network::PlayerPacketSender;
-use mchprs_redpiler::{BackendVariant, CompilerOptions};
+use mchprs_backend::fpga::{builds, interface};
+use mchprs_backend::Backend;
+use mchprs_redpiler::{graph_cache, BackendVariant, CompilerOptions, TraceOptions, TraceTrigger};
 use mchprs_save_data::plot_data::{Tps, WorldSendRate};
 use mchprs_text::TextComponent;
 use once_cell::sync::Lazy;
 use std::ops::Add;
+use std::path::Path;
 use std::str::FromStr;
 use std::time::Instant;
 use tracing::{debug, info, warn};
@@ -32,6 +35,14 @@ fn parse_relative_coord<F: FromStr + Add + Add<Output = F>>(
     }
 }
 
+/// A time stored as seconds since the Unix epoch, in the server's time zone
+fn format_timestamp(secs: u64) -> String {
+    match chrono::DateTime::from_timestamp(secs as i64, 0) {
+        Some(time) => time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
 impl Plot {
     /// Handles a command that starts with `/plot` or `/p`
     fn handle_plot_command(&mut self, player: usize, command: &str, args: &[&str]) {
@@ -179,6 +190,7 @@ impl Plot {
                 let args = args.join(" ");
                 let mut options = CompilerOptions::parse(&args);
                 options.backend_variant = BackendVariant::Direct;
+                options.trace = self.trace.clone();
 
                 if options.optimize {
                     let msg = "Redpiler optimization is highly unstable and can break builds. Use with caution!";
@@ -212,18 +224,204 @@ impl Plot {
             "reset" | "r" => {
                 self.reset_backend();
             }
+            "cancel" => {
+                self.cancel_compiles(player, |job| job.variant == BackendVariant::Direct);
+            }
+            "trace" | "t" => self.handle_trace_command(player, args),
             _ => self.players[player].send_error_message("Invalid argument for /redpiler"),
         }
     }
 
+    /// Handles `/redpiler trace`, which picks the nodes the next compile records and exports
+    /// what the running build recorded
+    fn handle_trace_command(&mut self, player: usize, args: &[&str]) {
+        const USAGE: &str = "Usage: /rp trace <add|trigger <rise|fall|change>|window <depth> [pretrigger]|clear|arm|dump>";
+        match args {
+            ["add"] | ["trigger", _] => {
+                let plr = &self.players[player];
+                let pos = worldedit::ray_trace_block(
+                    &*self.world.lock().unwrap(),
+                    plr.pos,
+                    plr.pitch as f64,
+                    plr.yaw as f64,
+                    10.0,
+                );
+                let Some(pos) = pos else {
+                    plr.send_error_message("Trace failed");
+                    return;
+                };
+                if let ["trigger", edge] = args {
+                    let edge = match *edge {
+                        "rise" | "rising" => TraceTrigger::Rising,
+                        "fall" | "falling" => TraceTrigger::Falling,
+                        "change" => TraceTrigger::Change,
+                        _ => {
+                            plr.send_error_message(USAGE);
+                            return;
+                        }
+                    };
+                    self.trace.trigger = Some((pos, edge));
+                    plr.send_system_message(&format!("Triggering on {:?} edges of {}", edge, pos));
+                } else if !self.trace.probes.contains(&pos) {
+                    self.trace.probes.push(pos);
+                    plr.send_system_message(&format!("Tracing {}, {} probes", pos, self.trace.probes.len()));
+                }
+            }
+            ["window", depth, rest @ ..] => {
+                let depth = depth.parse::<u32>().ok().filter(|&depth| depth > 0 && depth <= TraceOptions::MAX_DEPTH);
+                let pretrigger = match rest {
+                    [] => depth.map(|depth| depth / 2),
+                    [pretrigger] => pretrigger.parse::<u32>().ok().filter(|&pretrigger| depth.is_some_and(|depth| pretrigger < depth)),
+                    _ => None,
+                };
+                let (Some(depth), Some(pretrigger)) = (depth, pretrigger) else {
+                    self.players[player].send_error_message(&format!(
+                        "The window must hold 1 to {} ticks, with fewer of them before the trigger",
+                        TraceOptions::MAX_DEPTH
+                    ));
+                    return;
+                };
+                self.trace.depth = depth;
+                self.trace.pretrigger = pretrigger;
+                self.players[player].send_system_message(&format!(
+                    "Recording {} ticks, {} of them before the trigger",
+                    depth, pretrigger
+                ));
+            }
+            ["clear"] => {
+                self.trace = TraceOptions::default();
+                self.players[player].send_system_message("Cleared the trace probes and trigger.");
+            }
+            ["arm"] | ["dump"] => {
+                let Some(active) = self.active_backend else {
+                    self.players[player].send_error_message("No build is running");
+                    return;
+                };
+                let mut backends = self.backends.lock().unwrap();
+                let backend = &mut backends[active];
+                if args == ["arm"] {
+                    backend.arm_trace();
+                    self.players[player].send_system_message(&format!("Armed the trace of {}", backend.name));
+                    return;
+                }
+                let Some(trace) = backend.trace() else {
+                    self.players[player].send_error_message(&format!("{} was not compiled with trace probes", backend.name));
+                    return;
+                };
+                let (x, z) = self.world.lock().unwrap().get_plot();
+                let path = format!("traces/{}-{}/{}.vcd", x, z, backend.name);
+                match trace.write_vcd(Path::new(&path)) {
+                    Ok(()) => self.players[player].send_system_message(&format!("Wrote {} ticks to {}", trace.rows.len(), path)),
+                    Err(err) => self.players[player].send_error_message(&format!("Could not write {}: {}", path, err)),
+                }
+            }
+            _ => self.players[player].send_error_message(USAGE),
+        }
+    }
+
     /// Handles a command that starts with `/fpga`
     fn handle_fpga_command(&mut self, player: usize, command: &str, args: &[&str]) {
         match command {
-            "stop" => {
+            "list" | "l" => {
+                // Refresh the latency of the boards this plot is running on
+                if let Some(active) = self.active_backend {
+                    let plot = self.world.lock().unwrap().get_plot();
+                    let owned = self.scheduler.lock().unwrap().owned_by(plot);
+                    let ping = self.backends.lock().unwrap()[active]
+                        .fpga()
+                        .map(|fpga| (fpga.status(), fpga.ping()));
+                    if let Some((status, latency)) = ping {
+                        let mut scheduler = self.scheduler.lock().unwrap();
+                        for idx in owned {
+                            scheduler.update(idx, status, latency);
+                        }
+                    }
+                }
 
+                let scheduler = self.scheduler.lock().unwrap();
+                if scheduler.fpgas.is_empty() {
+                    self.players[player].send_system_message("No FPGAs are configured.");
+                }
+                for fpga in &scheduler.fpgas {
+                    let owner = match fpga.owner() {
+                        Some((x, z)) => format!("{},{}", x, z),
+                        None => "-".to_string(),
+                    };
+                    let ping = match fpga.latency {
+                        Some(latency) => format!("{}us", latency.as_micros()),
+                        None => "-".to_string(),
+                    };
+                    self.players[player].send_chat_message(&TextComponent::from_legacy_text(&format!(
+                        "&f{} &7({}) {} &7plot: &a{} &7ping: &a{}",
+                        fpga.config.name,
+                        fpga.config.device,
+                        fpga.status.to_str(),
+                        owner,
+                        ping
+                    )));
+                }
             }
-            "start" => {
-
+            "start" | "stop" => {
+                let [name] = args else {
+                    self.players[player].send_error_message(&format!("Usage: /fpga {} <device>", command));
+                    return;
+                };
+                let mut scheduler = self.scheduler.lock().unwrap();
+                let Some(idx) = scheduler.find(name) else {
+                    self.players[player].send_error_message(&format!("Unknown FPGA {}", name));
+                    return;
+                };
+                if command == "start" {
+                    scheduler.start(idx);
+                    self.players[player].send_system_message(&format!("{} is back in service.", name));
+                } else {
+                    scheduler.stop(idx);
+                    match scheduler.fpgas[idx].owner() {
+                        Some((x, z)) => self.players[player].send_system_message(&format!(
+                            "{} will be taken out of service once plot {},{} releases it.",
+                            name, x, z
+                        )),
+                        None => self.players[player].send_system_message(&format!("{} is out of service.", name)),
+                    }
+                }
+            }
+            "queue" | "q" => {
+                let scheduler = self.scheduler.lock().unwrap();
+                let mut empty = true;
+                for (i, entry) in scheduler.queue().enumerate() {
+                    empty = false;
+                    self.players[player].send_chat_message(&TextComponent::from_legacy_text(&format!(
+                        "&f{}. &a{} &7on plot {},{} for {}",
+                        i + 1,
+                        entry.build,
+                        entry.plot.0,
+                        entry.plot.1,
+                        entry.devices.join(", ")
+                    )));
+                }
+                if empty {
+                    self.players[player].send_system_message("No plots are waiting for an FPGA.");
+                }
+            }
+            "ports" | "p" => {
+                let ports = interface::available_ports();
+                if ports.is_empty() {
+                    self.players[player].send_system_message("No serial ports were found.");
+                }
+                let scheduler = self.scheduler.lock().unwrap();
+                for port in ports {
+                    let boards: Vec<&str> = scheduler
+                        .fpgas
+                        .iter()
+                        .filter(|fpga| fpga.config.command_com == port)
+                        .map(|fpga| fpga.config.name.as_str())
+                        .collect();
+                    let used = if boards.is_empty() { "-".to_string() } else { boards.join(", ") };
+                    self.players[player].send_chat_message(&TextComponent::from_legacy_text(&format!(
+                        "&f{} &7used by &a{}",
+                        port, used
+                    )));
+                }
             }
             _ => self.players[player].send_error_message("Invalid argument for /fpga"),
         }
@@ -233,51 +431,177 @@ impl Plot {
     fn handle_roc_command(&mut self, player: usize, command: &str, args: &[&str]) {
         match command {
             "compile" | "c" => {
-                let options = CompilerOptions::fpga();
+                let Some((name, flags)) = args.split_first() else {
+                    self.players[player].send_error_message("Usage: /roc compile <build> [--compile] [--wire-dot-out] [--lut]");
+                    return;
+                };
+                let mut options = CompilerOptions::fpga();
+                let parsed = CompilerOptions::parse(&flags.join(" "));
+                options.wire_dot_out = parsed.wire_dot_out;
+                options.lut = parsed.lut;
+                // Like `/rp compile -f`, only `-c` runs the vendor toolchain. Without it the build
+                // stops at the generated design and its bundle, to synthesize elsewhere.
+                options.compile_verilog = parsed.compile_verilog;
+                options.trace = self.trace.clone();
                 self.reset_backend();
-                self.start_backend(options, args[0].to_string(), player);
+                self.release_fpga();
+                self.start_backend(options, name.to_string(), player);
             }
             "run" | "r" => {
-                let mut backends = self.backends.lock().unwrap();
-                let mut i = 0;
+                let [name] = args else {
+                    self.players[player].send_error_message("Usage: /roc run <build>");
+                    return;
+                };
 
-                if !self.active_backend.is_none() {
-                    backends[self.active_backend.unwrap()].stop();
-                    self.active_backend = None;
-                    self.scheduler.lock().unwrap().free(self.world.lock().unwrap().get_plot());
+                if let Some(active) = self.active_backend.take() {
+                    self.backends.lock().unwrap()[active].stop(&mut *self.world.lock().unwrap());
                 }
-
-                if self.scheduler.lock().unwrap().lock(self.world.lock().unwrap().get_plot()) {
-                    for backend in &mut *backends {
-                        if backend.name == args[0] {
-                            break;
-                        }
-                        i += 1;
+                self.release_fpga();
+                self.run_fpga(player, name);
+            }
+            "stop" => {
+                if let Some(active) = self.active_backend.take() {
+                    self.backends.lock().unwrap()[active].stop(&mut *self.world.lock().unwrap());
+                }
+                self.release_fpga();
+            }
+            "cancel" => {
+                let name = args.first().copied();
+                self.cancel_compiles(player, |job| {
+                    job.variant != BackendVariant::Direct && name.is_none_or(|name| job.name == name)
+                });
+            }
+            "import" => {
+                let (name, file, partition) = match args {
+                    [name, file] => (name, file, Ok(0)),
+                    [name, file, partition] => (name, file, partition.parse::<usize>()),
+                    _ => {
+                        self.players[player].send_error_message("Usage: /roc import <build> <bitstream> [partition]");
+                        return;
                     }
-                    if i < backends.len() {
-                        backends[i].run();
-                        self.active_backend = Some(i);
+                };
+                let Ok(partition) = partition else {
+                    self.players[player].send_error_message("Unable to parse the partition");
+                    return;
+                };
+                let mut backends = self.backends.lock().unwrap();
+                let Some(fpga) = backends.iter_mut().find(|backend| backend.name == *name).and_then(|backend| backend.fpga()) else {
+                    drop(backends);
+                    self.players[player].send_error_message(&format!("There is no FPGA build named {}", name));
+                    return;
+                };
+                let result = fpga.import_bitstream(partition, Path::new(file));
+                drop(backends);
+                match result {
+                    Ok(target) => self.players[player].send_system_message(&format!("Imported {} as {}", file, target.display())),
+                    Err(err) => self.players[player].send_error_message(&format!("Could not import the bitstream: {}", err)),
+                }
+            }
+            "list" | "l" => {
+                let plot = self.plot_name();
+                let names = builds::list(&builds::plot_dir(&plot));
+                if names.is_empty() {
+                    self.players[player].send_system_message("No builds are compiled for this plot.");
+                }
+                for name in names {
+                    let dir = builds::plot_dir(&plot).join(&name);
+                    let line = match (builds::read_link(&dir), builds::Manifest::read(&dir)) {
+                        (Err(err), _) => format!("&f{} &cbroken: {}", name, err),
+                        (Ok(link), Ok(Some(manifest))) => format!(
+                            "&f{} &7for &a{} &7compiled &a{}",
+                            name,
+                            link.devices().join(", "),
+                            format_timestamp(manifest.compiled_at)
+                        ),
+                        (Ok(link), _) => format!("&f{} &7for &a{}", name, link.devices().join(", ")),
+                    };
+                    self.players[player].send_chat_message(&TextComponent::from_legacy_text(&line));
+                }
+            }
+            "info" | "i" => {
+                let [name] = args else {
+                    self.players[player].send_error_message("Usage: /roc info <build>");
+                    return;
+                };
+                let dir = builds::plot_dir(&self.plot_name()).join(name);
+                if !builds::is_valid_name(name) || !dir.is_dir() {
+                    self.players[player].send_error_message(&format!("There is no build named {}", name));
+                    return;
+                }
+                let mut lines = vec![format!("&6{}", name)];
+                match builds::Manifest::read(&dir) {
+                    Ok(Some(manifest)) => {
+                        let (first, second) = manifest.bounds;
+                        let options = manifest.options();
+                        let current = graph_cache::key(&*self.world.lock().unwrap(), manifest.bounds, &options);
+                        let changed = if current == manifest.content_hash { "" } else { " &e(region changed since)" };
+                        lines.push(format!("&7compiled: &a{}", format_timestamp(manifest.compiled_at)));
+                        lines.push(format!("&7region: &a{} &7to &a{}", first, second));
+                        lines.push(format!("&7options: &a{}", manifest.options));
+                        lines.push(format!("&7device: &a{}", manifest.devices.join(", ")));
+                        lines.push(format!("&7inputs: &a{} bits &7outputs: &a{} bits", manifest.input_bits, manifest.output_bits));
+                        lines.push(format!("&7content hash: &a{:016x}{}", manifest.content_hash, changed));
                     }
-                    else {
-                        self.players[player].send_error_message("Invalid Build Name");
+                    Ok(None) => {
+                        lines.push("&eNo manifest, the build was compiled before they were kept".to_string());
+                        if let Ok(link) = builds::read_link(&dir) {
+                            lines.push(format!("&7device: &a{}", link.devices().join(", ")));
+                        }
                     }
-                    
+                    Err(err) => lines.push(format!("&c{}", err)),
                 }
-                else {
-                    self.players[player].send_error_message("No Active FPGAs");
+                for line in lines {
+                    self.players[player].send_chat_message(&TextComponent::from_legacy_text(&line));
                 }
-                
             }
-            "stop" => {
-                let mut backends = self.backends.lock().unwrap();
-
-                if !self.active_backend.is_none() {
-                    backends[self.active_backend.unwrap()].stop();
-                    self.active_backend = None;
-                    self.scheduler.lock().unwrap().free(self.world.lock().unwrap().get_plot());
+            "delete" | "del" => {
+                let [name] = args else {
+                    self.players[player].send_error_message("Usage: /roc delete <build>");
+                    return;
+                };
+                if let Some(reason) = self.build_in_use(name) {
+                    self.players[player].send_error_message(&format!("{} {}", name, reason));
+                    return;
+                }
+                match builds::delete(&builds::plot_dir(&self.plot_name()), name) {
+                    Ok(()) => {
+                        self.forget_fpga_build(name);
+                        self.players[player].send_system_message(&format!("Deleted {}", name));
+                    }
+                    Err(err) => self.players[player].send_error_message(&err),
                 }
             }
-            _ => self.players[player].send_error_message("Invalid argument for /fpga"),
+            "rename" => {
+                let [old, new] = args else {
+                    self.players[player].send_error_message("Usage: /roc rename <build> <new name>");
+                    return;
+                };
+                if let Some(reason) = [old, new].into_iter().find_map(|name| self.build_in_use(name).map(|reason| (name, reason))) {
+                    self.players[player].send_error_message(&format!("{} {}", reason.0, reason.1));
+                    return;
+                }
+                let plot = self.plot_name();
+                if let Err(err) = builds::rename(&builds::plot_dir(&plot), old, new) {
+                    self.players[player].send_error_message(&err);
+                    return;
+                }
+                self.forget_fpga_build(old);
+                let configs = self.scheduler.lock().unwrap().configs();
+                match Backend::load_fpga(&plot, new, self.backend_tx.clone(), &configs) {
+                    Ok(backend) => {
+                        let mut backends = self.backends.lock().unwrap();
+                        // The new name takes over from a loaded build of the same name, such as a direct one
+                        match backends.iter().position(|backend| backend.name == *new) {
+                            Some(i) => backends[i] = backend,
+                            None => backends.push(backend),
+                        }
+                        drop(backends);
+                        self.players[player].send_system_message(&format!("Renamed {} to {}", old, new));
+                    }
+                    Err(err) => self.players[player].send_error_message(&format!("Renamed {} to {}, but could not load it: {}", old, new, err)),
+                }
+            }
+            _ => self.players[player].send_error_message("Invalid argument for /roc"),
         }
     }
 
@@ -376,13 +700,9 @@ impl Plot {
                 self.tps = tps;
                 self.reset_timings();
                 self.players[player].send_system_message("The rtps was successfully set.");
-                match tps {
-                    Tps::Limited(rtps) => {
-                        if !self.active_backend.is_none() {
-                            self.backends.lock().unwrap()[self.active_backend.unwrap()].set_rtps(rtps);
-                        }
-                    }
-                    _ => ()
+                if !self.active_backend.is_none() {
+                    let rtps = self.backend_rtps();
+                    self.backends.lock().unwrap()[self.active_backend.unwrap()].set_rtps(rtps);
                 }
             }
             "radv" | "radvance" => {
@@ -398,7 +718,7 @@ impl Plot {
                     return false;
                 };
                 let start_time = Instant::now();
-                self.tickn(ticks as u64);
+                self.advance(ticks as u64);
 
                 if !self.active_backend.is_none() {
                     self.backends.lock().unwrap()[self.active_backend.unwrap()].flush(&mut *self.world.lock().unwrap());
@@ -1033,7 +1353,7 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
             // 44: /redpiler
             Node {
                 flags: CommandFlags::LITERAL.bits() as i8,
-                children: vec![46, 47, 48], // Children are compile, inspect, reset
+                children: vec![46, 47, 48, 52, 53], // Children are compile, inspect, reset, cancel, trace
                 redirect_node: None,
                 name: Some("redpiler"),
                 parser: None,
@@ -1102,6 +1422,33 @@ pub static DECLARE_COMMANDS: Lazy<PacketEncoder> = Lazy::new(|| {
                 parser: None,
                 suggestions_type: None,
             },
+            // 52: /redpiler cancel
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("cancel"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 53: /redpiler trace
+            Node {
+                flags: (CommandFlags::LITERAL | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![54],
+                redirect_node: None,
+                name: Some("trace"),
+                parser: None,
+                suggestions_type: None,
+            },
+            // 54: /redpiler trace [args]
+            Node {
+                flags: (CommandFlags::ARGUMENT | CommandFlags::EXECUTABLE).bits() as i8,
+                children: vec![],
+                redirect_node: None,
+                name: Some("args"),
+                parser: Some(Parser::String(2)),
+                suggestions_type: None,
+            },
         ],
         root_index: 0,
     };
@@ -14,7 +14,7 @@ use crate::server::{BroadcastMessage, Message, PrivMessage};
 use crate::utils::HyphenatedUUID;
 use anyhow::Error;
 use bus::BusReader;
-use fpga::scheduler::FPGAScheduler;
+use fpga::scheduler::{FPGAScheduler, Lock};
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::items::Item;
@@ -22,8 +22,9 @@ use mchprs_blocks::{BlockFace, BlockPos};
 use mchprs_network::packets::clientbound::*;
 use mchprs_network::packets::serverbound::SUseItemOn;
 use mchprs_network::PlayerPacketSender;
-use mchprs_backend::{Backend, BackendMsg};
-use mchprs_redpiler::{BackendVariant, CompilerOptions};
+use mchprs_backend::{Backend, BackendMsg, BackendStatus};
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use mchprs_redpiler::{BackendVariant, CompilerOptions, TraceOptions};
 use mchprs_save_data::plot_data::{ChunkData, PlotData, Tps, WorldSendRate};
 use mchprs_text::TextComponent;
 use mchprs_world::storage::Chunk;
@@ -35,7 +36,7 @@ use std::collections::HashSet;
 use std::path::Path;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use tracing::{debug, error, warn};
@@ -67,7 +68,7 @@ pub struct Plot {
 
     backend_rx: Receiver<BackendMsg>,
     backend_tx: Sender<BackendMsg>,
-
+    compile_jobs: Vec<CompileJob>,
 
     // Thread communication
     message_receiver: BusReader<BroadcastMessage>,
@@ -102,6 +103,22 @@ pub struct Plot {
 
     //fpga
     scheduler: Arc<Mutex<FPGAScheduler>>,
+    /// Build waiting in the scheduler's queue for a board
+    queued_run: Option<String>,
+    /// Nodes the next compile records, set up with `/rp trace`
+    trace: TraceOptions,
+}
+
+/// A backend compiling on its own thread
+struct CompileJob {
+    name: String,
+    variant: BackendVariant,
+    monitor: Arc<TaskMonitor>,
+    handle: JoinHandle<Result<Backend, String>>,
+    /// Progress last sent to the scoreboard
+    shown: Option<Arc<String>>,
+    /// The world's pending ticks handed to the compile, given back if it yields no backend
+    ticks: Vec<TickEntry>,
 }
 
 pub struct PlotWorld {
@@ -295,6 +312,25 @@ impl Plot {
         }
     }
 
+    /// Runs exactly `ticks` ticks, even when the active backend ticks on its own clock
+    fn advance(&mut self, ticks: u64) {
+        if !self.active_backend.is_none() {
+            self.timings.tickn(ticks);
+            self.backends.lock().unwrap()[self.active_backend.unwrap()].advance(ticks);
+            return;
+        }
+        self.tickn(ticks);
+    }
+
+    /// The rtps a backend ticking on its own clock runs at. At unlimited rtps it is stepped by
+    /// the server instead, as fast as the server goes.
+    fn backend_rtps(&self) -> u32 {
+        match self.tps {
+            Tps::Limited(rtps) => rtps,
+            Tps::Unlimited => 0,
+        }
+    }
+
     fn tick(&mut self) {
         self.timings.tick();
         if !self.active_backend.is_none() {
@@ -726,35 +762,142 @@ impl Plot {
             } else {
                 self.world.lock().unwrap().get_corners()
             }.clone(); 
-        let config = if options.backend_variant == BackendVariant::FPGA {
-                Some(self.scheduler.lock().unwrap().get_config())
+        let devices = if options.backend_variant == BackendVariant::FPGA {
+                let plot = self.world.lock().unwrap().get_plot();
+                let devices = self.scheduler.lock().unwrap().devices_for(plot);
+                if devices.is_empty() {
+                    self.players[player].send_error_message("No FPGAs are in service");
+                    return;
+                }
+                devices
             }
             else {
-                None
+                Vec::new()
             };
-        let ticks = { self.world.lock().unwrap().to_be_ticked.drain(..).collect() };
+        let ticks: Vec<TickEntry> = { self.world.lock().unwrap().to_be_ticked.drain(..).collect() };
         let world = Arc::clone(&self.world);
-        let backends: Arc<Mutex<Vec<Backend>>> = Arc::clone(&self.backends);
         let sender = self.backend_tx.clone();
         let x = { self.world.lock().unwrap().x };
         let z = { self.world.lock().unwrap().z };
-
-        thread::spawn(move || {
-            let new_backend: Backend = Backend::new(
-                sender,
-                name,
-                format!("{}-{}", x, z),
-                config,
-                &world,
-                bounds,  
-                options,
-                ticks);
-            backends.lock().unwrap().push(new_backend);
+        let monitor = Arc::new(TaskMonitor::default());
+        let variant = options.backend_variant;
+
+        let handle = {
+            let name = name.clone();
+            let monitor = Arc::clone(&monitor);
+            let ticks = ticks.clone();
+            thread::spawn(move || {
+                Backend::new(
+                    sender,
+                    name,
+                    format!("{}-{}", x, z),
+                    devices,
+                    &world,
+                    bounds,  
+                    options,
+                    ticks,
+                    &monitor)
+            })
+        };
+        self.compile_jobs.push(CompileJob {
+            name,
+            variant,
+            monitor,
+            handle,
+            shown: None,
+            ticks,
         });
 
         self.reset_timings();
     }
 
+    /// Streams the progress of running compiles to the scoreboard and takes in the finished ones
+    fn poll_compile_jobs(&mut self) {
+        let mut i = 0;
+        while i < self.compile_jobs.len() {
+            let job = &mut self.compile_jobs[i];
+            let message = job.monitor.message();
+            if message != job.shown && !job.monitor.cancelled() {
+                if let Some(message) = &message {
+                    _ = self.backend_tx.send(BackendMsg::Progress { backend: job.name.clone(), message: message.to_string() });
+                }
+                job.shown = message;
+            }
+            if !job.handle.is_finished() {
+                i += 1;
+                continue;
+            }
+
+            let job = self.compile_jobs.remove(i);
+            if job.monitor.cancelled() {
+                // Whatever the compile got to is thrown away. It only leaves the scoreboard now that
+                // its thread can no longer report to it, unless the build is being compiled again.
+                _ = job.handle.join();
+                self.restore_ticks(job.ticks);
+                if !self.compile_jobs.iter().any(|other| other.name == job.name) {
+                    _ = self.backend_tx.send(BackendMsg::Delete { backend: job.name });
+                }
+                continue;
+            }
+            match job.handle.join() {
+                Ok(Ok(backend)) => {
+                    // A recompiled build takes the place of the old one
+                    let existing = self.backends.lock().unwrap().iter().position(|old| old.name == backend.name);
+                    match existing {
+                        Some(i) => {
+                            if self.active_backend == Some(i) {
+                                self.reset_backend();
+                            }
+                            self.backends.lock().unwrap()[i] = backend;
+                        }
+                        None => self.backends.lock().unwrap().push(backend),
+                    }
+                }
+                Ok(Err(reason)) => {
+                    self.restore_ticks(job.ticks);
+                    self.broadcast_plot_chat_message(&format!("&cCompiling {} failed: {}", job.name, reason));
+                }
+                Err(_) => {
+                    self.restore_ticks(job.ticks);
+                    error!("Compiling {} panicked", job.name);
+                    let reason = "the compiler crashed".to_string();
+                    self.broadcast_plot_chat_message(&format!("&cCompiling {} failed: {}", job.name, reason));
+                    _ = self.backend_tx.send(BackendMsg::BackendStatus { backend: job.name, status: BackendStatus::Failed(reason) });
+                }
+            }
+        }
+    }
+
+    /// Stops the compiles of the given kind. They are kept until their thread has finished,
+    /// and `poll_compile_jobs` then discards the result.
+    fn cancel_compiles(&mut self, player: usize, cancel: impl Fn(&CompileJob) -> bool) {
+        let mut cancelled = Vec::new();
+        for job in &self.compile_jobs {
+            if !job.monitor.cancelled() && cancel(job) {
+                job.monitor.cancel();
+                cancelled.push(job.name.clone());
+            }
+        }
+        if cancelled.is_empty() {
+            self.players[player].send_error_message("Nothing to cancel is compiling");
+            return;
+        }
+        for name in cancelled {
+            self.players[player].send_system_message(&format!("Cancelled compiling {}", name));
+        }
+    }
+
+    /// Gives the pending ticks of a compile that yields no backend back to the world,
+    /// apart from blocks the world has scheduled again since
+    fn restore_ticks(&mut self, ticks: Vec<TickEntry>) {
+        let mut world = self.world.lock().unwrap();
+        for entry in ticks {
+            if !world.pending_tick_at(entry.pos) {
+                world.to_be_ticked.push(entry);
+            }
+        }
+    }
+
     fn reset_backend(&mut self) {
 
         if !self.active_backend.is_none() {
@@ -772,6 +915,141 @@ impl Plot {
         }
     }
 
+    /// Runs a build on its boards, queueing it if the boards able to run it are busy.
+    /// Builds that do not run on an FPGA start right away.
+    fn run_fpga(&mut self, player: usize, name: &str) {
+        let devices = {
+            let mut backends = self.backends.lock().unwrap();
+            match backends.iter_mut().find(|backend| backend.name == name) {
+                Some(backend) => match backend.fpga() {
+                    Some(fpga) if !fpga.has_bitstream() => {
+                        drop(backends);
+                        self.players[player].send_error_message(&format!(
+                            "{} was only generated, compile it with /roc compile -c or bring in its bitstream with /roc import",
+                            name
+                        ));
+                        return;
+                    }
+                    fpga => fpga.map(|fpga| fpga.devices()),
+                },
+                None => {
+                    self.players[player].send_error_message("Invalid Build Name");
+                    return;
+                }
+            }
+        };
+        let Some(devices) = devices else {
+            self.start_fpga(name, &[]);
+            return;
+        };
+
+        let plot = self.world.lock().unwrap().get_plot();
+        let lock = self.scheduler.lock().unwrap().lock(plot, name, &devices);
+        match lock {
+            Lock::Acquired(boards) => self.start_fpga(name, &boards),
+            Lock::Queued(position) => {
+                self.queued_run = Some(name.to_owned());
+                self.players[player].send_system_message(&format!(
+                    "Every FPGA able to run {} is busy, queued at position {}",
+                    name, position
+                ));
+            }
+            Lock::Unavailable => self.players[player].send_error_message(&format!("No FPGA in service can run {}", name)),
+        }
+    }
+
+    /// Programs the boards assigned by the scheduler and makes the build the active backend
+    fn start_fpga(&mut self, name: &str, boards: &[usize]) {
+        let configs = {
+            let scheduler = self.scheduler.lock().unwrap();
+            boards.iter().map(|&idx| scheduler.fpgas[idx].config.clone()).collect()
+        };
+        let mut backends = self.backends.lock().unwrap();
+        let Some(i) = backends.iter().position(|backend| backend.name == name) else {
+            drop(backends);
+            self.release_fpga();
+            return;
+        };
+
+        if let Some(fpga) = backends[i].fpga() {
+            fpga.set_devices(configs);
+            fpga.read_roms(&*self.world.lock().unwrap());
+        }
+        backends[i].run();
+        backends[i].set_rtps(self.backend_rtps());
+        self.active_backend = Some(i);
+
+        let (status, latency, error) = match backends[i].fpga() {
+            Some(fpga) => (fpga.status(), fpga.ping(), fpga.error().map(str::to_owned)),
+            None => return,
+        };
+        drop(backends);
+        if let Some(error) = error {
+            self.broadcast_plot_chat_message(&format!("&cCould not run {}: {}", name, error));
+        }
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for &idx in boards {
+            scheduler.update(idx, status, latency);
+        }
+        drop(scheduler);
+        self.reset_timings();
+    }
+
+    /// Starts the queued build once the scheduler has handed this plot its boards
+    fn poll_fpga_queue(&mut self) {
+        let Some(name) = self.queued_run.clone() else {
+            return;
+        };
+        let plot = self.world.lock().unwrap().get_plot();
+        let granted = self.scheduler.lock().unwrap().owned_by(plot);
+        if !granted.is_empty() {
+            self.queued_run = None;
+            self.start_fpga(&name, &granted);
+        }
+    }
+
+    /// Gives the plot's boards back to the scheduler and leaves the queue
+    fn release_fpga(&mut self) {
+        self.queued_run = None;
+        let plot = self.world.lock().unwrap().get_plot();
+        self.scheduler.lock().unwrap().free(plot);
+    }
+
+    /// Name of the plot's directories under `FPGA/bin` and the caches, like `3--2`
+    fn plot_name(&self) -> String {
+        let (x, z) = self.world.lock().unwrap().get_plot();
+        format!("{}-{}", x, z)
+    }
+
+    /// Why a build's files cannot be deleted or renamed right now, if they cannot
+    fn build_in_use(&self, name: &str) -> Option<&'static str> {
+        if self.compile_jobs.iter().any(|job| job.name == name) {
+            return Some("is compiling");
+        }
+        if self.queued_run.as_deref() == Some(name) {
+            return Some("is queued to run");
+        }
+        let active = self.active_backend.map(|active| self.backends.lock().unwrap()[active].name.clone());
+        if active.as_deref() == Some(name) {
+            return Some("is running");
+        }
+        None
+    }
+
+    /// Unloads an FPGA build and takes it off the scoreboard
+    fn forget_fpga_build(&mut self, name: &str) {
+        let mut backends = self.backends.lock().unwrap();
+        let Some(i) = backends.iter_mut().position(|backend| backend.name == name && backend.fpga().is_some()) else {
+            return;
+        };
+        backends.remove(i);
+        drop(backends);
+        if let Some(active) = self.active_backend.filter(|&active| active > i) {
+            self.active_backend = Some(active - 1);
+        }
+        _ = self.backend_tx.send(BackendMsg::Delete { backend: name.to_owned() });
+    }
+
     fn is_io_only(&mut self) -> bool {
         if !self.active_backend.is_none() {
             self.backends.lock().unwrap()[self.active_backend.unwrap()].options().io_only
@@ -1033,6 +1311,7 @@ impl Plot {
     fn update(&mut self) {
         self.handle_messages();
 
+        self.poll_compile_jobs();
         let mut new_sb = false;
         while let Ok(message) = self.backend_rx.try_recv() {
             self.scoreboard.parse_scoreboard_msg(message);
@@ -1041,6 +1320,7 @@ impl Plot {
         if new_sb {
             self.scoreboard.update(&self.players);
         }
+        self.poll_fpga_queue();
 
         // Only tick if there are players in the plot
         if !self.players.is_empty() {
@@ -1193,7 +1473,8 @@ impl Plot {
         let tps = plot_data.tps;
         let world_send_rate = plot_data.world_send_rate;
         let (back_tx, back_rx) = mpsc::channel();
-        let backends = Backend::from_data((x,z), back_tx.clone(), fpga_scheduler.lock().unwrap().get_config());
+        let world = Arc::new(Mutex::new(world));
+        let backends = Backend::from_data((x,z), back_tx.clone(), &fpga_scheduler.lock().unwrap().configs(), &world);
         Plot {
             last_player_time: Instant::now(),
             last_update_time: Instant::now(),
@@ -1215,12 +1496,15 @@ impl Plot {
             active_backend: None,
             backend_rx: back_rx,
             backend_tx: back_tx,
+            compile_jobs: Vec::new(),
             timings: TimingsMonitor::new(tps),
             owner: database::get_plot_owner(x, z).map(|s| s.parse::<HyphenatedUUID>().unwrap().0),
             async_rt: Plot::create_async_rt(),
             scoreboard: Scoreboard::new(),
-            world:Arc::new(Mutex::new(world)),
+            world,
             scheduler: fpga_scheduler, 
+            queued_run: None,
+            trace: TraceOptions::default(),
         }
 
     }
@@ -1369,6 +1653,7 @@ impl Drop for Plot {
         }
 
         self.reset_backend();
+        self.release_fpga();
         self.world.lock().unwrap()
             .chunks
             .iter_mut()
@@ -11,9 +11,23 @@ use std::collections::HashMap;
 #[derive(Default)]
 pub struct Scoreboard {
     backend_list: HashMap<String, (CompilerOptions, BackendStatus)>,
+    /// Estimated FPGA utilization of the builds that run on a board
+    utilization: HashMap<String, f64>,
+    /// What the builds still compiling are doing
+    progress: HashMap<String, String>,
     current_state: Vec<String>,
 }
 
+/// Longest line shown below a backend before it is cut off
+const MAX_DETAIL_LEN: usize = 32;
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_DETAIL_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
 impl Scoreboard {
     pub fn new() -> Scoreboard{
         let mut sb: Scoreboard = Default::default();
@@ -26,6 +40,20 @@ impl Scoreboard {
 
         for (name, (options, status)) in &self.backend_list {
             sb.push(format!("&f{:15} {}", name, status.to_str()));
+            if let Some(message) = self.progress.get(name) {
+                sb.push(format!("&7  {}", truncate(message)));
+            }
+            if let BackendStatus::Failed(reason) = status {
+                sb.push(format!("&c  {}", truncate(reason)));
+            }
+            if let Some(&utilization) = self.utilization.get(name) {
+                let color = match utilization {
+                    u if u > 0.9 => "&c",
+                    u if u > 0.7 => "&e",
+                    _ => "&a",
+                };
+                sb.push(format!("&7  utilization: {}{:.0}%", color, utilization * 100.0));
+            }
             sb.extend(options.to_str_vec());
         }
 
@@ -34,7 +62,6 @@ impl Scoreboard {
         // if self.fpga_device_state != DeviceStatus::Inactive {
         //     state_str.push(format!("&7  device: &a{}",self.fpga_device_name.clone()));
         //     state_str.push(format!("&7  ping: &a{}us",self.fpga_ping));
-        //     state_str.push(format!("&7  utilization: &a22%"));
         // }
         
 
@@ -55,12 +82,26 @@ impl Scoreboard {
             BackendMsg::New { backend, options} => {
                 self.add_backend(backend.clone(), options);
                  self.backend_list.get_mut(&backend).unwrap().1 = BackendStatus::Redpiling;
+                self.utilization.remove(&backend);
             }
             BackendMsg::Delete { backend} => {
                 self.backend_list.remove(&backend);
+                self.utilization.remove(&backend);
+                self.progress.remove(&backend);
             }
             BackendMsg::BackendStatus { backend, status } => {
-                self.backend_list.get_mut(&backend).unwrap().1 = status;
+                if !matches!(status, BackendStatus::Redpiling | BackendStatus::Compiling) {
+                    self.progress.remove(&backend);
+                }
+                if let Some(entry) = self.backend_list.get_mut(&backend) {
+                    entry.1 = status;
+                }
+            }
+            BackendMsg::Progress { backend, message } => {
+                self.progress.insert(backend, message);
+            }
+            BackendMsg::Utilization { backend, utilization } => {
+                self.utilization.insert(backend, utilization);
             }
         }
     }
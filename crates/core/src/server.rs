@@ -7,6 +7,7 @@ use crate::{permissions, utils};
 use backtrace::Backtrace;
 use bus::Bus;
 use fpga::scheduler::FPGAScheduler;
+use mchprs_backend::fpga::toolchain::ToolchainConfig;
 use hmac::{Hmac, Mac};
 use mchprs_network::packets::clientbound::{
     CConfigurationPluginMessage, CDisconnectLogin, CFinishConfiguration, CGameEvent,
@@ -182,6 +183,12 @@ impl MinecraftServer {
             permissions::init(permissions_config.clone()).unwrap();
         }
 
+        let toolchain = ToolchainConfig::new(&CONFIG.fpga_compiler, &CONFIG.fpga_tools_path)
+            .unwrap_or_else(|err| {
+                warn!("{}, falling back to Quartus", err);
+                ToolchainConfig::default()
+            });
+
         // Create server struct
         let mut server = MinecraftServer {
             network: NetworkServer::new(bind_addr),
@@ -190,7 +197,7 @@ impl MinecraftServer {
             plot_sender: plot_tx,
             online_players: FxHashMap::default(),
             running_plots: Vec::new(),
-            fpga_scheduler: Arc::new(Mutex::new(FPGAScheduler::load_from_config("FPGA/config/devices.json"))),
+            fpga_scheduler: Arc::new(Mutex::new(FPGAScheduler::load_from_config("FPGA/config/devices.json", toolchain))),
             whitelist,
         };
 
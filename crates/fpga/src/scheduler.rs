@@ -1,61 +1,305 @@
 use mchprs_backend::fpga::compiler::DeviceConfig;
-use crate::scheduler;
-use std::{fs, path::Path};
+use mchprs_backend::fpga::interface::DeviceStatus;
+use mchprs_backend::fpga::toolchain::ToolchainConfig;
+use std::collections::VecDeque;
+use std::fs;
+use std::time::Duration;
 
+/// Position of the plot a board is assigned to
+pub type PlotPos = (i32, i32);
+
+/// Hands out the configured boards to plots, queueing plots while every suitable board is busy.
+/// A build split across several boards gets all of them at once or waits for them together.
 #[derive(Default)]
 pub struct FPGAScheduler {
-    pub fpgas: Vec<FPGA>
+    pub fpgas: Vec<FPGA>,
+    queue: VecDeque<QueueEntry>,
+}
 
+/// Outcome of asking for boards to run a build on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lock {
+    /// The boards at these indices now belong to the plot, one per partition of the build
+    Acquired(Vec<usize>),
+    /// Every suitable board is busy, the plot waits at this position (starting at 1)
+    Queued(usize),
+    /// No board in service can run the build
+    Unavailable,
 }
 
+/// A plot waiting for boards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub plot: PlotPos,
+    pub build: String,
+    /// Boards the build was compiled for, one per partition
+    pub devices: Vec<String>,
+}
 
 impl FPGAScheduler {
 
-    pub fn load_from_config(path: &str) -> FPGAScheduler {
+    pub fn load_from_config(path: &str, toolchain: ToolchainConfig) -> FPGAScheduler {
         let config_str = fs::read_to_string(path).unwrap();
         let configs: Vec<DeviceConfig> = serde_json::from_str(&config_str).unwrap();
 
-        let mut fpgas: Vec<FPGA> = Vec::new();
+        let fpgas = configs
+            .into_iter()
+            .map(|mut cfg| {
+                cfg.toolchain = toolchain.clone();
+                FPGA::new(cfg)
+            })
+            .collect();
 
-        for cfg in configs {
-            fpgas.push(FPGA { 
-                config: cfg,
-                owner: None, 
-            });
+        FPGAScheduler {
+            fpgas,
+            queue: VecDeque::new(),
         }
+    }
 
-        return FPGAScheduler {fpgas: fpgas};
+    pub fn configs(&self) -> Vec<DeviceConfig> {
+        self.fpgas.iter().map(|fpga| fpga.config.clone()).collect()
     }
 
-    pub fn get_config(&self) -> DeviceConfig {
-        self.fpgas[0].config.clone()
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.fpgas.iter().position(|fpga| fpga.config.name == name)
     }
 
-    pub fn lock(&mut self, plot: (i32,i32)) -> bool {
-        let mut i = 0;
-        for fpga in &self.fpgas {
-            if fpga.owner == None {
-                self.fpgas[i].owner = Some(plot);
-                return true;
+    /// Boards a plot should compile its next build for, in the order a build that does not fit
+    /// on one is split across them: the boards it already holds, then free boards, then any board in service
+    pub fn devices_for(&self, plot: PlotPos) -> Vec<DeviceConfig> {
+        let mut boards = self.owned_by(plot);
+        let free = (0..self.fpgas.len()).filter(|&idx| self.fpgas[idx].is_free());
+        let in_service = (0..self.fpgas.len()).filter(|&idx| self.fpgas[idx].in_service());
+        for idx in free.chain(in_service) {
+            if !boards.contains(&idx) {
+                boards.push(idx);
             }
-            i += 1;
         }
-        false
+        boards.into_iter().map(|idx| self.fpgas[idx].config.clone()).collect()
     }
 
-    pub fn free(&mut self, plot: (i32, i32)) {
-        let mut i = 0;
-        for fpga in &self.fpgas {
+    /// Boards currently assigned to a plot, in partition order
+    pub fn owned_by(&self, plot: PlotPos) -> Vec<usize> {
+        let mut boards: Vec<usize> = (0..self.fpgas.len()).filter(|&idx| self.fpgas[idx].owner == Some(plot)).collect();
+        boards.sort_by_key(|&idx| self.fpgas[idx].partition);
+        boards
+    }
+
+    /// Assigns the plot a board for every partition of a build compiled for `devices`,
+    /// preferring the named boards themselves. Any boards the plot already has are released first.
+    pub fn lock(&mut self, plot: PlotPos, build: &str, devices: &[String]) -> Lock {
+        self.free(plot);
+        let in_service = self.fpgas.iter().filter(|fpga| fpga.in_service()).count();
+        let runnable = devices
+            .iter()
+            .all(|device| self.fpgas.iter().any(|fpga| fpga.in_service() && fpga.can_run(&self.fpgas, device)));
+        if !runnable || devices.len() > in_service {
+            return Lock::Unavailable;
+        }
+        if let Some(boards) = self.free_boards_for(devices) {
+            self.assign(&boards, plot);
+            return Lock::Acquired(boards);
+        }
+        self.queue.push_back(QueueEntry {
+            plot,
+            build: build.to_owned(),
+            devices: devices.to_vec(),
+        });
+        Lock::Queued(self.queue.len())
+    }
+
+    /// Releases the plot's board and drops it from the queue. Freed boards go to the next plot waiting for them.
+    pub fn free(&mut self, plot: PlotPos) {
+        self.queue.retain(|entry| entry.plot != plot);
+        for fpga in &mut self.fpgas {
             if fpga.owner == Some(plot) {
-                self.fpgas[i].owner = None;
-                break;
+                fpga.release();
+            }
+        }
+        self.dispatch();
+    }
+
+    /// Takes a board out of service. A board in use keeps running until its plot releases it.
+    pub fn stop(&mut self, idx: usize) {
+        let fpga = &mut self.fpgas[idx];
+        fpga.enabled = false;
+        if fpga.owner.is_none() {
+            fpga.status = DeviceStatus::Failed;
+        }
+    }
+
+    /// Puts a board back into service
+    pub fn start(&mut self, idx: usize) {
+        let fpga = &mut self.fpgas[idx];
+        fpga.enabled = true;
+        if fpga.owner.is_none() {
+            fpga.status = DeviceStatus::Inactive;
+        }
+        self.dispatch();
+    }
+
+    pub fn queue(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.queue.iter()
+    }
+
+    /// Records the state of a board reported by the plot running it
+    pub fn update(&mut self, idx: usize, status: DeviceStatus, latency: Option<Duration>) {
+        let fpga = &mut self.fpgas[idx];
+        fpga.status = status;
+        if latency.is_some() {
+            fpga.latency = latency;
+        }
+    }
+
+    /// A distinct free board for every partition, or none if any partition has to wait
+    fn free_boards_for(&self, devices: &[String]) -> Option<Vec<usize>> {
+        let mut boards: Vec<usize> = Vec::with_capacity(devices.len());
+        for device in devices {
+            let available = |idx: usize| self.fpgas[idx].is_free() && !boards.contains(&idx);
+            let idx = self
+                .find(device)
+                .filter(|&idx| available(idx))
+                .or_else(|| (0..self.fpgas.len()).find(|&idx| available(idx) && self.fpgas[idx].can_run(&self.fpgas, device)))?;
+            boards.push(idx);
+        }
+        Some(boards)
+    }
+
+    fn assign(&mut self, boards: &[usize], plot: PlotPos) {
+        for (partition, &idx) in boards.iter().enumerate() {
+            self.fpgas[idx].assign(plot, partition);
+        }
+    }
+
+    /// Hands free boards to queued plots in order
+    fn dispatch(&mut self) {
+        let mut i = 0;
+        while i < self.queue.len() {
+            match self.free_boards_for(&self.queue[i].devices) {
+                Some(boards) => {
+                    let entry = self.queue.remove(i).unwrap();
+                    self.assign(&boards, entry.plot);
+                }
+                None => i += 1,
             }
-            i += 1;
         }
     }
 }
 
 pub struct FPGA {
     pub config: DeviceConfig,
-    owner: Option<(i32,i32)>,
+    owner: Option<PlotPos>,
+    /// Partition of the owner's build the board runs
+    partition: usize,
+    enabled: bool,
+    pub status: DeviceStatus,
+    /// Round trip time of the last ping
+    pub latency: Option<Duration>,
+}
+
+impl FPGA {
+    fn new(config: DeviceConfig) -> FPGA {
+        FPGA {
+            config,
+            owner: None,
+            partition: 0,
+            enabled: true,
+            status: DeviceStatus::Inactive,
+            latency: None,
+        }
+    }
+
+    pub fn owner(&self) -> Option<PlotPos> {
+        self.owner
+    }
+
+    pub fn in_service(&self) -> bool {
+        self.enabled
+    }
+
+    fn is_free(&self) -> bool {
+        self.enabled && self.owner.is_none()
+    }
+
+    /// Whether a build compiled for the named board runs on this one, which is the case for the same part
+    fn can_run(&self, fpgas: &[FPGA], device: &str) -> bool {
+        self.config.name == device
+            || fpgas
+                .iter()
+                .any(|other| other.config.name == device && other.config.device == self.config.device)
+    }
+
+    fn assign(&mut self, plot: PlotPos, partition: usize) {
+        self.owner = Some(plot);
+        self.partition = partition;
+        self.status = DeviceStatus::Programming;
+    }
+
+    fn release(&mut self) {
+        self.owner = None;
+        self.latency = None;
+        self.status = if self.enabled { DeviceStatus::Inactive } else { DeviceStatus::Failed };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(boards: &[(&str, &str)]) -> FPGAScheduler {
+        FPGAScheduler {
+            fpgas: boards
+                .iter()
+                .map(|(name, device)| {
+                    FPGA::new(DeviceConfig {
+                        name: name.to_string(),
+                        device: device.to_string(),
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn lock(scheduler: &mut FPGAScheduler, plot: PlotPos, devices: &[&str]) -> Lock {
+        let devices: Vec<String> = devices.iter().map(|device| device.to_string()).collect();
+        scheduler.lock(plot, "x", &devices)
+    }
+
+    #[test]
+    fn queue_until_free() {
+        let mut scheduler = scheduler(&[("a", "part"), ("b", "part"), ("c", "other")]);
+        assert_eq!(lock(&mut scheduler, (0, 0), &["a"]), Lock::Acquired(vec![0]));
+        assert_eq!(lock(&mut scheduler, (1, 0), &["a"]), Lock::Acquired(vec![1]));
+        assert_eq!(lock(&mut scheduler, (2, 0), &["b"]), Lock::Queued(1));
+        assert_eq!(lock(&mut scheduler, (3, 0), &["c"]), Lock::Acquired(vec![2]));
+
+        scheduler.free((0, 0));
+        assert_eq!(scheduler.owned_by((2, 0)), [0]);
+        assert_eq!(scheduler.queue().count(), 0);
+    }
+
+    #[test]
+    fn stopped_boards_are_not_assigned() {
+        let mut scheduler = scheduler(&[("a", "part")]);
+        scheduler.stop(0);
+        assert_eq!(lock(&mut scheduler, (0, 0), &["a"]), Lock::Unavailable);
+        scheduler.start(0);
+        assert_eq!(lock(&mut scheduler, (0, 0), &["a"]), Lock::Acquired(vec![0]));
+    }
+
+    #[test]
+    fn partitioned_builds_lock_every_board() {
+        let mut scheduler = scheduler(&[("a", "part"), ("b", "part"), ("c", "other")]);
+        assert_eq!(lock(&mut scheduler, (0, 0), &["b"]), Lock::Acquired(vec![1]));
+        assert_eq!(lock(&mut scheduler, (1, 0), &["a", "b"]), Lock::Queued(1));
+        assert!(scheduler.owned_by((1, 0)).is_empty());
+        assert_eq!(lock(&mut scheduler, (1, 0), &["a", "b", "c", "c"]), Lock::Unavailable);
+
+        scheduler.free((0, 0));
+        assert_eq!(lock(&mut scheduler, (1, 0), &["c", "a"]), Lock::Acquired(vec![2, 0]));
+        assert_eq!(scheduler.owned_by((1, 0)), [2, 0]);
+        assert_eq!(scheduler.devices_for((1, 0))[0].name, "c");
+    }
 }
@@ -3,6 +3,7 @@ use std::collections::HashSet;
 use mchprs_blocks::blocks::{ComparatorMode, Instrument};
 use mchprs_blocks::BlockPos;
 use petgraph::stable_graph::{NodeIndex, StableGraph};
+use serde::{Deserialize, Serialize};
 
 pub type NodeIdx = NodeIndex;
 
@@ -29,22 +30,65 @@ pub enum NodeType {
         instrument: Instrument,
         note: u32,
     },
-    DiscreteComparator {
-        states: u16
-    },
+    /// A comparator whose inputs only reach a few signal strengths, evaluated from its table.
+    /// Made by the `DiscreteComparators` pass.
     LUT {
-        input: Vec<LUTEntry>,
-        side: Vec<LUTEntry>,
-        output: Vec<LUTEntry>
+        table: LookupTable,
+        facing_diode: bool,
+    },
+    /// Pulses for a tick, a tick after the node on its one default link changes state. The link's
+    /// signal strength is not an input, only the changes of the watched node are.
+    Observer,
+}
+
+/// Output of a comparator for every pair of default and side input strengths it can see,
+/// with a far input already applied. `input` and `side` have a bit set for each strength the
+/// links can reach, and the output for the `i`th input and `j`th side strength is held in
+/// the 4 bits of `table` at entry `i * side.count_ones() + j`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LookupTable {
+    pub input: u16,
+    pub side: u16,
+    pub table: u64,
+}
+
+impl LookupTable {
+    /// Most pairs of strengths a table holds, as many as fit 4 bits each in `table`
+    pub const MAX_ENTRIES: u32 = 16;
+
+    /// Tabulates a function over the reachable strengths, `None` if there are too many pairs
+    pub fn new(input: u16, side: u16, f: impl Fn(u8, u8) -> u8) -> Option<LookupTable> {
+        if input.count_ones() * side.count_ones() > Self::MAX_ENTRIES {
+            return None;
+        }
+        let mut table = 0;
+        for (entry, (input, side)) in strengths(input).flat_map(|i| strengths(side).map(move |s| (i, s))).enumerate() {
+            table |= (f(input, side) as u64 & 0xF) << (entry * 4);
+        }
+        Some(LookupTable { input, side, table })
+    }
+
+    /// Output for a pair of input strengths, 0 for strengths the links cannot reach
+    pub fn get(self, input: u8, side: u8) -> u8 {
+        if self.input >> input & 1 == 0 || self.side >> side & 1 == 0 {
+            return 0;
+        }
+        let row = (self.input as u32 & ((1 << input) - 1)).count_ones();
+        let column = (self.side as u32 & ((1 << side) - 1)).count_ones();
+        let entry = row * self.side.count_ones() + column;
+        (self.table >> (entry * 4) & 0xF) as u8
+    }
+
+    /// Every pair of reachable input strengths with its output
+    pub fn entries(self) -> impl Iterator<Item = (u8, u8, u8)> {
+        strengths(self.input)
+            .flat_map(move |input| strengths(self.side).map(move |side| (input, side, self.get(input, side))))
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
-pub enum LUTEntry {
-    #[default]
-    None,
-    Discrete (u16),
-    Analog (u8) //only use max 4bits
+/// The signal strengths with a bit set in a mask
+pub fn strengths(mask: u16) -> impl Iterator<Item = u8> {
+    (0..16).filter(move |ss| mask >> ss & 1 == 1)
 }
 
 #[derive(Debug, Clone, Default)]
@@ -87,8 +131,38 @@ impl NodeState {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Annotations {}
+/// What the signs next to a component say about it, see `NodeAnnotation` for the language.
+/// Names are in lowercase.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations {
+    /// Board peripheral the component is bound to with a `[pin <name>]` sign
+    pub pin: Option<String>,
+    /// Name of the input or output the component was made with `[input <name>]` or `[output <name>]`
+    pub io: Option<String>,
+    /// Name the component is inspected by, given with `[probe <name>]`
+    pub probe: Option<String>,
+    /// Bus and bit the component carries, given with `[bus <name> <bit>]`
+    pub bus: Option<(String, u32)>,
+    /// Whether the component is marked as the build's clock with `[clock]`
+    pub clock: bool,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        *self == Annotations::default()
+    }
+
+    /// Name the component is shown by, like `sum` or `data[3]`
+    pub fn name(&self) -> Option<String> {
+        if let Some(name) = self.probe.as_ref().or(self.io.as_ref()) {
+            return Some(name.clone());
+        }
+        if let Some((bus, bit)) = &self.bus {
+            return Some(format!("{}[{}]", bus, bit));
+        }
+        self.clock.then(|| "clock".to_owned())
+    }
+}
 
 #[derive(Debug)]
 pub struct CompileNode {
@@ -103,7 +177,7 @@ pub struct CompileNode {
 
 impl CompileNode {
     pub fn is_removable(&self) -> bool {
-        !self.is_input && !self.is_output
+        !self.is_input && !self.is_output && self.annotations.is_empty()
     }
 }
 
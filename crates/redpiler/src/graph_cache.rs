@@ -0,0 +1,274 @@
+//! On disk cache of the graphs redpiled for the direct backend.
+//!
+//! A cached graph is keyed by a hash of the blocks in the region it was compiled from, with
+//! their power state left out, so flipping a lever keeps the cache valid while any change to
+//! the build throws it away. The node states are refreshed from the world when the graph is loaded.
+
+use crate::compile_graph::{
+    Annotations, CompileGraph, CompileLink, CompileNode, LinkType, LookupTable, NodeState, NodeType,
+};
+use crate::passes::{identify_block, to_nodes};
+use crate::redpiler_graph::{self, ComparatorMode, Node};
+use crate::{block_powered_mut, BackendVariant, CompilerOptions};
+use mchprs_blocks::block_entities::BlockEntity;
+use mchprs_blocks::blocks::{Block, ComparatorMode as CComparatorMode, Instrument};
+use mchprs_blocks::BlockPos;
+use mchprs_redstone::comparator;
+use mchprs_world::{for_each_block_optimized, World};
+use rustc_hash::FxHasher;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::debug;
+
+/// Directory the cached graphs of every plot are kept in
+pub const CACHE_DIR: &str = "redpiler/cache";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns the cache off for the rest of the process, for tests that compile many different
+/// worlds under the same plot and build name
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    !DISABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    key: u64,
+    bounds: (BlockPos, BlockPos),
+    optimize: bool,
+    io_only: bool,
+    update: bool,
+    wire_dot_out: bool,
+}
+
+/// A graph loaded from the cache together with what it was compiled with
+pub struct CachedGraph {
+    pub bounds: (BlockPos, BlockPos),
+    pub options: CompilerOptions,
+    pub graph: CompileGraph,
+}
+
+impl CachedGraph {
+    /// Whether compiling the region with these options would give the same graph
+    pub fn fits(&self, bounds: (BlockPos, BlockPos), options: &CompilerOptions) -> bool {
+        self.bounds == bounds
+            && self.options.optimize == options.optimize
+            && self.options.io_only == options.io_only
+            && self.options.wire_dot_out == options.wire_dot_out
+    }
+}
+
+/// Directory of a plot's cached graphs
+pub fn plot_dir(plot: &str) -> PathBuf {
+    Path::new(CACHE_DIR).join(plot)
+}
+
+/// Where a build's graph is cached
+pub fn path(plot: &str, name: &str) -> PathBuf {
+    plot_dir(plot).join(format!("{}.bc", name))
+}
+
+/// Block id with the power state stripped, so only changes to the design itself change it
+fn design_id(mut block: Block) -> u32 {
+    if let Some(powered) = block_powered_mut(&mut block) {
+        *powered = false;
+    }
+    match &mut block {
+        Block::RedstoneRepeater { repeater } => repeater.locked = false,
+        Block::RedstoneWire { wire } => wire.power = 0,
+        _ => {}
+    }
+    block.get_id()
+}
+
+/// Hash of everything in the region and the options that shape the compiled graph
+pub fn key<W: World>(world: &W, bounds: (BlockPos, BlockPos), options: &CompilerOptions) -> u64 {
+    let mut hasher = FxHasher::default();
+    redpiler_graph::VERSION.hash(&mut hasher);
+    bounds.hash(&mut hasher);
+    options.optimize.hash(&mut hasher);
+    options.io_only.hash(&mut hasher);
+    options.wire_dot_out.hash(&mut hasher);
+
+    for_each_block_optimized(world, bounds.0, bounds.1, |pos| {
+        let id = world.get_block_raw(pos);
+        if id == 0 {
+            return;
+        }
+        let block = Block::from_id(id);
+        pos.hash(&mut hasher);
+        design_id(block).hash(&mut hasher);
+        if comparator::has_override(block) {
+            comparator::get_override(block, world, pos).hash(&mut hasher);
+        }
+        if let Some(BlockEntity::Sign(sign)) = world.get_block_entity(pos) {
+            sign.front_rows.hash(&mut hasher);
+        }
+    });
+    hasher.finish()
+}
+
+/// Counts the caches written, to give every write its own temporary file
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a graph compiled from the region whose blocks hashed to `key`. The file is written
+/// next to the cache and renamed over it, so readers never see it half written.
+pub fn store(
+    path: &Path,
+    key: u64,
+    bounds: (BlockPos, BlockPos),
+    options: &CompilerOptions,
+    graph: &CompileGraph,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let header = Header {
+        key,
+        bounds,
+        optimize: options.optimize,
+        io_only: options.io_only,
+        update: options.update,
+        wire_dot_out: options.wire_dot_out,
+    };
+    let tmp = path.with_extension(format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let encode = io::Error::other;
+    bincode::serialize_into(&mut writer, &redpiler_graph::VERSION).map_err(encode)?;
+    bincode::serialize_into(&mut writer, &header).map_err(encode)?;
+    redpiler_graph::serialize_into(&mut writer, &to_nodes(graph)).map_err(encode)?;
+    writer.into_inner().map_err(|err| err.into_error())?;
+    fs::rename(&tmp, path)
+}
+
+/// Reads a cached graph if it is still valid for the world. Caches from another version
+/// of the format or of a region that has changed since are deleted.
+pub fn load<W: World>(path: &Path, world: &W) -> Option<CachedGraph> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let version: u32 = bincode::deserialize_from(&mut reader).ok()?;
+    if version != redpiler_graph::VERSION {
+        debug!("Discarding cached graph {:?} of version {}", path, version);
+        _ = fs::remove_file(path);
+        return None;
+    }
+    let header: Header = bincode::deserialize_from(&mut reader).ok()?;
+    let options = CompilerOptions {
+        optimize: header.optimize,
+        io_only: header.io_only,
+        update: header.update,
+        wire_dot_out: header.wire_dot_out,
+        backend_variant: BackendVariant::Direct,
+        ..Default::default()
+    };
+    if key(world, header.bounds, &options) != header.key {
+        debug!("Discarding stale cached graph {:?}", path);
+        _ = fs::remove_file(path);
+        return None;
+    }
+    let nodes: Vec<Node> = bincode::deserialize_from(&mut reader).ok()?;
+
+    let mut graph = from_nodes(&nodes);
+    // The design is unchanged, but inputs may have been flipped since the graph was cached
+    for node in graph.node_weights_mut() {
+        let Some((pos, _)) = node.block else {
+            continue;
+        };
+        let id = world.get_block_raw(pos);
+        if let Some((_, state)) = identify_block(Block::from_id(id), pos, world) {
+            node.block = Some((pos, id));
+            node.state = state;
+        }
+    }
+    Some(CachedGraph {
+        bounds: header.bounds,
+        options,
+        graph,
+    })
+}
+
+/// Rebuilds a graph from its exported nodes
+pub fn from_nodes(nodes: &[Node]) -> CompileGraph {
+    let mut graph = CompileGraph::new();
+    let indices: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            graph.add_node(CompileNode {
+                ty: convert_type(node),
+                block: node
+                    .block
+                    .map(|(pos, id)| (BlockPos::new(pos.x, pos.y, pos.z), id)),
+                state: NodeState {
+                    powered: node.state.powered,
+                    repeater_locked: node.state.repeater_locked,
+                    output_strength: node.state.output_strength,
+                },
+                is_input: node.is_input,
+                is_output: node.is_output,
+                annotations: Annotations {
+                    pin: node.annotations.pin.clone(),
+                    io: node.annotations.io.clone(),
+                    probe: node.annotations.probe.clone(),
+                    bus: node.annotations.bus.clone(),
+                    clock: node.annotations.clock,
+                },
+            })
+        })
+        .collect();
+
+    for (node, &idx) in nodes.iter().zip(&indices) {
+        for link in &node.inputs {
+            let ty = match link.ty {
+                redpiler_graph::LinkType::Default => LinkType::Default,
+                redpiler_graph::LinkType::Side => LinkType::Side,
+            };
+            graph.add_edge(indices[link.to], idx, CompileLink { ty, ss: link.weight });
+        }
+    }
+    graph
+}
+
+fn convert_type(node: &Node) -> NodeType {
+    match node.ty {
+        redpiler_graph::NodeType::Repeater(delay) => NodeType::Repeater {
+            delay,
+            facing_diode: node.facing_diode,
+        },
+        redpiler_graph::NodeType::Torch => NodeType::Torch,
+        redpiler_graph::NodeType::Comparator(mode) => NodeType::Comparator {
+            mode: match mode {
+                ComparatorMode::Compare => CComparatorMode::Compare,
+                ComparatorMode::Subtract => CComparatorMode::Subtract,
+            },
+            far_input: node.comparator_far_input,
+            facing_diode: node.facing_diode,
+        },
+        redpiler_graph::NodeType::Lamp => NodeType::Lamp,
+        redpiler_graph::NodeType::Button => NodeType::Button,
+        redpiler_graph::NodeType::Lever => NodeType::Lever,
+        redpiler_graph::NodeType::PressurePlate => NodeType::PressurePlate,
+        redpiler_graph::NodeType::Trapdoor => NodeType::Trapdoor,
+        redpiler_graph::NodeType::Wire => NodeType::Wire,
+        redpiler_graph::NodeType::Constant => NodeType::Constant,
+        redpiler_graph::NodeType::NoteBlock { instrument, note } => NodeType::NoteBlock {
+            instrument: Instrument::from_id(instrument),
+            note,
+        },
+        redpiler_graph::NodeType::LUT { input, side, table } => NodeType::LUT {
+            table: LookupTable { input, side, table },
+            facing_diode: node.facing_diode,
+        },
+        redpiler_graph::NodeType::Observer => NodeType::Observer,
+    }
+}
@@ -1,10 +1,13 @@
 pub mod compile_graph;
+pub mod graph_cache;
 pub mod redpiler_graph;
 pub mod passes;
+pub mod task_monitor;
 
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::BlockPos;
 use mchprs_world::World;
+use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use tracing::warn;
 
@@ -21,6 +24,7 @@ pub fn block_powered_mut(block: &mut Block) -> Option<&mut bool> {
         Block::RedstoneLamp { lit } => lit,
         Block::IronTrapdoor { powered, .. } => powered,
         Block::NoteBlock { powered, .. } => powered,
+        Block::Observer { powered, .. } => powered,
         _ => return None,
     })
 }
@@ -43,8 +47,54 @@ pub struct CompilerOptions {
     pub selection: bool,
     /// Run the verilog through compiler
     pub compile_verilog: bool,
+    /// Evaluate comparators whose inputs only reach a few signal strengths from lookup tables
+    pub lut: bool,
     /// The backend variant to be used after compilation
     pub backend_variant: BackendVariant,
+    /// Nodes recorded tick by tick for waveform export
+    pub trace: TraceOptions,
+}
+
+/// Edge of the trigger node that starts a trace capture
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum TraceTrigger {
+    Rising,
+    Falling,
+    /// Either edge
+    Change,
+}
+
+/// What a build records into its trace buffer, set up with `/rp trace`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TraceOptions {
+    /// Blocks whose nodes are recorded, in the order they were added
+    pub probes: Vec<BlockPos>,
+    /// Node whose output starts the capture. Without one the buffer keeps the last `depth` ticks.
+    pub trigger: Option<(BlockPos, TraceTrigger)>,
+    /// Ticks the buffer holds
+    pub depth: u32,
+    /// Ticks of the window recorded before the trigger
+    pub pretrigger: u32,
+}
+
+impl TraceOptions {
+    /// Deepest buffer a board is asked to hold
+    pub const MAX_DEPTH: u32 = 65536;
+
+    pub fn is_enabled(&self) -> bool {
+        !self.probes.is_empty()
+    }
+}
+
+impl Default for TraceOptions {
+    fn default() -> TraceOptions {
+        TraceOptions {
+            probes: Vec::new(),
+            trigger: None,
+            depth: 1024,
+            pretrigger: 512,
+        }
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
@@ -53,6 +103,15 @@ pub enum BackendVariant {
     #[default]
     Direct,
     FPGA,
+    /// Generates the FPGA design but runs it in the in-crate netlist interpreter
+    FPGASim,
+}
+
+impl BackendVariant {
+    /// Whether the build runs as an FPGA design, on a board or in the simulation of one
+    pub fn is_fpga(self) -> bool {
+        matches!(self, BackendVariant::FPGA | BackendVariant::FPGASim)
+    }
 }
 
 impl CompilerOptions {
@@ -71,6 +130,7 @@ impl CompilerOptions {
                     "--selection" => co.selection = true,
                     "--fpga" => co.backend_variant = BackendVariant::FPGA,
                     "--compile" => co.compile_verilog = true,
+                    "--lut" => co.lut = true,
                     // FIXME: use actual error handling
                     _ => warn!("Unrecognized option: {}", option),
                 }
@@ -86,6 +146,7 @@ impl CompilerOptions {
                         "s" => co.selection = true,
                         "f" => co.backend_variant = BackendVariant::FPGA,
                         "c" => co.compile_verilog = true,
+                        "l" => co.lut = true,
                         // FIXME: use actual error handling
                         _ => warn!("Unrecognized option: -{}", c),
                     }
@@ -98,6 +159,28 @@ impl CompilerOptions {
         co
     }
 
+    /// The flags `parse` reads back into these options, leaving out the trace
+    pub fn to_flags(&self) -> String {
+        let flags = [
+            (self.optimize, "--optimize"),
+            (self.export, "--export"),
+            (self.io_only, "--io-only"),
+            (self.update, "--update"),
+            (self.export_dot_graph, "--export-dot"),
+            (self.wire_dot_out, "--wire-dot-out"),
+            (self.selection, "--selection"),
+            (self.backend_variant == BackendVariant::FPGA, "--fpga"),
+            (self.compile_verilog, "--compile"),
+            (self.lut, "--lut"),
+        ];
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
         pub fn to_str_vec(&self) -> Vec<String> {
         let mut flags = Vec::new();
         let backend = self.backend_variant;
@@ -119,6 +202,9 @@ impl CompilerOptions {
         if self.selection && backend == BackendVariant::Direct{
             flags.push("    &3- selection only".to_string());
         }
+        if self.lut {
+            flags.push("    &3- lookup tables".to_string());
+        }
         flags
     }
 
@@ -38,7 +38,8 @@ fn fold(graph: &mut CompileGraph) -> usize {
         let mut side_power = 0;
         for edge in graph.edges_directed(idx, Direction::Incoming) {
             let constant = &graph[edge.source()];
-            if constant.ty != NodeType::Constant {
+            // Constants that are inputs, like FPGA ROM cells, can still change at run time
+            if constant.ty != NodeType::Constant || !constant.is_removable() {
                 continue 'nodes;
             }
 
@@ -1,42 +1,50 @@
+//! # [`DiscreteComparators`]
+//!
+//! This pass replaces comparators whose inputs only reach a few signal strengths with lookup tables.
+//!
+//! The strengths every node can output are found first: on/off components output 0 or 15, constants
+//! their own strength and comparators whatever their table gives for the strengths reaching them,
+//! found by repeating until no comparator can reach anything new. A comparator with at most
+//! [`LookupTable::MAX_ENTRIES`] pairs of reachable default and side input strengths then becomes a
+//! [`NodeType::LUT`] holding its output for each pair, with its mode and far input folded in.
+//!
+//! The strengths comparators hold when compiled count as reachable, so the tables are only valid
+//! for the state the build was compiled in. This pass only runs with `--lut`.
+
 use super::Pass;
-use crate::compile_graph::{Annotations, CompileGraph, CompileLink, CompileNode, NodeIdx, NodeState, NodeType, LinkType};
+use crate::compile_graph::{strengths, CompileGraph, LinkType, LookupTable, NodeIdx, NodeType};
 use crate::{CompilerInput, CompilerOptions};
 use mchprs_blocks::blocks::ComparatorMode;
 use mchprs_world::World;
-use petgraph::adj::EdgeReference;
-use petgraph::data::Build;
-use petgraph::visit::{EdgeFiltered, EdgeIndexable, EdgeRef, IntoEdgesDirected, NodeIndexable};
+use petgraph::visit::EdgeRef;
 use petgraph::Direction;
-use crate::redpiler_graph::Link;
+use rustc_hash::FxHashMap;
+use tracing::trace;
 
 pub struct DiscreteComparators;
 
-struct CompInput {
-    states: Vec<InputWeight>,
-}
-
-enum InputWeight {
-    Default (u8),
-    Side (u8),
-    Both (u8, u8),
-}
-
 impl<W: World> Pass<W> for DiscreteComparators {
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
-        let mut starting_nodes : Vec<NodeIdx> = Vec::new();
-        
-        'next: for i in 0..graph.node_bound() {
-            let start_idx = NodeIdx::new(i);
-            if !graph.contains_node(start_idx) {
-                continue 'next;
-            }
-
-            match graph[start_idx].ty {
-                NodeType::Comparator { mode, far_input:_, facing_diode:_ } =>
-                    search(graph, start_idx, mode),
-                _ => {}
-            }
+        let domains = comparator_domains(graph);
+
+        let mut num_converted = 0;
+        for &idx in domains.keys() {
+            let NodeType::Comparator { mode, far_input, facing_diode } = graph[idx].ty else {
+                continue;
+            };
+            let input = input_domain(graph, &domains, idx, LinkType::Default);
+            let side = input_domain(graph, &domains, idx, LinkType::Side);
+            let Some(table) = LookupTable::new(input, side, |input, side| comparator_output(mode, far_input, input, side)) else {
+                continue;
+            };
+            graph[idx].ty = NodeType::LUT { table, facing_diode };
+            num_converted += 1;
         }
+        trace!("Converted {} of {} comparators", num_converted, domains.len());
+    }
+
+    fn should_run(&self, options: &CompilerOptions) -> bool {
+        options.lut
     }
 
     fn status_message(&self) -> &'static str {
@@ -44,72 +52,103 @@ impl<W: World> Pass<W> for DiscreteComparators {
     }
 }
 
-fn search (graph: &mut CompileGraph, node: NodeIdx, mode: ComparatorMode) {
-
-    // for neighbor in graph.neighbors_directed(node, Direction::Incoming) {
-    //     if matches!(graph[neighbor].ty, NodeType::Comparator { .. }) {
-    //         return
-    //     }
-    // }
-
-    // let mut inputs: Vec<CompInput> = Vec::new();
-
-    // let mut direct_const: u8 = 0;
-    // let mut side_const: u8 = 0;
-
-    // for i_node in graph.neighbors_directed(node, Direction::Incoming) {
-    //     let links: Vec<&CompileLink> = Vec::new(); 
-    //     for i_edge in graph.edges_connecting(i_node, node) {
-    //         links.push(i_edge.weight());
-    //     }
-
-    //     match inputs
-            
-
-    //     } else {
-    //         let input =  if links.len() == 2 {
-    //             if links[0].ty == LinkType::Default {
-    //                 InputWeight::Both(links[0].ss, links[1].ss)
-    //             } else {
-    //                 InputWeight::Both(links[1].ss, links[0].ss)
-    //             } 
-    //         } else {
-    //             if links[0].ty == LinkType::Default {
-    //                 InputWeight::Default(links[0].ss)
-    //             } else {
-    //                 InputWeight::Side(links[0].ss)
-    //             } 
-    //         };
-    
-    //         inputs.push(CompInput{id:i_node, w:input});
-    //     }
-    // }
-
-
-    // for i in 0..inputs.len() {
-    //     let 
-    //     for j in i..inputs.len() {
+/// Every strength
+const ALL: u16 = u16::MAX;
+/// Off and fully powered
+const ON_OFF: u16 = 1 | 1 << 15;
+
+/// Output of a comparator, the same way the backends compute it
+fn comparator_output(mode: ComparatorMode, far_input: Option<u8>, input: u8, side: u8) -> u8 {
+    let input = match far_input {
+        Some(far) if input < 15 => far,
+        _ => input,
+    };
+    match mode {
+        _ if input < side => 0,
+        ComparatorMode::Compare => input,
+        ComparatorMode::Subtract => input - side,
+    }
+}
 
-    //     }
-    // }
+/// The strengths each comparator can output, as a mask with a bit per strength
+fn comparator_domains(graph: &CompileGraph) -> FxHashMap<NodeIdx, u16> {
+    let mut domains: FxHashMap<NodeIdx, u16> = graph
+        .node_indices()
+        .filter(|&idx| matches!(graph[idx].ty, NodeType::Comparator { .. }))
+        .map(|idx| (idx, 1 << graph[idx].state.output_strength))
+        .collect();
+
+    // Domains only ever grow and have 16 strengths at most, so this settles
+    loop {
+        let mut changed = false;
+        for idx in domains.keys().copied().collect::<Vec<_>>() {
+            let NodeType::Comparator { mode, far_input, .. } = graph[idx].ty else {
+                unreachable!();
+            };
+            let input = input_domain(graph, &domains, idx, LinkType::Default);
+            let side = input_domain(graph, &domains, idx, LinkType::Side);
+            let mut domain = domains[&idx];
+            for input in strengths(input) {
+                for side in strengths(side) {
+                    domain |= 1 << comparator_output(mode, far_input, input, side);
+                }
+            }
+            if domain != domains[&idx] {
+                domains.insert(idx, domain);
+                changed = true;
+            }
+        }
+        if !changed {
+            return domains;
+        }
+    }
+}
 
-    // let mut edges: Vec<(NodeIdx, NodeIdx, CompileLink)> = Vec::new();
-    // for edge in graph.edges(node) {
-    //     let source = edge.source();
-    //     let target = edge.target();
-    //     let weight = edge.weight();
+/// The strengths a node can output
+fn output_domain(graph: &CompileGraph, domains: &FxHashMap<NodeIdx, u16>, idx: NodeIdx) -> u16 {
+    let node = &graph[idx];
+    match node.ty {
+        NodeType::Comparator { .. } | NodeType::LUT { .. } => domains[&idx],
+        // Containers marked as inputs can be reloaded with anything
+        NodeType::Constant if node.is_input => ALL,
+        NodeType::Constant => 1 << node.state.output_strength,
+        NodeType::Repeater { .. }
+        | NodeType::Torch
+        | NodeType::Lamp
+        | NodeType::Button
+        | NodeType::Lever
+        | NodeType::PressurePlate
+        | NodeType::Observer => ON_OFF,
+        _ => ALL,
+    }
+}
 
-    //     if source == node {
-    //         edges.push((d_comp, target, CompileLink{ss:weight.ss, ty:weight.ty}));
-    //     }
-    //     else {
-    //         edges.push((source, d_comp, CompileLink{ss:weight.ss, ty:weight.ty}));
-    //     }
-    // }
-    // for (o_node,i_node, link) in edges {
-    //     graph.add_edge(o_node, i_node, link);
-    // }
+/// The strengths reaching a node through its links of one type: the strongest signal after each
+/// link's distance. A strength is reachable when one link can carry it while all others can
+/// carry something weaker.
+fn input_domain(graph: &CompileGraph, domains: &FxHashMap<NodeIdx, u16>, idx: NodeIdx, ty: LinkType) -> u16 {
+    let links: Vec<u16> = graph
+        .edges_directed(idx, Direction::Incoming)
+        .filter(|edge| edge.weight().ty == ty)
+        .map(|edge| {
+            let distance = edge.weight().ss;
+            strengths(output_domain(graph, domains, edge.source()))
+                .fold(0, |mask, ss| mask | 1 << ss.saturating_sub(distance))
+        })
+        .collect();
+    if links.is_empty() {
+        return 1;
+    }
 
-    // graph.remove_node(node);
+    let weakest: Vec<u32> = links.iter().map(|mask| mask.trailing_zeros()).collect();
+    let mut domain = 0;
+    for (i, &mask) in links.iter().enumerate() {
+        for ss in strengths(mask) {
+            let others_weaker = weakest.iter().enumerate().all(|(j, &weakest)| i == j || weakest <= ss as u32);
+            if others_weaker {
+                domain |= 1 << ss;
+            }
+        }
+    }
+    domain
 }
-
@@ -7,7 +7,7 @@ use mchprs_world::World;
 use petgraph::visit::EdgeRef;
 use petgraph::Direction;
 use crate::redpiler_graph::{
-    serialize, BlockPos, ComparatorMode, Link, LinkType, Node, NodeState, NodeType,
+    serialize, Annotations, BlockPos, ComparatorMode, Link, LinkType, Node, NodeState, NodeType,
 };
 use rustc_hash::FxHashMap;
 use std::fs;
@@ -39,9 +39,9 @@ fn convert_node(
         .collect();
 
     let facing_diode = match node.ty {
-        CNodeType::Repeater { facing_diode, .. } | CNodeType::Comparator { facing_diode, .. } => {
-            facing_diode
-        }
+        CNodeType::Repeater { facing_diode, .. }
+        | CNodeType::Comparator { facing_diode, .. }
+        | CNodeType::LUT { facing_diode, .. } => facing_diode,
         _ => false,
     };
 
@@ -65,9 +65,16 @@ fn convert_node(
             CNodeType::Trapdoor => NodeType::Trapdoor,
             CNodeType::Wire => NodeType::Wire,
             CNodeType::Constant => NodeType::Constant,
-            CNodeType::NoteBlock { .. } => NodeType::NoteBlock,
-            _ => NodeType::Comparator(ComparatorMode::Compare)
-
+            CNodeType::NoteBlock { instrument, note } => NodeType::NoteBlock {
+                instrument: instrument.get_id(),
+                note,
+            },
+            CNodeType::LUT { table, .. } => NodeType::LUT {
+                input: table.input,
+                side: table.side,
+                table: table.table,
+            },
+            CNodeType::Observer => NodeType::Observer,
         },
         block: node.block.map(|(pos, id)| {
             (
@@ -84,6 +91,15 @@ fn convert_node(
             powered: node.state.powered,
             repeater_locked: node.state.repeater_locked,
         },
+        is_input: node.is_input,
+        is_output: node.is_output,
+        annotations: Annotations {
+            pin: node.annotations.pin.clone(),
+            io: node.annotations.io.clone(),
+            probe: node.annotations.probe.clone(),
+            bus: node.annotations.bus.clone(),
+            clock: node.annotations.clock,
+        },
         comparator_far_input,
         facing_diode,
         inputs,
@@ -91,21 +107,25 @@ fn convert_node(
     }
 }
 
+/// Converts the graph to the exported format, numbering the nodes in index order
+pub(crate) fn to_nodes(graph: &CompileGraph) -> Vec<Node> {
+    let mut nodes_map =
+        FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
+    for node in graph.node_indices() {
+        nodes_map.insert(node, nodes_map.len());
+    }
+
+    graph
+        .node_indices()
+        .map(|idx| convert_node(graph, idx, &nodes_map))
+        .collect_vec()
+}
+
 pub struct ExportGraph;
 
 impl<W: World> Pass<W> for ExportGraph {
     fn run_pass(&self, graph: &mut CompileGraph, _: &CompilerOptions, _: &CompilerInput<'_, W>) {
-        let mut nodes_map =
-            FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
-        for node in graph.node_indices() {
-            nodes_map.insert(node, nodes_map.len());
-        }
-
-        let nodes = graph
-            .node_indices()
-            .map(|idx| convert_node(graph, idx, &nodes_map))
-            .collect_vec();
-
+        let nodes = to_nodes(graph);
         fs::write("redpiler_graph.bc", serialize(nodes.as_slice()).unwrap()).unwrap();
     }
 
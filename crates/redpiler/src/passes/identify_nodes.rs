@@ -3,19 +3,25 @@
 //! This pass populates the graph with nodes using the input given in [`CompilerInput`].
 //! This pass is *mandatory*. Without it, the graph will never be populated.
 //!
-//! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph.
+//! If `optimize` is set in [`CompilerOptions`], redstone wires will not be added to the graph,
+//! except for those watched by an observer, which sees their power level change.
+//!
+//! For the FPGA backends, containers read by comparators are marked as inputs: they become
+//! ROM cells loaded when the build runs, so their contents must not be folded into the design.
+//!
+//! Signs whose text is a bracketed annotation, see [`NodeAnnotation`], annotate the component
+//! behind them. Annotated components are never optimized away.
 //!
 //! There are no requirements for this pass.
 
 use super::Pass;
 use crate::compile_graph::{Annotations, CompileGraph, CompileNode, NodeIdx, NodeState, NodeType};
 use crate::{CompilerInput, CompilerOptions};
-use crate::BackendVariant;
 use itertools::Itertools;
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::Block;
 use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
-use mchprs_redstone::{self, comparator, noteblock, wire};
+use mchprs_redstone::{self, comparator, noteblock, observer, wire};
 use mchprs_world::{for_each_block_optimized, World};
 use rustc_hash::{FxHashMap, FxHashSet};
 use serde_json::Value;
@@ -30,7 +36,6 @@ impl<W: World> Pass<W> for IdentifyNodes {
         options: &CompilerOptions,
         input: &CompilerInput<W>,
     ) {
-        let ignore_wires = options.optimize || (options.backend_variant == BackendVariant::FPGA);
         let plot = &*input.world.lock().unwrap();
 
         let mut first_pass = FxHashMap::default();
@@ -43,8 +48,7 @@ impl<W: World> Pass<W> for IdentifyNodes {
                 graph,
                 &mut first_pass,
                 &mut second_pass,
-                ignore_wires,
-                options.wire_dot_out,
+                options,
                 plot,
                 pos,
             );
@@ -69,8 +73,7 @@ fn for_pos<W: World>(
     graph: &mut CompileGraph,
     first_pass: &mut FxHashMap<BlockPos, NodeIdx>,
     second_pass: &mut FxHashSet<BlockPos>,
-    ignore_wires: bool,
-    wire_dot_out: bool,
+    options: &CompilerOptions,
     world: &W,
     pos: BlockPos,
 ) {
@@ -86,16 +89,22 @@ fn for_pos<W: World>(
         return;
     };
 
+    let fpga = options.backend_variant.is_fpga();
     let is_input = matches!(
         ty,
         NodeType::Button | NodeType::Lever | NodeType::PressurePlate
-    );
+    ) || (fpga && comparator::has_override(block));
     let is_output = matches!(
         ty,
         NodeType::Trapdoor | NodeType::Lamp | NodeType::NoteBlock { .. }
-    ) || matches!(block, Block::RedstoneWire { wire } if wire_dot_out && wire::is_dot(wire));
+    ) || matches!(block, Block::RedstoneWire { wire } if options.wire_dot_out && wire::is_dot(wire));
 
-    if ignore_wires && ty == NodeType::Wire && !(is_input | is_output) {
+    let ignore_wires = options.optimize || fpga;
+    if ignore_wires
+        && ty == NodeType::Wire
+        && !(is_input | is_output)
+        && !observer::is_observed(world, pos)
+    {
         return;
     }
 
@@ -111,7 +120,7 @@ fn for_pos<W: World>(
     first_pass.insert(pos, node_idx);
 }
 
-fn identify_block<W: World>(
+pub(crate) fn identify_block<W: World>(
     block: Block,
     pos: BlockPos,
     world: &W,
@@ -156,6 +165,7 @@ fn identify_block<W: World>(
             (NodeType::PressurePlate, NodeState::simple(powered))
         }
         Block::IronTrapdoor { powered, .. } => (NodeType::Trapdoor, NodeState::simple(powered)),
+        Block::Observer { powered, .. } => (NodeType::Observer, NodeState::simple(powered)),
         Block::RedstoneBlock {} => (NodeType::Constant, NodeState::ss(15)),
         Block::NoteBlock {
             instrument: _,
@@ -185,7 +195,7 @@ fn apply_annotations<W: World>(
     pos: BlockPos,
 ) {
     let block = world.get_block(pos);
-    let annotations = parse_sign_annotations(world.get_block_entity(pos));
+    let annotations = parse_sign_annotations(world.get_block_entity(pos), pos);
     if annotations.is_empty() {
         return;
     }
@@ -224,38 +234,124 @@ fn apply_annotations<W: World>(
     }
 }
 
-fn parse_sign_annotations(entity: Option<&BlockEntity>) -> Vec<NodeAnnotation> {
+fn parse_sign_annotations(entity: Option<&BlockEntity>, pos: BlockPos) -> Vec<NodeAnnotation> {
     if let Some(BlockEntity::Sign(sign)) = entity {
         sign.front_rows
             .iter()
             .flat_map(|row| serde_json::from_str(row))
-            .flat_map(|json: Value| NodeAnnotation::parse(json.as_object()?.get("text")?.as_str()?))
+            .flat_map(|json: Value| Some(json.as_object()?.get("text")?.as_str()?.to_owned()))
+            .flat_map(|text| match NodeAnnotation::parse(&text) {
+                Ok(annotation) => annotation,
+                Err(msg) => {
+                    warn!("{} at {}", msg, pos);
+                    None
+                }
+            })
             .collect_vec()
     } else {
         vec![]
     }
 }
 
-pub enum NodeAnnotation {}
+pub enum NodeAnnotation {
+    /// `[pin <name>]`: binds the component to a peripheral of the FPGA board, such as a switch or an LED
+    Pin(String),
+    /// `[input <name>]`: makes the component a named input, driven from outside the design
+    Input(String),
+    /// `[output <name>]`: makes the component a named output, shown in the world even in io only builds
+    Output(String),
+    /// `[probe <name>]`: keeps the component and names it for inspection and traces
+    Probe(String),
+    /// `[bus <name> <bit>]`: makes the component a bit of a multi-bit bus
+    Bus(String, u32),
+    /// `[clock]`: marks the component as the build's clock
+    Clock,
+}
 
 impl NodeAnnotation {
-    fn parse(s: &str) -> Option<Self> {
+    /// Parses a line of sign text. Text that isn't bracketed is not an annotation, but bracketed
+    /// text that isn't a valid annotation is an error, so typos don't go unnoticed.
+    fn parse(s: &str) -> Result<Option<Self>, String> {
         let s = s.trim().to_ascii_lowercase();
         if !(s.starts_with('[') && s.ends_with(']')) {
-            return None;
+            return Ok(None);
         }
         let parts = s[1..s.len() - 1].split(' ').collect_vec();
-        match parts.as_slice() {
-            _ => None,
-        }
+        let annotation = match parts.as_slice() {
+            ["pin", name] => NodeAnnotation::Pin(name.to_string()),
+            ["input", name] => NodeAnnotation::Input(name.to_string()),
+            ["output", name] => NodeAnnotation::Output(name.to_string()),
+            ["probe", name] => NodeAnnotation::Probe(name.to_string()),
+            ["bus", name, bit] => match bit.parse() {
+                Ok(bit) => NodeAnnotation::Bus(name.to_string(), bit),
+                Err(_) => return Err(format!("Bus bit {} of {} is not a number", bit, name)),
+            },
+            ["clock"] => NodeAnnotation::Clock,
+            _ => return Err(format!("Unknown annotation {}", s)),
+        };
+        Ok(Some(annotation))
     }
 
     fn apply(
         self,
-        _graph: &mut CompileGraph,
-        _node_idx: NodeIdx,
+        graph: &mut CompileGraph,
+        node_idx: NodeIdx,
         _options: &CompilerOptions,
     ) -> Result<(), String> {
-        match self {}
+        let node = &mut graph[node_idx];
+        let annotations = &mut node.annotations;
+        match self {
+            NodeAnnotation::Pin(name) => {
+                if let Some(pin) = &annotations.pin {
+                    return Err(format!("Component is already bound to {}, not binding it to {}", pin, name));
+                }
+                annotations.pin = Some(name);
+            }
+            NodeAnnotation::Input(name) | NodeAnnotation::Output(name) if annotations.io.is_some() => {
+                let io = annotations.io.as_ref().unwrap();
+                return Err(format!("Component is already named {}, not naming it {}", io, name));
+            }
+            NodeAnnotation::Input(name) => {
+                // Only these can be driven, by the player or a board's input bits
+                if !matches!(node.ty, NodeType::Lever | NodeType::Button | NodeType::PressurePlate) {
+                    return Err(format!("Component cannot be input {}, only levers, buttons and pressure plates can", name));
+                }
+                node.is_input = true;
+                annotations.io = Some(name);
+            }
+            NodeAnnotation::Output(name) => {
+                node.is_output = true;
+                annotations.io = Some(name);
+            }
+            NodeAnnotation::Probe(name) => {
+                if let Some(probe) = &annotations.probe {
+                    return Err(format!("Component is already probed as {}, not probing it as {}", probe, name));
+                }
+                annotations.probe = Some(name);
+            }
+            NodeAnnotation::Bus(name, bit) => {
+                if let Some((bus, other)) = &annotations.bus {
+                    return Err(format!("Component is already bit {} of {}, not making it bit {} of {}", other, bus, bit, name));
+                }
+                annotations.bus = Some((name, bit));
+            }
+            NodeAnnotation::Clock => annotations.clock = true,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeAnnotation;
+
+    #[test]
+    fn malformed_annotations_are_errors() {
+        assert!(matches!(NodeAnnotation::parse("Hello"), Ok(None)));
+        assert!(matches!(NodeAnnotation::parse(" [Bus leds 3] "), Ok(Some(NodeAnnotation::Bus(name, 3))) if name == "leds"));
+        assert!(NodeAnnotation::parse("[bus leds x]").is_err());
+        assert!(NodeAnnotation::parse("[bus leds]").is_err());
+        assert!(NodeAnnotation::parse("[inptu start]").is_err());
+        assert!(NodeAnnotation::parse("[]").is_err());
     }
 }
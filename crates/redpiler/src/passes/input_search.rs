@@ -71,6 +71,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::RedstoneComparator { comparator } if comparator.facing.block_face() == side => {
                 true
             }
+            Block::Observer { facing, .. } if facing.block_face() == side => true,
             _ => false,
         }
     }
@@ -92,6 +93,7 @@ impl<'a, W: World> InputSearchState<'a, W> {
             },
             Block::RedstoneRepeater { .. } => self.provides_weak_power(block, side),
             Block::RedstoneComparator { .. } => self.provides_weak_power(block, side),
+            Block::Observer { .. } => self.provides_weak_power(block, side),
             _ => false,
         }
     }
@@ -327,6 +329,13 @@ impl<'a, W: World> InputSearchState<'a, W> {
             Block::RedstoneWire { .. } => {
                 self.search_wire(id, pos, LinkType::Default, 0);
             }
+            Block::Observer { facing, .. } => {
+                // Not a power input, the link only tells the observer what it watches
+                let observed_pos = pos.offset(facing.block_face());
+                if let Some(&observed) = self.pos_map.get(&observed_pos) {
+                    self.graph.add_edge(observed, id, CompileLink::default(0));
+                }
+            }
             Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. } => {
                 for face in &BlockFace::values() {
                     let neighbor_pos = pos.offset(*face);
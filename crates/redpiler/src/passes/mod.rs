@@ -4,19 +4,23 @@ mod coalesce;
 mod constant_coalesce;
 mod constant_fold;
 mod dedup_links;
+mod discrete_comps;
 mod export_graph;
 mod identify_nodes;
 mod input_search;
 mod prune_orphans;
 mod unreachable_output;
 
+pub(crate) use export_graph::to_nodes;
+pub(crate) use identify_nodes::identify_block;
+
 use mchprs_world::World;
 
 use super::compile_graph::CompileGraph;
+use super::task_monitor::TaskMonitor;
 use super::{CompilerInput, CompilerOptions};
 use std::time::Instant;
 use tracing::trace;
-use crate::BackendVariant;
 
 pub const fn make_default_pass_manager<'w, W: World>() -> PassManager<'w, W> {
     PassManager::new(&[
@@ -29,6 +33,7 @@ pub const fn make_default_pass_manager<'w, W: World>() -> PassManager<'w, W> {
         &unreachable_output::UnreachableOutput,
         &constant_coalesce::ConstantCoalesce,
         &coalesce::Coalesce,
+        &discrete_comps::DiscreteComparators,
         &prune_orphans::PruneOrphans,
         &export_graph::ExportGraph,
     ])
@@ -43,19 +48,25 @@ impl<'p, W: World> PassManager<'p, W> {
         Self { passes }
     }
 
+    /// Runs every pass in order, stopping early if the monitor is cancelled
     pub fn run_passes(
         &self,
         options: &CompilerOptions,
         input: &CompilerInput<'_, W>,
+        monitor: &TaskMonitor,
     ) -> CompileGraph {
         let mut graph = CompileGraph::new();
     
         for &pass in self.passes {
+            if monitor.cancelled() {
+                trace!("Compile cancelled before pass: {}", pass.name());
+                break;
+            }
             if !pass.should_run(options) {
                 trace!("Skipping pass: {}", pass.name());
                 continue;
             }
-
+            monitor.set_message(pass.status_message().to_string());
 
             trace!("Running pass: {}", pass.name());
             let start = Instant::now();
@@ -87,7 +98,7 @@ pub trait Pass<W: World> {
 
     fn should_run(&self, options: &CompilerOptions) -> bool {
         // Run passes for optimized builds by default
-        options.optimize || (options.backend_variant == BackendVariant::FPGA)
+        options.optimize || options.backend_variant.is_fpga()
     }
 
     fn status_message(&self) -> &'static str;
@@ -54,7 +54,7 @@ impl<W: World> Pass<W> for UnreachableOutput {
                 continue;
             }
 
-            if graph[constant_idx].ty != NodeType::Constant {
+            if graph[constant_idx].ty != NodeType::Constant || !graph[constant_idx].is_removable() {
                 continue;
             }
 
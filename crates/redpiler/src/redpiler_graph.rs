@@ -1,6 +1,9 @@
 use bincode::{BincodeRead, Result};
 use serde::{Deserialize, Serialize};
 
+/// Version of the format, bumped whenever a change to the types below changes the encoding
+pub const VERSION: u32 = 5;
+
 pub type NodeId = usize;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug, Serialize, Deserialize, Hash)]
@@ -41,7 +44,11 @@ pub enum NodeType {
     Trapdoor,
     Wire,
     Constant,
-    NoteBlock,
+    NoteBlock { instrument: u32, note: u32 },
+    /// A comparator evaluated from a table, laid out like the compile graph's `LookupTable`
+    LUT { input: u16, side: u16, table: u64 },
+    /// Linked to the node it watches by its one default link
+    Observer,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -51,12 +58,25 @@ pub struct NodeState {
     pub output_strength: u8,
 }
 
+/// What the signs next to a node say about it, names in lowercase
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Annotations {
+    pub pin: Option<String>,
+    pub io: Option<String>,
+    pub probe: Option<String>,
+    pub bus: Option<(String, u32)>,
+    pub clock: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct Node {
     pub ty: NodeType,
     /// Position and protocol id for block
     pub block: Option<(BlockPos, u32)>,
     pub state: NodeState,
+    pub is_input: bool,
+    pub is_output: bool,
+    pub annotations: Annotations,
 
     pub facing_diode: bool,
     pub comparator_far_input: Option<u8>,
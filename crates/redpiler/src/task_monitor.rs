@@ -0,0 +1,31 @@
+//! Progress and cancellation of a compile running on another thread.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Shared between a compile and whoever started it. The compile reports what it is doing and
+/// checks between steps whether it should give up.
+#[derive(Debug, Default)]
+pub struct TaskMonitor {
+    cancelled: AtomicBool,
+    message: Mutex<Option<Arc<String>>>,
+}
+
+impl TaskMonitor {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_message(&self, message: String) {
+        *self.message.lock().unwrap() = Some(Arc::new(message));
+    }
+
+    /// What the compile is currently doing
+    pub fn message(&self) -> Option<Arc<String>> {
+        self.message.lock().unwrap().clone()
+    }
+}
@@ -4,6 +4,7 @@
 
 pub mod comparator;
 pub mod noteblock;
+pub mod observer;
 pub mod repeater;
 pub mod wire;
 
@@ -13,6 +14,16 @@ use mchprs_blocks::{BlockDirection, BlockFace, BlockPos};
 use mchprs_world::TickPriority;
 use mchprs_world::World;
 
+/// Sets a block and lets the observers watching it see the change.
+/// Returns true if the block changed.
+pub fn set_block(world: &mut impl World, pos: BlockPos, block: Block) -> bool {
+    let changed = world.set_block(pos, block);
+    if changed {
+        observer::notify_observers(world, pos);
+    }
+    changed
+}
+
 pub fn bool_to_ss(b: bool) -> u8 {
     match b {
         true => 15,
@@ -39,6 +50,7 @@ fn get_weak_power(
         {
             15
         }
+        Block::Observer { facing, powered: true } if facing.block_face() == side => 15,
         Block::RedstoneComparator { comparator } if comparator.facing.block_face() == side => {
             if let Some(BlockEntity::Comparator { output_strength }) = world.get_block_entity(pos) {
                 *output_strength
@@ -95,6 +107,7 @@ fn get_strong_power(
         Block::RedstoneWire { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneRepeater { .. } => get_weak_power(block, world, pos, side, dust_power),
         Block::RedstoneComparator { .. } => get_weak_power(block, world, pos, side, dust_power),
+        Block::Observer { .. } => get_weak_power(block, world, pos, side, dust_power),
         _ => 0,
     }
 }
@@ -209,7 +222,7 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
             if lit && !should_be_lit {
                 world.schedule_tick(pos, 2, TickPriority::Normal);
             } else if !lit && should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: true });
+                set_block(world, pos, Block::RedstoneLamp { lit: true });
             }
         }
         Block::IronTrapdoor {
@@ -224,7 +237,7 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
                     half,
                     powered: should_be_powered,
                 };
-                world.set_block(pos, new_block);
+                set_block(world, pos, new_block);
             }
         }
         Block::NoteBlock {
@@ -250,7 +263,7 @@ pub fn update(block: Block, world: &mut impl World, pos: BlockPos) {
                 if should_be_powered && noteblock::is_noteblock_unblocked(world, pos) {
                     noteblock::play_note(world, pos, instrument, note);
                 }
-                world.set_block(pos, new_block);
+                set_block(world, pos, new_block);
             }
         }
         _ => {}
@@ -265,36 +278,39 @@ pub fn tick(block: Block, world: &mut impl World, pos: BlockPos) {
         Block::RedstoneComparator { comparator } => {
             comparator::tick(comparator, world, pos);
         }
+        Block::Observer { facing, powered } => {
+            observer::tick(facing, powered, world, pos);
+        }
         Block::RedstoneTorch { lit } => {
             let should_be_off = torch_should_be_off(world, pos);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: false });
+                set_block(world, pos, Block::RedstoneTorch { lit: false });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneTorch { lit: true });
+                set_block(world, pos, Block::RedstoneTorch { lit: true });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneWallTorch { lit, facing } => {
             let should_be_off = wall_torch_should_be_off(world, pos, facing);
             if lit && should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: false, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: false, facing });
                 update_surrounding_blocks(world, pos);
             } else if !lit && !should_be_off {
-                world.set_block(pos, Block::RedstoneWallTorch { lit: true, facing });
+                set_block(world, pos, Block::RedstoneWallTorch { lit: true, facing });
                 update_surrounding_blocks(world, pos);
             }
         }
         Block::RedstoneLamp { lit } => {
             let should_be_lit = redstone_lamp_should_be_lit(world, pos);
             if lit && !should_be_lit {
-                world.set_block(pos, Block::RedstoneLamp { lit: false });
+                set_block(world, pos, Block::RedstoneLamp { lit: false });
             }
         }
         Block::StoneButton { mut button } => {
             if button.powered {
                 button.powered = false;
-                world.set_block(pos, Block::StoneButton { button });
+                set_block(world, pos, Block::StoneButton { button });
                 update_surrounding_blocks(world, pos);
                 match button.face {
                     ButtonFace::Ceiling => {
@@ -361,19 +377,19 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
             if repeater.delay > 4 {
                 repeater.delay -= 4;
             }
-            world.set_block(pos, Block::RedstoneRepeater { repeater });
+            set_block(world, pos, Block::RedstoneRepeater { repeater });
             true
         }
         Block::RedstoneComparator { comparator } => {
             let mut comparator = comparator;
             comparator.mode = comparator.mode.toggle();
             comparator::tick(comparator, world, pos);
-            world.set_block(pos, Block::RedstoneComparator { comparator });
+            set_block(world, pos, Block::RedstoneComparator { comparator });
             true
         }
         Block::Lever { mut lever } => {
             lever.powered = !lever.powered;
-            world.set_block(pos, Block::Lever { lever });
+            set_block(world, pos, Block::Lever { lever });
             update_surrounding_blocks(world, pos);
             match lever.face {
                 LeverFace::Ceiling => {
@@ -392,7 +408,7 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
         Block::StoneButton { mut button } => {
             if !button.powered {
                 button.powered = true;
-                world.set_block(pos, Block::StoneButton { button });
+                set_block(world, pos, Block::StoneButton { button });
                 world.schedule_tick(pos, 10, TickPriority::Normal);
                 update_surrounding_blocks(world, pos);
                 match button.face {
@@ -420,7 +436,7 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
                 new_wire.power = wire.power;
                 new_wire = wire::get_regulated_sides(new_wire, world, pos);
                 if wire != new_wire {
-                    world.set_block(pos, Block::RedstoneWire { wire: new_wire });
+                    set_block(world, pos, Block::RedstoneWire { wire: new_wire });
                     update_wire_neighbors(world, pos);
                     return true;
                 }
@@ -431,7 +447,8 @@ pub fn on_use(block: Block, world: &mut impl World, pos: BlockPos) -> bool {
             let note = (note + 1) % 25;
             let instrument = noteblock::get_noteblock_instrument(world, pos);
 
-            world.set_block(
+            set_block(
+                world,
                 pos,
                 Block::NoteBlock {
                     instrument,
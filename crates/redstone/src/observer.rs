@@ -0,0 +1,52 @@
+use mchprs_blocks::blocks::Block;
+use mchprs_blocks::{BlockFace, BlockFacing, BlockPos};
+use mchprs_world::{TickPriority, World};
+
+/// Positions of the observers watching the block at `pos`, and whether they are powered
+fn observers(world: &impl World, pos: BlockPos) -> impl Iterator<Item = (BlockPos, bool)> + '_ {
+    BlockFace::values().into_iter().filter_map(move |face| {
+        let observer_pos = pos.offset(face);
+        match world.get_block(observer_pos) {
+            Block::Observer { facing, powered }
+                if observer_pos.offset(facing.block_face()) == pos =>
+            {
+                Some((observer_pos, powered))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Returns true if an observer is watching the block at `pos`
+pub fn is_observed(world: &impl World, pos: BlockPos) -> bool {
+    observers(world, pos).next().is_some()
+}
+
+/// Lets the observers watching `pos` see that its block changed
+pub fn notify_observers(world: &mut impl World, pos: BlockPos) {
+    let observers: Vec<_> = observers(world, pos).collect();
+    for (observer_pos, powered) in observers {
+        if !powered && !world.pending_tick_at(observer_pos) {
+            world.schedule_tick(observer_pos, 1, TickPriority::Normal);
+        }
+    }
+}
+
+fn on_state_change(facing: BlockFacing, world: &mut impl World, pos: BlockPos) {
+    let back_pos = pos.offset(facing.opposite().block_face());
+    let back_block = world.get_block(back_pos);
+    super::update(back_block, world, back_pos);
+    for direction in &BlockFace::values() {
+        let neighbor_pos = back_pos.offset(*direction);
+        let block = world.get_block(neighbor_pos);
+        super::update(block, world, neighbor_pos);
+    }
+}
+
+pub fn tick(facing: BlockFacing, powered: bool, world: &mut impl World, pos: BlockPos) {
+    super::set_block(world, pos, Block::Observer { facing, powered: !powered });
+    if !powered {
+        world.schedule_tick(pos, 1, TickPriority::Normal);
+    }
+    on_state_change(facing, world, pos);
+}
@@ -1,9 +1,17 @@
 use mchprs_blocks::block_entities::BlockEntity;
 use mchprs_blocks::blocks::Block;
-use mchprs_blocks::BlockPos;
-use mchprs_redpiler::{BackendVariant, Backend, CompilerOptions};
+use mchprs_blocks::{BlockFace, BlockPos};
+use mchprs_backend::fpga::builds;
+use mchprs_backend::fpga::compiler::DeviceConfig;
+use mchprs_backend::fpga::estimate::{self, Resources};
+use mchprs_backend::Backend;
+use mchprs_redpiler::graph_cache;
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use mchprs_redpiler::{BackendVariant, CompilerOptions};
 use mchprs_world::storage::Chunk;
 use mchprs_world::{TickEntry, TickPriority, World};
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
 
 #[derive(Clone)]
 pub struct TestWorld {
@@ -127,26 +135,103 @@ struct RedpilerInstance {
     compiler: Backend,
 }
 
-impl RedpilerInstance {
-    fn new(world: &TestWorld, variant: BackendVariant) -> RedpilerInstance {
-        let options = CompilerOptions {
-            backend_variant: variant,
+/// Boards with room for little more than the largest component, so most builds are split across several
+fn tiny_boards() -> Vec<DeviceConfig> {
+    let logic = Resources {
+        flip_flops: 8,
+        luts: 28,
+    };
+    (0..256)
+        .map(|i| DeviceConfig {
+            name: format!("board{i}"),
+            capacity: Some(estimate::CONTROLLER + logic),
             ..Default::default()
-        };
-        let mut compiler = Backend::default();
-        let max = world.size * 16 - 1;
-        let bounds = (BlockPos::new(0, 0, 0), BlockPos::new(max, max, max));
-        let monitor = Default::default();
-        let ticks = world.to_be_ticked.clone();
-        compiler.compile(world, bounds, options.clone(), ticks, monitor);
+        })
+        .collect()
+}
+
+impl RedpilerInstance {
+    fn new(world: &TestWorld, options: CompilerOptions, devices: Vec<DeviceConfig>) -> RedpilerInstance {
+        let compiler = compile_with(world, options, devices, &TaskMonitor::default()).unwrap();
+        let options = compiler.options().clone();
         RedpilerInstance { options, compiler }
     }
 }
 
+/// Compiles the whole world for a backend
+pub fn compile(
+    world: &TestWorld,
+    variant: BackendVariant,
+    devices: Vec<DeviceConfig>,
+    monitor: &TaskMonitor,
+) -> Result<Backend, String> {
+    let options = CompilerOptions {
+        backend_variant: variant,
+        ..Default::default()
+    };
+    compile_with(world, options, devices, monitor)
+}
+
+/// Compiles the whole world with the given options
+pub fn compile_with(
+    world: &TestWorld,
+    options: CompilerOptions,
+    devices: Vec<DeviceConfig>,
+    monitor: &TaskMonitor,
+) -> Result<Backend, String> {
+    compile_as(world, "test", "test", options, devices, monitor)
+}
+
+/// Compiles the whole world into a build of its own, for tests whose files must not clash.
+/// The graph cache is off, since every test compiles its own world under the same names.
+pub fn compile_as(
+    world: &TestWorld,
+    plot: &str,
+    name: &str,
+    options: CompilerOptions,
+    devices: Vec<DeviceConfig>,
+    monitor: &TaskMonitor,
+) -> Result<Backend, String> {
+    let max = world.size * 16 - 1;
+    let bounds = (BlockPos::new(0, 0, 0), BlockPos::new(max, max, max));
+    let ticks = world.to_be_ticked.clone();
+    let (sender, _) = mpsc::channel();
+    graph_cache::disable();
+    Backend::new(
+        sender,
+        name.to_string(),
+        plot.to_string(),
+        devices,
+        &Mutex::new(world.clone()),
+        bounds,
+        options,
+        ticks,
+        monitor,
+    )
+}
+
+/// A plot's directory under `FPGA/bin`, removed when dropped so a failing test doesn't leave
+/// its builds behind
+pub struct PlotDir(PathBuf);
+
+impl PlotDir {
+    pub fn new(plot: &str) -> PlotDir {
+        PlotDir(builds::plot_dir(plot))
+    }
+}
+
+impl Drop for PlotDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum TestBackend {
     Redstone,
     Redpiler(BackendVariant),
+    /// The FPGA simulation with the build split across many small boards
+    PartitionedSim,
 }
 
 pub struct BackendRunner {
@@ -156,13 +241,36 @@ pub struct BackendRunner {
 
 impl BackendRunner {
     pub fn new(world: TestWorld, backend: TestBackend) -> BackendRunner {
+        BackendRunner::with_options(world, backend, CompilerOptions::default())
+    }
+
+    /// Runs the world on a backend, compiling it with the given options on top of the backend's variant.
+    /// The FPGA simulations compile the graph a board gets, with the options `/roc compile` uses and
+    /// only the flags it takes copied over, so they only see the board's outputs.
+    pub fn with_options(world: TestWorld, backend: TestBackend, options: CompilerOptions) -> BackendRunner {
+        let options = |backend_variant: BackendVariant| {
+            if !backend_variant.is_fpga() {
+                return CompilerOptions { backend_variant, ..options };
+            }
+            CompilerOptions {
+                backend_variant,
+                wire_dot_out: options.wire_dot_out,
+                lut: options.lut,
+                trace: options.trace,
+                ..CompilerOptions::fpga()
+            }
+        };
         match backend {
             TestBackend::Redstone => BackendRunner {
                 world,
                 redpiler: None,
             },
             TestBackend::Redpiler(variant) => BackendRunner {
-                redpiler: Some(RedpilerInstance::new(&world, variant)),
+                redpiler: Some(RedpilerInstance::new(&world, options(variant), Vec::new())),
+                world,
+            },
+            TestBackend::PartitionedSim => BackendRunner {
+                redpiler: Some(RedpilerInstance::new(&world, options(BackendVariant::FPGASim), tiny_boards())),
                 world,
             },
         }
@@ -196,6 +304,18 @@ impl BackendRunner {
         mchprs_redstone::on_use(self.world.get_block(pos), &mut self.world, pos);
     }
 
+    /// Steps on or off a pressure plate the way a player does
+    pub fn set_pressure_plate(&mut self, pos: BlockPos, powered: bool) {
+        if let Some(redpiler) = &mut self.redpiler {
+            redpiler.compiler.set_pressure_plate(pos, powered);
+            redpiler.compiler.flush(&mut self.world);
+            return;
+        }
+        self.world.set_block(pos, Block::StonePressurePlate { powered });
+        mchprs_redstone::update_surrounding_blocks(&mut self.world, pos);
+        mchprs_redstone::update_surrounding_blocks(&mut self.world, pos.offset(BlockFace::Bottom));
+    }
+
     pub fn check_block_powered(&self, pos: BlockPos, powered: bool) {
         if let Some(redpiler) = &self.redpiler {
             assert_eq!(
@@ -213,6 +333,14 @@ impl BackendRunner {
         );
     }
 
+    /// Checks the power level of redstone dust, which is only kept up to date on redpiler for dots with `wire_dot_out`
+    pub fn check_wire_power(&self, pos: BlockPos, power: u8) {
+        match self.world.get_block(pos) {
+            Block::RedstoneWire { wire } => assert_eq!(wire.power, power, "wire power at {}", pos),
+            block => panic!("expected redstone wire at {}, found {:?}", pos, block),
+        }
+    }
+
     pub fn check_powered_for(&mut self, pos: BlockPos, powered: bool, ticks: usize) {
         for _ in 0..ticks {
             self.check_block_powered(pos, powered);
@@ -233,6 +361,7 @@ fn is_block_powered(block: Block) -> Option<bool> {
         Block::RedstoneLamp { lit } => lit,
         Block::IronTrapdoor { powered, .. } => powered,
         Block::NoteBlock { powered, .. } => powered,
+        Block::Observer { powered, .. } => powered,
         _ => return None,
     })
 }
@@ -244,6 +373,25 @@ macro_rules! test_all_backends {
             fn [< $name _redstone >]() { $name(TestBackend::Redstone) }
             #[test]
             fn [< $name _rp_direct >]() { $name(TestBackend::Redpiler(BackendVariant::Direct)) }
+            #[test]
+            fn [< $name _rp_fpga_sim >]() { $name(TestBackend::Redpiler(BackendVariant::FPGASim)) }
+            #[test]
+            fn [< $name _rp_fpga_partitioned >]() { $name(TestBackend::PartitionedSim) }
+        }
+    };
+    // FPGA cases that cannot pass are ignored with the reason, `--ignored` still runs them
+    ($name:ident, ignore_fpga = $reason:literal) => {
+        paste::paste! {
+            #[test]
+            fn [< $name _redstone >]() { $name(TestBackend::Redstone) }
+            #[test]
+            fn [< $name _rp_direct >]() { $name(TestBackend::Redpiler(BackendVariant::Direct)) }
+            #[test]
+            #[ignore = $reason]
+            fn [< $name _rp_fpga_sim >]() { $name(TestBackend::Redpiler(BackendVariant::FPGASim)) }
+            #[test]
+            #[ignore = $reason]
+            fn [< $name _rp_fpga_partitioned >]() { $name(TestBackend::PartitionedSim) }
         }
     };
 }
@@ -1,11 +1,23 @@
 mod common;
 
 use common::{test_all_backends, BackendRunner, TestBackend, TestWorld};
-use mchprs_blocks::blocks::{Block, Lever, LeverFace, RedstoneRepeater};
-use mchprs_blocks::{BlockDirection, BlockPos};
-use mchprs_redpiler::BackendVariant;
+use mchprs_backend::fpga::compiler::{DeviceConfig, Peripheral, PeripheralKind};
+use mchprs_backend::fpga::interface::DeviceStatus;
+use mchprs_backend::fpga::toolchain::{ToolchainConfig, ToolchainKind};
+use mchprs_backend::fpga::{builds, estimate};
+use mchprs_blocks::block_entities::{BlockEntity, SignBlockEntity};
+use mchprs_blocks::blocks::{
+    Block, ButtonFace, ComparatorMode, Lever, LeverFace, RedstoneComparator, RedstoneRepeater, RedstoneWire, StoneButton,
+};
+use mchprs_blocks::{BlockDirection, BlockFacing, BlockPos, SignType};
+use mchprs_redpiler::compile_graph::{Annotations, CompileGraph, NodeType};
+use mchprs_redpiler::graph_cache;
+use mchprs_redpiler::passes::make_default_pass_manager;
+use mchprs_redpiler::task_monitor::TaskMonitor;
+use mchprs_redpiler::{block_powered_mut, BackendVariant, CompilerInput, CompilerOptions, TraceOptions, TraceTrigger};
 use mchprs_redstone::wire::make_cross;
 use mchprs_world::World;
+use std::sync::Mutex;
 
 fn pos(x: i32, y: i32, z: i32) -> BlockPos {
     BlockPos::new(x, y, z)
@@ -38,6 +50,28 @@ fn make_lever(world: &mut TestWorld, lever_pos: BlockPos) {
     );
 }
 
+/// Places a wall sign at `sign_pos` facing north, annotating the component south of it with one row per text
+fn make_sign(world: &mut TestWorld, sign_pos: BlockPos, texts: &[&str]) {
+    world.set_block(
+        sign_pos,
+        Block::WallSign {
+            sign_type: SignType(0),
+            facing: BlockDirection::North,
+        },
+    );
+    let mut front_rows: [String; 4] = Default::default();
+    for (row, text) in front_rows.iter_mut().zip(texts) {
+        *row = format!(r#"{{"text":"{}"}}"#, text);
+    }
+    world.set_block_entity(
+        sign_pos,
+        BlockEntity::Sign(Box::new(SignBlockEntity {
+            front_rows,
+            back_rows: Default::default(),
+        })),
+    );
+}
+
 test_all_backends!(lever_on_off);
 fn lever_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 1, 0);
@@ -55,6 +89,115 @@ fn lever_on_off(backend: TestBackend) {
     runner.check_block_powered(lever_pos, false);
 }
 
+test_all_backends!(button_pulse);
+fn button_pulse(backend: TestBackend) {
+    let button_pos = pos(0, 2, 0);
+    let trapdoor_pos = pos(2, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    place_on_block(
+        &mut world,
+        button_pos,
+        Block::StoneButton {
+            button: StoneButton::new(ButtonFace::Floor, BlockDirection::North, false),
+        },
+    );
+    place_on_block(
+        &mut world,
+        pos(1, 1, 0),
+        Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                facing: BlockDirection::West,
+                ..Default::default()
+            },
+        },
+    );
+    world.set_block(trapdoor_pos, trapdoor());
+
+    // A button stays pressed for 10 ticks, pressing it again meanwhile does nothing
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(button_pos);
+    runner.check_block_powered(button_pos, true);
+    runner.check_powered_for(trapdoor_pos, false, 1);
+    runner.check_powered_for(button_pos, true, 4);
+    runner.use_block(button_pos);
+    runner.check_powered_for(button_pos, true, 5);
+    runner.check_block_powered(button_pos, false);
+    runner.check_powered_for(trapdoor_pos, true, 1);
+    runner.check_block_powered(trapdoor_pos, false);
+
+    // and can be pressed again once released
+    runner.use_block(button_pos);
+    runner.check_powered_for(button_pos, true, 10);
+    runner.check_block_powered(button_pos, false);
+}
+
+test_all_backends!(pressure_plate_on_off);
+fn pressure_plate_on_off(backend: TestBackend) {
+    let plate_pos = pos(0, 2, 0);
+    let trapdoor_pos = pos(2, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    place_on_block(&mut world, plate_pos, Block::StonePressurePlate { powered: false });
+    place_on_block(
+        &mut world,
+        pos(1, 1, 0),
+        Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                facing: BlockDirection::West,
+                ..Default::default()
+            },
+        },
+    );
+    world.set_block(trapdoor_pos, trapdoor());
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.set_pressure_plate(plate_pos, true);
+    runner.check_block_powered(plate_pos, true);
+    runner.check_powered_for(trapdoor_pos, false, 1);
+    runner.check_powered_for(trapdoor_pos, true, 3);
+
+    runner.set_pressure_plate(plate_pos, false);
+    runner.check_block_powered(plate_pos, false);
+    runner.check_powered_for(trapdoor_pos, true, 1);
+    runner.check_block_powered(trapdoor_pos, false);
+}
+
+test_all_backends!(hex_lamp);
+fn hex_lamp(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 0);
+    let dot_pos = pos(5, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    for x in 1..=3 {
+        place_on_block(&mut world, pos(x, 1, 0), Block::RedstoneWire { wire: make_cross(0) });
+    }
+    place_on_block(
+        &mut world,
+        pos(4, 1, 0),
+        Block::RedstoneComparator {
+            comparator: RedstoneComparator::new(BlockDirection::West, ComparatorMode::Compare, false),
+        },
+    );
+    place_on_block(&mut world, dot_pos, Block::RedstoneWire { wire: RedstoneWire::default() });
+
+    // The comparator reads the dust two blocks from the lever and passes its signal strength on to the dot
+    let options = CompilerOptions {
+        wire_dot_out: true,
+        ..Default::default()
+    };
+    let mut runner = BackendRunner::with_options(world, backend, options);
+    runner.use_block(lever_pos);
+    runner.check_wire_power(dot_pos, 0);
+    runner.tick();
+    runner.check_wire_power(dot_pos, 13);
+
+    runner.use_block(lever_pos);
+    runner.tick();
+    runner.check_wire_power(dot_pos, 0);
+}
+
 test_all_backends!(trapdoor_on_off);
 fn trapdoor_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 1, 0);
@@ -74,7 +217,7 @@ fn trapdoor_on_off(backend: TestBackend) {
     runner.check_block_powered(trapdoor_pos, false);
 }
 
-test_all_backends!(lamp_on_off);
+test_all_backends!(lamp_on_off, ignore_fpga = "the FPGA samples its inputs on the tick, so it misses the 0 tick pulse");
 fn lamp_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 1, 0);
     let lamp_pos = pos(1, 0, 0);
@@ -94,7 +237,26 @@ fn lamp_on_off(backend: TestBackend) {
     runner.check_block_powered(lamp_pos, false);
 }
 
-test_all_backends!(wall_torch_on_off);
+// The lamp above is only powered for a 0 tick pulse, which the FPGA does not sample
+test_all_backends!(lamp_stays_lit);
+fn lamp_stays_lit(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+    let lamp_pos = pos(1, 0, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(lamp_pos, Block::RedstoneLamp { lit: false });
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 1);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(lamp_pos, true, 2);
+    runner.check_block_powered(lamp_pos, false);
+}
+
+test_all_backends!(wall_torch_on_off, ignore_fpga = "a torch nothing reads is pruned from FPGA builds");
 fn wall_torch_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 1, 0);
     let torch_pos = pos(1, 0, 0);
@@ -121,7 +283,43 @@ fn wall_torch_on_off(backend: TestBackend) {
     runner.check_block_powered(torch_pos, true);
 }
 
-test_all_backends!(torch_on_off);
+// The torch above as a board shows it, through the trapdoor it powers
+test_all_backends!(wall_torch_powers_trapdoor);
+fn wall_torch_powers_trapdoor(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+    let trapdoor_pos = pos(2, 0, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    world.set_block(
+        pos(1, 0, 0),
+        Block::RedstoneWallTorch {
+            lit: true,
+            facing: BlockDirection::East,
+        },
+    );
+    world.set_block(
+        trapdoor_pos,
+        Block::IronTrapdoor {
+            facing: Default::default(),
+            half: Default::default(),
+            powered: true,
+        },
+    );
+
+    let mut runner = BackendRunner::new(world, backend);
+    runner.check_block_powered(trapdoor_pos, true);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, true, 1);
+    runner.check_block_powered(trapdoor_pos, false);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, false, 1);
+    runner.check_block_powered(trapdoor_pos, true);
+}
+
+test_all_backends!(torch_on_off, ignore_fpga = "a torch nothing reads is pruned from FPGA builds");
 fn torch_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 2, 0);
     let torch_pos = pos(2, 2, 0);
@@ -149,7 +347,7 @@ fn torch_on_off(backend: TestBackend) {
     runner.check_block_powered(torch_pos, true);
 }
 
-test_all_backends!(repeater_on_off);
+test_all_backends!(repeater_on_off, ignore_fpga = "the FPGA samples its inputs on the tick, so it misses the 0 tick pulse");
 fn repeater_on_off(backend: TestBackend) {
     let lever_pos = pos(0, 2, 0);
     let trapdoor_pos = pos(2, 1, 0);
@@ -189,3 +387,596 @@ fn repeater_on_off(backend: TestBackend) {
         runner.check_block_powered(trapdoor_pos, false);
     }
 }
+
+// The FPGA only samples its inputs on the tick edge, so it never sees the 0 tick pulse above
+test_all_backends!(repeater_one_tick_pulse);
+fn repeater_one_tick_pulse(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 0);
+    let trapdoor_pos = pos(2, 1, 0);
+
+    for delay in 1..=4 {
+        let mut world = TestWorld::new(1);
+        make_lever(&mut world, lever_pos);
+        place_on_block(
+            &mut world,
+            pos(1, 1, 0),
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    delay: delay as u8,
+                    ..Default::default()
+                },
+            },
+        );
+        world.set_block(trapdoor_pos, trapdoor());
+
+        let mut runner = BackendRunner::new(world, backend);
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, false, delay);
+        runner.check_block_powered(trapdoor_pos, true);
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, true, delay);
+        runner.check_block_powered(trapdoor_pos, false);
+    }
+}
+
+test_all_backends!(comparator_subtract);
+fn comparator_subtract(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 0);
+    let side_lever_pos = pos(1, 2, 2);
+    let comparator_pos = pos(1, 1, 0);
+    let trapdoor_pos = pos(2, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    make_lever(&mut world, side_lever_pos);
+    place_on_block(
+        &mut world,
+        pos(1, 1, 1),
+        Block::RedstoneWire {
+            wire: make_cross(0),
+        },
+    );
+    place_on_block(
+        &mut world,
+        comparator_pos,
+        Block::RedstoneComparator {
+            comparator: RedstoneComparator::new(BlockDirection::West, ComparatorMode::Subtract, false),
+        },
+    );
+    world.set_block(trapdoor_pos, trapdoor());
+
+    // Boards only show their outputs, so the comparator is seen through the trapdoor it powers
+    let mut runner = BackendRunner::new(world, backend);
+    runner.check_block_powered(trapdoor_pos, false);
+
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, false, 1);
+    runner.check_block_powered(trapdoor_pos, true);
+
+    // The side input is as strong as the rear input, so nothing is left after subtracting
+    runner.use_block(side_lever_pos);
+    runner.check_powered_for(trapdoor_pos, true, 1);
+    runner.check_block_powered(trapdoor_pos, false);
+}
+
+/// A lever reaching a comparator through dust, another lever powering its side and a dot after it
+fn comparator_with_side_lever() -> TestWorld {
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 2, 0));
+    for x in 1..=3 {
+        place_on_block(&mut world, pos(x, 1, 0), Block::RedstoneWire { wire: make_cross(0) });
+    }
+    place_on_block(
+        &mut world,
+        pos(4, 1, 0),
+        Block::RedstoneComparator {
+            comparator: RedstoneComparator::new(BlockDirection::West, ComparatorMode::Compare, false),
+        },
+    );
+    place_on_block(&mut world, pos(4, 1, 1), Block::RedstoneWire { wire: make_cross(0) });
+    make_lever(&mut world, pos(4, 2, 2));
+    place_on_block(&mut world, pos(5, 1, 0), Block::RedstoneWire { wire: RedstoneWire::default() });
+    world
+}
+
+test_all_backends!(comparator_lookup_table);
+fn comparator_lookup_table(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 0);
+    let side_lever_pos = pos(4, 2, 2);
+    let dot_pos = pos(5, 1, 0);
+
+    let options = CompilerOptions {
+        wire_dot_out: true,
+        lut: true,
+        ..Default::default()
+    };
+    let mut runner = BackendRunner::with_options(comparator_with_side_lever(), backend, options);
+    runner.use_block(lever_pos);
+    runner.tick();
+    runner.check_wire_power(dot_pos, 13);
+
+    // The side lever is stronger than the dust, so the comparator turns off until it is flipped back
+    runner.use_block(side_lever_pos);
+    runner.tick();
+    runner.check_wire_power(dot_pos, 0);
+    runner.use_block(side_lever_pos);
+    runner.tick();
+    runner.check_wire_power(dot_pos, 13);
+}
+
+#[test]
+fn comparators_become_lookup_tables() {
+    let world = comparator_with_side_lever();
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let input = CompilerInput { world: &Mutex::new(world.clone()), bounds };
+    let compile = |lut| {
+        let options = CompilerOptions {
+            wire_dot_out: true,
+            lut,
+            ..Default::default()
+        };
+        make_default_pass_manager().run_passes(&options, &input, &TaskMonitor::default())
+    };
+    let table = |graph: &CompileGraph| {
+        graph.node_weights().find_map(|node| match node.ty {
+            NodeType::LUT { table, .. } => Some(table),
+            _ => None,
+        })
+    };
+
+    assert_eq!(table(&compile(false)), None);
+    // The dust only ever carries 0 or 13 and the side lever 0 or 15
+    let table = table(&compile(true)).unwrap();
+    assert_eq!(table.entries().collect::<Vec<_>>(), [(0, 0, 0), (0, 15, 0), (13, 0, 13), (13, 15, 0)]);
+    assert_eq!(table.get(13, 0), 13);
+    assert_eq!(table.get(12, 0), 0);
+}
+
+test_all_backends!(repeater_comparator_line);
+fn repeater_comparator_line(backend: TestBackend) {
+    let lever_pos = pos(0, 2, 0);
+    let trapdoor_pos = pos(9, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    for x in 1..=8 {
+        let block = if x % 2 == 1 {
+            Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    facing: BlockDirection::West,
+                    ..Default::default()
+                },
+            }
+        } else {
+            Block::RedstoneComparator {
+                comparator: RedstoneComparator::new(BlockDirection::West, ComparatorMode::Compare, false),
+            }
+        };
+        place_on_block(&mut world, pos(x, 1, 0), block);
+    }
+    world.set_block(trapdoor_pos, trapdoor());
+
+    // Every component delays by a tick, no matter how many boards the line is split across
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, false, 8);
+    runner.check_block_powered(trapdoor_pos, true);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, true, 8);
+    runner.check_block_powered(trapdoor_pos, false);
+}
+
+/// Places an observer at `observer_pos` watching the block west of it, with a trapdoor behind it
+fn make_observer(world: &mut TestWorld, observer_pos: BlockPos) -> BlockPos {
+    let trapdoor_pos = observer_pos + pos(1, 0, 0);
+    world.set_block(
+        observer_pos,
+        Block::Observer {
+            facing: BlockFacing::West,
+            powered: false,
+        },
+    );
+    world.set_block(trapdoor_pos, trapdoor());
+    trapdoor_pos
+}
+
+test_all_backends!(observer_pulse);
+fn observer_pulse(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    let trapdoor_pos = make_observer(&mut world, pos(1, 1, 0));
+
+    // Turning the lever on and off are both changes, each gives a 1 tick pulse a tick later
+    let mut runner = BackendRunner::new(world, backend);
+    for _ in 0..2 {
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, false, 1);
+        runner.check_powered_for(trapdoor_pos, true, 1);
+        runner.check_block_powered(trapdoor_pos, false);
+    }
+}
+
+test_all_backends!(observer_watching_repeater);
+fn observer_watching_repeater(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    place_on_block(
+        &mut world,
+        pos(1, 1, 0),
+        Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                facing: BlockDirection::West,
+                ..Default::default()
+            },
+        },
+    );
+    let trapdoor_pos = make_observer(&mut world, pos(2, 1, 0));
+
+    // The repeater changes a tick after the lever
+    let mut runner = BackendRunner::new(world, backend);
+    runner.use_block(lever_pos);
+    runner.check_powered_for(trapdoor_pos, false, 2);
+    runner.check_powered_for(trapdoor_pos, true, 1);
+    runner.check_block_powered(trapdoor_pos, false);
+}
+
+test_all_backends!(observer_watching_dust);
+fn observer_watching_dust(backend: TestBackend) {
+    let lever_pos = pos(0, 1, 0);
+
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    place_on_block(&mut world, pos(1, 1, 0), Block::RedstoneWire { wire: make_cross(0) });
+    let trapdoor_pos = make_observer(&mut world, pos(2, 1, 0));
+
+    // The dust is kept in optimized builds so the observer can see its power change
+    let options = CompilerOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let mut runner = BackendRunner::with_options(world, backend, options);
+    for _ in 0..2 {
+        runner.use_block(lever_pos);
+        runner.check_powered_for(trapdoor_pos, false, 1);
+        runner.check_powered_for(trapdoor_pos, true, 1);
+        runner.check_block_powered(trapdoor_pos, false);
+    }
+}
+
+#[test]
+fn cancelled_compile() {
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 1, 0));
+
+    let monitor = TaskMonitor::default();
+    monitor.cancel();
+    let result = common::compile(&world, BackendVariant::Direct, Vec::new(), &monitor);
+    assert_eq!(result.err().as_deref(), Some("cancelled"));
+}
+
+#[test]
+fn build_too_large_for_boards() {
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 1, 0));
+
+    // A board with room for nothing but the command controller
+    let board = DeviceConfig {
+        name: "board".to_string(),
+        capacity: Some(estimate::CONTROLLER),
+        ..Default::default()
+    };
+    let result = common::compile(&world, BackendVariant::FPGASim, vec![board], &TaskMonitor::default());
+    assert!(result.is_err());
+}
+
+#[test]
+fn graph_cache_survives_state_changes_only() {
+    let lever_pos = pos(0, 1, 0);
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+
+    let options = CompilerOptions::default();
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let key = graph_cache::key(&world, bounds, &options);
+    let input = CompilerInput { world: &Mutex::new(world.clone()), bounds };
+    let graph = make_default_pass_manager().run_passes(&options, &input, &TaskMonitor::default());
+    let path = std::env::temp_dir().join(format!("graph_cache_{}.bc", std::process::id()));
+    graph_cache::store(&path, key, bounds, &options, &graph).unwrap();
+
+    // Flipping the lever keeps the cache, and the loaded graph picks up the new state
+    let mut lever = world.get_block(lever_pos);
+    *block_powered_mut(&mut lever).unwrap() = true;
+    world.set_block(lever_pos, lever);
+    let cached = graph_cache::load(&path, &world).expect("cache should still be valid");
+    assert!(cached.fits(bounds, &options));
+    assert_eq!(cached.graph.node_count(), graph.node_count());
+    assert!(cached.graph.node_weights().any(|node| node.block.map(|(pos, _)| pos) == Some(lever_pos) && node.state.powered));
+
+    // Only io builds prune the components nothing reads, so their graphs don't fit other builds
+    let io_only = CompilerOptions {
+        io_only: true,
+        ..options.clone()
+    };
+    assert!(!cached.fits(bounds, &io_only));
+    assert_ne!(graph_cache::key(&world, bounds, &io_only), graph_cache::key(&world, bounds, &options));
+
+    // Any change to the design throws it away
+    place_on_block(&mut world, pos(2, 1, 0), trapdoor());
+    assert!(graph_cache::load(&path, &world).is_none());
+    assert!(!path.exists());
+}
+
+#[test]
+fn containers_stay_rom_cells_on_fpga() {
+    let mut world = TestWorld::new(1);
+    place_on_block(&mut world, pos(0, 1, 0), Block::Cauldron { level: 2 });
+    place_on_block(
+        &mut world,
+        pos(1, 1, 0),
+        Block::RedstoneComparator {
+            comparator: RedstoneComparator::new(BlockDirection::West, ComparatorMode::Compare, false),
+        },
+    );
+    world.set_block(pos(2, 1, 0), trapdoor());
+
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let input = CompilerInput { world: &Mutex::new(world.clone()), bounds };
+    let compile = |backend_variant| {
+        let options = CompilerOptions {
+            optimize: true,
+            backend_variant,
+            ..Default::default()
+        };
+        make_default_pass_manager().run_passes(&options, &input, &TaskMonitor::default())
+    };
+    let has_comparator = |graph: &CompileGraph| graph.node_weights().any(|node| matches!(node.ty, NodeType::Comparator { .. }));
+
+    // The direct backend folds the comparator away, on the FPGA the cauldron can be reloaded at run time
+    assert!(!has_comparator(&compile(BackendVariant::Direct)));
+    let graph = compile(BackendVariant::FPGA);
+    assert!(has_comparator(&graph));
+    assert!(graph.node_weights().any(|node| node.ty == NodeType::Constant && node.is_input && node.state.output_strength == 2));
+    // and the generated ROM cell elaborates
+    assert!(common::compile(&world, BackendVariant::FPGASim, Vec::new(), &TaskMonitor::default()).is_ok());
+}
+
+#[test]
+fn direct_and_fpga_traces_match() {
+    let lever_pos = pos(0, 2, 0);
+    let repeater_pos = pos(1, 1, 0);
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    place_on_block(
+        &mut world,
+        repeater_pos,
+        Block::RedstoneRepeater {
+            repeater: RedstoneRepeater {
+                facing: BlockDirection::West,
+                delay: 2,
+                ..Default::default()
+            },
+        },
+    );
+    world.set_block(pos(2, 1, 0), trapdoor());
+
+    let trace = |backend_variant| {
+        let options = CompilerOptions {
+            backend_variant,
+            trace: TraceOptions {
+                probes: vec![lever_pos, repeater_pos],
+                trigger: Some((lever_pos, TraceTrigger::Rising)),
+                depth: 6,
+                pretrigger: 2,
+            },
+            ..Default::default()
+        };
+        let mut world = world.clone();
+        let mut backend = common::compile_with(&world, options, Vec::new(), &TaskMonitor::default()).unwrap();
+        for tick in 0..10 {
+            if tick == 3 || tick == 5 {
+                backend.on_use_block(lever_pos);
+            }
+            backend.tick();
+            backend.flush(&mut world);
+        }
+        backend.trace().unwrap()
+    };
+
+    let direct = trace(BackendVariant::Direct);
+    assert_eq!(direct.rows.len(), 6);
+    assert_eq!(direct.trigger, Some(2));
+    // Signals are named after the block they were placed at
+    assert_eq!(direct.probes[1].name, "repeater_1_1_0");
+    assert_eq!(direct.to_vcd(), trace(BackendVariant::FPGASim).to_vcd());
+}
+
+#[test]
+fn sign_binds_lever_to_board_switch() {
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 2, 1));
+    make_sign(&mut world, pos(0, 2, 0), &["[pin SW0]"]);
+
+    let compile = |peripherals| {
+        let device = DeviceConfig {
+            name: "board".to_owned(),
+            peripherals,
+            ..Default::default()
+        };
+        let options = CompilerOptions {
+            backend_variant: BackendVariant::FPGASim,
+            ..Default::default()
+        };
+        common::compile_with(&world, options, vec![device], &TaskMonitor::default()).map(|_| ())
+    };
+    assert_eq!(compile(Vec::new()), Err("board has no peripheral sw0".to_owned()));
+    let switch = Peripheral {
+        name: "SW0".to_owned(),
+        kind: PeripheralKind::Switch,
+        pins: vec!["AB12".to_owned()],
+        active_low: false,
+    };
+    assert_eq!(compile(vec![switch]), Ok(()));
+}
+
+#[test]
+fn signs_annotate_components() {
+    let lever_pos = pos(0, 2, 1);
+    let trapdoor_pos = pos(1, 2, 1);
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, lever_pos);
+    make_sign(&mut world, pos(0, 2, 0), &["[input Start]", "[clock]"]);
+    world.set_block(trapdoor_pos, trapdoor());
+    make_sign(&mut world, pos(1, 2, 0), &["[output led]", "[bus leds 3]", "[bus leds x]"]);
+    let door_pos = pos(2, 2, 1);
+    world.set_block(door_pos, trapdoor());
+    make_sign(&mut world, pos(2, 2, 0), &["[input door]"]);
+
+    let annotations_at = |graph: &CompileGraph, at: BlockPos| {
+        let node = graph.node_weights().find(|node| node.block.map(|(pos, _)| pos) == Some(at)).unwrap();
+        node.annotations.clone()
+    };
+    let lever = Annotations {
+        io: Some("start".to_owned()),
+        clock: true,
+        ..Default::default()
+    };
+    // The bus with a bit that is not a number is reported and ignored
+    let led = Annotations {
+        io: Some("led".to_owned()),
+        bus: Some(("leds".to_owned(), 3)),
+        ..Default::default()
+    };
+    assert_eq!(led.name().as_deref(), Some("led"));
+
+    let options = CompilerOptions::default();
+    let bounds = (pos(0, 0, 0), pos(15, 15, 15));
+    let input = CompilerInput { world: &Mutex::new(world.clone()), bounds };
+    let graph = make_default_pass_manager().run_passes(&options, &input, &TaskMonitor::default());
+    assert_eq!(annotations_at(&graph, lever_pos), lever);
+    assert_eq!(annotations_at(&graph, trapdoor_pos), led);
+    // Nothing drives a trapdoor, so it cannot be an input
+    assert_eq!(annotations_at(&graph, door_pos), Annotations::default());
+    assert!(!graph.node_weights().any(|node| node.block.map(|(pos, _)| pos) == Some(door_pos) && node.is_input));
+
+    // Exported graphs keep them
+    let path = std::env::temp_dir().join(format!("graph_annotations_{}.bc", std::process::id()));
+    graph_cache::store(&path, graph_cache::key(&world, bounds, &options), bounds, &options, &graph).unwrap();
+    let cached = graph_cache::load(&path, &world).unwrap();
+    assert_eq!(annotations_at(&cached.graph, trapdoor_pos), led);
+    _ = std::fs::remove_file(&path);
+
+    // Traces name annotated nodes after them
+    let options = CompilerOptions {
+        trace: TraceOptions {
+            probes: vec![lever_pos],
+            depth: 2,
+            pretrigger: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut backend = common::compile_with(&world, options, Vec::new(), &TaskMonitor::default()).unwrap();
+    backend.tick();
+    assert_eq!(backend.trace().unwrap().probes[0].name, "start");
+
+    // and so does the link file of FPGA builds
+    let options = CompilerOptions {
+        backend_variant: BackendVariant::FPGA,
+        compile_verilog: false,
+        ..Default::default()
+    };
+    let device = DeviceConfig {
+        name: "board".to_owned(),
+        ..Default::default()
+    };
+    let _plot = common::PlotDir::new("annotations");
+    common::compile_as(&world, "annotations", "signs", options, vec![device], &TaskMonitor::default()).unwrap();
+    let link = builds::read_link(&builds::plot_dir("annotations").join("signs")).unwrap();
+    let annotated: Vec<_> = link.annotations.into_iter().map(|block| (block.pos, block.annotations)).collect();
+    assert!(annotated.contains(&(lever_pos, lever)));
+    assert!(annotated.contains(&(trapdoor_pos, led)));
+}
+
+#[test]
+fn generated_fpga_build_waits_for_imported_bitstream() {
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 1, 0));
+    let device = DeviceConfig {
+        name: "board".to_owned(),
+        ..Default::default()
+    };
+    let options = CompilerOptions {
+        backend_variant: BackendVariant::FPGA,
+        compile_verilog: false,
+        ..Default::default()
+    };
+    let mut backend = common::compile_with(&world, options, vec![device.clone()], &TaskMonitor::default()).unwrap();
+
+    // The bundle holds everything needed to synthesize the design elsewhere
+    let dir = builds::plot_dir("test").join("test");
+    let files = std::fs::read_to_string(dir.join("hdl/files.f")).unwrap();
+    for source in files.lines().skip(1).chain(["parameters.vh", "pins.vh"]) {
+        assert!(dir.join("hdl").join(source).exists(), "{source} is missing from the bundle");
+    }
+    assert!(files.contains("components.sv") && files.contains("redstone.sv"));
+    let manifest = builds::Manifest::read(&dir).unwrap().unwrap();
+    assert!(!manifest.options().compile_verilog);
+
+    let fpga = backend.fpga().unwrap();
+    assert!(!fpga.has_bitstream());
+    let bitstream = std::env::temp_dir().join(format!("roc_import_{}.sof", std::process::id()));
+    std::fs::write(&bitstream, "bitstream").unwrap();
+    assert!(fpga.import_bitstream(0, &dir.join("redstone.sv")).is_err());
+    assert!(fpga.import_bitstream(1, &bitstream).is_err());
+    fpga.import_bitstream(0, &bitstream).unwrap();
+    assert!(fpga.has_bitstream());
+
+    _ = std::fs::remove_file(&bitstream);
+    _ = std::fs::remove_dir_all(builds::plot_dir("test"));
+}
+
+#[test]
+fn fpga_board_running_another_design_is_refused() {
+    let device = DeviceConfig {
+        name: "board".to_owned(),
+        command_com: "tcp://127.0.0.1:47821".to_owned(),
+        toolchain: ToolchainConfig {
+            kind: ToolchainKind::Emulator,
+            tools_path: None,
+        },
+        ..Default::default()
+    };
+    let options = CompilerOptions {
+        backend_variant: BackendVariant::FPGA,
+        compile_verilog: true,
+        ..Default::default()
+    };
+    let monitor = TaskMonitor::default();
+    let mut world = TestWorld::new(1);
+    make_lever(&mut world, pos(0, 1, 0));
+    let mut single = common::compile_as(&world, "identify", "single", options.clone(), vec![device.clone()], &monitor).unwrap();
+    make_lever(&mut world, pos(2, 1, 0));
+    common::compile_as(&world, "identify", "double", options, vec![device], &monitor).unwrap();
+
+    single.run();
+    let fpga = single.fpga().unwrap();
+    assert_eq!(fpga.status(), DeviceStatus::Connected);
+    assert_eq!(fpga.error(), None);
+
+    // Programming the board with the other build's design leaves it identifying as that one
+    let dir = builds::plot_dir("identify");
+    for file in ["redstone.sv", "parameters.vh"] {
+        std::fs::copy(dir.join("double").join(file), dir.join("single").join(file)).unwrap();
+    }
+    single.run();
+    let fpga = single.fpga().unwrap();
+    assert_eq!(fpga.status(), DeviceStatus::Failed);
+    assert!(fpga.error().unwrap().contains("is running design"), "{:?}", fpga.error());
+
+    _ = std::fs::remove_dir_all(dir);
+}
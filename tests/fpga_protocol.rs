@@ -0,0 +1,492 @@
+use mchprs_backend::fpga::interface::{
+    apply_changes, crc8, design_hash, encode_changes, BinaryIterator, FPGACommand, Frame, Interface, InterfaceError, Response, PROTOCOL_VERSION, STATUS_ACK,
+};
+use mchprs_backend::fpga::mock::{loopback, Fault, MockController, MockServer, MockState};
+use mchprs_backend::fpga::sim::Netlist;
+use mchprs_backend::fpga::trace::{TraceState, TraceStatus};
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+fn connect(inputs: usize, outputs: usize) -> (Interface, MockController) {
+    let (host, device) = loopback(TIMEOUT);
+    let mock = MockController::spawn(device, MockState::new(inputs, outputs));
+    (Interface::with_transport(host), mock)
+}
+
+#[test]
+fn crc8_check_value() {
+    assert_eq!(crc8(b"123456789"), 0xF4);
+}
+
+#[test]
+fn frame_roundtrip() {
+    let frame = Frame::new(7, FPGACommand::SetRTPS(0).code(), FPGACommand::SetRTPS(1234).payload());
+    let bytes = frame.encode();
+    assert_eq!(Frame::read(&mut bytes.as_slice()).unwrap(), frame);
+
+    let mut corrupted = bytes.clone();
+    corrupted[7] ^= 0x10;
+    assert!(matches!(
+        Frame::read(&mut corrupted.as_slice()),
+        Err(InterfaceError::Checksum)
+    ));
+}
+
+#[test]
+fn binary_iterator_fields() {
+    // A lamp, a hex lamp straddling the byte boundary and a trapdoor
+    let mut bits = BinaryIterator::new(vec![0b1101_0001, 0b0000_0011]);
+    assert_eq!(bits.next(1), Some(1));
+    assert_eq!(bits.next(4), Some(0b1000));
+    assert_eq!(bits.next(4), Some(0b1110));
+    assert_eq!(bits.next(1), Some(1));
+    assert_eq!(bits.next(8), None);
+}
+
+#[test]
+fn ping() {
+    let (mut fpga, _mock) = connect(3, 12);
+    assert_eq!(
+        fpga.send_command(FPGACommand::Ping).unwrap(),
+        Response::Pong {
+            version: PROTOCOL_VERSION,
+            inputs: 3,
+            outputs: 12
+        }
+    );
+}
+
+#[test]
+fn set_inputs_and_rtps() {
+    let (mut fpga, mock) = connect(4, 1);
+    fpga.send_command(FPGACommand::SetInputs(2, 0, 1)).unwrap();
+    fpga.send_command(FPGACommand::SetRTPS(1000)).unwrap();
+
+    let state = mock.state.lock().unwrap();
+    assert_eq!(state.inputs, [false, false, true, false]);
+    assert_eq!(state.tps, 1000);
+}
+
+#[test]
+fn staged_inputs_are_committed_together() {
+    let (mut fpga, mock) = connect(4, 1);
+    fpga.send_command(FPGACommand::StageInput(1, 1)).unwrap();
+    fpga.send_command(FPGACommand::StageInput(3, 1)).unwrap();
+    assert_eq!(mock.state.lock().unwrap().inputs, [false; 4]);
+
+    mock.state.lock().unwrap().faults.push_back(Fault::DropResponse);
+    fpga.send_command(FPGACommand::CommitInputs).unwrap();
+    let state = mock.state.lock().unwrap();
+    assert_eq!(state.inputs, [false, true, false, true]);
+    assert_eq!(state.executed, 3);
+}
+
+#[test]
+fn capture_outputs() {
+    let (mut fpga, mock) = connect(1, 10);
+    mock.state.lock().unwrap().outputs[0] = true;
+    mock.state.lock().unwrap().outputs[9] = true;
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0, 0])
+    );
+
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0b1, 0b10])
+    );
+}
+
+#[test]
+fn output_changes() {
+    let (mut fpga, mock) = connect(1, 20);
+    let mut outputs = vec![0; 3];
+    mock.state.lock().unwrap().outputs[9] = true;
+    let Response::Changes(changes) = fpga.send_command(FPGACommand::GetChanges(true)).unwrap() else {
+        panic!("expected changes");
+    };
+    assert_eq!(changes, [0b111, 0, 0b10, 0]);
+    assert_eq!(apply_changes(&mut outputs, &changes), Some(vec![1]));
+
+    // Only the byte that changed is sent, also when the response has to be retransmitted
+    mock.state.lock().unwrap().outputs[17] = true;
+    mock.state.lock().unwrap().faults.push_back(Fault::DropResponse);
+    let Response::Changes(changes) = fpga.send_command(FPGACommand::GetChanges(false)).unwrap() else {
+        panic!("expected changes");
+    };
+    assert_eq!(changes, [0b100, 0b10]);
+    assert_eq!(apply_changes(&mut outputs, &changes), Some(vec![2]));
+    assert_eq!(outputs, [0, 0b10, 0b10]);
+
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetChanges(false)).unwrap(),
+        Response::Changes(vec![0])
+    );
+    assert_eq!(apply_changes(&mut outputs, &[0b1]), None);
+    assert_eq!(apply_changes(&mut outputs, &[0, 7]), None);
+    assert_eq!(encode_changes(&[1, 2], &[1, 3], false), [0b10, 3]);
+}
+
+#[test]
+fn corrupted_command_is_retried() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().faults.extend([Fault::CorruptCommand, Fault::CorruptCommand]);
+    fpga.send_command(FPGACommand::SetRTPS(20)).unwrap();
+
+    let state = mock.state.lock().unwrap();
+    assert_eq!(state.tps, 20);
+    assert_eq!(state.executed, 1);
+}
+
+#[test]
+fn corrupted_response_is_retried_without_executing_twice() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().faults.push_back(Fault::CorruptResponse);
+    fpga.send_command(FPGACommand::SetInputs(0, 0, 1)).unwrap();
+
+    let state = mock.state.lock().unwrap();
+    assert_eq!(state.inputs, [true]);
+    assert_eq!(state.executed, 1);
+}
+
+#[test]
+fn dropped_response_is_retried_without_executing_twice() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().faults.push_back(Fault::DropResponse);
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    fpga.send_command(FPGACommand::Ping).unwrap();
+
+    assert_eq!(mock.state.lock().unwrap().executed, 2);
+}
+
+#[test]
+fn gives_up_after_retries() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().faults.extend([Fault::DropResponse; 4]);
+    assert!(matches!(
+        fpga.send_command(FPGACommand::Ping),
+        Err(InterfaceError::Timeout)
+    ));
+
+    // The link is usable again once the device responds
+    fpga.send_command(FPGACommand::Ping).unwrap();
+}
+
+#[test]
+fn failsafe_is_recovered() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().faults.push_back(Fault::EnterFailsafe);
+    fpga.send_command(FPGACommand::SetRTPS(5)).unwrap();
+
+    let state = mock.state.lock().unwrap();
+    assert!(!state.failsafe);
+    assert_eq!(state.tps, 5);
+}
+
+#[test]
+fn repeated_bad_frames_enter_failsafe() {
+    let (mut fpga, mock) = connect(1, 1);
+    // Three NAKs are retried, the fourth bad frame trips failsafe which is then acknowledged
+    mock.state.lock().unwrap().faults.extend([Fault::CorruptCommand; 4]);
+    assert!(fpga.send_command(FPGACommand::Ping).is_err());
+    assert!(!mock.state.lock().unwrap().failsafe);
+    fpga.send_command(FPGACommand::Ping).unwrap();
+}
+
+#[test]
+fn version_mismatch() {
+    let (host, mut device) = loopback(TIMEOUT);
+    let device = thread::spawn(move || {
+        let (frame, _) = Frame::read_raw(&mut device).unwrap();
+        let mut reply = Frame::new(frame.seq, STATUS_ACK, Vec::new());
+        reply.version = 1;
+        device.write_all(&reply.encode()).unwrap();
+    });
+
+    let mut fpga = Interface::with_transport(host);
+    assert!(matches!(
+        fpga.send_command(FPGACommand::Ping),
+        Err(InterfaceError::VersionMismatch(1))
+    ));
+    device.join().unwrap();
+}
+
+#[test]
+fn not_connected() {
+    let mut fpga = Interface::default();
+    assert!(matches!(
+        fpga.send_command(FPGACommand::Ping),
+        Err(InterfaceError::NotConnected)
+    ));
+}
+
+const INVERTER: &str = "module RoC #(
+    parameter OUTPUTS,
+    parameter INPUTS
+) (
+    input                   tick,
+    input   [INPUTS-1:0]    inputs,
+    output  [OUTPUTS-1:0]   outputs
+);
+    wire w0;
+    wire w1;
+    assign w0 = inputs[0];
+    torch #(1'b1) c1 (.i_clk(tick), .i_in(w0), .o_out(w1));
+    assign outputs[0] = w1;
+endmodule";
+
+#[test]
+fn identify() {
+    assert_eq!(design_hash(b""), 0x811C_9DC5);
+    assert_eq!(design_hash(b"a"), 0xE40C_292C);
+
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().design_hash = 0xDEAD_BEEF;
+    assert_eq!(
+        fpga.send_command(FPGACommand::Identify).unwrap(),
+        Response::Identity {
+            version: PROTOCOL_VERSION,
+            design: 0xDEAD_BEEF,
+        }
+    );
+    assert_eq!(fpga.identify().unwrap(), 0xDEAD_BEEF);
+}
+
+#[test]
+fn emulated_board() {
+    let board = MockServer::bind("127.0.0.1:0").unwrap();
+    board.load(Netlist::elaborate(INVERTER, 1, 1).unwrap());
+
+    let mut fpga = Interface::default();
+    assert!(fpga.serial_start(&format!("tcp://{}", board.addr()), 2500000));
+    fpga.send_command(FPGACommand::SetRTPS(1000)).unwrap();
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![1])
+    );
+
+    fpga.send_command(FPGACommand::SetInputs(0, 0, 1)).unwrap();
+    let ticks = board.state.lock().unwrap().ticks;
+    let start = Instant::now();
+    while board.state.lock().unwrap().ticks < ticks + 2 {
+        assert!(start.elapsed() < Duration::from_secs(5), "emulated board is not ticking");
+        thread::sleep(Duration::from_millis(5));
+    }
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0])
+    );
+}
+
+#[test]
+fn dropped_emulated_board_hangs_up() {
+    let board = MockServer::bind("127.0.0.1:0").unwrap();
+    let addr = board.addr();
+    let mut fpga = Interface::default();
+    assert!(fpga.serial_start(&format!("tcp://{}", addr), 2500000));
+    fpga.send_command(FPGACommand::Ping).unwrap();
+
+    drop(board);
+    assert!(fpga.send_command(FPGACommand::Ping).is_err());
+    // The listener is closed, so the address can be served again
+    MockServer::bind(addr).unwrap();
+}
+
+#[test]
+fn step() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().load(Netlist::elaborate(INVERTER, 1, 1).unwrap());
+    fpga.send_command(FPGACommand::SetInputs(0, 0, 1)).unwrap();
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![1])
+    );
+
+    // Without a tick rate the design only moves when stepped
+    fpga.send_command(FPGACommand::Step(3)).unwrap();
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0])
+    );
+    assert_eq!(mock.state.lock().unwrap().ticks, 3);
+}
+
+#[test]
+fn run_until_tick() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().load(Netlist::elaborate(INVERTER, 1, 1).unwrap());
+    fpga.send_command(FPGACommand::SetRTPS(10)).unwrap();
+    fpga.send_command(FPGACommand::Pause).unwrap();
+    assert_eq!(mock.state.lock().unwrap().tps, 0);
+    assert_eq!(fpga.send_command(FPGACommand::GetTicks).unwrap(), Response::Ticks(0));
+
+    fpga.send_command(FPGACommand::RunUntil(5)).unwrap();
+    assert_eq!(fpga.send_command(FPGACommand::GetTicks).unwrap(), Response::Ticks(5));
+
+    // A tick already passed is not run again
+    fpga.send_command(FPGACommand::RunUntil(3)).unwrap();
+    fpga.send_command(FPGACommand::Step(2)).unwrap();
+    assert_eq!(fpga.send_command(FPGACommand::GetTicks).unwrap(), Response::Ticks(7));
+    assert_eq!(
+        FPGACommand::decode(0xCB, &FPGACommand::RunUntil(1 << 40).payload()),
+        Some(FPGACommand::RunUntil(1 << 40))
+    );
+}
+
+const ROM: &str = "module RoC #(
+    parameter OUTPUTS,
+    parameter INPUTS
+) (
+    input                   clk,
+    input                   tick,
+    input   [INPUTS-1:0]    inputs,
+    output  [OUTPUTS-1:0]   outputs,
+    input                   rom_we,
+    input   [23:0]          rom_addr,
+    input   [3:0]           rom_data
+);
+    wire [3:0] w0;
+    wire [3:0] w1;
+    rom #(24'd0, 4, 4'd0, 4'd3) c0 (.i_clk(clk), .i_we(rom_we), .i_addr(rom_addr), .i_data(rom_data), .o_out(w0));
+    rom #(24'd1, 1, 4'd9, 1'd0) c1 (.i_clk(clk), .i_we(rom_we), .i_addr(rom_addr), .i_data(rom_data), .o_out(w1));
+    assign outputs[3:0] = w0;
+    assign outputs[7:4] = w1;
+endmodule";
+
+#[test]
+fn load_rom() {
+    let (mut fpga, mock) = connect(1, 8);
+    mock.state.lock().unwrap().load(Netlist::elaborate(ROM, 1, 8).unwrap());
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0x03])
+    );
+
+    // A one bit cell reads as its region's signal strength, unknown addresses are ignored
+    fpga.send_command(FPGACommand::LoadROM(0, 12)).unwrap();
+    fpga.send_command(FPGACommand::LoadROM(1, 1)).unwrap();
+    fpga.send_command(FPGACommand::LoadROM(2, 15)).unwrap();
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![0x9C])
+    );
+}
+
+const SCAN: &str = "module RoC #(
+    parameter OUTPUTS,
+    parameter INPUTS
+) (
+    input                   clk,
+    input                   tick,
+    input   [INPUTS-1:0]    inputs,
+    output  [OUTPUTS-1:0]   outputs,
+    input                   scan_load,
+    input                   scan_shift,
+    output                  scan_out
+);
+    wire w0;
+    wire w1;
+    wire w2;
+    wire [3:0] w3;
+    wire w2_scan;
+    wire w3_scan;
+    assign w0 = inputs[0];
+    torch #(1'b1) c1 (.i_clk(tick), .i_in(w0), .o_out(w1));
+    torch #(1'b0) c2 (.i_clk(tick), .i_in(w1), .o_out(w2));
+    assign w3 = 4'd9;
+    scan #(1) s1 (.i_clk(clk), .i_load(scan_load), .i_shift(scan_shift), .i_in(w1), .i_chain(w2_scan), .o_out(scan_out));
+    scan #(1) s2 (.i_clk(clk), .i_load(scan_load), .i_shift(scan_shift), .i_in(w2), .i_chain(w3_scan), .o_out(w2_scan));
+    scan #(4) s3 (.i_clk(clk), .i_load(scan_load), .i_shift(scan_shift), .i_in(w3), .i_chain(1'b0), .o_out(w3_scan));
+    assign outputs[0] = w2;
+endmodule";
+
+#[test]
+fn scan() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().load(Netlist::elaborate(SCAN, 1, 1).unwrap());
+    // First stage first, each stage least significant bit first
+    assert_eq!(fpga.send_command(FPGACommand::Scan).unwrap(), Response::Scan(vec![0b10_0101]));
+
+    fpga.send_command(FPGACommand::SetInputs(0, 0, 1)).unwrap();
+    fpga.send_command(FPGACommand::Step(2)).unwrap();
+    assert_eq!(fpga.send_command(FPGACommand::Scan).unwrap(), Response::Scan(vec![0b10_0110]));
+    // Scanning leaves the design as it was
+    fpga.send_command(FPGACommand::Capture).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::GetOutputs).unwrap(),
+        Response::Outputs(vec![1])
+    );
+}
+
+const TRACE: &str = "module RoC #(
+    parameter OUTPUTS,
+    parameter INPUTS,
+    parameter TRACE_BYTES
+) (
+    input                   clk,
+    input                   tick,
+    input   [INPUTS-1:0]    inputs,
+    output  [OUTPUTS-1:0]   outputs,
+    input                   trace_arm,
+    input   [23:0]          trace_addr,
+    output  [TRACE_BYTES*8-1:0] trace_data,
+    output  [1:0]           trace_state,
+    output  [23:0]          trace_start,
+    output  [23:0]          trace_count
+);
+    wire w0;
+    wire w1;
+    assign w0 = inputs[0];
+    torch #(1'b1) c1 (.i_clk(tick), .i_in(w0), .o_out(w1));
+    trace #(8, 4, 1, 2'd0) t0 (.i_clk(clk), .i_tick(tick), .i_arm(trace_arm), .i_probes({6'd0, w1, w0}), .i_trigger(w0), .i_addr(trace_addr), .o_data(trace_data), .o_state(trace_state), .o_start(trace_start), .o_count(trace_count));
+    assign outputs[0] = w1;
+endmodule";
+
+#[test]
+fn trace() {
+    let (mut fpga, mock) = connect(1, 1);
+    mock.state.lock().unwrap().load(Netlist::elaborate(TRACE, 1, 1).unwrap());
+    fpga.send_command(FPGACommand::Step(2)).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::TraceStatus).unwrap(),
+        Response::TraceStatus(TraceStatus { state: TraceState::Armed, start: 0, count: 2 })
+    );
+
+    // The rising input fires the trigger, after which two more ticks fill the window
+    fpga.send_command(FPGACommand::SetInputs(0, 0, 1)).unwrap();
+    fpga.send_command(FPGACommand::Step(5)).unwrap();
+    let Response::TraceStatus(status) = fpga.send_command(FPGACommand::TraceStatus).unwrap() else {
+        panic!("expected the trace status");
+    };
+    assert_eq!(status.state, TraceState::Done);
+    assert_eq!(status.count, 4);
+    let rows: Vec<_> = (0..4)
+        .map(|i| match fpga.send_command(FPGACommand::ReadTrace((status.start + i) % 4)).unwrap() {
+            Response::TraceRow(row) => row[0],
+            response => panic!("expected a trace row, got {:?}", response),
+        })
+        .collect();
+    assert_eq!(rows, vec![0b10, 0b11, 0b01, 0b01]);
+
+    // Arming again starts over
+    fpga.send_command(FPGACommand::TraceArm).unwrap();
+    fpga.send_command(FPGACommand::Step(1)).unwrap();
+    assert_eq!(
+        fpga.send_command(FPGACommand::TraceStatus).unwrap(),
+        Response::TraceStatus(TraceStatus { state: TraceState::Armed, start: 0, count: 1 })
+    );
+    assert_eq!(
+        FPGACommand::decode(0xD0, &FPGACommand::ReadTrace(0x12345).payload()),
+        Some(FPGACommand::ReadTrace(0x12345))
+    );
+}